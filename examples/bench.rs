@@ -0,0 +1,8 @@
+// cargo run --release --example bench --features bench
+use knn_classifier::{bench, Parallelism};
+fn main() {
+    for s in bench::standard_scenarios() {
+        println!("serial   {}", bench::run(&s, |_| {}));
+        println!("parallel {}", bench::run(&s, |clf| clf.parallelism = Parallelism::threads(0)));
+    }
+}
@@ -0,0 +1,81 @@
+//! Active learning: choosing which unlabeled points to label next.
+
+use crate::stats::quantile_sorted;
+use crate::KnnClassifier;
+
+impl KnnClassifier {
+    /// Function to rank unlabeled points by how much labeling them would help, returns up to `n` indices
+    ///
+    /// The uncertainty of a point is `1 - margin`, where the margin is the difference between the vote
+    /// shares of the two best labels among its k nearest neighbors. Points are picked greedily by uncertainty,
+    /// damped for points close to an already picked one, so the suggestions are spread over different regions
+    /// instead of piling up on one border. Before any item is learned, points are simply taken in order.
+    pub fn suggest_labels(&self, unlabeled: &[Vec<f64>], n: usize) -> Vec<usize> {
+        if self.items.is_empty() {
+            return (0..unlabeled.len().min(n)).collect();
+        }
+        let mut uncertainty = vec![];
+        let mut nearest_dist = vec![];
+        for item in unlabeled {
+            let neighbors = self.nearest(item, self.k);
+            let mut shares: Vec<(&str, f64)> = vec![];
+            for ((i, _), weight) in neighbors.iter().zip(self.neighbor_weights(&neighbors)) {
+                let label = self.items[*i].label.as_str();
+                match shares.iter_mut().find(|(l, _)| *l == label) {
+                    Some((_, w)) => *w += weight,
+                    None => shares.push((label, weight)),
+                }
+            }
+            let total: f64 = shares.iter().map(|(_, w)| w).sum();
+            shares.sort_by(|a, b| b.1.total_cmp(&a.1));
+            let margin = match (shares.first(), shares.get(1)) {
+                _ if total <= 0.0 => 0.0,
+                (Some(a), Some(b)) => (a.1 - b.1) / total,
+                _ => 1.0,
+            };
+            uncertainty.push(1.0 - margin);
+            nearest_dist.push(neighbors[0].1);
+        }
+        // distances closer than the typical distance to the training data count as the same region
+        nearest_dist.sort_by(|a, b| a.total_cmp(b));
+        let scale = quantile_sorted(&nearest_dist, 0.5);
+        let mut to_picked = vec![f64::INFINITY; unlabeled.len()];
+        let mut picked: Vec<usize> = vec![];
+        while picked.len() < n.min(unlabeled.len()) {
+            let mut best: Option<(usize, f64)> = None;
+            for (i, u) in uncertainty.iter().enumerate() {
+                if picked.contains(&i) { continue; }
+                let d = to_picked[i];
+                let score = if d.is_infinite() { *u } else if d + scale > 0.0 { u * d / (d + scale) } else { 0.0 };
+                if best.is_none_or(|(_, s)| score > s) { best = Some((i, score)); }
+            }
+            let Some((p, _)) = best else { break };
+            picked.push(p);
+            for (i, item) in unlabeled.iter().enumerate() {
+                to_picked[i] = to_picked[i].min(self.distance(item, &unlabeled[p]));
+            }
+        }
+        picked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suggest_labels() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.], &[11.], &[12.], &[20.], &[21.], &[22.]],
+            &["a", "a", "a", "b", "b", "b", "c", "c", "c"]);
+        let unlabeled = vec![vec![0.5], vec![6.0], vec![6.1], vec![11.5], vec![6.2], vec![16.0]];
+        // border points first; the other border (16.0) comes before the points next to 6.0
+        assert_eq!(c.suggest_labels(&unlabeled, 3), [1, 5, 4]);
+        assert_eq!(c.suggest_labels(&unlabeled, 10).len(), 6);
+        assert_eq!(KnnClassifier::new(3).suggest_labels(&unlabeled, 2), [0, 1]);
+        // infinite points have NaN distances to infinite items and do not break the ranking
+        let mut d = KnnClassifier::new(1);
+        d.fit(&[&[f64::INFINITY], &[f64::INFINITY]], &["a", "b"]);
+        assert_eq!(d.suggest_labels(&[vec![f64::INFINITY], vec![f64::INFINITY]], 2).len(), 2);
+    }
+}
@@ -0,0 +1,107 @@
+//! Predictions and searches restricted to some items (allowed classes or an item predicate).
+
+use crate::index::neighbor_order;
+use crate::{Backend, KnnClassifier, KnnItem};
+
+impl KnnClassifier {
+    /// Function to predict, only voting among the items of the allowed classes
+    ///
+    /// The k nearest items with an allowed label vote, as if the model only held those classes
+    /// (e.g. the labels valid in the user's region). The model is not copied.
+    /// Returns `fallback` (`None` without one) when no item has an allowed label.
+    pub fn predict_one_among(&self, item: &[f64], labels: &[&str]) -> Option<String> {
+        self.predict_one_filtered(item, |label| labels.contains(&label))
+    }
+    /// Function to predict, only voting among the items whose label is accepted by the filter
+    pub fn predict_one_filtered(&self, item: &[f64], filter: impl Fn(&str) -> bool) -> Option<String> {
+        self.predict_one_where(item, |it| filter(&it.label))
+    }
+    /// Function to predict, only voting among the items accepted by the predicate (e.g. by `source` or `timestamp`)
+    ///
+    /// The predicate is evaluated during the search, so the model is not copied. Returns `fallback` (`None` without one) when no item is accepted.
+    ///
+    /// ```rs
+    /// let label = clf.predict_one_where(&query, |it| it.timestamp.is_some_and(|t| t + 30 * 86400 >= now));
+    /// ```
+    pub fn predict_one_where(&self, item: &[f64], filter: impl Fn(&KnnItem) -> bool) -> Option<String> {
+        let keep = |i: usize| filter(&self.items[i]);
+        let neighbors = self.nearest_filtered(item, self.k, keep);
+        self.decide_filtered(item, &neighbors, keep).map(|l| l.to_string())
+    }
+    /// Function to find the n nearest items accepted by the predicate as (index, label, distance) (see `kneighbors`)
+    pub fn kneighbors_where(&self, item: &[f64], n: usize, filter: impl Fn(&KnnItem) -> bool) -> Vec<(usize, &str, f64)> {
+        let found = self.nearest_filtered(item, n, |i| filter(&self.items[i]));
+        found.into_iter().map(|(i, d)| (i, self.items[i].label.as_str(), d)).collect()
+    }
+    // Function to find the n nearest items accepted by `keep` (same order as `nearest`)
+    //
+    // Brute force skips the other items before computing their distance. An index is asked for
+    // more and more neighbors until n of them are accepted, so it still helps when the accepted items are common.
+    pub(crate) fn nearest_filtered(&self, item: &[f64], n: usize, keep: impl Fn(usize) -> bool) -> Vec<(usize, f64)> {
+        let item = &*self.projected(item);
+        if self.backend == Backend::BruteForce {
+            let query = self.query_point(item);
+            let mut found: Vec<(usize, f64)> = (0..self.items.len())
+                .filter(|i| !self.items[*i].deleted && keep(*i))
+                .map(|i| (i, query.distance_to(i)))
+                .collect();
+            found.sort_by(neighbor_order);
+            found.truncate(n);
+            return found;
+        }
+        let mut wanted = n.saturating_mul(4).max(32);
+        loop {
+            let found = self.nearest(item, wanted);
+            let complete = found.len() < wanted;
+            let kept: Vec<(usize, f64)> = found.into_iter().filter(|(i, _)| keep(*i)).take(n).collect();
+            if kept.len() == n || complete {
+                return kept;
+            }
+            wanted = wanted.saturating_mul(4);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier};
+
+    #[test]
+    fn test_predict_one_among() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..300 {
+            c.fit_one(&[i as f64], ["a", "b", "c"][i % 3]);
+        }
+        c.fit_one(&[1000.], "d");
+        c.fit_one(&[1001.], "d");
+        assert_eq!(c.predict_one(&[150.]), "a");
+        assert_eq!(c.predict_one_among(&[150.], &["b", "c"]).unwrap(), "b");
+        assert_eq!(c.predict_one_among(&[150.], &["x"]), None);
+        for backend in [Backend::BruteForce, Backend::KdTree, Backend::VpTree] {
+            c.backend = backend;
+            // the only two "d" items are far away; the search widens until it finds them
+            assert_eq!(c.predict_one_among(&[0.], &["d"]).unwrap(), "d");
+            let n = c.nearest_filtered(&[0.], 3, |i| c.items[i].label == "d");
+            assert_eq!(n, [(300, 1000.), (301, 1001.)]);
+            assert_eq!(c.predict_one_filtered(&[151.], |l| l != "a").unwrap(), "c");
+        }
+    }
+    #[test]
+    fn test_predict_one_where() {
+        let mut c = KnnClassifier::new(3);
+        for day in 0..100u64 {
+            c.fit_one_at(&[day as f64], if day < 90 { "old" } else { "new" }, day);
+        }
+        c.fit_source(&[&[50.]], &["manual"], "human");
+        c.delete(95);
+        let recent = |it: &crate::KnnItem| it.timestamp.is_some_and(|t| t + 30 >= 100);
+        for backend in [Backend::BruteForce, Backend::BallTree] {
+            c.backend = backend;
+            assert_eq!(c.predict_one(&[50.]), "old");
+            assert_eq!(c.predict_one_where(&[50.], recent).unwrap(), "old");
+            assert_eq!(c.kneighbors_where(&[95.], 3, |it| it.label == "new"), [(94, "new", 1.), (96, "new", 1.), (93, "new", 2.)]);
+            assert_eq!(c.predict_one_where(&[50.], |it| it.source.is_some()).unwrap(), "manual");
+            assert_eq!(c.predict_one_where(&[50.], |it| it.timestamp.is_some_and(|t| t > 1000)), None);
+        }
+    }
+}
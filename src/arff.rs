@@ -0,0 +1,187 @@
+//! Reader for ARFF (Weka) files.
+//!
+//! Numeric attributes (`numeric`, `real`, `integer`) are used as is, and nominal attributes
+//! (`{a, b, c}`) are encoded as the position of the value in the declaration (0, 1, 2).
+//! Missing values (`?`) become `NaN`. Sparse data rows (`{0 1.5, 3 x}`) are supported.
+
+use crate::{KnnClassifier, KnnError, KnnItem};
+
+enum AttrType {
+    Numeric,
+    Nominal(Vec<String>),
+    Other(String),
+}
+
+struct Attr {
+    name: String,
+    ty: AttrType,
+}
+
+impl KnnClassifier {
+    /// read ARFF text, using the attribute at `label_col` as the label
+    ///
+    /// Most ARFF datasets put the class attribute last, so `label_col` is usually `n_attributes - 1`.
+    pub fn from_arff(&mut self, s: &str, label_col: usize) -> Result<(), KnnError> {
+        let mut attrs: Vec<Attr> = vec![];
+        let mut in_data = false;
+        let mut items = vec![];
+        for (no, line) in s.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('%') { continue; }
+            let err = |msg: &str| KnnError::Parse { line: no + 1, msg: msg.to_string() };
+            if !in_data {
+                let lower = line.to_lowercase();
+                if lower.starts_with("@relation") {
+                    continue;
+                } else if lower.starts_with("@attribute") {
+                    attrs.push(parse_attribute(&line["@attribute".len()..]).ok_or_else(|| err("invalid @attribute"))?);
+                } else if lower.starts_with("@data") {
+                    if label_col >= attrs.len() {
+                        return Err(err("label_col is out of range"));
+                    }
+                    for (i, a) in attrs.iter().enumerate() {
+                        if let AttrType::Other(t) = &a.ty {
+                            if i != label_col {
+                                return Err(err(&format!("unsupported attribute type for feature {}: {}", a.name, t)));
+                            }
+                        }
+                    }
+                    in_data = true;
+                } else {
+                    return Err(err("unknown declaration"));
+                }
+                continue;
+            }
+            // data row
+            let values = if line.starts_with('{') && line.ends_with('}') {
+                parse_sparse(&line[1..line.len() - 1], &attrs).ok_or_else(|| err("invalid sparse row"))?
+            } else {
+                split_values(line)
+            };
+            if values.len() != attrs.len() {
+                return Err(err(&format!("expected {} values, found {}", attrs.len(), values.len())));
+            }
+            let mut it = KnnItem::new("", &[]);
+            for (i, (v, a)) in values.iter().zip(attrs.iter()).enumerate() {
+                if i == label_col {
+                    it.label = v.clone();
+                    continue;
+                }
+                let d = if v == "?" {
+                    f64::NAN
+                } else {
+                    match &a.ty {
+                        AttrType::Numeric => v.parse().map_err(|_| err(&format!("invalid number: {}", v)))?,
+                        AttrType::Nominal(values) => values.iter().position(|n| n == v)
+                            .ok_or_else(|| err(&format!("unknown nominal value for {}: {}", a.name, v)))? as f64,
+                        AttrType::Other(_) => unreachable!(),
+                    }
+                };
+                it.data.push(d);
+            }
+            // rows without a class can not be learned
+            if it.label == "?" { continue; }
+            items.push(it);
+        }
+        for it in items {
+            self.push_item(it);
+        }
+        self.fit_statistics();
+        Ok(())
+    }
+}
+
+// remove surrounding quotes
+fn unquote(s: &str) -> String {
+    let s = s.trim();
+    if s.len() >= 2 && ((s.starts_with('\'') && s.ends_with('\'')) || (s.starts_with('"') && s.ends_with('"'))) {
+        s[1..s.len() - 1].replace("\\'", "'").replace("\\\"", "\"")
+    } else {
+        s.to_string()
+    }
+}
+
+// split by commas, keeping quoted values together
+fn split_values(s: &str) -> Vec<String> {
+    let mut values = vec![];
+    let mut cur = String::new();
+    let mut quote = None;
+    let mut escaped = false;
+    for c in s.chars() {
+        match quote {
+            _ if escaped => { cur.push(c); escaped = false; },
+            Some(_) if c == '\\' => { cur.push(c); escaped = true; },
+            Some(q) if c == q => { cur.push(c); quote = None; },
+            None if c == '\'' || c == '"' => { cur.push(c); quote = Some(c); },
+            None if c == ',' => values.push(unquote(&std::mem::take(&mut cur))),
+            _ => cur.push(c),
+        }
+    }
+    values.push(unquote(&cur));
+    values
+}
+
+// "name type" after @attribute
+fn parse_attribute(s: &str) -> Option<Attr> {
+    let s = s.trim();
+    let (name, rest) = match s.chars().next()? {
+        q @ ('\'' | '"') => {
+            let end = s[1..].find(q)? + 1;
+            (s[1..end].to_string(), &s[end + 1..])
+        },
+        _ => {
+            let end = s.find(|c: char| c.is_whitespace() || c == '{')?;
+            (s[..end].to_string(), &s[end..])
+        },
+    };
+    let rest = rest.trim();
+    let ty = if rest.starts_with('{') && rest.ends_with('}') {
+        AttrType::Nominal(split_values(&rest[1..rest.len() - 1]))
+    } else {
+        match rest.to_lowercase().as_str() {
+            "numeric" | "real" | "integer" => AttrType::Numeric,
+            t => AttrType::Other(t.to_string()),
+        }
+    };
+    Some(Attr { name, ty })
+}
+
+// "index value, index value" of a sparse row; omitted values are 0 (first value for nominal)
+fn parse_sparse(s: &str, attrs: &[Attr]) -> Option<Vec<String>> {
+    let mut values: Vec<String> = attrs.iter().map(|a| match &a.ty {
+        AttrType::Nominal(v) => v[0].clone(),
+        _ => "0".to_string(),
+    }).collect();
+    for pair in split_values(s) {
+        if pair.is_empty() { continue; }
+        let (idx, v) = pair.split_once(char::is_whitespace)?;
+        *values.get_mut(idx.parse::<usize>().ok()?)? = unquote(v);
+    }
+    Some(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_arff() {
+        let s = "% weather\n@RELATION weather\n\n@ATTRIBUTE outlook {sunny, overcast, 'rainy day'}\n\
+            @attribute temperature REAL\n@attribute 'play it' {yes,no}\n@data\n\
+            sunny,85,no\n'rainy day',?,yes\n{1 70, 2 yes}\novercast,64,?\n";
+        let mut c = KnnClassifier::new(1);
+        c.from_arff(s, 2).unwrap();
+        assert_eq!(c.items.len(), 3);
+        assert_eq!(c.items[0].label, "no");
+        assert_eq!(c.items[0].data, [0.0, 85.0]);
+        assert_eq!(c.items[1].data[0], 2.0);
+        assert!(c.items[1].data[1].is_nan());
+        assert_eq!(c.items[2].label, "yes");
+        assert_eq!(c.items[2].data, [0.0, 70.0]);
+        // errors
+        let mut c = KnnClassifier::new(1);
+        assert_eq!(c.from_arff("@attribute a real\n@attribute b {x}\n@data\n1,y\n", 0),
+            Err(KnnError::Parse { line: 4, msg: "unknown nominal value for b: y".to_string() }));
+        assert!(c.from_arff("@attribute a string\n@attribute b {x}\n@data\n", 1).is_err());
+    }
+}
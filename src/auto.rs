@@ -0,0 +1,141 @@
+//! Automatic choice of k, metric, vote and scaling by cross-validation.
+
+use crate::{KnnClassifier, Metric, Vote};
+use std::time::{Duration, Instant};
+
+/// Maximum number of items `auto_configure` cross-validates (larger sets are sampled evenly)
+pub const AUTO_CONFIGURE_SAMPLE: usize = 2000;
+
+// folds of the cross-validation and the largest k tried
+const FOLDS: usize = 5;
+const MAX_K: usize = 25;
+
+/// Configuration chosen by `KnnClassifier::auto_configure`
+#[derive(Debug, Clone, PartialEq)]
+pub struct AutoConfig {
+    pub k: usize,
+    pub metric: Metric,
+    pub voting: Vote,
+    /// true when the features are scaled to their range (through `feature_weights`)
+    pub scaled: bool,
+    pub feature_weights: Vec<f64>,
+    /// cross-validated accuracy of the configuration
+    pub accuracy: f64,
+    /// number of configurations cross-validated within the budget
+    pub evaluated: usize,
+}
+
+impl KnnClassifier {
+    /// Function to search k, the metric, the vote and the feature scaling by cross-validation, and apply the best
+    ///
+    /// The current configuration is evaluated first, then Euclidean, Manhattan, Chebyshev, cosine and Canberra distances
+    /// (unscaled, and scaled so each feature's range counts 1.0), uniform and distance votes and odd k up to 25.
+    /// The search stops when `budget` runs out (after at least one configuration), and the best configuration
+    /// so far is kept; ties keep the configuration evaluated first. Sets `k`, `metric`, `voting` and `feature_weights`.
+    /// At most `AUTO_CONFIGURE_SAMPLE` items are used. Returns `None` when there are less than two items.
+    pub fn auto_configure(&mut self, budget: Duration) -> Option<AutoConfig> {
+        let start = Instant::now();
+        let mut sample = self.sample_for_search();
+        if sample.items.len() < 2 {
+            return None;
+        }
+        let mut candidates = vec![(self.metric, self.feature_weights.clone(), !self.feature_weights.is_empty())];
+        for metric in [Metric::Euclidean, Metric::Manhattan, Metric::Chebyshev, Metric::Cosine, Metric::Canberra] {
+            let mut grid = vec![(metric, vec![], false)];
+            // preprocessed features are scaled already
+            if metric != Metric::Canberra && !sample.transforms_points() {
+                grid.push((metric, range_weights(&sample.feature_ranges, metric), true));
+            }
+            candidates.extend(grid.into_iter().filter(|(m, w, _)| *m != self.metric || *w != self.feature_weights));
+        }
+        let mut votes = vec![self.voting];
+        votes.extend([Vote::Uniform, Vote::Distance].into_iter().filter(|v| *v != self.voting));
+        let mut ks = vec![self.k];
+        ks.extend((1..=MAX_K).step_by(2).filter(|k| *k != self.k && *k < sample.items.len()));
+        let mut best: Option<AutoConfig> = None;
+        let mut evaluated = 0;
+        'search: for (metric, weights, scaled) in candidates {
+            sample.metric = metric;
+            sample.feature_weights = weights;
+            let cache = sample.distance_cache();
+            for voting in &votes {
+                for k in &ks {
+                    if best.is_some() && start.elapsed() >= budget {
+                        break 'search;
+                    }
+                    sample.voting = *voting;
+                    sample.k = *k;
+                    let accuracy = cache.cross_validate(&sample, FOLDS).ok()?;
+                    evaluated += 1;
+                    if best.as_ref().is_none_or(|b| accuracy > b.accuracy) {
+                        best = Some(AutoConfig { k: *k, metric, voting: *voting, scaled, feature_weights: sample.feature_weights.clone(), accuracy, evaluated: 0 });
+                    }
+                }
+            }
+        }
+        let mut best = best?;
+        best.evaluated = evaluated;
+        self.k = best.k;
+        self.metric = best.metric;
+        self.voting = best.voting;
+        self.feature_weights = best.feature_weights.clone();
+        Some(best)
+    }
+    // classifier with the vote settings and at most `AUTO_CONFIGURE_SAMPLE` live items, taken evenly
+    fn sample_for_search(&self) -> KnnClassifier {
+        let live = self.items.len() - self.deleted_count();
+        let step = live.div_ceil(AUTO_CONFIGURE_SAMPLE).max(1);
+        let mut sample = KnnClassifier {
+            inverse_covariance: self.inverse_covariance.clone(),
+            projection: self.projection.clone(),
+            preprocess: self.preprocess.clone(),
+            standardize: self.standardize,
+            scaler: self.scaler.clone(),
+            source_weights: self.source_weights.clone(),
+            tie_break: self.tie_break,
+            vote_strategy: self.vote_strategy.clone(),
+            ..KnnClassifier::new(self.k)
+        };
+        for (_, it) in self.live_items().step_by(step) {
+            sample.push_item(it.clone());
+        }
+        sample
+    }
+}
+
+// feature weights that scale each feature's range to 1.0 in the terms of the metric
+fn range_weights(ranges: &[(f64, f64)], metric: Metric) -> Vec<f64> {
+    let p = match metric {
+        Metric::Manhattan | Metric::Chebyshev => 1.0,
+        _ => 2.0,
+    };
+    ranges.iter().map(|(min, max)| if max > min { 1.0 / (max - min).powf(p) } else { 1.0 }).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KnnClassifier, Metric};
+    use crate::testutil::Rng;
+    use std::time::Duration;
+
+    #[test]
+    fn test_auto_configure() {
+        let mut c = KnnClassifier::new(1);
+        let mut rng = Rng(11u64);
+        let mut rand = || rng.uniform();
+        // feature 0 decides the label, feature 1 is noise on a much larger scale
+        for _ in 0..200 {
+            let x = rand();
+            c.fit_one(&[x, rand() * 1000.0], if x < 0.5 { "a" } else { "b" });
+        }
+        let before = c.cross_validate(5).unwrap();
+        let zero = c.clone().auto_configure(Duration::ZERO).unwrap();
+        assert_eq!((zero.evaluated, zero.k, zero.metric), (1, 1, Metric::Euclidean));
+        assert_eq!(zero.accuracy, before);
+        let best = c.auto_configure(Duration::from_secs(60)).unwrap();
+        assert!(best.scaled && best.accuracy > 0.9 && best.accuracy > before + 0.2, "{:?} {}", best, before);
+        assert_eq!((c.k, c.metric), (best.k, best.metric));
+        assert_eq!(c.cross_validate(5).unwrap(), best.accuracy);
+        assert_eq!(KnnClassifier::new(3).auto_configure(Duration::from_secs(1)), None);
+    }
+}
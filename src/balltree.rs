@@ -0,0 +1,202 @@
+//! Ball tree for neighbor search under any true metric (see `Backend::BallTree`).
+
+use crate::index::{insert_neighbor, BuiltIndex, NeighborIndex, QueryPoint};
+#[cfg(feature = "msgpack")]
+use crate::index::{leaves_cover_items, IndexParts, PartsReader, TreeNode};
+use crate::instrument;
+use crate::KnnClassifier;
+
+// largest number of items in a leaf
+const LEAF_SIZE: usize = 16;
+// relative slack for rounding errors of the triangle inequality bound
+const BOUND_SLACK: f64 = 1e-9;
+
+#[derive(Debug)]
+enum Children {
+    Leaf(Vec<usize>),
+    Split(usize, usize),
+}
+
+// all items of a node are within `radius` of the item `center`
+#[derive(Debug)]
+struct Node {
+    center: usize,
+    radius: f64,
+    children: Children,
+}
+
+#[derive(Debug)]
+pub(crate) struct BallTree {
+    features: usize,
+    // nodes[0] is the root
+    nodes: Vec<Node>,
+}
+
+impl BallTree {
+    // Function to build the tree with the distance of the classifier, None when the items can not be indexed
+    pub(crate) fn build(clf: &KnnClassifier) -> Option<BallTree> {
+        let features = clf.items.first()?.data.len();
+        if clf.items.iter().any(|it| it.data.len() != features || it.data.iter().any(|v| v.is_nan())) {
+            return None;
+        }
+        let mut tree = BallTree { features, nodes: vec![] };
+        let mut ids: Vec<usize> = (0..clf.items.len()).collect();
+        tree.build_node(clf, &mut ids);
+        Some(tree)
+    }
+    fn build_node(&mut self, clf: &KnnClassifier, ids: &mut [usize]) -> usize {
+        let dist = |i: usize, j: usize| clf.distance(&clf.items[i].data, &clf.items[j].data);
+        // the center is the item nearest to the mean of the items
+        let mut mean = vec![0.0; self.features];
+        for i in ids.iter() {
+            for (m, v) in mean.iter_mut().zip(&clf.items[*i].data) {
+                *m += v / ids.len() as f64;
+            }
+        }
+        let from_mean: Vec<f64> = ids.iter().map(|i| clf.distance(&clf.items[*i].data, &mean)).collect();
+        let center = ids[(0..ids.len()).min_by(|a, b| from_mean[*a].total_cmp(&from_mean[*b])).unwrap()];
+        let farthest = |from: usize| *ids.iter().max_by(|a, b| dist(from, **a).total_cmp(&dist(from, **b))).unwrap();
+        let radius = ids.iter().map(|i| dist(center, *i)).fold(0.0, f64::max);
+        let node = self.nodes.len();
+        self.nodes.push(Node { center, radius, children: Children::Leaf(ids.to_vec()) });
+        if ids.len() <= LEAF_SIZE {
+            return node;
+        }
+        // split between two far apart items, at the median so the tree stays balanced
+        let a = farthest(center);
+        let b = farthest(a);
+        let mid = ids.len() / 2;
+        ids.select_nth_unstable_by(mid, |i, j| (dist(*i, a) - dist(*i, b)).total_cmp(&(dist(*j, a) - dist(*j, b))));
+        let (l, r) = ids.split_at_mut(mid);
+        let left = self.build_node(clf, l);
+        let right = self.build_node(clf, r);
+        self.nodes[node].children = Children::Split(left, right);
+        node
+    }
+    fn search(&self, node: usize, query: &QueryPoint, n: usize, found: &mut Vec<(usize, f64)>) {
+        instrument::count_node();
+        match &self.nodes[node].children {
+            Children::Leaf(ids) => {
+                for &i in ids {
+                    insert_neighbor(found, n, i, query.distance_to(i));
+                }
+            },
+            Children::Split(left, right) => {
+                // lower bound of the distance to the items of a child: d(query, center) - radius
+                let bound = |c: usize| {
+                    let d = query.distance_to(self.nodes[c].center);
+                    (c, d, d - self.nodes[c].radius)
+                };
+                let (mut near, mut far) = (bound(*left), bound(*right));
+                if far.2 < near.2 {
+                    std::mem::swap(&mut near, &mut far);
+                }
+                for (c, d, lb) in [near, far] {
+                    let limit = if found.len() < n { f64::INFINITY } else { found[n - 1].1 };
+                    if lb - BOUND_SLACK * (1.0 + d + limit) <= limit {
+                        self.search(c, query, n, found);
+                    } else {
+                        instrument::count_pruned(1);
+                    }
+                }
+            },
+        }
+    }
+}
+
+impl NeighborIndex for BallTree {
+    // same result as brute force
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        if !clf.metric.is_metric() || query.len() != self.features {
+            return None;
+        }
+        let mut found = vec![];
+        if n > 0 {
+            self.search(0, &clf.query_point(query), n, &mut found);
+        }
+        Some(found)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl BallTree {
+    // Function to read the tree written by `to_parts`, None when it is invalid
+    pub(crate) fn from_parts(r: &mut PartsReader, items: usize) -> Option<BallTree> {
+        let features = r.int()? as usize;
+        let count = r.int()? as usize;
+        let mut nodes = vec![];
+        for node in 0..count {
+            let center = r.below(items)?;
+            let children = match r.int()? {
+                0 => Children::Leaf(r.ids(items)?),
+                1 => Children::Split(r.child(node, count)?, r.child(node, count)?),
+                _ => return None,
+            };
+            nodes.push(Node { center, radius: r.float()?, children });
+        }
+        let covered = leaves_cover_items(nodes.len(), items, |i| match &nodes[i].children {
+            Children::Leaf(ids) => TreeNode::Leaf(ids),
+            Children::Split(left, right) => TreeNode::Split(*left, *right),
+        });
+        covered.then_some(BallTree { features, nodes })
+    }
+}
+
+impl BuiltIndex for BallTree {
+    // [features, nodes, then the center and (0, ids) for a leaf or (1, left, right) for a split], and the radii
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts {
+        let mut parts = IndexParts { ints: vec![self.features as u64, self.nodes.len() as u64], floats: vec![] };
+        for node in &self.nodes {
+            parts.ints.push(node.center as u64);
+            match &node.children {
+                Children::Leaf(ids) => {
+                    parts.ints.push(0);
+                    parts.push_ids(ids);
+                },
+                Children::Split(left, right) => parts.ints.extend([1, *left as u64, *right as u64]),
+            }
+            parts.floats.push(node.radius);
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, Metric};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_ball_tree() {
+        let mut c = KnnClassifier::new(5);
+        let mut rng = Rng(7u64);
+        let mut rand = || rng.uniform();
+        for i in 0..1500 {
+            // 20 features, a few rounded so items tie
+            let p: Vec<f64> = (0..20).map(|f| if f < 2 { (rand() * 4.0).round() } else { rand() * 0.1 }).collect();
+            c.fit_one(&p, if i % 3 == 0 { "a" } else { "b" });
+        }
+        let queries: Vec<Vec<f64>> = (0..30).map(|_| (0..20).map(|_| rand() * 4.0).collect()).collect();
+        let check = |c: &mut KnnClassifier| {
+            c.backend = Backend::BruteForce;
+            let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 9)).collect();
+            c.backend = Backend::BallTree;
+            for (q, e) in queries.iter().zip(&expected) {
+                assert_eq!(c.nearest_indexed(q, 9).as_ref(), Some(e));
+            }
+        };
+        for metric in [Metric::Euclidean, Metric::Manhattan, Metric::Chebyshev, Metric::Canberra] {
+            c.metric = metric;
+            check(&mut c);
+        }
+        // the tree is rebuilt for new distance settings
+        c.metric = Metric::Euclidean;
+        let mut weights = vec![1.0; 20];
+        weights[0] = 5.0;
+        c.set_feature_weights(&weights).unwrap();
+        check(&mut c);
+        c.metric = Metric::Cosine;
+        assert!(c.nearest_indexed(&queries[0], 3).is_none());
+    }
+}
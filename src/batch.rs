@@ -0,0 +1,176 @@
+//! Batch prediction that shares pruning bounds between similar queries.
+
+use crate::index::{insert_neighbor, neighbor_order};
+use crate::instrument;
+use crate::KnnClassifier;
+
+// relative slack for rounding errors of the triangle inequality bound
+const BOUND_SLACK: f64 = 1e-9;
+
+impl KnnClassifier {
+    /// Function to predict a batch of queries, sharing work between similar queries
+    ///
+    /// The queries are grouped around a few leader queries. The distances from a leader to all items
+    /// are computed once, and for every query of its group the triangle inequality
+    /// (`|d(leader, item) - d(leader, query)| <= d(query, item)`) skips the items that can not be neighbors.
+    /// This saves most distance computations when the queries are correlated (e.g. nearby sensor readings).
+    /// The search is always exact and does not use `backend`, so the results are identical to brute-force `predict`
+    /// (and to `predict` with any exact backend), including `reject.label` and `fallback`. Runs serially.
+    /// With a distance that breaks the triangle inequality (cosine, Minkowski with p < 1, missing features),
+    /// every query is a full scan.
+    pub fn predict_batched(&self, items: &[Vec<f64>]) -> Vec<String> {
+        items.iter().for_each(|item| self.warn_range(item));
+        self.nearest_batched(items, self.k).0.iter().zip(items).map(|(neighbors, item)| {
+            self.decide_or_reject(item, neighbors).unwrap_or_else(|e| panic!("{}", e))
+        }).collect()
+    }
+    // Function to find the `n` nearest items of every query (same result as `nearest` with brute force)
+    // Also returns the number of distance computations.
+    pub(crate) fn nearest_batched(&self, queries: &[Vec<f64>], n: usize) -> (Vec<Vec<(usize, f64)>>, usize) {
+        let mut computed = 0;
+        if queries.is_empty() || self.items.is_empty() {
+            return (vec![vec![]; queries.len()], 0);
+        }
+        // masked distances of missing features (NaN) do not obey the triangle inequality either
+        let missing = queries.iter().flatten().chain(self.items.iter().flat_map(|it| it.data.iter())).any(|v| v.is_nan());
+        if !self.metric.is_metric() || missing {
+            // a full scan, also when the backend is approximate
+            let results = queries.iter().map(|q| {
+                let query = self.query_point(q);
+                let mut found = vec![];
+                for (i, _) in self.live_items() {
+                    insert_neighbor(&mut found, n, i, query.distance_to(i));
+                }
+                found
+            }).collect();
+            return (results, queries.len() * self.items.len());
+        }
+        // choose leaders by farthest-first traversal, assigning every query to its nearest leader
+        let leader_count = (queries.len() as f64).sqrt().ceil() as usize;
+        let mut leaders = vec![0];
+        let mut assigned: Vec<(usize, f64)> = queries.iter().map(|q| (0, self.distance(q, &queries[0]))).collect();
+        computed += queries.len();
+        while leaders.len() < leader_count {
+            let (far, (_, d)) = assigned.iter().enumerate()
+                .fold((0, (0, 0.0)), |best, (i, a)| if a.1 > best.1 .1 { (i, *a) } else { best });
+            if d == 0.0 { break; }
+            let li = leaders.len();
+            leaders.push(far);
+            for (q, a) in queries.iter().zip(assigned.iter_mut()) {
+                let d = self.distance(q, &queries[far]);
+                if d < a.1 { *a = (li, d); }
+            }
+            computed += queries.len();
+        }
+        let mut results = vec![vec![]; queries.len()];
+        for (li, leader) in leaders.iter().enumerate() {
+            // items sorted by distance from the leader
+            let query = self.query_point(&queries[*leader]);
+            let mut by_leader: Vec<(usize, f64)> = self.live_items()
+                .map(|(i, _)| (i, query.distance_to(i)))
+                .collect();
+            computed += by_leader.len();
+            by_leader.sort_by(neighbor_order);
+            for (qi, _) in assigned.iter().enumerate().filter(|(_, a)| a.0 == li) {
+                let (found, c) = self.nearest_pruned(&queries[qi], n, &by_leader, assigned[qi].1);
+                results[qi] = found;
+                computed += c;
+            }
+        }
+        (results, computed)
+    }
+    // Function to scan the items outward from the query's position in the leader order,
+    // stopping on each side when the lower bound exceeds the current n-th distance
+    fn nearest_pruned(&self, query: &[f64], n: usize, by_leader: &[(usize, f64)], delta: f64) -> (Vec<(usize, f64)>, usize) {
+        let mut found: Vec<(usize, f64)> = vec![];
+        let mut computed = 0;
+        let query = self.query_point(query);
+        let start = by_leader.partition_point(|(_, d)| *d < delta);
+        let (mut lo, mut hi) = (start, start);
+        let kth = |found: &Vec<(usize, f64)>| if found.len() < n { f64::INFINITY } else { found[n - 1].1 };
+        loop {
+            // lower bounds of the next item on each side
+            let lb_lo = if lo > 0 { delta - by_leader[lo - 1].1 } else { f64::INFINITY };
+            let lb_hi = if hi < by_leader.len() { by_leader[hi].1 - delta } else { f64::INFINITY };
+            let (lb, pos) = if lb_lo <= lb_hi { (lb_lo, lo - (lo > 0) as usize) } else { (lb_hi, hi) };
+            let limit = kth(&found);
+            if lb == f64::INFINITY || lb - BOUND_SLACK * (1.0 + delta + limit) > limit {
+                instrument::count_pruned(by_leader.len() - (hi - lo));
+                break;
+            }
+            if lb_lo <= lb_hi { lo -= 1; } else { hi += 1; }
+            let i = by_leader[pos].0;
+            let d = query.distance_to(i);
+            computed += 1;
+            insert_neighbor(&mut found, n, i, d);
+        }
+        (found, computed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, HnswParams, Metric, Reject};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_predict_batched() {
+        // two clusters of training items and correlated queries
+        let mut c = KnnClassifier::new(3);
+        let mut rng = Rng(12345u64);
+        let mut rand = || rng.uniform();
+        for i in 0..400 {
+            let base = if i % 2 == 0 { 0.0 } else { 5.0 };
+            c.fit_one(&[base + rand(), base + rand()], if i % 2 == 0 { "a" } else { "b" });
+        }
+        let queries: Vec<Vec<f64>> = (0..100).map(|i| {
+            let base = if i < 50 { 0.5 } else { 4.5 };
+            vec![base + rand() * 0.2, base + rand() * 0.2]
+        }).collect();
+        assert_eq!(c.predict_batched(&queries), c.predict(&queries));
+        let (found, computed) = c.nearest_batched(&queries, 5);
+        for (q, f) in queries.iter().zip(&found) {
+            assert_eq!(*f, c.nearest(q, 5));
+        }
+        assert!(computed < queries.len() * c.items.len() / 2);
+        // duplicated items tie in distance
+        c.fit(&[&[9., 9.], &[9., 9.], &[9., 9.]], &["x", "y", "z"]);
+        assert_eq!(c.nearest_batched(&[vec![9., 9.]], 2).0[0], c.nearest(&[9., 9.], 2));
+        // the search stays exact with an approximate backend, also for a distance that is not a metric
+        let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 5)).collect();
+        let labels = c.predict(&queries);
+        c.backend = Backend::Hnsw(HnswParams { m: 2, ef_construction: 2, ef_search: 1 });
+        assert_eq!(c.nearest_batched(&queries, 5).0, expected);
+        assert_eq!(c.predict_batched(&queries), labels);
+        c.metric = Metric::Cosine;
+        c.backend = Backend::BruteForce;
+        let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 5)).collect();
+        c.backend = Backend::Hnsw(HnswParams { m: 2, ef_construction: 2, ef_search: 1 });
+        assert_eq!(c.nearest_batched(&queries, 5).0, expected);
+    }
+    #[test]
+    fn test_predict_batched_reject() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[1.]], &["a", "b"]);
+        c.reject = Reject { max_distance: Some(0.5), label: Some("unknown".to_string()), ..Default::default() };
+        let queries = vec![vec![0.9], vec![3.]];
+        assert_eq!(c.predict_batched(&queries), ["b", "unknown"]);
+        assert_eq!(c.predict_batched(&queries), c.predict(&queries));
+    }
+    #[test]
+    fn test_predict_batched_edges() {
+        // empty model: no neighbors, so the fallback answers
+        let mut c = KnnClassifier::new(3);
+        assert_eq!(c.nearest_batched(&[vec![1.0]], 3).0, vec![vec![]]);
+        c.fallback = Some("none".to_string());
+        assert_eq!(c.predict_batched(&[vec![1.0]]), ["none"]);
+        // a deleted item is never a neighbor, and a NaN query is compared on its observed features
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[1., 1.], &[5., 5.]], &["a", "b", "c"]);
+        c.delete(1);
+        let queries = vec![vec![0.9, 0.9], vec![f64::NAN, 4.0], vec![1.0, 1.0]];
+        assert_eq!(c.predict_batched(&queries), ["a", "c", "a"]);
+        assert_eq!(c.predict_batched(&queries), c.predict(&queries));
+    }
+}
@@ -0,0 +1,137 @@
+//! Standardized benchmark scenarios (feature `bench`).
+//!
+//! The scenarios use synthetic data from a fixed seed, so results can be compared
+//! between machines and between classifier settings.
+//!
+//! ```rs
+//! use knn_classifier::bench;
+//! for s in bench::standard_scenarios() {
+//!     let r = bench::run(&s, |clf| clf.parallelism = Parallelism::threads(0));
+//!     println!("{}", r);
+//! }
+//! ```
+
+use crate::KnnClassifier;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// A benchmark scenario
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scenario {
+    pub name: String,
+    /// number of training items
+    pub n: usize,
+    /// number of features
+    pub d: usize,
+    pub k: usize,
+    /// number of classes
+    pub classes: usize,
+    /// number of queries to predict
+    pub queries: usize,
+}
+
+impl Scenario {
+    pub fn new(n: usize, d: usize, k: usize) -> Scenario {
+        Scenario { name: format!("n={} d={} k={}", n, d, k), n, d, k, classes: 5, queries: 1000 }
+    }
+    /// synthetic training data: `classes` gaussian-like blobs
+    pub fn train_data(&self) -> (Vec<Vec<f64>>, Vec<String>) {
+        let mut rng = Rng(0x853c49e6748fea9b ^ (self.n as u64) << 16 ^ self.d as u64);
+        let centers: Vec<Vec<f64>> = (0..self.classes).map(|_| (0..self.d).map(|_| rng.uniform() * 10.0).collect()).collect();
+        (0..self.n).map(|i| {
+            let c = i % self.classes.max(1);
+            let data = centers[c].iter().map(|v| v + rng.noise()).collect();
+            (data, format!("class{}", c))
+        }).unzip()
+    }
+    /// synthetic queries drawn from the same distribution as the training data
+    pub fn query_data(&self) -> Vec<Vec<f64>> {
+        let mut rng = Rng(0xda3e39cb94b95bdb ^ self.queries as u64);
+        (0..self.queries).map(|_| (0..self.d).map(|_| rng.uniform() * 10.0 + rng.noise()).collect()).collect()
+    }
+}
+
+/// standard scenarios varying n, d and k
+pub fn standard_scenarios() -> Vec<Scenario> {
+    vec![
+        Scenario::new(1000, 2, 5),
+        Scenario::new(10000, 2, 5),
+        Scenario::new(10000, 16, 5),
+        Scenario::new(10000, 64, 5),
+        Scenario::new(10000, 16, 25),
+        Scenario::new(100000, 8, 5),
+    ]
+}
+
+/// Result of a scenario
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchResult {
+    pub name: String,
+    /// time to learn the training data
+    pub fit: Duration,
+    /// time to predict all queries
+    pub predict: Duration,
+    pub queries: usize,
+}
+
+impl BenchResult {
+    /// predictions per second
+    pub fn throughput(&self) -> f64 {
+        self.queries as f64 / self.predict.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: fit {:?}, predict {:?} ({:.0} queries/s)", self.name, self.fit, self.predict, self.throughput())
+    }
+}
+
+/// run a scenario; `configure` sets up the classifier (parallelism, ...) before fitting
+pub fn run(scenario: &Scenario, configure: impl Fn(&mut KnnClassifier)) -> BenchResult {
+    let (data, labels) = scenario.train_data();
+    let queries = scenario.query_data();
+    let mut clf = KnnClassifier::new(scenario.k);
+    configure(&mut clf);
+    let start = Instant::now();
+    let rows = data.into_iter().zip(labels).map(Ok::<_, ()>);
+    clf.try_fit_from_iter(rows).unwrap();
+    let fit = start.elapsed();
+    let start = Instant::now();
+    let result = clf.predict(&queries);
+    let predict = start.elapsed();
+    assert_eq!(result.len(), queries.len());
+    BenchResult { name: scenario.name.clone(), fit, predict, queries: queries.len() }
+}
+
+// deterministic random generator (xorshift64*)
+struct Rng(u64);
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545f4914f6cdd1d)
+    }
+    fn uniform(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+    // sum of uniforms: roughly normal with mean 0
+    fn noise(&mut self) -> f64 {
+        (0..4).map(|_| self.uniform()).sum::<f64>() - 2.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_run() {
+        let s = Scenario { queries: 10, ..Scenario::new(100, 3, 5) };
+        assert_eq!(s.train_data(), s.train_data());
+        let r = run(&s, |_| {});
+        assert_eq!(r.queries, 10);
+        assert!(r.to_string().starts_with("n=100 d=3 k=5: fit"));
+    }
+}
@@ -0,0 +1,167 @@
+// Command line tool for model files (MessagePack or CSV with the label in column 0)
+//
+// cargo run --features msgpack --bin knn -- info model.bin
+// cargo run --features msgpack --bin knn -- clean data.csv --edit --condense --dedup -o cleaned.csv
+// printf '150,80\n170,60\n' | cargo run --features msgpack --bin knn -- stream model.bin
+use knn_classifier::{Backend, KnnClassifier, KnnError};
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+const USAGE: &str = "usage:
+  knn info <model file>
+  knn clean <data.csv> [--dedup] [--edit] [--condense] [-k K] [-o <out.csv>]
+  knn stream <model file>
+
+clean runs the selected steps in the order dedup, edit, condense.
+dedup: remove duplicated items (same label and features)
+edit: remove items whose k nearest neighbors vote for another label (Wilson; K >= 1, an even K is raised by one)
+condense: keep only the items needed to classify the others with 1-nn (Hart)
+The cleaned data is written to stdout unless -o is given.
+
+stream reads one row of comma-separated features per line from stdin and writes the predicted label
+for every line to stdout at once. A line that can not be parsed or predicted gives an empty line (the error goes to stderr).";
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let result = match args.iter().map(|s| s.as_str()).collect::<Vec<_>>().as_slice() {
+        ["info", path] => info(Path::new(path)),
+        ["stream", path] => stream(Path::new(path)),
+        ["clean", path, options @ ..] => match CleanOptions::parse(options) {
+            Some(opts) => clean(Path::new(path), &opts),
+            None => {
+                eprintln!("{}", USAGE);
+                std::process::exit(2);
+            },
+        },
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        },
+    };
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
+    }
+}
+
+// Function to load a model file, MessagePack unless the file is named *.csv
+fn load(path: &Path) -> Result<(KnnClassifier, &'static str, usize), KnnError> {
+    let bytes = std::fs::read(path).map_err(|e| KnnError::Io(format!("{}: {}", path.display(), e)))?;
+    if path.extension().is_some_and(|e| e.eq_ignore_ascii_case("csv")) {
+        let mut clf = KnnClassifier::new(0);
+        clf.from_csv_reader(bytes.as_slice(), ',', 0, false, |_| {})?;
+        Ok((clf, "CSV", bytes.len()))
+    } else {
+        Ok((KnnClassifier::from_msgpack(&bytes)?, "MessagePack", bytes.len()))
+    }
+}
+
+fn info(path: &Path) -> Result<(), KnnError> {
+    let (clf, format, size) = load(path)?;
+    println!("file:     {} ({}, {} bytes)", path.display(), format, size);
+    if format == "CSV" {
+        println!("k:        {} (not stored in CSV files)", clf.k);
+    } else {
+        println!("k:        {}", clf.k);
+    }
+    if format == "CSV" {
+        println!("metric:   {:?} (not stored in CSV files)", clf.metric);
+    } else {
+        println!("metric:   {:?}", clf.metric);
+        println!("voting:   {:?}", clf.voting);
+    }
+    if format == "CSV" {
+        println!("index:    {:?} (not stored in CSV files)", clf.backend);
+    } else if clf.index_built() {
+        println!("index:    {:?} (loaded from the file)", clf.backend);
+    } else if clf.backend == Backend::BruteForce {
+        println!("index:    {:?}", clf.backend);
+    } else {
+        println!("index:    {:?} (built on the first query)", clf.backend);
+    }
+    println!("items:    {}", clf.items.len());
+    println!("features: {}", clf.items.first().map(|it| it.data.len()).unwrap_or(0));
+    println!("memory:   {} bytes", clf.memory_usage());
+    let summaries = clf.class_summaries();
+    println!("classes:  {}", summaries.len());
+    let width = summaries.iter().map(|s| s.label.chars().count()).max().unwrap_or(0);
+    for s in summaries {
+        let share = s.count as f64 * 100.0 / clf.items.len() as f64;
+        println!("  {:<width$}  {:>8}  {:5.1}%", s.label, s.count, share, width = width);
+    }
+    Ok(())
+}
+
+struct CleanOptions {
+    dedup: bool,
+    edit: bool,
+    condense: bool,
+    k: usize,
+    output: Option<String>,
+}
+
+impl CleanOptions {
+    fn parse(args: &[&str]) -> Option<CleanOptions> {
+        let mut opts = CleanOptions { dedup: false, edit: false, condense: false, k: 3, output: None };
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match *arg {
+                "--dedup" => opts.dedup = true,
+                "--edit" => opts.edit = true,
+                "--condense" => opts.condense = true,
+                // a positive k, made odd like `KnnClassifier::new`
+                "-k" => opts.k = args.next()?.parse().ok().filter(|k| *k >= 1).map(|k| KnnClassifier::new(k).k)?,
+                "-o" => opts.output = Some(args.next()?.to_string()),
+                _ => return None,
+            }
+        }
+        Some(opts)
+    }
+}
+
+fn clean(path: &Path, opts: &CleanOptions) -> Result<(), KnnError> {
+    let (mut clf, _, _) = load(path)?;
+    clf.k = opts.k;
+    let before = clf.items.len();
+    if opts.dedup {
+        eprintln!("dedup: removed {} items", clf.dedup(1e-9));
+    }
+    if opts.edit {
+        eprintln!("edit: removed {} items", clf.edit());
+    }
+    if opts.condense {
+        eprintln!("condense: removed {} items", clf.condense());
+    }
+    eprintln!("{} of {} items kept", clf.items.len(), before);
+    let csv = clf.to_csv(',');
+    match &opts.output {
+        Some(out) => std::fs::write(out, csv).map_err(|e| KnnError::Io(format!("{}: {}", out, e))),
+        None => {
+            print!("{}", csv);
+            Ok(())
+        },
+    }
+}
+
+fn stream(path: &Path) -> Result<(), KnnError> {
+    let (clf, _, _) = load(path)?;
+    let mut out = std::io::stdout().lock();
+    for (no, line) in std::io::stdin().lock().lines().enumerate() {
+        let line = line.map_err(|e| KnnError::Io(e.to_string()))?;
+        let row: Result<Vec<f64>, _> = line.split(',').map(|v| v.trim().parse::<f64>()).collect();
+        // a line that can not be predicted gets an empty answer, so the pipe keeps going
+        let label = match row.map_err(|e| e.to_string()).and_then(|row| clf.try_predict_one(&row).map_err(|e| e.to_string())) {
+            Ok(label) => label,
+            Err(e) => {
+                eprintln!("line {}: {}", no + 1, e);
+                String::new()
+            },
+        };
+        // flush every line, so a caller waiting for the answer gets it at once;
+        // writes block while the reader is behind, and a closed pipe ends the stream
+        if writeln!(out, "{}", label).and_then(|_| out.flush()).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Coarse-to-fine classification for problems with many classes.
+
+use crate::index::neighbor_order;
+use crate::{KnnClassifier, KnnError, ShardedClassifier};
+
+/// Two-stage classifier: nearest centroid, then k-nn
+///
+/// The coarse stage compares the query with the centroid of every class (one distance per class)
+/// and keeps the `candidates` nearest classes. The fine stage runs k-nn over the items of those
+/// classes only. With many classes this scans a small part of the data for each query.
+/// Both stages use the distance settings of the classifier (see `ShardedClassifier`).
+#[derive(Debug, Clone)]
+pub struct CoarseToFineClassifier {
+    /// number of candidate classes passed to the k-nn stage
+    pub candidates: usize,
+    fine: ShardedClassifier,
+    // (label, sum of items, number of items)
+    centroids: Vec<(String, Vec<f64>, usize)>,
+}
+
+impl CoarseToFineClassifier {
+    /// build from a classifier
+    pub fn from_classifier(clf: KnnClassifier, candidates: usize) -> CoarseToFineClassifier {
+        let fine = ShardedClassifier::from_classifier(clf);
+        let centroids = fine.classes().zip(fine.centroids()).map(|(label, c)| {
+            let n = fine.class_len(label);
+            (label.to_string(), c.iter().map(|v| v * n as f64).collect(), n)
+        }).collect();
+        CoarseToFineClassifier { candidates, fine, centroids }
+    }
+    /// Function to add a single data point (`KnnError::Invalid` when the number of features differs, see `ShardedClassifier::fit_one`)
+    pub fn fit_one(&mut self, data: &[f64], label: &str) -> Result<(), KnnError> {
+        self.fine.fit_one(data, label)?;
+        let point = self.fine.point(data);
+        match self.centroids.iter_mut().find(|(l, _, _)| l == label) {
+            Some((_, sum, n)) => {
+                sum.iter_mut().zip(&point).for_each(|(s, v)| *s += v);
+                *n += 1;
+            },
+            None => self.centroids.push((label.to_string(), point, 1)),
+        }
+        Ok(())
+    }
+    /// candidate classes of the coarse stage, nearest centroid first (ties and undefined distances in class order)
+    pub fn candidate_classes(&self, item: &[f64]) -> Vec<&str> {
+        let item = &self.fine.point(item);
+        let mut distances: Vec<(usize, f64)> = self.centroids.iter().enumerate().map(|(c, (_, sum, n))| {
+            let centroid: Vec<f64> = sum.iter().map(|s| s / *n as f64).collect();
+            (c, self.fine.point_distance(&centroid, item))
+        }).collect();
+        distances.sort_by(neighbor_order);
+        distances.into_iter().take(self.candidates.max(1)).map(|(c, _)| self.centroids[c].0.as_str()).collect()
+    }
+    /// Function to predict based on a single data point
+    ///
+    /// Panics when there are no items, like `KnnClassifier::predict_one`; `try_predict_one` returns an error instead.
+    pub fn predict_one(&self, item: &[f64]) -> String {
+        self.try_predict_one(item).unwrap_or_else(|e| panic!("{}", e))
+    }
+    /// Function to predict based on a single data point, `KnnError::NoNeighbors` when there are no items
+    pub fn try_predict_one(&self, item: &[f64]) -> Result<String, KnnError> {
+        let classes = self.candidate_classes(item);
+        self.fine.predict_one_among(item, &classes).ok_or(KnnError::NoNeighbors)
+    }
+    /// Function to predict based on multiple data points
+    pub fn predict(&self, items: &[Vec<f64>]) -> Vec<String> {
+        items.iter().map(|it| self.predict_one(it)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coarse_to_fine() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..10 {
+            let x = i as f64 * 10.0;
+            c.fit(&[&[x, 0.0], &[x + 1.0, 0.0], &[x, 1.0]], &[&i.to_string(), &i.to_string(), &i.to_string()]);
+        }
+        let mut cf = CoarseToFineClassifier::from_classifier(c, 2);
+        assert_eq!(cf.candidate_classes(&[31.0, 0.0]), ["3", "4"]);
+        assert_eq!(cf.predict(&[vec![31.0, 0.0], vec![89.0, 1.0]]), ["3", "9"]);
+        cf.fit_one(&[200.0, 0.0], "x").unwrap();
+        cf.fit_one(&[201.0, 0.0], "x").unwrap();
+        assert!(cf.fit_one(&[1.0], "x").is_err());
+        assert_eq!(cf.predict_one(&[190.0, 0.0]), "x");
+        // an undefined distance does not panic
+        assert_eq!(cf.candidate_classes(&[31.0, f64::NAN]).len(), 2);
+        assert!(cf.try_predict_one(&[31.0, f64::NAN]).is_ok());
+        let empty = CoarseToFineClassifier::from_classifier(KnnClassifier::new(3), 2);
+        assert_eq!(empty.try_predict_one(&[1.0, 2.0]), Err(KnnError::NoNeighbors));
+    }
+}
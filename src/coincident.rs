@@ -0,0 +1,104 @@
+//! Decision for queries that coincide with training items.
+
+use crate::KnnClassifier;
+
+/// Decision of `predict_one` when all k nearest items coincide with the query (distance 0)
+///
+/// With `k` or more training items at the query, every one of them is an equally near neighbor,
+/// so which ones fill the k slots would only depend on the learning order. This setting makes the decision explicit.
+/// With fewer coincident items, they simply vote with the other neighbors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CoincidentVote {
+    /// weighted majority of all coincident items, even more than `k`;
+    /// ties go to the label of the coincident item learned first, or as `tie_break` says (default)
+    #[default]
+    Majority,
+    /// label of the coincident item learned first
+    First,
+    /// vote among the k coincident items learned first
+    Neighbors,
+}
+
+impl KnnClassifier {
+    // Function to decide the label of a query from its k nearest neighbors (sorted), applying `coincident`
+    // (`fallback` when there are no neighbors)
+    pub(crate) fn decide(&self, item: &[f64], neighbors: &[(usize, f64)]) -> Option<&str> {
+        self.decide_filtered(item, neighbors, |_| true)
+    }
+    // same as `decide`, only counting the coincident items accepted by `keep`
+    pub(crate) fn decide_filtered(&self, item: &[f64], neighbors: &[(usize, f64)], keep: impl Fn(usize) -> bool) -> Option<&str> {
+        let decided = self.decide_with(neighbors, |i| self.items[i].label.as_str(), || self.coincident_items(item, keep));
+        // no neighbors at all
+        decided.or(self.fallback.as_deref())
+    }
+    // Function to decide the label from the neighbors (sorted), applying `coincident`, None when there are no neighbors
+    // (`label` gives the label of an item and `at_query` the items at distance 0, so `FrozenKnnClassifier` decides alike)
+    pub(crate) fn decide_with<'a>(&self, neighbors: &[(usize, f64)], label: impl Fn(usize) -> &'a str, at_query: impl FnOnce() -> Vec<usize>) -> Option<&'a str> {
+        if !self.coincident_decides(neighbors) {
+            let labels: Vec<&str> = neighbors.iter().map(|(i, _)| label(*i)).collect();
+            let weights = self.labeled_neighbor_weights(neighbors, &labels);
+            return self.tally(labels.into_iter().zip(weights));
+        }
+        let mut coincident = at_query().into_iter();
+        match self.coincident {
+            CoincidentVote::First => coincident.next().map(label),
+            _ => self.tally(coincident.map(|i| (label(i), self.vote_weight(i)))),
+        }
+    }
+    // Function to list the votes behind `decide` as (item, weight): the neighbors, or the coincident items
+    // when `coincident` decides (only the first of them with `CoincidentVote::First`)
+    pub(crate) fn voters(&self, item: &[f64], neighbors: &[(usize, f64)]) -> Vec<(usize, f64)> {
+        self.voters_with(neighbors, |i| self.items[i].label.as_str(), || self.coincident_items(item, |_| true))
+    }
+    // same as `voters`, with the labels and the items at the query given like for `decide_with`
+    pub(crate) fn voters_with<'a>(&self, neighbors: &[(usize, f64)], label: impl Fn(usize) -> &'a str, at_query: impl FnOnce() -> Vec<usize>) -> Vec<(usize, f64)> {
+        if !self.coincident_decides(neighbors) {
+            let labels: Vec<&str> = neighbors.iter().map(|(i, _)| label(*i)).collect();
+            let weights = self.labeled_neighbor_weights(neighbors, &labels);
+            return neighbors.iter().map(|(i, _)| *i).zip(weights).collect();
+        }
+        let coincident = at_query();
+        let n = if self.coincident == CoincidentVote::First { 1 } else { coincident.len() };
+        coincident.into_iter().take(n).map(|i| (i, self.vote_weight(i))).collect()
+    }
+    // true when `coincident` decides instead of a vote of the neighbors (all k of them are at the query)
+    pub(crate) fn coincident_decides(&self, neighbors: &[(usize, f64)]) -> bool {
+        self.coincident != CoincidentVote::Neighbors && neighbors.len() >= self.k && neighbors.last().is_some_and(|n| n.1 == 0.0)
+    }
+    // all live items at distance 0 accepted by `keep`, with their vote weights, in learning order
+    pub(crate) fn coincident_votes(&self, item: &[f64], keep: impl Fn(usize) -> bool) -> Vec<(usize, f64)> {
+        self.coincident_items(item, keep).into_iter().map(|i| (i, self.vote_weight(i))).collect()
+    }
+    // all live items at distance 0 accepted by `keep`, in learning order
+    fn coincident_items(&self, item: &[f64], keep: impl Fn(usize) -> bool) -> Vec<usize> {
+        let query = self.query_point(item);
+        (0..self.items.len())
+            .filter(|i| !self.items[*i].deleted && keep(*i) && query.distance_to(*i) == 0.0)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coincident_vote() {
+        let mut c = KnnClassifier::new(3);
+        // 5 items at the query: 2 "a" learned first, then 3 "b"; "c" is near
+        c.fit(&[&[1., 1.], &[1., 1.], &[1., 1.], &[1., 1.], &[1., 1.], &[1.1, 1.]], &["a", "a", "b", "b", "b", "c"]);
+        assert_eq!(c.predict_one(&[1., 1.]), "b");
+        c.coincident = CoincidentVote::First;
+        assert_eq!(c.predict_one(&[1., 1.]), "a");
+        c.coincident = CoincidentVote::Neighbors;
+        // the 3 nearest are the items learned first
+        assert_eq!(c.predict_one(&[1., 1.]), "a");
+        // ties go to the label learned first
+        c.coincident = CoincidentVote::Majority;
+        c.fit(&[&[2., 2.], &[2., 2.], &[2., 2.], &[2., 2.]], &["y", "x", "x", "y"]);
+        assert_eq!(c.predict_one(&[2., 2.]), "y");
+        // less than k coincident items vote with the others
+        c.fit(&[&[3., 3.], &[3.1, 3.], &[3.2, 3.]], &["p", "q", "q"]);
+        assert_eq!(c.predict_one(&[3., 3.]), "q");
+    }
+}
@@ -0,0 +1,246 @@
+//! Cross-validation and comparison of distance functions.
+
+use crate::index::neighbor_order;
+use crate::{Fnv64, KnnClassifier, KnnError};
+
+/// Distance function between two feature vectors
+pub type DistanceFn = fn(&[f64], &[f64]) -> f64;
+
+/// Cross-validated accuracy of one distance function (see `compare_metrics`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct MetricScore {
+    pub name: String,
+    pub accuracy: f64,
+}
+
+/// One bin of a reliability diagram (see `calibration_curve`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct CalibrationBin {
+    pub lower: f64,
+    pub upper: f64,
+    /// number of predictions whose vote fraction is in this bin
+    pub count: usize,
+    /// mean vote fraction of those predictions
+    pub confidence: f64,
+    /// fraction of those predictions that were correct
+    pub accuracy: f64,
+}
+
+/// Pairwise distances of the items, reused across cross-validation runs (see `KnnClassifier::distance_cache`)
+///
+/// Grid searches evaluate the same data many times with different `k`, folds or votes.
+/// The cache computes every distance once and keeps the neighbor order of every item,
+/// so each later run only walks the sorted lists.
+#[derive(Debug, Clone)]
+pub struct DistanceCache {
+    // fingerprint of the items and the metric the cache was built for
+    fingerprint: u64,
+    // other live items of every live item, nearest first: (index, [(index, distance)])
+    order: Vec<(usize, Vec<(usize, f64)>)>,
+}
+
+impl DistanceCache {
+    /// number of distances stored
+    pub fn len(&self) -> usize {
+        self.order.iter().map(|(_, o)| o.len()).sum()
+    }
+    /// true when no distance is stored
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+    /// true when the cache was built for the current items and metric of the classifier
+    pub fn is_valid_for(&self, clf: &KnnClassifier) -> bool {
+        self.fingerprint == fingerprint(clf)
+    }
+    /// Function to measure the accuracy with k-fold cross-validation (see `KnnClassifier::cross_validate`)
+    ///
+    /// Uses the current `k` and item weights of `clf`, so those can change between runs.
+    /// Fails when the items or the metric changed since the cache was built, or there are less than two items.
+    pub fn cross_validate(&self, clf: &KnnClassifier, folds: usize) -> Result<f64, KnnError> {
+        if !self.is_valid_for(clf) {
+            return Err(KnnError::Invalid("distance cache was built for other items or another metric".to_string()));
+        }
+        let n = self.order.len();
+        if n < 2 {
+            return Err(KnnError::Invalid("cross-validation needs at least two items".to_string()));
+        }
+        let folds = folds.clamp(2, n);
+        let mut correct = 0;
+        for (i, order) in &self.order {
+            let neighbors: Vec<(usize, f64)> = order.iter().filter(|(j, _)| j % folds != i % folds).take(clf.k).copied().collect();
+            if clf.vote(&neighbors) == Some(clf.items[*i].label.as_str()) {
+                correct += 1;
+            }
+        }
+        Ok(correct as f64 / n as f64)
+    }
+}
+
+// hash of the live features and the distance settings (labels, k and weights do not change distances)
+fn fingerprint(clf: &KnnClassifier) -> u64 {
+    let mut h = Fnv64::new();
+    h.write(format!("{:?}{:?}{:?}{:?}{:?}", clf.metric, clf.inverse_covariance, clf.feature_weights, clf.preprocess, clf.active_scaler()).as_bytes());
+    for (i, it) in clf.live_items() {
+        h.write(&(i as u64).to_le_bytes());
+        h.write(&(it.data.len() as u64).to_le_bytes());
+        for d in &it.data {
+            h.write(&d.to_bits().to_le_bytes());
+        }
+    }
+    h.finish()
+}
+
+impl KnnClassifier {
+    /// Function to compute all pairwise distances of the live items once, for repeated cross-validation
+    pub fn distance_cache(&self) -> DistanceCache {
+        let live: Vec<(usize, &[f64])> = self.live_items().map(|(i, it)| (i, it.data.as_slice())).collect();
+        let n = live.len();
+        let mut dist = vec![vec![0.0; n]; n];
+        for (a, (_, x)) in live.iter().enumerate() {
+            for (b, (_, y)) in live.iter().enumerate().skip(a + 1) {
+                let d = self.distance(x, y);
+                dist[a][b] = d;
+                dist[b][a] = d;
+            }
+        }
+        let order = dist.iter().enumerate().map(|(a, row)| {
+            // ties in index order, like `nearest`
+            let mut o: Vec<(usize, f64)> = row.iter().enumerate().filter(|(b, _)| *b != a).map(|(b, d)| (live[b].0, *d)).collect();
+            o.sort_by(neighbor_order);
+            (live[a].0, o)
+        }).collect();
+        DistanceCache { fingerprint: fingerprint(self), order }
+    }
+    /// Function to measure the accuracy with k-fold cross-validation (with `metric`)
+    ///
+    /// Item `i` belongs to fold `i % folds`, so the result is deterministic. Deleted items are left out.
+    /// Returns `None` when there are less than two live items.
+    pub fn cross_validate(&self, folds: usize) -> Option<f64> {
+        self.cross_validate_by(folds, |a, b| self.distance(a, b))
+    }
+    /// Function to measure the accuracy with k-fold cross-validation under another distance function
+    pub fn cross_validate_with(&self, folds: usize, distance: DistanceFn) -> Option<f64> {
+        self.cross_validate_by(folds, distance)
+    }
+    pub(crate) fn cross_validate_by(&self, folds: usize, distance: impl Fn(&[f64], &[f64]) -> f64) -> Option<f64> {
+        let n = self.live_items().count();
+        if n < 2 { return None; }
+        let folds = folds.clamp(2, n);
+        let mut correct = 0;
+        for (i, query) in self.live_items() {
+            let mut distances: Vec<(usize, f64)> = self.live_items()
+                .filter(|(j, _)| j % folds != i % folds)
+                .map(|(j, it)| (j, distance(&it.data, &query.data)))
+                .collect();
+            distances.sort_by(neighbor_order);
+            distances.truncate(self.k);
+            if self.vote(&distances) == Some(query.label.as_str()) {
+                correct += 1;
+            }
+        }
+        Some(correct as f64 / n as f64)
+    }
+    /// Function to cross-validate the same data under several distance functions
+    ///
+    /// Returns the scores ranked by accuracy, best first (ties keep the given order).
+    /// Returns an empty list when there are less than two items.
+    pub fn compare_metrics(&self, metrics: &[(&str, DistanceFn)], folds: usize) -> Vec<MetricScore> {
+        let mut scores: Vec<MetricScore> = metrics.iter().filter_map(|(name, distance)| {
+            let accuracy = self.cross_validate_with(folds, *distance)?;
+            Some(MetricScore { name: name.to_string(), accuracy })
+        }).collect();
+        scores.sort_by(|a, b| b.accuracy.total_cmp(&a.accuracy));
+        scores
+    }
+    /// Function to make reliability-diagram bins from validation data
+    ///
+    /// The vote fraction of the predicted label (0.0 to 1.0) is split into `bins` equal-width bins,
+    /// and each bin compares the mean vote fraction with the accuracy of its predictions.
+    /// For a well calibrated model the two are close. Empty bins are omitted.
+    /// Vote fractions use the item weights (see `merge_weighted`) and `voting`.
+    pub fn calibration_curve(&self, items: &[Vec<f64>], labels: &[&str], bins: usize) -> Vec<CalibrationBin> {
+        let bins = bins.max(1);
+        // (confidence sum, correct, count) per bin
+        let mut acc = vec![(0.0, 0, 0); bins];
+        for (item, label) in items.iter().zip(labels) {
+            let neighbors = self.nearest(item, self.k);
+            let Some(predicted) = self.vote(&neighbors) else { continue };
+            let weights = self.neighbor_weights(&neighbors);
+            let weight = |same: bool| neighbors.iter().zip(&weights)
+                .filter(|((i, _), _)| !same || self.items[*i].label == predicted)
+                .map(|(_, w)| w).sum::<f64>();
+            let confidence = if weight(false) > 0.0 { weight(true) / weight(false) } else { 0.0 };
+            let b = ((confidence * bins as f64) as usize).min(bins - 1);
+            acc[b].0 += confidence;
+            acc[b].1 += (predicted == *label) as usize;
+            acc[b].2 += 1;
+        }
+        acc.iter().enumerate().filter(|(_, a)| a.2 > 0).map(|(b, (sum, correct, count))| CalibrationBin {
+            lower: b as f64 / bins as f64,
+            upper: (b + 1) as f64 / bins as f64,
+            count: *count,
+            confidence: sum / *count as f64,
+            accuracy: *correct as f64 / *count as f64,
+        }).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compare_metrics() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[0., 1.], &[5., 5.], &[5., 6.]], &["a", "a", "b", "b"]);
+        assert_eq!(c.cross_validate(2), Some(1.0));
+        // a "distance" that ignores the features can not beat the real one
+        let scores = c.compare_metrics(&[
+            ("constant", |_, _| 0.0),
+            ("manhattan", crate::manhattan_distance),
+        ], 4);
+        assert_eq!(scores[0], MetricScore { name: "manhattan".to_string(), accuracy: 1.0 });
+        assert_eq!(scores[1].accuracy, 0.5);
+        assert_eq!(KnnClassifier::new(1).cross_validate(5), None);
+    }
+    #[test]
+    fn test_distance_cache() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.], &[11.], &[3.], &[12.]], &["a", "a", "b", "b", "b", "a", "b"]);
+        let cache = c.distance_cache();
+        assert_eq!(cache.len(), 7 * 6);
+        for k in [1, 3, 5] {
+            c.k = k;
+            for folds in [2, 3, 7] {
+                assert_eq!(cache.cross_validate(&c, folds).ok(), c.cross_validate(folds));
+            }
+        }
+        c.fit_one(&[4.], "a");
+        assert!(!cache.is_valid_for(&c));
+        assert!(cache.cross_validate(&c, 2).is_err());
+        // a deleted item is neither a query nor a neighbor
+        c.k = 1;
+        c.fit_one(&[2.5], "b");
+        c.delete(8);
+        let cache = c.distance_cache();
+        assert_eq!(cache.len(), 8 * 7);
+        assert_eq!(cache.cross_validate(&c, 8).ok(), c.cross_validate(8));
+        let mut compacted = c.clone();
+        compacted.compact();
+        assert_eq!(c.cross_validate(8), compacted.cross_validate(8));
+        c.undelete(8);
+        assert!(!cache.is_valid_for(&c));
+    }
+    #[test]
+    fn test_calibration_curve() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.], &[11.], &[12.]], &["a", "a", "b", "b", "b", "b"]);
+        let queries = vec![vec![0.5], vec![1.5], vec![11.], vec![11.5]];
+        let bins = c.calibration_curve(&queries, &["a", "b", "b", "b"], 4);
+        assert_eq!(bins.len(), 2);
+        // 0.5 and 1.5 are predicted "a" with 2 of 3 votes
+        assert_eq!((bins[0].lower, bins[0].upper, bins[0].count), (0.5, 0.75, 2));
+        assert_eq!((bins[0].confidence, bins[0].accuracy), (2. / 3., 0.5));
+        assert_eq!((bins[1].count, bins[1].confidence, bins[1].accuracy), (2, 1.0, 1.0));
+    }
+}
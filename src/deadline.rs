@@ -0,0 +1,85 @@
+//! Prediction with a time limit.
+
+use crate::index::insert_neighbor;
+use crate::KnnClassifier;
+use std::time::{Duration, Instant};
+
+// number of items scanned between clock checks
+const CLOCK_CHECK_ITEMS: usize = 64;
+
+impl KnnClassifier {
+    /// Function to predict, stopping the scan of the items when `limit` has passed
+    ///
+    /// Returns the label voted by the nearest items found so far and `true` when all items were scanned
+    /// (the result is then the same as `predict_one`, including `reject.label`). The first items are always
+    /// scanned, so there is an answer even with a zero limit. Returns `None` when there are no items (and no `fallback`).
+    ///
+    /// With `preprocess` or `standardize`, the items are transformed one by one during the scan, so the limit
+    /// covers the transformation too (unless an earlier prediction already cached the transformed items).
+    /// The limit does not cover the vote: when all k neighbors found coincide with the query,
+    /// `coincident` still looks at every item.
+    pub fn predict_one_within(&self, item: &[f64], limit: Duration) -> Option<(String, bool)> {
+        let start = Instant::now();
+        self.warn_range(item);
+        let mut found: Vec<(usize, f64)> = vec![];
+        let mut complete = true;
+        // transforming all items up front would not check the clock: without cached points, each item is
+        // transformed when its distance is calculated
+        let query = self.query_point_with(item, || self.cached_item_points());
+        for (i, it) in self.items.iter().enumerate() {
+            if i > 0 && i % CLOCK_CHECK_ITEMS == 0 && start.elapsed() >= limit {
+                complete = false;
+                break;
+            }
+            if !it.deleted {
+                insert_neighbor(&mut found, self.k, i, query.distance_to(i));
+            }
+        }
+        let label = self.decide_or_reject(item, &found).ok()?;
+        Some((label, complete))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Reject;
+
+    #[test]
+    fn test_predict_one_within() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..1000 {
+            c.fit_one(&[i as f64], if i < 900 { "a" } else { "b" });
+        }
+        assert_eq!(c.predict_one_within(&[950.], Duration::from_secs(60)), Some(("b".to_string(), true)));
+        // only the first items are scanned
+        assert_eq!(c.predict_one_within(&[950.], Duration::ZERO), Some(("a".to_string(), false)));
+        assert_eq!(KnnClassifier::new(3).predict_one_within(&[1.], Duration::ZERO), None);
+    }
+    #[test]
+    fn test_predict_one_within_standardize() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..1000 {
+            c.fit_one(&[i as f64, (i % 7) as f64], if i < 900 { "a" } else { "b" });
+        }
+        c.standardize = true;
+        c.fit_scaler();
+        // the items are not transformed before the scan
+        assert_eq!(c.predict_one_within(&[950., 3.], Duration::ZERO), Some(("a".to_string(), false)));
+        assert!(c.cached_item_points().is_none());
+        let expected = c.predict_one(&[950., 3.]);
+        assert!(c.cached_item_points().is_some());
+        assert_eq!(c.predict_one_within(&[950., 3.], Duration::from_secs(60)), Some((expected.clone(), true)));
+        c.index.clear();
+        assert_eq!(c.predict_one_within(&[950., 3.], Duration::from_secs(60)), Some((expected, true)));
+    }
+    #[test]
+    fn test_predict_one_within_reject() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.], &[11.], &[12.]], &["a", "a", "a", "b", "b", "b"]);
+        c.reject = Reject { max_distance: Some(2.0), label: Some("unknown".to_string()), ..Default::default() };
+        assert_eq!(c.predict_one(&[20.]), "unknown");
+        assert_eq!(c.predict_one_within(&[20.], Duration::from_secs(60)), Some(("unknown".to_string(), true)));
+        assert_eq!(c.predict_one_within(&[11.], Duration::from_secs(60)), Some(("b".to_string(), true)));
+    }
+}
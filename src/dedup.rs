@@ -0,0 +1,96 @@
+//! Tolerance-based comparison, duplicate removal and conflict detection.
+
+use crate::KnnClassifier;
+
+/// Function to compare feature vectors with a tolerance
+///
+/// Two values are equal when `|x - y| <= eps * max(1, |x|, |y|)`: an absolute tolerance for
+/// values around 1 or smaller and a relative one for large values, so floating-point noise
+/// from different pipelines (`0.1 + 0.2` vs `0.3`) does not defeat exact matching.
+pub fn approx_eq(a: &[f64], b: &[f64], eps: f64) -> bool {
+    a.len() == b.len() && a.iter().zip(b.iter()).all(|(x, y)| approx_eq_f64(*x, *y, eps))
+}
+
+fn approx_eq_f64(x: f64, y: f64, eps: f64) -> bool {
+    x == y || (x - y).abs() <= eps * 1f64.max(x.abs()).max(y.abs())
+}
+
+impl KnnClassifier {
+    /// Function to remove duplicated items (same label, features equal within `eps`)
+    ///
+    /// The first item of each group of duplicates is kept. Deleted items are neither compared nor removed.
+    /// Returns the number of removed items.
+    pub fn dedup(&mut self, eps: f64) -> usize {
+        let mut remove = vec![false; self.items.len()];
+        for (i, j) in self.similar_pairs(eps) {
+            if self.items[i].label == self.items[j].label && !remove[i] {
+                remove[j] = true;
+            }
+        }
+        let before = self.items.len();
+        let mut flags = remove.into_iter();
+        self.items.retain(|_| !flags.next().unwrap());
+        let removed = before - self.items.len();
+        if removed > 0 {
+            self.update_feature_ranges();
+        }
+        removed
+    }
+    /// Function to find conflicting duplicates: pairs of live items `(i, j)` (`i < j`) with features
+    /// equal within `eps` but different labels
+    pub fn find_conflicts(&self, eps: f64) -> Vec<(usize, usize)> {
+        self.similar_pairs(eps).into_iter().filter(|(i, j)| self.items[*i].label != self.items[*j].label).collect()
+    }
+    // pairs (i, j), i < j, of live items with features equal within eps, sorted
+    fn similar_pairs(&self, eps: f64) -> Vec<(usize, usize)> {
+        // sort by the first feature and only compare items close in it
+        let first = |i: usize| self.items[i].data.first().copied().unwrap_or(0.0);
+        let mut order: Vec<usize> = self.live_items().map(|(i, _)| i).collect();
+        order.sort_by(|a, b| first(*a).total_cmp(&first(*b)));
+        let mut pairs = vec![];
+        for (n, &i) in order.iter().enumerate() {
+            for &j in &order[n + 1..] {
+                if !approx_eq_f64(first(i), first(j), eps) { break; }
+                if approx_eq(&self.items[i].data, &self.items[j].data, eps) {
+                    pairs.push((i.min(j), i.max(j)));
+                }
+            }
+        }
+        pairs.sort();
+        pairs
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dedup() {
+        assert!(approx_eq(&[0.1 + 0.2, 1e9], &[0.3, 1e9 + 1.0], 1e-6));
+        assert!(!approx_eq(&[0.3], &[0.31], 1e-6));
+        let mut c = KnnClassifier::new(1);
+        c.fit(
+            &[&[0.3, 1.0], &[0.1 + 0.2, 1.0], &[5.0, 5.0], &[0.3, 1.0 + 1e-12], &[5.0, 5.0]],
+            &["a", "a", "b", "c", "b"]);
+        assert_eq!(c.find_conflicts(1e-9), [(0, 3), (1, 3)]);
+        assert_eq!(c.dedup(1e-9), 2);
+        assert_eq!(c.to_csv(','), "a,0.3,1\nb,5,5\nc,0.3,1.000000000001\n");
+        assert_eq!(c.dedup(0.0), 0);
+    }
+    #[test]
+    fn test_dedup_deleted() {
+        // the live twin of a deleted item is kept
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[0.], &[5.]], &["a", "a", "b"]);
+        c.delete(0);
+        assert_eq!(c.dedup(1e-9), 0);
+        assert_eq!(c.predict_one(&[0.]), "a");
+        c.fit_one(&[5.], "c");
+        assert_eq!(c.find_conflicts(1e-9), [(2, 3)]);
+        c.undelete(0);
+        assert_eq!(c.dedup(1e-9), 1);
+        assert_eq!((c.items.len(), c.deleted_count()), (3, 0));
+        assert_eq!(c.feature_ranges, [(0., 5.)]);
+    }
+}
@@ -0,0 +1,74 @@
+//! Encrypted MessagePack of the classifier (feature `encryption`).
+//!
+//! The MessagePack bytes of `to_msgpack` are sealed with XChaCha20-Poly1305 (authenticated encryption)
+//! under a 32-byte key given by the caller:
+//!
+//! ```text
+//! "KNNE" 0x01 | nonce (24 random bytes) | ciphertext and tag (16 bytes)
+//! ```
+//!
+//! The 5-byte header is authenticated too. A wrong key, a modified byte or a truncated file fails with
+//! `KnnError::Decode` before anything is parsed. The nonce is drawn from the operating system for every call,
+//! so the same key can seal many models.
+
+use crate::{KnnClassifier, KnnError};
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+
+// magic and version of the format written by `to_encrypted_msgpack`
+const HEADER: &[u8; 5] = b"KNNE\x01";
+const NONCE_LEN: usize = 24;
+
+impl KnnClassifier {
+    /// convert to MessagePack bytes encrypted with `key` (see `to_msgpack`)
+    pub fn to_encrypted_msgpack(&self, key: &[u8; 32]) -> Vec<u8> {
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let sealed = cipher.encrypt(&nonce, Payload { msg: &self.to_msgpack(), aad: HEADER })
+            .expect("XChaCha20-Poly1305 encrypts any message that fits in memory");
+        let mut buf = Vec::with_capacity(HEADER.len() + NONCE_LEN + sealed.len());
+        buf.extend_from_slice(HEADER);
+        buf.extend_from_slice(&nonce);
+        buf.extend_from_slice(&sealed);
+        buf
+    }
+    /// convert from MessagePack bytes encrypted with `key` by `to_encrypted_msgpack`
+    pub fn from_encrypted_msgpack(bytes: &[u8], key: &[u8; 32]) -> Result<KnnClassifier, KnnError> {
+        let rest = bytes.strip_prefix(HEADER).ok_or_else(|| KnnError::Decode("not an encrypted model".to_string()))?;
+        if rest.len() < NONCE_LEN {
+            return Err(KnnError::Decode("unexpected end of data".to_string()));
+        }
+        let (nonce, sealed) = rest.split_at(NONCE_LEN);
+        let cipher = XChaCha20Poly1305::new(key.into());
+        let plain = cipher.decrypt(XNonce::from_slice(nonce), Payload { msg: sealed, aad: HEADER })
+            .map_err(|_| KnnError::Decode("decryption failed: wrong key or modified data".to_string()))?;
+        KnnClassifier::from_msgpack(&plain)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypted_msgpack() {
+        let key = [7u8; 32];
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[150., 80.], &[170., 60.], &[160., 70.]], &["肥満", "標準", "標準"]);
+        let bytes = c.to_encrypted_msgpack(&key);
+        assert!(!bytes.windows(6).any(|w| w == "標準".as_bytes()));
+        let c2 = KnnClassifier::from_encrypted_msgpack(&bytes, &key).unwrap();
+        assert_eq!((c2.k, c2.to_csv(',')), (3, c.to_csv(',')));
+        // a new nonce for every call
+        assert_ne!(c.to_encrypted_msgpack(&key), bytes);
+        // wrong key, modified, truncated or plain data
+        assert!(matches!(KnnClassifier::from_encrypted_msgpack(&bytes, &[8u8; 32]), Err(KnnError::Decode(_))));
+        for i in [4, 10, bytes.len() - 1] {
+            let mut broken = bytes.clone();
+            broken[i] ^= 1;
+            assert!(matches!(KnnClassifier::from_encrypted_msgpack(&broken, &key), Err(KnnError::Decode(_))), "{}", i);
+        }
+        assert!(matches!(KnnClassifier::from_encrypted_msgpack(&bytes[..20], &key), Err(KnnError::Decode(_))));
+        assert!(matches!(KnnClassifier::from_encrypted_msgpack(&c.to_msgpack(), &key), Err(KnnError::Decode(_))));
+    }
+}
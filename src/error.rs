@@ -0,0 +1,41 @@
+//! Error type of this crate.
+
+use std::fmt;
+
+/// Errors returned by the fallible functions of this crate
+#[derive(Debug, Clone, PartialEq)]
+pub enum KnnError {
+    /// The input could not be decoded (broken or unsupported model data)
+    Decode(String),
+    /// Reading the input failed
+    Io(String),
+    /// A line of text input could not be parsed (`line` starts at 1)
+    Parse { line: usize, msg: String },
+    /// The model breaks an invariant (see `KnnClassifier::validate`)
+    Invalid(String),
+    /// A query feature is outside the training range of the feature
+    OutOfRange { feature: usize, value: f64, min: f64, max: f64 },
+    /// The checksum stored in the model data does not match its content
+    Checksum { expected: u64, actual: u64 },
+    /// Settings of the classifier conflict with each other or with the data (see `KnnClassifier::check_config`)
+    Config { problem: String, fix: String },
+    /// No item can vote (the model is empty, or all items are deleted or filtered out) and there is no `fallback`
+    NoNeighbors,
+}
+
+impl fmt::Display for KnnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KnnError::Decode(msg) => write!(f, "decode error: {}", msg),
+            KnnError::Io(msg) => write!(f, "io error: {}", msg),
+            KnnError::Parse { line, msg } => write!(f, "parse error at line {}: {}", line, msg),
+            KnnError::Invalid(msg) => write!(f, "invalid model: {}", msg),
+            KnnError::OutOfRange { feature, value, min, max } => write!(f, "feature {} is out of the training range: {} (range: {} to {})", feature, value, min, max),
+            KnnError::Checksum { expected, actual } => write!(f, "checksum mismatch: expected {:016x}, got {:016x}", expected, actual),
+            KnnError::Config { problem, fix } => write!(f, "invalid configuration: {} (fix: {})", problem, fix),
+            KnnError::NoNeighbors => write!(f, "no items to predict from (set `fallback` for a default label)"),
+        }
+    }
+}
+
+impl std::error::Error for KnnError {}
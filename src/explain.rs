@@ -0,0 +1,264 @@
+//! Explanations of predictions ("why this label?").
+
+use crate::json::Json;
+use crate::{KnnClassifier, KnnError, Metric};
+
+/// One of the k neighbors that voted
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExplainedNeighbor {
+    /// index in `KnnClassifier::items`
+    pub index: usize,
+    pub label: String,
+    pub data: Vec<f64>,
+    pub distance: f64,
+    /// weight of its vote (see `KnnClassifier::voting`, times the item and source weights)
+    pub weight: f64,
+}
+
+/// Unit of the distances shown in an explanation
+///
+/// Raw distances are multiplied by `scale` and reported in `name` units,
+/// e.g. `DistanceUnit::new("z", 1.0)` after standardization or `DistanceUnit::new("km", 0.001)` for meters.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DistanceUnit {
+    pub name: String,
+    pub scale: f64,
+}
+
+impl DistanceUnit {
+    pub fn new(name: &str, scale: f64) -> DistanceUnit {
+        DistanceUnit { name: name.to_string(), scale }
+    }
+}
+
+/// Explanation of a single prediction
+///
+/// `to_json` converts it to JSON, to be sent to a frontend that renders the neighbors and the vote.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Explanation {
+    pub query: Vec<f64>,
+    /// predicted label (the same as `predict_one`)
+    pub label: String,
+    /// k nearest neighbors, nearest first
+    pub neighbors: Vec<ExplainedNeighbor>,
+    /// number of votes per label, most votes first
+    pub votes: Vec<(String, usize)>,
+    /// unit of the neighbor distances (`None`: raw distances, see `with_unit`)
+    pub unit: Option<DistanceUnit>,
+}
+
+impl Explanation {
+    /// attach a unit, scaling the neighbor distances from raw distances to that unit
+    ///
+    /// Replacing a unit rescales from the previous unit.
+    pub fn with_unit(mut self, unit: DistanceUnit) -> Explanation {
+        let previous = self.unit.as_ref().map(|u| u.scale).unwrap_or(1.0);
+        for n in self.neighbors.iter_mut() {
+            n.distance = n.distance / previous * unit.scale;
+        }
+        self.unit = Some(unit);
+        self
+    }
+    /// convert to JSON text
+    ///
+    /// `{"query":[..],"label":"..","neighbors":[{"index":0,"label":"..","data":[..],"distance":1.5,"weight":1.0}],"votes":[{"label":"..","count":2}]}`
+    ///
+    /// With a unit, `"unit":{"name":"..","scale":1.0}` is added.
+    pub fn to_json(&self) -> String {
+        let nums = |v: &[f64]| Json::Arr(v.iter().map(|d| Json::Num(*d)).collect());
+        let neighbors = self.neighbors.iter().map(|n| Json::Obj(vec![
+            ("index".to_string(), Json::Num(n.index as f64)),
+            ("label".to_string(), Json::Str(n.label.clone())),
+            ("data".to_string(), nums(&n.data)),
+            ("distance".to_string(), Json::Num(n.distance)),
+            ("weight".to_string(), Json::Num(n.weight)),
+        ])).collect();
+        let votes = self.votes.iter().map(|(label, count)| Json::Obj(vec![
+            ("label".to_string(), Json::Str(label.clone())),
+            ("count".to_string(), Json::Num(*count as f64)),
+        ])).collect();
+        let mut members = vec![
+            ("query".to_string(), nums(&self.query)),
+            ("label".to_string(), Json::Str(self.label.clone())),
+            ("neighbors".to_string(), Json::Arr(neighbors)),
+            ("votes".to_string(), Json::Arr(votes)),
+        ];
+        if let Some(unit) = &self.unit {
+            members.push(("unit".to_string(), Json::Obj(vec![
+                ("name".to_string(), Json::Str(unit.name.clone())),
+                ("scale".to_string(), Json::Num(unit.scale)),
+            ])));
+        }
+        Json::Obj(members).to_string()
+    }
+}
+
+impl KnnClassifier {
+    /// Function to predict a single data point with the k neighbors that voted, nearest first
+    ///
+    /// The label is the same as `predict_one` (`reject.label` when `reject` rejects the vote). Each neighbor comes
+    /// with its features, label, distance and the weight of its vote, e.g. to show end users why a sample got its label.
+    /// Returns `KnnError::NoNeighbors` when no item can vote and there is no `fallback`.
+    pub fn predict_explain(&self, item: &[f64]) -> Result<(String, Vec<ExplainedNeighbor>), KnnError> {
+        let nearest = self.nearest(item, self.k);
+        let label = self.decide_or_reject(item, &nearest)?;
+        Ok((label, self.explained_neighbors(nearest)))
+    }
+    // Function to attach the features, labels and vote weights to the neighbors
+    fn explained_neighbors(&self, nearest: Vec<(usize, f64)>) -> Vec<ExplainedNeighbor> {
+        let weights = self.neighbor_weights(&nearest);
+        nearest.into_iter().zip(weights).map(|((i, distance), weight)| {
+            let it = &self.items[i];
+            ExplainedNeighbor { index: i, label: it.label.clone(), data: it.data.clone(), distance, weight }
+        }).collect()
+    }
+    /// Function to predict a single data point with an explanation of the vote (see `predict_explain`)
+    pub fn explain_one(&self, item: &[f64]) -> Result<Explanation, KnnError> {
+        let (label, neighbors) = self.predict_explain(item)?;
+        let mut votes: Vec<(String, usize)> = vec![];
+        for n in &neighbors {
+            match votes.iter_mut().find(|(l, _)| *l == n.label) {
+                Some((_, count)) => *count += 1,
+                None => votes.push((n.label.clone(), 1)),
+            }
+        }
+        // stable sort: ties stay in order of the nearest neighbor
+        votes.sort_by_key(|v| std::cmp::Reverse(v.1));
+        Ok(Explanation { query: item.to_vec(), label, neighbors, votes, unit: None })
+    }
+    /// Function to attribute a prediction to the features
+    ///
+    /// For the neighbors that voted for the predicted label (the decisive neighbors), the distance
+    /// is split into per-feature terms that add up to it, on the points as the distance sees them (`transform_point`,
+    /// so in z-score units with `standardize` and one share per component after a projection), with the `feature_weights`:
+    /// - Euclidean (and others): `(query[i] - neighbor[i])^2`
+    /// - Manhattan and Minkowski: `|query[i] - neighbor[i]|^p`
+    /// - Canberra: `|query[i] - neighbor[i]| / (|query[i]| + |neighbor[i]|)`
+    /// - Chebyshev: the largest difference only
+    /// - Cosine: the squared difference of the normalized vectors
+    ///
+    /// The terms are summed over those neighbors and normalized so the result sums to 1.0. A large share means the feature kept
+    /// the decisive neighbors away from the query; a small share means the neighbors match it closely.
+    /// All zeros when the decisive neighbors coincide with the query, or when there are no neighbors.
+    /// A rejected vote (see `reject`) is attributed like any other.
+    pub fn feature_contributions(&self, item: &[f64]) -> Vec<f64> {
+        let nearest = self.nearest(item, self.k);
+        let Some(label) = self.decide(item, &nearest).map(|l| l.to_string()) else { return vec![0.0; item.len()] };
+        let neighbors = self.explained_neighbors(nearest);
+        // the weights scale the coordinates, as in the distance
+        let weighted = |v: Vec<f64>| match self.feature_scale(v.len()) {
+            Some(scale) => v.iter().zip(&scale).map(|(x, s)| x * s).collect(),
+            None => v,
+        };
+        let item = &weighted(self.transform_point(item));
+        let mut terms = vec![0.0; item.len()];
+        for n in neighbors.iter().filter(|n| n.label == label) {
+            let data = &weighted(self.transform_point(&n.data));
+            if self.metric == Metric::Cosine {
+                // for unit vectors, |q - d|^2 / 2 = 1 - cos
+                let unit = |v: &[f64]| {
+                    let norm = v.iter().map(|x| x * x).sum::<f64>().sqrt();
+                    v.iter().map(|x| if norm > 0.0 { x / norm } else { 0.0 }).collect::<Vec<f64>>()
+                };
+                for (t, (q, d)) in terms.iter_mut().zip(unit(item).iter().zip(unit(data).iter())) {
+                    *t += (q - d).powi(2);
+                }
+                continue;
+            }
+            let diffs = item.iter().zip(data.iter()).map(|(q, d)| (q - d).abs());
+            if self.metric == Metric::Chebyshev {
+                // the whole distance comes from the feature with the largest difference (first one on a tie)
+                let mut max: Option<(usize, f64)> = None;
+                for (i, v) in diffs.enumerate() {
+                    if max.is_none_or(|(_, m)| v > m) { max = Some((i, v)); }
+                }
+                if let Some((i, v)) = max { terms[i] += v; }
+                continue;
+            }
+            for (t, (diff, (q, d))) in terms.iter_mut().zip(diffs.zip(item.iter().zip(data.iter()))) {
+                *t += match self.metric {
+                    Metric::Manhattan => diff,
+                    Metric::Canberra if q.abs() + d.abs() > 0.0 => diff / (q.abs() + d.abs()),
+                    Metric::Canberra => 0.0,
+                    Metric::Minkowski(p) => diff.powf(p),
+                    _ => diff.powi(2),
+                };
+            }
+        }
+        let total: f64 = terms.iter().sum();
+        if total > 0.0 {
+            terms.iter_mut().for_each(|t| *t /= total);
+        }
+        terms
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vote;
+
+    #[test]
+    fn test_explain_one() {
+        let mut c = KnnClassifier::new(3);
+        assert!(matches!(c.predict_explain(&[159., 85.]), Err(KnnError::NoNeighbors)));
+        assert!(c.explain_one(&[159., 85.]).is_err());
+        assert_eq!(c.feature_contributions(&[159., 85.]), [0.0, 0.0]);
+        c.fit(
+            &[&[170., 60.], &[166., 58.], &[152., 99.], &[163., 95.], &[150., 90.]],
+            &["Normal", "Normal", "Obesity", "Obesity", "Obesity"]);
+        let e = c.explain_one(&[159., 85.]).unwrap();
+        assert_eq!(e.label, "Obesity");
+        assert_eq!(e.neighbors.iter().map(|n| n.index).collect::<Vec<_>>(), [4, 3, 2]);
+        assert_eq!(e.votes, [("Obesity".to_string(), 3)]);
+        c.voting = Vote::Distance;
+        let (label, neighbors) = c.predict_explain(&[159., 85.]).unwrap();
+        assert_eq!(label, c.predict_one(&[159., 85.]));
+        assert_eq!(neighbors[0].data, [150., 90.]);
+        assert_eq!(neighbors[0].weight, 1.0 / neighbors[0].distance);
+        // feature contributions
+        let mut c1 = KnnClassifier::new(1);
+        c1.fit(&[&[0., 0.], &[10., 10.]], &["a", "b"]);
+        assert_eq!(c1.feature_contributions(&[3., 1.]), [0.9, 0.1]);
+        assert_eq!(c1.feature_contributions(&[0., 0.]), [0.0, 0.0]);
+        c1.metric = Metric::Chebyshev;
+        assert_eq!(c1.feature_contributions(&[3., 1.]), [1.0, 0.0]);
+        c1.metric = Metric::Euclidean;
+        c1.set_feature_weights(&[0.0, 1.0]).unwrap();
+        assert_eq!(c1.feature_contributions(&[3., 1.]), [0.0, 1.0]);
+        c1.set_feature_weights(&[1.0, 9.0]).unwrap();
+        assert_eq!(c1.feature_contributions(&[3., 1.]), [0.5, 0.5]);
+        // in z-score units: the height (mm) varies much more than the weight (kg)
+        let mut c2 = KnnClassifier::new(1);
+        c2.fit(&[&[1500., 60.], &[1900., 62.], &[1700., 90.]], &["a", "b", "c"]);
+        assert!(c2.feature_contributions(&[1550., 70.])[0] > 0.9);
+        c2.standardize = true;
+        c2.fit_scaler();
+        let scaled = c2.transform_point(&[1550., 70.]);
+        let neighbor = c2.transform_point(&[1500., 60.]);
+        let sq: Vec<f64> = scaled.iter().zip(&neighbor).map(|(q, d)| (q - d).powi(2)).collect();
+        let contributions = c2.feature_contributions(&[1550., 70.]);
+        assert!((contributions[1] - sq[1] / (sq[0] + sq[1])).abs() < 1e-12 && contributions[1] > 0.5);
+        let mut c = KnnClassifier::new(1);
+        c.fit_one(&[0., 0.], "a\"b");
+        assert_eq!(c.explain_one(&[3., 4.]).unwrap().to_json(),
+            r#"{"query":[3,4],"label":"a\"b","neighbors":[{"index":0,"label":"a\"b","data":[0,0],"distance":5,"weight":1}],"votes":[{"label":"a\"b","count":1}]}"#);
+        // distances in other units
+        let e = c.explain_one(&[3., 4.]).unwrap().with_unit(DistanceUnit::new("km", 0.001));
+        assert_eq!(e.neighbors[0].distance, 0.005);
+        assert!(e.to_json().ends_with(r#""unit":{"name":"km","scale":0.001}}"#));
+        assert_eq!(e.with_unit(DistanceUnit::new("m", 1.0)).neighbors[0].distance, 5.0);
+    }
+    #[test]
+    fn test_explain_reject() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[1.]], &["a", "b"]);
+        c.reject = crate::Reject { max_distance: Some(0.5), label: Some("unknown".to_string()), ..Default::default() };
+        let (label, neighbors) = c.predict_explain(&[3.]).unwrap();
+        assert_eq!((label.as_str(), neighbors[0].label.as_str()), ("unknown", "b"));
+        assert_eq!(c.explain_one(&[3.]).unwrap().label, c.predict_one(&[3.]));
+        assert_eq!(c.explain_one(&[0.9]).unwrap().label, "b");
+        // the rejected vote is still attributed
+        assert_eq!(c.feature_contributions(&[3.]), [1.0]);
+    }
+}
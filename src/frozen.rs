@@ -0,0 +1,229 @@
+//! Immutable classifier for serving.
+
+use crate::index::{neighbor_order, BruteForce, ItemPoints, NeighborIndex, QueryPoint};
+use crate::{KnnClassifier, KnnError};
+use std::sync::Arc;
+
+/// Classifier that can only predict (see `KnnClassifier::freeze`)
+///
+/// The model can not change after freezing, so serving code can share it between threads
+/// (`&FrozenKnnClassifier` is `Sync`) without locks or rebuilds. The search index of `backend`
+/// is built once when freezing, and each label is stored once. Without an index,
+/// the items are kept in one flat array, which a full scan reads sequentially.
+#[derive(Debug, Clone)]
+pub struct FrozenKnnClassifier {
+    // settings, item weights and sources; the items keep their data only for the index
+    clf: KnnClassifier,
+    index: Option<Arc<dyn NeighborIndex>>,
+    // data of all items when they are stored flat, `dim` values per item
+    flat: bool,
+    data: Vec<f64>,
+    dim: usize,
+    // items after `transform_point`, made before the data moved to `data`
+    points: Option<Arc<ItemPoints>>,
+    // distinct labels in order of first appearance, and the label of each item
+    labels: Vec<String>,
+    item_labels: Vec<usize>,
+}
+
+impl KnnClassifier {
+    /// Function to finalize the classifier for serving (removes the deleted items and builds the index of `backend`)
+    ///
+    /// Without an index, all items must have the same number of features to be stored flat;
+    /// otherwise they are searched as they are.
+    pub fn freeze(mut self) -> FrozenKnnClassifier {
+        self.compact();
+        let index = self.search_index();
+        let points = self.item_points();
+        self.index.clear();
+        let mut labels: Vec<String> = vec![];
+        let mut item_labels = Vec::with_capacity(self.items.len());
+        for it in &mut self.items {
+            let label = std::mem::take(&mut it.label);
+            match labels.iter().position(|l| *l == label) {
+                Some(p) => item_labels.push(p),
+                None => {
+                    item_labels.push(labels.len());
+                    labels.push(label);
+                },
+            }
+        }
+        let dim = self.items.first().map_or(0, |it| it.data.len());
+        let mut data = vec![];
+        let flat = index.is_none() && self.items.iter().all(|it| it.data.len() == dim);
+        if flat {
+            data.reserve(self.items.len() * dim);
+            for it in &mut self.items {
+                data.append(&mut it.data);
+                it.data.shrink_to_fit();
+            }
+        }
+        FrozenKnnClassifier { clf: self, index, flat, data, dim, points, labels, item_labels }
+    }
+}
+
+impl FrozenKnnClassifier {
+    /// number of neighbors that vote
+    pub fn k(&self) -> usize {
+        self.clf.k
+    }
+    /// number of items
+    pub fn len(&self) -> usize {
+        self.item_labels.len()
+    }
+    /// true when there are no items
+    pub fn is_empty(&self) -> bool {
+        self.item_labels.is_empty()
+    }
+    /// labels of all classes, in order of first appearance
+    pub fn classes(&self) -> Vec<&str> {
+        self.labels.iter().map(|l| l.as_str()).collect()
+    }
+    /// label of the item
+    pub fn label(&self, i: usize) -> &str {
+        &self.labels[self.item_labels[i]]
+    }
+    /// features of the item
+    pub fn data(&self, i: usize) -> &[f64] {
+        if self.flat {
+            &self.data[i * self.dim..(i + 1) * self.dim]
+        } else {
+            &self.clf.items[i].data
+        }
+    }
+    /// Function to find the n nearest items as (index, distance), nearest first (ties in learning order)
+    pub fn nearest(&self, item: &[f64], n: usize) -> Vec<(usize, f64)> {
+        let item = &*self.clf.projected(item);
+        if !item.iter().any(|v| v.is_nan()) {
+            if let Some(found) = self.index.as_ref().and_then(|ix| self.clf.search_with(&**ix, item, n)) {
+                return found;
+            }
+        }
+        if !self.flat {
+            return BruteForce.nearest(&self.clf, item, n).unwrap_or_default();
+        }
+        let query = self.query_point(item);
+        let mut distances: Vec<(usize, f64)> = (0..self.len()).map(|i| (i, query.distance_with(i, self.data(i)))).collect();
+        distances.sort_by(neighbor_order);
+        distances.truncate(n);
+        distances
+    }
+    /// Function to find the n nearest items as (index, label, distance), nearest first (see `KnnClassifier::kneighbors`)
+    pub fn kneighbors(&self, item: &[f64], n: usize) -> Vec<(usize, &str, f64)> {
+        self.nearest(item, n).into_iter().map(|(i, d)| (i, self.label(i), d)).collect()
+    }
+    /// Function to predict based on a single data point (see `KnnClassifier::predict_one`)
+    pub fn predict_one(&self, item: &[f64]) -> &str {
+        self.clf.warn_range(item);
+        let neighbors = self.nearest(item, self.clf.k);
+        let label = self.decide(item, &neighbors).or(self.clf.fallback.as_deref()).unwrap_or_else(|| panic!("{}", KnnError::NoNeighbors));
+        if let Some(reject) = &self.clf.reject.label {
+            let labels: Vec<&str> = neighbors.iter().map(|(i, _)| self.label(*i)).collect();
+            if self.clf.rejects(&neighbors, &labels, label) {
+                return reject;
+            }
+        }
+        label
+    }
+    /// Function to predict based on multiple data points (on multiple threads when `parallelism` allows it)
+    pub fn predict(&self, items: &[Vec<f64>]) -> Vec<&str> {
+        self.clf.map_queries(items, |it| self.predict_one(it))
+    }
+    /// Function to estimate the probability of every class (see `KnnClassifier::predict_proba_one`)
+    pub fn predict_proba_one(&self, item: &[f64]) -> Vec<(&str, f64)> {
+        let mut votes = vec![0.0; self.labels.len()];
+        let neighbors = self.nearest(item, self.clf.k);
+        for (i, w) in self.clf.voters_with(&neighbors, |i| self.label(i), || self.coincident_items(item)) {
+            votes[self.item_labels[i]] += w;
+        }
+        let alpha = self.clf.smoothing.max(0.0);
+        let total = votes.iter().sum::<f64>() + alpha * self.labels.len() as f64;
+        self.labels.iter().zip(votes).map(|(c, v)| {
+            (c.as_str(), if total > 0.0 { (v + alpha) / total } else { 0.0 })
+        }).collect()
+    }
+    /// Function to estimate the class probabilities of multiple data points (see `KnnClassifier::predict_proba`)
+    pub fn predict_proba(&self, items: &[Vec<f64>]) -> Vec<Vec<(&str, f64)>> {
+        self.clf.map_queries(items, |it| self.predict_proba_one(it))
+    }
+    // the query prepared for the distances to the items, which may be stored flat
+    fn query_point<'a>(&'a self, item: &'a [f64]) -> QueryPoint<'a> {
+        self.clf.query_point_with(item, || self.points.clone())
+    }
+    // same decision as `KnnClassifier::decide`, with the interned labels
+    fn decide(&self, item: &[f64], neighbors: &[(usize, f64)]) -> Option<&str> {
+        self.clf.decide_with(neighbors, |i| self.label(i), || self.coincident_items(item))
+    }
+    // the items at distance 0 from the query, in learning order
+    fn coincident_items(&self, item: &[f64]) -> Vec<usize> {
+        let query = self.query_point(item);
+        (0..self.len()).filter(|i| query.distance_with(*i, self.data(*i)) == 0.0).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, Reject, Vote};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_freeze() {
+        let mut c = KnnClassifier::new(3);
+        let mut rng = Rng(5u64);
+        let mut rand = || rng.uniform();
+        for i in 0..300 {
+            let p = [rand(), (rand() * 3.0).round()];
+            c.fit_one(&p, ["a", "b", "c"][i % 3]);
+        }
+        // more than k coincident items
+        for label in ["b", "c", "c", "b", "c"] {
+            c.fit_one(&[0.5, 1.0], label);
+        }
+        c.voting = Vote::Distance;
+        let mut queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rand(), (rand() * 3.0).round()]).collect();
+        queries.push(vec![0.5, 1.0]);
+        queries.push(vec![f64::NAN, 1.0]);
+        for backend in [Backend::BruteForce, Backend::KdTree] {
+            c.backend = backend;
+            let expected = c.predict(&queries);
+            let proba: Vec<_> = queries.iter().map(|q| c.predict_proba_one(q)).collect();
+            let f = c.clone().freeze();
+            assert_eq!(f.len(), 305);
+            assert_eq!(f.classes(), ["a", "b", "c"]);
+            assert_eq!(f.data(7), c.items[7].data);
+            assert_eq!(f.predict(&queries), expected);
+            assert_eq!(f.predict_one(&[0.5, 1.0]), "c");
+            assert_eq!(f.kneighbors(&queries[0], 4), c.kneighbors(&queries[0], 4));
+            for (q, p) in queries.iter().zip(&proba) {
+                let fp: Vec<(String, f64)> = f.predict_proba_one(q).into_iter().map(|(l, v)| (l.to_string(), v)).collect();
+                assert_eq!(&fp, p);
+            }
+        }
+    }
+    #[test]
+    fn test_freeze_edges() {
+        // empty model
+        let mut c = KnnClassifier::new(3);
+        c.fallback = Some("none".to_string());
+        let f = c.freeze();
+        assert!(f.is_empty() && f.nearest(&[1.0], 3).is_empty());
+        assert_eq!(f.predict_one(&[1.0]), "none");
+        // the deleted items are removed, NaN features are left out, and reject applies
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[1., 1.], &[5., 5.]], &["a", "b", "c"]);
+        c.delete(1);
+        c.reject = Reject { max_distance: Some(2.0), label: Some("unknown".to_string()), ..Default::default() };
+        let queries = vec![vec![0.9, 0.9], vec![f64::NAN, 4.0], vec![20.0, 20.0]];
+        let expected = c.predict(&queries);
+        let f = c.freeze();
+        assert_eq!(f.classes(), ["a", "c"]);
+        assert_eq!(f.predict(&queries), expected);
+        assert_eq!(expected, ["a", "c", "unknown"]);
+        // the coincident items decide the probabilities like the label
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[1.], &[1.], &[1.], &[1.], &[1.]], &["a", "a", "b", "b", "b"]);
+        let f = c.freeze();
+        assert_eq!(f.predict_proba_one(&[1.]), [("a", 0.4), ("b", 0.6)]);
+        assert_eq!(f.predict_one(&[1.]), "b");
+    }
+}
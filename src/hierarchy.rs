@@ -0,0 +1,101 @@
+//! Hierarchical classification over a label taxonomy.
+
+use crate::{CoincidentVote, KnnClassifier, KnnError};
+
+/// Separator of the levels of a hierarchical label ("animal/dog/beagle")
+pub const HIERARCHY_SEPARATOR: char = '/';
+
+impl KnnClassifier {
+    /// Function to predict at each level of the label hierarchy
+    ///
+    /// Returns the predicted label prefix of every level with its vote share among the k neighbors,
+    /// from the top level down. Each level only considers the neighbors under the prefix chosen above.
+    /// The neighbors vote as in `predict_one` (`voting`, item weights, `coincident` and `tie_break`),
+    /// so the shares are weighted like the vote. Empty when there are no items.
+    pub fn predict_levels(&self, item: &[f64]) -> Vec<(String, f64)> {
+        let neighbors = self.nearest(item, self.k);
+        // the voters of `decide`: the neighbors, or the items at the query when they decide
+        let coincident = self.coincident_decides(&neighbors);
+        let mut voters: Vec<(usize, f64)> = if coincident {
+            self.coincident_votes(item, |_| true)
+        } else {
+            neighbors.iter().map(|(i, _)| *i).zip(self.neighbor_weights(&neighbors)).collect()
+        };
+        if coincident && self.coincident == CoincidentVote::First {
+            voters.truncate(1);
+        }
+        let total: f64 = voters.iter().map(|(_, w)| w).sum();
+        let mut levels: Vec<(String, f64)> = vec![];
+        loop {
+            let depth = levels.len() + 1;
+            let votes = voters.iter().filter_map(|(i, w)| prefix(&self.items[*i].label, depth).map(|p| (p, *w)));
+            let Some(best) = self.tally(votes) else { break };
+            let weight: f64 = voters.iter().filter(|(i, _)| prefix(&self.items[*i].label, depth) == Some(best)).map(|(_, w)| w).sum();
+            levels.push((best.to_string(), if total > 0.0 { weight / total } else { 0.0 }));
+            voters.retain(|(i, _)| prefix(&self.items[*i].label, depth) == Some(best));
+        }
+        levels
+    }
+    /// Function to predict the deepest label whose vote share is at least `min_share`
+    ///
+    /// Backs off to the parent label when the finer level is not confident enough.
+    /// When even the top level is below `min_share`, the top level prediction is returned.
+    /// Without items, returns `fallback` like `predict_one` (and panics without one).
+    pub fn predict_hierarchical(&self, item: &[f64], min_share: f64) -> String {
+        let levels = self.predict_levels(item);
+        let confident = levels.iter().take_while(|(_, share)| *share >= min_share).last();
+        confident.or(levels.first()).map(|(label, _)| label.clone())
+            .or_else(|| self.fallback.clone())
+            .unwrap_or_else(|| panic!("{}", KnnError::NoNeighbors))
+    }
+}
+
+// first `depth` levels of the label ("a/b/c", 2 -> "a/b"), None when the label is shallower
+fn prefix(label: &str, depth: usize) -> Option<&str> {
+    let mut end = 0;
+    for (n, part) in label.split(HIERARCHY_SEPARATOR).enumerate() {
+        if n > 0 { end += HIERARCHY_SEPARATOR.len_utf8(); }
+        end += part.len();
+        if n + 1 == depth { return Some(&label[..end]); }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Vote;
+
+    #[test]
+    fn test_predict_hierarchical() {
+        let mut c = KnnClassifier::new(5);
+        c.fit(
+            &[&[1.0, 1.0], &[1.1, 1.0], &[1.0, 1.1], &[1.2, 1.2], &[0.9, 1.3], &[5.0, 5.0]],
+            &["animal/dog/beagle", "animal/dog/beagle", "animal/dog/poodle", "animal/dog/poodle", "animal/cat", "plant"]);
+        let levels = c.predict_levels(&[1.0, 1.0]);
+        assert_eq!(levels, [
+            ("animal".to_string(), 1.0),
+            ("animal/dog".to_string(), 0.8),
+            ("animal/dog/beagle".to_string(), 0.4),
+        ]);
+        assert_eq!(c.predict_hierarchical(&[1.0, 1.0], 0.6), "animal/dog");
+        assert_eq!(c.predict_hierarchical(&[1.0, 1.0], 0.3), "animal/dog/beagle");
+        assert_eq!(prefix("a/bb/c", 2), Some("a/bb"));
+        assert_eq!(prefix("a", 2), None);
+        // the neighbors vote with `voting`: the two beagles next to the query outweigh the poodles
+        assert_eq!(c.predict_hierarchical(&[1.05, 1.0], 0.6), "animal/dog");
+        c.voting = Vote::Distance;
+        let levels = c.predict_levels(&[1.05, 1.0]);
+        assert!(levels[1].1 > 0.9 && levels[2].1 > 0.6, "{:?}", levels);
+        assert_eq!(c.predict_hierarchical(&[1.05, 1.0], 0.6), "animal/dog/beagle");
+        // the beagle at the query decides alone
+        assert_eq!(c.predict_levels(&[1.0, 1.0])[2], ("animal/dog/beagle".to_string(), 1.0));
+    }
+    #[test]
+    fn test_predict_hierarchical_empty() {
+        let mut c = KnnClassifier::new(3);
+        assert!(c.predict_levels(&[1.0]).is_empty());
+        c.fallback = Some("unknown".to_string());
+        assert_eq!(c.predict_hierarchical(&[1.0], 0.5), "unknown");
+    }
+}
@@ -0,0 +1,243 @@
+//! Hierarchical navigable small world graph for approximate neighbor search (see `Backend::Hnsw`).
+
+use crate::index::{BuiltIndex, NeighborIndex, QueryPoint};
+#[cfg(feature = "msgpack")]
+use crate::index::{IndexParts, PartsReader};
+use crate::instrument;
+use crate::{Backend, KnnClassifier};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+/// Parameters of `Backend::Hnsw`
+///
+/// Larger values find the true neighbors more often (higher recall) at the cost of speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HnswParams {
+    /// number of links per item and layer (twice as many on the bottom layer)
+    pub m: usize,
+    /// candidates examined when an item is inserted
+    pub ef_construction: usize,
+    /// candidates examined per query (at least the number of neighbors); can be changed without rebuilding
+    pub ef_search: usize,
+}
+
+impl Default for HnswParams {
+    fn default() -> Self {
+        HnswParams { m: 16, ef_construction: 100, ef_search: 50 }
+    }
+}
+
+impl HnswParams {
+    // true when the graphs built with the two parameter sets are the same (`ef_search` does not matter)
+    pub(crate) fn same_graph(&self, other: &HnswParams) -> bool {
+        self.m == other.m && self.ef_construction == other.ef_construction
+    }
+}
+
+// (distance, item) ordered by distance, then item
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Candidate(f64, usize);
+
+impl Eq for Candidate {}
+
+impl Ord for Candidate {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl PartialOrd for Candidate {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Hnsw {
+    m: usize,
+    // links[item][layer]: neighbors of the item on the layer
+    links: Vec<Vec<Vec<usize>>>,
+    entry: usize,
+}
+
+impl Hnsw {
+    // Function to build the graph by inserting the items in order, None when there are no items
+    pub(crate) fn build(clf: &KnnClassifier, params: &HnswParams) -> Option<Hnsw> {
+        if clf.items.is_empty() {
+            return None;
+        }
+        let m = params.m.max(2);
+        let mut g = Hnsw { m, links: vec![], entry: 0 };
+        for i in 0..clf.items.len() {
+            g.insert(clf, i, params.ef_construction.max(m));
+        }
+        Some(g)
+    }
+    // layer of an item: geometric distribution from a hash of its index, so builds are reproducible
+    fn level(&self, i: usize) -> usize {
+        let mut x = (i as u64).wrapping_add(0x9e3779b97f4a7c15);
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        let u = ((x >> 11) as f64 + 1.0) / (1u64 << 53) as f64;
+        (-u.ln() / (self.m as f64).ln()) as usize
+    }
+    fn top(&self) -> usize {
+        self.links[self.entry].len() - 1
+    }
+    fn insert(&mut self, clf: &KnnClassifier, i: usize, ef: usize) {
+        let level = self.level(i);
+        self.links.push(vec![vec![]; level + 1]);
+        if i == 0 {
+            return;
+        }
+        let query = clf.query_point(&clf.items[i].data);
+        let top = self.top();
+        let mut ep = vec![Candidate(query.distance_to(self.entry), self.entry)];
+        for layer in (level + 1..=top).rev() {
+            ep = self.search_layer(&query, ep, 1, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            ep = self.search_layer(&query, ep, ef, layer);
+            let max = if layer == 0 { self.m * 2 } else { self.m };
+            for c in ep.iter().take(self.m) {
+                self.links[i][layer].push(c.1);
+                self.links[c.1][layer].push(i);
+                if self.links[c.1][layer].len() > max {
+                    // keep the nearest links of the neighbor
+                    let base = clf.query_point(&clf.items[c.1].data);
+                    let mut ns: Vec<Candidate> = self.links[c.1][layer].iter()
+                        .map(|n| Candidate(base.distance_to(*n), *n)).collect();
+                    ns.sort();
+                    self.links[c.1][layer] = ns.iter().take(max).map(|c| c.1).collect();
+                }
+            }
+        }
+        if level > top {
+            self.entry = i;
+        }
+    }
+    // Function to search one layer from the entry points, returns up to `ef` candidates, nearest first
+    fn search_layer(&self, query: &QueryPoint, ep: Vec<Candidate>, ef: usize, layer: usize) -> Vec<Candidate> {
+        let mut visited: HashSet<usize> = ep.iter().map(|c| c.1).collect();
+        let mut candidates: BinaryHeap<Reverse<Candidate>> = ep.iter().map(|c| Reverse(*c)).collect();
+        let mut found: BinaryHeap<Candidate> = ep.into_iter().collect();
+        while let Some(Reverse(c)) = candidates.pop() {
+            if found.len() >= ef && found.peek().is_some_and(|f| c.0 > f.0) {
+                instrument::count_pruned(candidates.len() + 1);
+                break;
+            }
+            instrument::count_node();
+            for &n in &self.links[c.1][layer] {
+                if !visited.insert(n) { continue; }
+                let d = query.distance_to(n);
+                if found.len() < ef || found.peek().is_some_and(|f| d < f.0) {
+                    candidates.push(Reverse(Candidate(d, n)));
+                    found.push(Candidate(d, n));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+        found.into_sorted_vec()
+    }
+}
+
+impl NeighborIndex for Hnsw {
+    // approximately the n nearest items, sorted like brute force
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        let Backend::Hnsw(params) = clf.backend else { return None };
+        if n == 0 {
+            return Some(vec![]);
+        }
+        let query = clf.query_point(query);
+        let mut ep = vec![Candidate(query.distance_to(self.entry), self.entry)];
+        for layer in (1..=self.top()).rev() {
+            ep = self.search_layer(&query, ep, 1, layer);
+        }
+        let found = self.search_layer(&query, ep, params.ef_search.max(n), 0);
+        Some(found.into_iter().take(n).map(|c| (c.1, c.0)).collect())
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Hnsw {
+    // Function to read the graph written by `to_parts`, None when it is invalid
+    pub(crate) fn from_parts(r: &mut PartsReader, items: usize) -> Option<Hnsw> {
+        let m = r.int()? as usize;
+        let entry = r.below(items)?;
+        if r.int()? != items as u64 {
+            return None;
+        }
+        let mut links = vec![];
+        for _ in 0..items {
+            let levels = r.int()?;
+            let layers: Vec<Vec<usize>> = (0..levels).map(|_| r.ids(items)).collect::<Option<_>>()?;
+            if layers.is_empty() {
+                return None;
+            }
+            links.push(layers);
+        }
+        // a search only follows links to items that are on the same layer
+        let linked = links.iter().all(|layers| layers.iter().enumerate().all(|(layer, ns)| ns.iter().all(|n| links[*n].len() > layer)));
+        linked.then_some(Hnsw { m: m.max(2), links, entry })
+    }
+}
+
+impl BuiltIndex for Hnsw {
+    // [m, entry, items, then the layers of every item, each as its links]
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts {
+        let mut parts = IndexParts { ints: vec![self.m as u64, self.entry as u64, self.links.len() as u64], floats: vec![] };
+        for layers in &self.links {
+            parts.ints.push(layers.len() as u64);
+            for links in layers {
+                parts.push_ids(links);
+            }
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, HnswParams, KnnClassifier};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_hnsw() {
+        let mut c = KnnClassifier::new(5);
+        let mut rng = Rng(3u64);
+        let mut rand = || rng.uniform();
+        for i in 0..1000 {
+            let p: Vec<f64> = (0..8).map(|_| rand()).collect();
+            c.fit_one(&p, if i % 2 == 0 { "a" } else { "b" });
+        }
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| (0..8).map(|_| rand()).collect()).collect();
+        let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 10)).collect();
+        let recall = |c: &KnnClassifier| {
+            let hits: usize = queries.iter().zip(&expected).map(|(q, e)| {
+                let found = c.nearest(q, 10);
+                assert!(found.windows(2).all(|w| w[0].1 <= w[1].1));
+                found.iter().filter(|f| e.contains(f)).count()
+            }).sum();
+            hits as f64 / (queries.len() * 10) as f64
+        };
+        c.backend = Backend::Hnsw(HnswParams::default());
+        let r = recall(&c);
+        assert!(r > 0.9, "recall {}", r);
+        // examining every item finds the exact neighbors
+        c.backend = Backend::Hnsw(HnswParams { ef_search: 1000, ..HnswParams::default() });
+        assert_eq!(recall(&c), 1.0);
+        // re-ranking more candidates raises the recall of a small `ef_search`
+        c.backend = Backend::Hnsw(HnswParams { m: 4, ef_search: 10, ..HnswParams::default() });
+        let r = recall(&c);
+        c.rerank = 4;
+        assert!(recall(&c) > r, "recall {} -> {}", r, recall(&c));
+        c.rerank = 100;
+        assert_eq!(recall(&c), 1.0);
+        let f = c.clone().freeze();
+        assert_eq!(f.nearest(&queries[0], 10), expected[0]);
+    }
+}
@@ -0,0 +1,80 @@
+//! Imputation of missing features from the nearest neighbors.
+
+use crate::{KnnClassifier, KnnItem};
+
+/// Imputer that fills missing features (NaN) with the mean of the k nearest rows that have them
+///
+/// The neighbors are found with the NaN-aware distance of `KnnClassifier` over the observed features,
+/// so `knn` also sets the metric, the preprocessing and the vote weights (`voting`) of the mean.
+#[derive(Debug, Clone)]
+pub struct KnnImputer {
+    /// neighbor search over the reference rows (their labels are not used)
+    pub knn: KnnClassifier,
+}
+
+impl KnnImputer {
+    /// new imputer averaging k neighbors (0 = 5)
+    pub fn new(k: usize) -> KnnImputer {
+        KnnImputer { knn: KnnClassifier { k: if k > 0 { k } else { 5 }, ..KnnClassifier::new(k) } }
+    }
+    /// new imputer over the items and settings of a classifier
+    pub fn from_classifier(clf: &KnnClassifier) -> KnnImputer {
+        KnnImputer { knn: clf.clone() }
+    }
+    /// Function to learn the reference rows (they may have missing values themselves)
+    pub fn fit(&mut self, data: &[&[f64]]) {
+        for row in data {
+            self.knn.push_item(KnnItem::new("", row));
+        }
+        self.knn.fit_statistics();
+    }
+    /// Function to fill the missing features of a row
+    ///
+    /// Each missing feature is the mean over the k nearest rows where it is observed. It stays NaN
+    /// when no reference row has it, or when the row has no observed feature to measure distances with.
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        let mut filled = row.to_vec();
+        if !row.iter().any(|v| v.is_nan()) {
+            return filled;
+        }
+        let neighbors: Vec<(usize, f64)> = self.knn.nearest(row, self.knn.items.len()).into_iter().filter(|(_, d)| d.is_finite()).collect();
+        for (f, v) in filled.iter_mut().enumerate().filter(|(_, v)| v.is_nan()) {
+            let donors: Vec<(usize, f64)> = neighbors.iter().copied()
+                .filter(|(i, _)| self.knn.items[*i].data.get(f).is_some_and(|x| !x.is_nan()))
+                .take(self.knn.k)
+                .collect();
+            let weights = self.knn.neighbor_weights(&donors);
+            let total: f64 = weights.iter().sum();
+            *v = if total > 0.0 {
+                donors.iter().zip(&weights).map(|((i, _), w)| self.knn.items[*i].data[f] * w).sum::<f64>() / total
+            } else {
+                donors.first().map_or(f64::NAN, |(i, _)| self.knn.items[*i].data[f])
+            };
+        }
+        filled
+    }
+    /// Function to fill the missing features of multiple rows (on multiple threads when `parallelism` allows it)
+    pub fn transform_all(&self, rows: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        self.knn.map_queries(rows, |row| self.transform(row))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_knn_imputer() {
+        let nan = f64::NAN;
+        let mut m = KnnImputer::new(2);
+        m.fit(&[&[0.0, 0.0, 1.0], &[1.0, 1.0, nan], &[1.1, 1.0, 3.0], &[10.0, 10.0, 50.0], &[0.5, 0.5, 2.0]]);
+        // the nearest rows with feature 2 are (1.1, 1.0) and (0.5, 0.5)
+        assert_eq!(m.transform(&[1.0, 1.0, nan]), [1.0, 1.0, 2.5]);
+        assert_eq!(m.transform(&[1.0, 2.0, 3.0]), [1.0, 2.0, 3.0]);
+        // the distance only uses the observed feature
+        assert_eq!(m.transform(&[nan, 9.0, nan]), [5.5, 9.0, 26.5]);
+        assert!(m.transform(&[nan, nan, nan]).iter().all(|v| v.is_nan()));
+        let filled = m.transform_all(&[vec![1.0, 1.0, nan], vec![0.1, nan, 1.0]]);
+        assert_eq!(filled[1][1], 0.25);
+    }
+}
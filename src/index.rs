@@ -0,0 +1,602 @@
+//! Neighbor search backends.
+
+use crate::balltree::BallTree;
+use crate::hnsw::Hnsw;
+use crate::kdtree::KdTree;
+use crate::lsh::Lsh;
+use crate::vptree::VpTree;
+use crate::{HnswParams, KnnClassifier, LshParams, Metric, Preprocess, StandardScaler};
+use std::borrow::Cow;
+use std::cmp::Ordering;
+use std::panic::RefUnwindSafe;
+use std::sync::{Arc, RwLock};
+
+/// Neighbor search of a classifier
+///
+/// The exact backends (all but `Hnsw` and `Lsh`) find the same neighbors in the same order as brute force,
+/// so switching between them never changes a prediction, only its speed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// compare the query with every item (default)
+    #[default]
+    BruteForce,
+    /// k-d tree over the features, built on the first query after the items change
+    ///
+    /// Fast for low-dimensional data with many items. Brute force is used instead with more than
+    /// `KD_TREE_MAX_FEATURES` features, missing features (NaN), feature weights,
+    /// or a metric other than Euclidean, Manhattan, Minkowski and Chebyshev.
+    KdTree,
+    /// ball tree, built on the first query after the items or the distance settings change
+    ///
+    /// Prunes with the triangle inequality only, so it works for any number of features and with every metric
+    /// except cosine and Minkowski with p < 1 (including feature weights and Mahalanobis).
+    /// Brute force is used for missing features (NaN).
+    BallTree,
+    /// vantage-point tree, built on the first query after the items or the distance settings change
+    ///
+    /// Splits the items by their distance from a vantage point, so it only needs distances between items.
+    /// Works with every metric, including cosine and Minkowski with p < 1, which are searched through
+    /// an equivalent true metric. Brute force is used for missing features (NaN).
+    VpTree,
+    /// approximate search on a hierarchical navigable small world graph, built on the first query
+    /// after the items or the distance settings change
+    ///
+    /// For very large training sets: a query examines about `ef_search` items instead of all of them,
+    /// but may miss some of the true neighbors. Raise `ef_search` (or `KnnClassifier::rerank`) for a higher recall.
+    /// Works with every metric. Brute force is used for queries with missing features (NaN).
+    Hnsw(HnswParams),
+    /// approximate search by locality-sensitive hashing, built on the first query after the items change
+    ///
+    /// For very high-dimensional data: every table hashes the items by the sides of random hyperplanes
+    /// through their mean, and a query only compares the items sharing a bucket with it in some table.
+    /// When fewer than k items share a bucket, the query falls back to brute force.
+    /// Works with every metric; it suits Euclidean and cosine distances best.
+    Lsh(LshParams),
+    /// the index given to `set_index`; brute force without one
+    Custom,
+}
+
+/// Neighbor search structure over the items of a classifier
+///
+/// All backends implement it. To plug in another search (an external ANN library, a GPU, ...),
+/// build the structure over `clf.items` and pass it to `KnnClassifier::set_index`.
+/// `RefUnwindSafe` keeps the classifier usable in `catch_unwind` (wrap a `RefCell` in a `Mutex` instead).
+pub trait NeighborIndex: Send + Sync + RefUnwindSafe + std::fmt::Debug {
+    /// Function to find the `n` nearest items of `clf` to the query as (index in `items`, distance)
+    ///
+    /// The result should be ordered like brute force: nearest first, equal distances in index order,
+    /// with distances from `KnnClassifier::distance`.
+    /// Returns `None` when the index can not answer the query, and brute force is used instead.
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>>;
+}
+
+// index built for a backend (all but `Custom`), which can be saved with the model
+pub(crate) trait BuiltIndex: NeighborIndex {
+    // Function to flatten the index for `to_msgpack`
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts;
+}
+
+// flat form of a built index: integers (sizes, item and node numbers) and floats, in the order the backend writes them
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct IndexParts {
+    pub(crate) ints: Vec<u64>,
+    pub(crate) floats: Vec<f64>,
+}
+
+#[cfg(feature = "msgpack")]
+impl IndexParts {
+    // Function to add a list of item or node numbers, preceded by its length
+    pub(crate) fn push_ids(&mut self, ids: &[usize]) {
+        self.ints.push(ids.len() as u64);
+        self.ints.extend(ids.iter().map(|i| *i as u64));
+    }
+}
+
+// reads `IndexParts` back in order; every read is checked, since the parts come from a file
+#[cfg(feature = "msgpack")]
+pub(crate) struct PartsReader<'a> {
+    parts: &'a IndexParts,
+    int: usize,
+    float: usize,
+}
+
+#[cfg(feature = "msgpack")]
+impl PartsReader<'_> {
+    pub(crate) fn int(&mut self) -> Option<u64> {
+        let v = *self.parts.ints.get(self.int)?;
+        self.int += 1;
+        Some(v)
+    }
+    // Function to read a float, None when it is not finite (a NaN split would send searches the wrong way)
+    pub(crate) fn float(&mut self) -> Option<f64> {
+        let v = *self.parts.floats.get(self.float)?;
+        self.float += 1;
+        v.is_finite().then_some(v)
+    }
+    // Function to read a number below `limit` (an item, a feature, ...)
+    pub(crate) fn below(&mut self, limit: usize) -> Option<usize> {
+        self.int().filter(|v| *v < limit as u64).map(|v| v as usize)
+    }
+    // Function to read the number of a child node, which comes after its parent (so a search always ends)
+    pub(crate) fn child(&mut self, parent: usize, nodes: usize) -> Option<usize> {
+        self.below(nodes).filter(|c| *c > parent)
+    }
+    // Function to read a list written by `push_ids`, every number below `limit`
+    pub(crate) fn ids(&mut self, limit: usize) -> Option<Vec<usize>> {
+        let n = self.int()?;
+        if n > (self.parts.ints.len() - self.int) as u64 {
+            return None;
+        }
+        (0..n).map(|_| self.below(limit)).collect()
+    }
+}
+
+// node of a saved tree as seen by `leaves_cover_items`: the items of a leaf, or the two children of a split
+#[cfg(feature = "msgpack")]
+pub(crate) enum TreeNode<'a> {
+    Leaf(&'a [usize]),
+    Split(usize, usize),
+}
+
+// Function to check that the leaves reachable from the root (node 0) hold every item exactly once
+// and that no node is reached twice, so a search of a saved tree finds what brute force finds
+#[cfg(feature = "msgpack")]
+pub(crate) fn leaves_cover_items<'a>(nodes: usize, items: usize, node: impl Fn(usize) -> TreeNode<'a>) -> bool {
+    let (mut visited, mut seen) = (vec![false; nodes], vec![false; items]);
+    let mut stack = vec![0];
+    while let Some(i) = stack.pop() {
+        if i >= nodes || std::mem::replace(&mut visited[i], true) {
+            return false;
+        }
+        match node(i) {
+            TreeNode::Leaf(ids) => {
+                for &id in ids {
+                    if id >= items || std::mem::replace(&mut seen[id], true) {
+                        return false;
+                    }
+                }
+            },
+            TreeNode::Split(a, b) => stack.extend([a, b]),
+        }
+    }
+    seen.iter().all(|s| *s)
+}
+
+// Function to read an index saved by `BuiltIndex::to_parts` for `items` items, None when the parts are invalid
+#[cfg(feature = "msgpack")]
+pub(crate) fn index_from_parts(backend: Backend, parts: &IndexParts, items: usize) -> Option<Arc<dyn BuiltIndex>> {
+    let mut r = PartsReader { parts, int: 0, float: 0 };
+    let index: Arc<dyn BuiltIndex> = match backend {
+        Backend::KdTree => Arc::new(KdTree::from_parts(&mut r, items)?),
+        Backend::BallTree => Arc::new(BallTree::from_parts(&mut r, items)?),
+        Backend::VpTree => Arc::new(VpTree::from_parts(&mut r, items)?),
+        Backend::Hnsw(_) => Arc::new(Hnsw::from_parts(&mut r, items)?),
+        Backend::Lsh(_) => Arc::new(Lsh::from_parts(&mut r, items)?),
+        Backend::BruteForce | Backend::Custom => return None,
+    };
+    // every part is used
+    (r.int == parts.ints.len() && r.float == parts.floats.len()).then_some(index)
+}
+
+// comparing the query with every item
+#[derive(Debug)]
+pub(crate) struct BruteForce;
+
+impl NeighborIndex for BruteForce {
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        let query = clf.query_point(query);
+        let mut distances: Vec<(usize, f64)> = (0..clf.items.len()).map(|i| (i, query.distance_to(i))).collect();
+        // Sort by distance (ties in learning order)
+        distances.sort_by(neighbor_order);
+        distances.truncate(n);
+        Some(distances)
+    }
+}
+
+// search structure of the items, built lazily for the backend and the number of items
+#[derive(Default)]
+pub(crate) struct IndexCell {
+    built: RwLock<Option<Arc<Built>>>,
+    // index given to `set_index`, with the number of items it was given for
+    custom: Option<(usize, Arc<dyn NeighborIndex>)>,
+    // items after `transform_point`, built lazily like `built`
+    points: RwLock<Option<Arc<ItemPoints>>>,
+}
+
+// items as the distance sees them (see `KnnClassifier::query_point`)
+#[derive(Debug)]
+pub(crate) struct ItemPoints {
+    len: usize,
+    // transformations the points were made with
+    preprocess: Vec<Preprocess>,
+    scaler: Option<StandardScaler>,
+    // None for items with missing features (NaN), which `distance` masks before transforming
+    pub(crate) rows: Vec<Option<Vec<f64>>>,
+}
+
+/// Query prepared once for the distances to the items
+///
+/// With `preprocess` or `standardize`, the query is transformed here and compared with the items
+/// transformed when they were learned, instead of transforming both points of every distance.
+pub(crate) struct QueryPoint<'a> {
+    clf: &'a KnnClassifier,
+    raw: Cow<'a, [f64]>,
+    // transformed query and items, None when the distance transforms nothing or a query feature is missing
+    point: Option<(Vec<f64>, Arc<ItemPoints>)>,
+}
+
+impl QueryPoint<'_> {
+    // Function to calculate the distance to the item at `i` (same value as `KnnClassifier::distance`)
+    pub(crate) fn distance_to(&self, i: usize) -> f64 {
+        self.distance_with(i, &self.clf.items[i].data)
+    }
+    // Function to calculate the distance to the item at `i` whose features are `data`
+    pub(crate) fn distance_with(&self, i: usize, data: &[f64]) -> f64 {
+        if let Some((query, points)) = &self.point {
+            if let Some(Some(row)) = points.rows.get(i) {
+                return self.clf.point_distance(row, query);
+            }
+        }
+        self.clf.distance(data, &self.raw)
+    }
+}
+
+pub(crate) struct Built {
+    backend: Backend,
+    len: usize,
+    // distance settings a ball tree, a vantage-point tree or a graph was built with
+    metric: Metric,
+    feature_weights: Vec<f64>,
+    inverse_covariance: Vec<Vec<f64>>,
+    preprocess: Vec<Preprocess>,
+    scaler: Option<StandardScaler>,
+    // None when the backend can not index the items (NaN, mixed lengths, too many features)
+    index: Option<Arc<dyn BuiltIndex>>,
+}
+
+impl Clone for IndexCell {
+    fn clone(&self) -> Self {
+        IndexCell {
+            built: RwLock::new(self.built.read().unwrap().clone()),
+            custom: self.custom.clone(),
+            points: RwLock::new(self.points.read().unwrap().clone()),
+        }
+    }
+}
+
+impl std::fmt::Debug for IndexCell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let built = self.built.read().unwrap();
+        match built.as_ref() {
+            Some(b) => write!(f, "IndexCell({:?}, {} items)", b.backend, b.len),
+            None => write!(f, "IndexCell(empty)"),
+        }
+    }
+}
+
+impl IndexCell {
+    // the index built for the current items, backend and distance settings of `clf`, if any
+    fn current(&self, clf: &KnnClassifier) -> Option<Arc<Built>> {
+        let built = self.built.read().unwrap();
+        let b = built.as_ref()?;
+        let same_distance = matches!(clf.backend, Backend::KdTree | Backend::Lsh(_)) || (b.metric == clf.metric
+            && b.feature_weights == clf.feature_weights && b.inverse_covariance == clf.inverse_covariance
+            && b.preprocess == clf.preprocess && b.scaler.as_ref() == clf.active_scaler());
+        let same_backend = match (b.backend, clf.backend) {
+            (Backend::Hnsw(a), Backend::Hnsw(c)) => a.same_graph(&c),
+            (a, c) => a == c,
+        };
+        (same_backend && b.len == clf.items.len() && same_distance).then(|| b.clone())
+    }
+    fn get(&self, clf: &KnnClassifier) -> Arc<Built> {
+        if let Some(b) = self.current(clf) {
+            return b;
+        }
+        let index = match clf.backend {
+            Backend::BruteForce | Backend::Custom => None,
+            Backend::KdTree => KdTree::build(&clf.items).map(|t| Arc::new(t) as _),
+            Backend::BallTree => BallTree::build(clf).map(|t| Arc::new(t) as _),
+            Backend::VpTree => VpTree::build(clf).map(|t| Arc::new(t) as _),
+            Backend::Hnsw(params) => Hnsw::build(clf, &params).map(|g| Arc::new(g) as _),
+            Backend::Lsh(params) => Lsh::build(clf, &params).map(|l| Arc::new(l) as _),
+        };
+        self.store(clf, index)
+    }
+    // Function to keep the index as built for the items, backend and distance settings of `clf`
+    pub(crate) fn store(&self, clf: &KnnClassifier, index: Option<Arc<dyn BuiltIndex>>) -> Arc<Built> {
+        let built = Arc::new(Built {
+            backend: clf.backend,
+            len: clf.items.len(),
+            metric: clf.metric,
+            feature_weights: clf.feature_weights.clone(),
+            inverse_covariance: clf.inverse_covariance.clone(),
+            preprocess: clf.preprocess.clone(),
+            scaler: clf.active_scaler().cloned(),
+            index,
+        });
+        *self.built.write().unwrap() = Some(built.clone());
+        built
+    }
+    // the transformed items of `clf`, rebuilt when the transformations changed
+    // (every change of the items clears the cell: `push_item`, `update_feature_ranges`, ...)
+    fn points(&self, clf: &KnnClassifier) -> Arc<ItemPoints> {
+        if let Some(p) = self.cached_points(clf) {
+            return p;
+        }
+        let rows = clf.items.iter().map(|it| {
+            (!it.data.iter().any(|v| v.is_nan())).then(|| clf.transform_point(&it.data))
+        }).collect();
+        let points = Arc::new(ItemPoints {
+            len: clf.items.len(),
+            preprocess: clf.preprocess.clone(),
+            scaler: clf.active_scaler().cloned(),
+            rows,
+        });
+        *self.points.write().unwrap() = Some(points.clone());
+        points
+    }
+    // the transformed items of `clf` when they are cached for its transformations (never builds them)
+    fn cached_points(&self, clf: &KnnClassifier) -> Option<Arc<ItemPoints>> {
+        self.points.read().unwrap().as_ref()
+            .filter(|p| p.len == clf.items.len() && p.preprocess == clf.preprocess && p.scaler.as_ref() == clf.active_scaler())
+            .cloned()
+    }
+    pub(crate) fn clear(&mut self) {
+        *self.built.get_mut().unwrap() = None;
+        *self.points.get_mut().unwrap() = None;
+    }
+    // same as `clear`, also dropping the index given to `set_index` (the items may have changed in place)
+    pub(crate) fn invalidate(&mut self) {
+        self.clear();
+        self.custom = None;
+    }
+}
+
+impl KnnClassifier {
+    /// Function to search the neighbors with another index (sets `backend` to `Backend::Custom`)
+    ///
+    /// The index should be built over the current `items`. It is used until the number of items changes,
+    /// a class quota removes an item or `update_feature_ranges` is called; after that, brute force is used until `set_index` is called again.
+    pub fn set_index(&mut self, index: impl NeighborIndex + 'static) {
+        self.index.custom = Some((self.items.len(), Arc::new(index)));
+        self.backend = Backend::Custom;
+    }
+    /// true when the index of `backend` is built for the current items (e.g. loaded with the model by `from_msgpack`),
+    /// so the next query does not build it
+    pub fn index_built(&self) -> bool {
+        match self.backend {
+            Backend::BruteForce => false,
+            Backend::Custom => self.search_index().is_some(),
+            _ => self.index.current(self).is_some_and(|b| b.index.is_some()),
+        }
+    }
+    // Function to get the (built) index of the backend, None when brute force should be used
+    pub(crate) fn search_index(&self) -> Option<Arc<dyn NeighborIndex>> {
+        match self.backend {
+            Backend::BruteForce => None,
+            Backend::Custom => match &self.index.custom {
+                Some((len, index)) if *len == self.items.len() => Some(index.clone()),
+                _ => None,
+            },
+            _ => self.index.get(self).index.clone().map(|index| index as Arc<dyn NeighborIndex>),
+        }
+    }
+    // Function to flatten the index of the backend for saving (built when needed),
+    // None for brute force, a custom index, or items the backend can not index
+    #[cfg(feature = "msgpack")]
+    pub(crate) fn index_parts(&self) -> Option<IndexParts> {
+        match self.backend {
+            Backend::BruteForce | Backend::Custom => None,
+            _ => self.index.get(self).index.as_ref().map(|index| index.to_parts()),
+        }
+    }
+    // Function to get the items as the distance sees them, None when the distance transforms nothing
+    pub(crate) fn item_points(&self) -> Option<Arc<ItemPoints>> {
+        self.transforms_points().then(|| self.index.points(self))
+    }
+    // same as `item_points`, None when the transformed items are not cached yet
+    pub(crate) fn cached_item_points(&self) -> Option<Arc<ItemPoints>> {
+        self.transforms_points().then(|| self.index.cached_points(self)).flatten()
+    }
+    // Function to prepare a query for the distances to the items
+    pub(crate) fn query_point<'a>(&'a self, item: &'a [f64]) -> QueryPoint<'a> {
+        self.query_point_with(item, || self.item_points())
+    }
+    // same as `query_point`, with the transformed items from `points`
+    pub(crate) fn query_point_with<'a>(&'a self, item: &'a [f64], points: impl FnOnce() -> Option<Arc<ItemPoints>>) -> QueryPoint<'a> {
+        let raw = self.projected(item);
+        let point = if raw.iter().any(|v| v.is_nan()) { None } else { points().map(|p| (self.transform_point(&raw), p)) };
+        QueryPoint { clf: self, raw, point }
+    }
+    // Function to find the n nearest items with the backend, None when brute force should be used
+    pub(crate) fn nearest_indexed(&self, item: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        if item.iter().any(|v| v.is_nan()) {
+            return None;
+        }
+        self.search_with(&*self.search_index()?, item, n)
+    }
+    // Function to find the n nearest items with an index of the backend, re-ranking the candidates of an approximate backend
+    pub(crate) fn search_with(&self, index: &dyn NeighborIndex, item: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        if self.rerank <= 1 || !matches!(self.backend, Backend::Hnsw(_) | Backend::Lsh(_)) {
+            return index.nearest(self, item, n);
+        }
+        let candidates = index.nearest(self, item, n.saturating_mul(self.rerank))?;
+        let query = self.query_point(item);
+        let mut found: Vec<(usize, f64)> = candidates.into_iter().map(|(i, _)| (i, query.distance_to(i))).collect();
+        found.sort_by(neighbor_order);
+        found.truncate(n);
+        Some(found)
+    }
+}
+
+// Order of neighbors (index, distance): nearest first, equal distances in learning order (index),
+// NaN distances last. Every search sorts by it, so the neighbors never depend on the sort algorithm.
+pub(crate) fn neighbor_order(a: &(usize, f64), b: &(usize, f64)) -> Ordering {
+    a.1.partial_cmp(&b.1).unwrap_or_else(|| a.1.is_nan().cmp(&b.1.is_nan())).then(a.0.cmp(&b.0))
+}
+
+// Function to insert a neighbor into a list sorted by `neighbor_order` and keep the first n
+pub(crate) fn insert_neighbor(found: &mut Vec<(usize, f64)>, n: usize, i: usize, d: f64) {
+    let at = found.partition_point(|x| neighbor_order(x, &(i, d)) == Ordering::Less);
+    if at < n {
+        found.insert(at, (i, d));
+        found.truncate(n);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, MinMaxScaler, NeighborIndex};
+
+    // returns the first n items, to see which index answered
+    #[derive(Debug)]
+    struct FirstItems;
+
+    impl NeighborIndex for FirstItems {
+        fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+            Some((0..n.min(clf.items.len())).map(|i| (i, clf.distance(&clf.items[i].data, query))).collect())
+        }
+    }
+
+    #[test]
+    fn test_custom_index() {
+        let mut c = KnnClassifier::new(1);
+        c.fit_one(&[0.0], "a");
+        c.fit_one(&[10.0], "b");
+        c.set_index(FirstItems);
+        assert_eq!(c.backend, Backend::Custom);
+        assert_eq!(c.predict_one(&[9.0]), "a");
+        // the index is dropped when the items change
+        c.fit_one(&[20.0], "c");
+        assert_eq!(c.predict_one(&[9.0]), "b");
+        // the built-in backends answer through the trait too
+        c.backend = Backend::VpTree;
+        assert_eq!(c.nearest(&[19.0], 2), vec![(2, 1.0), (1, 9.0)]);
+    }
+    #[test]
+    fn test_item_points() {
+        let rows: Vec<Vec<f64>> = (0..30).map(|i| vec![(i * 7 % 11) as f64, (i * 5 % 13) as f64 * 10.0]).collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        let mut c = KnnClassifier::new(3);
+        c.fit(&data, &vec!["x"; 30]);
+        c.fit_one(&[f64::NAN, 40.0], "y");
+        c.preprocess.push(MinMaxScaler::fit(&data).into());
+        c.standardize = true;
+        c.fit_scaler();
+        let direct = |c: &KnnClassifier, q: &[f64]| {
+            let mut ds: Vec<(usize, f64)> = c.items.iter().enumerate().map(|(i, it)| (i, c.distance(&it.data, q))).collect();
+            ds.sort_by(super::neighbor_order);
+            ds.truncate(5);
+            ds
+        };
+        // the items transformed once give the same distances, also for missing features
+        for backend in [Backend::BruteForce, Backend::BallTree, Backend::VpTree] {
+            c.backend = backend;
+            for q in [[3.0, 55.0], [f64::NAN, 20.0], [10.0, 0.0]] {
+                assert_eq!(c.nearest(&q, 5), direct(&c, &q));
+            }
+        }
+        // the transformed items follow the transformations
+        c.backend = Backend::BruteForce;
+        c.standardize = false;
+        assert_eq!(c.nearest(&[3.0, 55.0], 5), direct(&c, &[3.0, 55.0]));
+        c.preprocess.clear();
+        assert_eq!(c.nearest(&[3.0, 55.0], 5), direct(&c, &[3.0, 55.0]));
+        c.preprocess.push(MinMaxScaler::fit(&data).into());
+        // and the edited items
+        assert_eq!(c.nearest(&[3.0, 55.0], 1)[0].0, 27);
+        c.items[7].data = vec![3.0, 55.0];
+        c.update_feature_ranges();
+        assert_eq!(c.nearest(&[3.0, 55.0], 5), direct(&c, &[3.0, 55.0]));
+        assert_eq!(c.nearest(&[3.0, 55.0], 1)[0], (7, 0.0));
+        let f = c.clone().freeze();
+        assert_eq!(f.nearest(&[3.0, 55.0], 5), direct(&c, &[3.0, 55.0]));
+    }
+    #[test]
+    fn test_edited_items() {
+        let mut c = KnnClassifier::new(1);
+        for i in 0..100 {
+            c.fit_one(&[i as f64, (i * 7 % 13) as f64], &format!("l{}", i));
+        }
+        let q = [10.4, 5.2];
+        for backend in [Backend::BruteForce, Backend::KdTree, Backend::BallTree, Backend::VpTree] {
+            c.backend = backend;
+            assert_eq!(c.predict_one(&q), "l10");
+        }
+        // `update_feature_ranges` after editing an item in place drops the built indexes
+        c.items[90].data = q.to_vec();
+        c.update_feature_ranges();
+        for backend in [Backend::KdTree, Backend::BallTree, Backend::VpTree, Backend::BruteForce] {
+            c.backend = backend;
+            assert_eq!(c.nearest(&q, 1), [(90, 0.0)], "{:?}", backend);
+        }
+        // and the index given to `set_index`
+        c.set_index(FirstItems);
+        c.items.swap(0, 90);
+        c.update_feature_ranges();
+        assert_eq!(c.nearest(&q, 1), [(0, 0.0)]);
+        // a changed number of items drops them without it
+        c.backend = Backend::KdTree;
+        c.nearest(&q, 1);
+        c.items.truncate(50);
+        assert_eq!(c.nearest(&q, 1), [(0, 0.0)]);
+        // items marked deleted directly are skipped too
+        c.items[0].deleted = true;
+        assert_eq!(c.nearest(&q, 1)[0].0, 10);
+    }
+    #[cfg(feature = "msgpack")]
+    #[test]
+    fn test_index_parts() {
+        use super::index_from_parts;
+        use crate::{HnswParams, LshParams};
+        let mut c = KnnClassifier::new(3);
+        for i in 0..120 {
+            c.fit_one(&[(i * 37 % 101) as f64, (i * 11 % 29) as f64], "a");
+        }
+        let backends = [Backend::KdTree, Backend::BallTree, Backend::VpTree, Backend::Hnsw(HnswParams { m: 4, ..HnswParams::default() }),
+            Backend::Lsh(LshParams { tables: 2, bits: 3 })];
+        for backend in backends {
+            c.backend = backend;
+            let parts = c.index_parts().unwrap();
+            let index = index_from_parts(backend, &parts, 120).unwrap();
+            assert_eq!(index.to_parts(), parts);
+            assert_eq!(index.nearest(&c, &[50.0, 10.0], 5), c.nearest_indexed(&[50.0, 10.0], 5));
+            // parts that do not match the items are refused
+            assert!(index_from_parts(backend, &parts, 119).is_none());
+            let mut longer = c.index_parts().unwrap();
+            longer.ints.push(0);
+            assert!(index_from_parts(backend, &longer, 120).is_none());
+            // broken parts never make a search panic
+            for i in 0..parts.ints.len() {
+                for v in [0, 1, 119, 120, u64::MAX] {
+                    let mut broken = c.index_parts().unwrap();
+                    broken.ints[i] = v;
+                    if let Some(index) = index_from_parts(backend, &broken, 120) {
+                        index.nearest(&c, &[50.0, 10.0], 5);
+                    }
+                }
+            }
+        }
+        // the leaves of a tree hold every item once, and the floats are finite
+        for backend in [Backend::KdTree, Backend::BallTree, Backend::VpTree] {
+            c.backend = backend;
+            let parts = c.index_parts().unwrap();
+            // position of the ids of the first leaf: a node is [center,] 0, ids or a split of 4 numbers
+            let ball = backend == Backend::BallTree;
+            let mut at = 2;
+            while parts.ints[at + ball as usize] == 1 {
+                at += 4;
+            }
+            let first = at + ball as usize + 2;
+            let mut duplicated = c.index_parts().unwrap();
+            duplicated.ints[first + 1] = duplicated.ints[first];
+            assert!(index_from_parts(backend, &duplicated, 120).is_none(), "{:?}", backend);
+            let mut nan = c.index_parts().unwrap();
+            nan.floats[0] = f64::NAN;
+            assert!(index_from_parts(backend, &nan, 120).is_none(), "{:?}", backend);
+            assert!(index_from_parts(backend, &parts, 120).is_some());
+        }
+    }
+}
@@ -0,0 +1,114 @@
+//! Per-query counters of the neighbor search (feature `instrument`).
+//!
+//! The counters are kept per thread and only while `instrumented` runs, so an uninstrumented
+//! build pays nothing and an instrumented one pays little outside of measured calls.
+
+#[cfg(feature = "instrument")]
+use std::cell::Cell;
+
+/// Work done by the neighbor search of the calls measured with `instrumented`
+#[cfg(feature = "instrument")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct QueryStats {
+    /// distances computed (including those of an index built by the call)
+    pub distances: u64,
+    /// nodes of a tree or graph visited (0 for brute force)
+    pub nodes: u64,
+    /// subtrees or items skipped because a bound showed they can not hold neighbors
+    pub pruned: u64,
+}
+
+#[cfg(feature = "instrument")]
+thread_local! {
+    static STATS: Cell<Option<QueryStats>> = const { Cell::new(None) };
+}
+
+/// Function to run `f` and count the work of the neighbor searches it does on the current thread
+///
+/// Searches on other threads (e.g. `predict` with `parallelism`) are not counted.
+///
+/// ```rs
+/// let (label, stats) = knn_classifier::instrumented(|| clf.predict_one(&query));
+/// println!("{} distances, {} nodes, {} pruned", stats.distances, stats.nodes, stats.pruned);
+/// ```
+#[cfg(feature = "instrument")]
+pub fn instrumented<T>(f: impl FnOnce() -> T) -> (T, QueryStats) {
+    let outer = STATS.with(|s| s.replace(Some(QueryStats::default())));
+    let result = f();
+    let stats = STATS.with(|s| s.replace(outer)).unwrap_or_default();
+    // nested measurements also count for the outer one
+    if let Some(o) = outer {
+        STATS.with(|s| s.set(Some(QueryStats {
+            distances: o.distances + stats.distances,
+            nodes: o.nodes + stats.nodes,
+            pruned: o.pruned + stats.pruned,
+        })));
+    }
+    (result, stats)
+}
+
+#[cfg(feature = "instrument")]
+fn count(f: impl FnOnce(&mut QueryStats)) {
+    STATS.with(|s| {
+        if let Some(mut stats) = s.get() {
+            f(&mut stats);
+            s.set(Some(stats));
+        }
+    });
+}
+
+// Functions to count a distance, a visited node and pruned subtrees or items (no-ops without the feature)
+#[inline]
+pub(crate) fn count_distance() {
+    #[cfg(feature = "instrument")]
+    count(|s| s.distances += 1);
+}
+
+#[inline]
+pub(crate) fn count_node() {
+    #[cfg(feature = "instrument")]
+    count(|s| s.nodes += 1);
+}
+
+#[inline]
+pub(crate) fn count_pruned(n: usize) {
+    #[cfg(feature = "instrument")]
+    count(|s| s.pruned += n as u64);
+    #[cfg(not(feature = "instrument"))]
+    let _ = n;
+}
+
+#[cfg(all(test, feature = "instrument"))]
+mod tests {
+    use super::*;
+    use crate::{Backend, KnnClassifier};
+
+    #[test]
+    fn test_instrumented() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..1000 {
+            c.fit_one(&[(i % 100) as f64, (i / 100) as f64], if i % 2 == 0 { "a" } else { "b" });
+        }
+        let q = [50.3, 4.2];
+        let (label, brute) = instrumented(|| c.predict_one(&q));
+        assert_eq!(brute, QueryStats { distances: 1000, nodes: 0, pruned: 0 });
+        c.backend = Backend::KdTree;
+        // the first query builds the tree (which computes no distances)
+        let (tree_label, tree) = instrumented(|| c.predict_one(&q));
+        assert_eq!(tree_label, label);
+        assert!(tree.distances < 200 && tree.nodes > 0 && tree.pruned > 0, "{:?}", tree);
+        c.backend = Backend::BallTree;
+        let (_, build) = instrumented(|| c.predict_one(&q));
+        let (_, ball) = instrumented(|| c.predict_one(&q));
+        assert!(ball.distances < build.distances && ball.pruned > 0);
+        // nested calls count for both
+        let ((_, inner), outer) = instrumented(|| {
+            c.predict_one(&q);
+            instrumented(|| c.predict_one(&q))
+        });
+        assert_eq!(outer.distances, 2 * inner.distances);
+        // nothing is counted outside
+        c.predict_one(&q);
+        assert_eq!(instrumented(|| ()).1, QueryStats::default());
+    }
+}
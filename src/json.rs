@@ -0,0 +1,303 @@
+//! Minimal JSON reader and writer used by the model importers and explanation payloads.
+
+use crate::KnnError;
+use std::fmt;
+
+/// JSON value
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Json {
+    Null,
+    Bool(bool),
+    Num(f64),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// parse JSON text
+    pub(crate) fn parse(s: &str) -> Result<Json, KnnError> {
+        let mut p = Parser { chars: s.chars().collect(), pos: 0, depth: 0 };
+        let v = p.value()?;
+        p.skip_ws();
+        if p.pos < p.chars.len() {
+            return Err(p.err("unexpected trailing characters"));
+        }
+        Ok(v)
+    }
+    /// get a member of an object
+    pub(crate) fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_f64(&self) -> Option<f64> {
+        match self {
+            Json::Num(v) => Some(*v),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::Str(s) => Some(s),
+            _ => None,
+        }
+    }
+    pub(crate) fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Json::Arr(a) => Some(a),
+            _ => None,
+        }
+    }
+}
+
+// compact JSON text (non-finite numbers are written as null)
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            Json::Num(v) if v.is_finite() => write!(f, "{}", v),
+            Json::Num(_) => write!(f, "null"),
+            Json::Str(s) => write_str(f, s),
+            Json::Arr(values) => {
+                write!(f, "[")?;
+                for (i, v) in values.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write!(f, "{}", v)?;
+                }
+                write!(f, "]")
+            },
+            Json::Obj(members) => {
+                write!(f, "{{")?;
+                for (i, (k, v)) in members.iter().enumerate() {
+                    if i > 0 { write!(f, ",")?; }
+                    write_str(f, k)?;
+                    write!(f, ":{}", v)?;
+                }
+                write!(f, "}}")
+            },
+        }
+    }
+}
+
+fn write_str(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+// deepest nesting of arrays and objects the parser accepts (each level is a recursive call)
+const MAX_DEPTH: usize = 256;
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    // arrays and objects open at `pos`
+    depth: usize,
+}
+
+impl Parser {
+    fn err(&self, msg: &str) -> KnnError {
+        KnnError::Decode(format!("json: {} at {}", msg, self.pos))
+    }
+    fn skip_ws(&mut self) {
+        while self.pos < self.chars.len() && self.chars[self.pos].is_whitespace() {
+            self.pos += 1;
+        }
+    }
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+    fn expect(&mut self, c: char) -> Result<(), KnnError> {
+        self.skip_ws();
+        if self.peek() != Some(c) {
+            return Err(self.err(&format!("expected '{}'", c)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+    fn keyword(&mut self, word: &str, v: Json) -> Result<Json, KnnError> {
+        for c in word.chars() {
+            if self.peek() != Some(c) {
+                return Err(self.err("unknown keyword"));
+            }
+            self.pos += 1;
+        }
+        Ok(v)
+    }
+    fn value(&mut self) -> Result<Json, KnnError> {
+        self.skip_ws();
+        if matches!(self.peek(), Some('{' | '[')) {
+            if self.depth == MAX_DEPTH {
+                return Err(self.err("too deeply nested"));
+            }
+            self.depth += 1;
+            let v = self.container();
+            self.depth -= 1;
+            return v;
+        }
+        self.scalar()
+    }
+    fn container(&mut self) -> Result<Json, KnnError> {
+        match self.peek() {
+            Some('{') => {
+                self.pos += 1;
+                let mut members = vec![];
+                self.skip_ws();
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Obj(members));
+                }
+                loop {
+                    self.skip_ws();
+                    let key = self.string()?;
+                    self.expect(':')?;
+                    members.push((key, self.value()?));
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some('}') => { self.pos += 1; return Ok(Json::Obj(members)); },
+                        _ => return Err(self.err("expected ',' or '}'")),
+                    }
+                }
+            },
+            Some('[') => {
+                self.pos += 1;
+                let mut values = vec![];
+                self.skip_ws();
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Arr(values));
+                }
+                loop {
+                    values.push(self.value()?);
+                    self.skip_ws();
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        Some(']') => { self.pos += 1; return Ok(Json::Arr(values)); },
+                        _ => return Err(self.err("expected ',' or ']'")),
+                    }
+                }
+            },
+            _ => self.scalar(),
+        }
+    }
+    fn scalar(&mut self) -> Result<Json, KnnError> {
+        match self.peek() {
+            Some('"') => Ok(Json::Str(self.string()?)),
+            Some('t') => self.keyword("true", Json::Bool(true)),
+            Some('f') => self.keyword("false", Json::Bool(false)),
+            Some('n') => self.keyword("null", Json::Null),
+            // Python's json module writes these for non-finite floats
+            Some('N') => self.keyword("NaN", Json::Num(f64::NAN)),
+            Some('I') => self.keyword("Infinity", Json::Num(f64::INFINITY)),
+            Some(_) => self.number(),
+            None => Err(self.err("unexpected end of data")),
+        }
+    }
+    fn number(&mut self) -> Result<Json, KnnError> {
+        let start = self.pos;
+        if self.peek() == Some('-') && self.chars.get(self.pos + 1) == Some(&'I') {
+            self.pos += 1;
+            return self.keyword("Infinity", Json::Num(f64::NEG_INFINITY));
+        }
+        while let Some(c) = self.peek() {
+            if c.is_ascii_digit() || "+-.eE".contains(c) {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+        let s: String = self.chars[start..self.pos].iter().collect();
+        s.parse().map(Json::Num).map_err(|_| self.err("invalid number"))
+    }
+    fn string(&mut self) -> Result<String, KnnError> {
+        if self.peek() != Some('"') {
+            return Err(self.err("expected string"));
+        }
+        self.pos += 1;
+        let mut s = String::new();
+        loop {
+            let c = self.peek().ok_or_else(|| self.err("unterminated string"))?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(s),
+                '\\' => {
+                    let e = self.peek().ok_or_else(|| self.err("unterminated string"))?;
+                    self.pos += 1;
+                    match e {
+                        'n' => s.push('\n'),
+                        't' => s.push('\t'),
+                        'r' => s.push('\r'),
+                        'b' => s.push('\u{8}'),
+                        'f' => s.push('\u{c}'),
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // surrogate pair: a high surrogate must be followed by an escaped low one
+                            if (0xd800..0xdc00).contains(&code) {
+                                if self.chars.get(self.pos..self.pos + 2) != Some(&['\\', 'u'][..]) {
+                                    return Err(self.err("invalid unicode escape"));
+                                }
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                if !(0xdc00..0xe000).contains(&low) {
+                                    return Err(self.err("invalid unicode escape"));
+                                }
+                                code = 0x10000 + ((code - 0xd800) << 10) + (low - 0xdc00);
+                            }
+                            s.push(char::from_u32(code).ok_or_else(|| self.err("invalid unicode escape"))?);
+                        },
+                        c => s.push(c),
+                    }
+                },
+                c => s.push(c),
+            }
+        }
+    }
+    fn hex4(&mut self) -> Result<u32, KnnError> {
+        if self.pos + 4 > self.chars.len() {
+            return Err(self.err("invalid unicode escape"));
+        }
+        let s: String = self.chars[self.pos..self.pos + 4].iter().collect();
+        self.pos += 4;
+        u32::from_str_radix(&s, 16).map_err(|_| self.err("invalid unicode escape"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let v = Json::parse(r#" {"a": [1, -2.5e1, true, null], "b": "x\"あ", "c": {}} "#).unwrap();
+        assert_eq!(v.get("a").unwrap().as_array().unwrap()[1], Json::Num(-25.0));
+        assert_eq!(v.get("b").unwrap().as_str(), Some("x\"あ"));
+        assert_eq!(v.get("c"), Some(&Json::Obj(vec![])));
+        assert!(Json::parse("[1, 2").is_err());
+        assert!(Json::parse("[1] 2").is_err());
+        assert_eq!(Json::parse(r#""\ud83d\ude00""#).unwrap().as_str(), Some("😀"));
+        assert!(Json::parse(r#""\ud800\u0041""#).is_err());
+        assert!(Json::parse(r#""\ud800xu0041""#).is_err());
+        assert!(Json::parse(r#""\ud800""#).is_err());
+        // nesting deeper than MAX_DEPTH fails instead of overflowing the stack
+        assert!(Json::parse(&"[".repeat(200000)).is_err());
+        let deep = format!("{}{}", "[".repeat(MAX_DEPTH), "]".repeat(MAX_DEPTH));
+        assert!(Json::parse(&deep).is_ok());
+        // write
+        let text = r#"{"a":[1,-25,true,null],"b":"x\"あ\n","c":{}}"#;
+        assert_eq!(Json::parse(text).unwrap().to_string(), text);
+    }
+}
@@ -0,0 +1,190 @@
+//! k-d tree for neighbor search in low dimensions (see `Backend::KdTree`).
+
+use crate::index::{insert_neighbor, BuiltIndex, NeighborIndex};
+#[cfg(feature = "msgpack")]
+use crate::index::{leaves_cover_items, IndexParts, PartsReader, TreeNode};
+use crate::instrument;
+use crate::{KnnClassifier, KnnItem, Metric};
+
+/// Largest number of features indexed by `Backend::KdTree`
+///
+/// With more features, a k-d tree visits almost every leaf and is slower than brute force.
+pub const KD_TREE_MAX_FEATURES: usize = 16;
+
+// largest number of items in a leaf
+const LEAF_SIZE: usize = 16;
+// relative slack for rounding errors of the distance bound
+const BOUND_SLACK: f64 = 1e-9;
+
+#[derive(Debug)]
+enum Node {
+    Leaf(Vec<usize>),
+    // items of `left` have `data[axis] <= value`, items of `right` have `data[axis] >= value`
+    Split { axis: usize, value: f64, left: usize, right: usize },
+}
+
+#[derive(Debug)]
+pub(crate) struct KdTree {
+    features: usize,
+    // nodes[0] is the root
+    nodes: Vec<Node>,
+}
+
+impl KdTree {
+    // Function to build the tree, None when the items can not be indexed
+    pub(crate) fn build(items: &[KnnItem]) -> Option<KdTree> {
+        let features = items.first()?.data.len();
+        if features == 0 || features > KD_TREE_MAX_FEATURES {
+            return None;
+        }
+        if items.iter().any(|it| it.data.len() != features || it.data.iter().any(|v| v.is_nan())) {
+            return None;
+        }
+        let mut tree = KdTree { features, nodes: vec![] };
+        let mut ids: Vec<usize> = (0..items.len()).collect();
+        tree.build_node(items, &mut ids);
+        Some(tree)
+    }
+    fn build_node(&mut self, items: &[KnnItem], ids: &mut [usize]) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node::Leaf(ids.to_vec()));
+        if ids.len() <= LEAF_SIZE {
+            return node;
+        }
+        // split at the median of the feature with the largest spread
+        let spread = |axis: usize| {
+            let values = ids.iter().map(|i| items[*i].data[axis]);
+            values.clone().fold(f64::NEG_INFINITY, f64::max) - values.fold(f64::INFINITY, f64::min)
+        };
+        let axis = (0..self.features).max_by(|a, b| spread(*a).total_cmp(&spread(*b))).unwrap();
+        let mid = ids.len() / 2;
+        ids.select_nth_unstable_by(mid, |a, b| items[*a].data[axis].total_cmp(&items[*b].data[axis]));
+        let value = items[ids[mid]].data[axis];
+        let (l, r) = ids.split_at_mut(mid);
+        let left = self.build_node(items, l);
+        let right = self.build_node(items, r);
+        self.nodes[node] = Node::Split { axis, value, left, right };
+        node
+    }
+    fn search(&self, node: usize, clf: &KnnClassifier, query: &[f64], n: usize, found: &mut Vec<(usize, f64)>) {
+        instrument::count_node();
+        match &self.nodes[node] {
+            Node::Leaf(ids) => {
+                for &i in ids {
+                    insert_neighbor(found, n, i, clf.distance(&clf.items[i].data, query));
+                }
+            },
+            Node::Split { axis, value, left, right } => {
+                let diff = query[*axis] - value;
+                let (near, far) = if diff < 0.0 { (*left, *right) } else { (*right, *left) };
+                self.search(near, clf, query, n, found);
+                // every item on the far side differs by at least |diff| in one feature,
+                // which is a lower bound of its distance under all supported metrics
+                let limit = if found.len() < n { f64::INFINITY } else { found[n - 1].1 };
+                if diff.abs() - BOUND_SLACK * (1.0 + limit) <= limit {
+                    self.search(far, clf, query, n, found);
+                } else {
+                    instrument::count_pruned(1);
+                }
+            },
+        }
+    }
+}
+
+impl NeighborIndex for KdTree {
+    // same result as brute force
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        let supported = clf.feature_weights.is_empty() && !clf.transforms_points() && match clf.metric {
+            Metric::Euclidean | Metric::Manhattan | Metric::Chebyshev => true,
+            Metric::Minkowski(p) => p >= 1.0,
+            _ => false,
+        };
+        if !supported || query.len() != self.features {
+            return None;
+        }
+        let mut found = vec![];
+        if n > 0 {
+            self.search(0, clf, query, n, &mut found);
+        }
+        Some(found)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl KdTree {
+    // Function to read the tree written by `to_parts`, None when it is invalid
+    pub(crate) fn from_parts(r: &mut PartsReader, items: usize) -> Option<KdTree> {
+        let features = r.int()? as usize;
+        let count = r.int()? as usize;
+        let mut nodes = vec![];
+        for node in 0..count {
+            nodes.push(match r.int()? {
+                0 => Node::Leaf(r.ids(items)?),
+                1 => Node::Split { axis: r.below(features)?, left: r.child(node, count)?, right: r.child(node, count)?, value: r.float()? },
+                _ => return None,
+            });
+        }
+        let covered = leaves_cover_items(nodes.len(), items, |i| match &nodes[i] {
+            Node::Leaf(ids) => TreeNode::Leaf(ids),
+            Node::Split { left, right, .. } => TreeNode::Split(*left, *right),
+        });
+        covered.then_some(KdTree { features, nodes })
+    }
+}
+
+impl BuiltIndex for KdTree {
+    // [features, nodes, then (0, ids) for a leaf or (1, axis, left, right) for a split], and the split values
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts {
+        let mut parts = IndexParts { ints: vec![self.features as u64, self.nodes.len() as u64], floats: vec![] };
+        for node in &self.nodes {
+            match node {
+                Node::Leaf(ids) => {
+                    parts.ints.push(0);
+                    parts.push_ids(ids);
+                },
+                Node::Split { axis, value, left, right } => {
+                    parts.ints.extend([1, *axis as u64, *left as u64, *right as u64]);
+                    parts.floats.push(*value);
+                },
+            }
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, Metric};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_kd_tree() {
+        let mut c = KnnClassifier::new(5);
+        let mut rng = Rng(42u64);
+        let mut rand = || rng.uniform();
+        for i in 0..2000 {
+            // rounded values make many ties
+            let p = [(rand() * 20.0).round(), (rand() * 20.0).round(), rand()];
+            c.fit_one(&p, if i % 3 == 0 { "a" } else { "b" });
+        }
+        let queries: Vec<Vec<f64>> = (0..50).map(|_| vec![rand() * 20.0, (rand() * 20.0).round(), rand()]).collect();
+        for metric in [Metric::Euclidean, Metric::Manhattan, Metric::Minkowski(3.0), Metric::Chebyshev] {
+            c.metric = metric;
+            c.backend = Backend::BruteForce;
+            let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 7)).collect();
+            c.backend = Backend::KdTree;
+            for (q, e) in queries.iter().zip(&expected) {
+                assert_eq!(c.nearest(q, 7), *e);
+                assert_eq!(c.nearest_indexed(q, 7).as_ref(), Some(e));
+            }
+        }
+        // the index follows new items
+        c.fit_one(&[100., 100., 0.], "c");
+        assert_eq!(c.nearest(&[99., 99., 0.], 1)[0].0, 2000);
+        // unsupported settings fall back to brute force
+        assert!(c.nearest_indexed(&[1., f64::NAN, 0.], 3).is_none());
+        c.metric = Metric::Cosine;
+        assert!(c.nearest_indexed(&[1., 1., 0.], 3).is_none());
+    }
+}
@@ -0,0 +1,98 @@
+//! Renaming labels and consolidating label spellings.
+
+use crate::{KnnClassifier, KnnError, KnnItem};
+
+impl KnnClassifier {
+    /// Function to rename a label on all items (and its class radius)
+    ///
+    /// Renaming to an existing label merges the two classes. Returns the number of renamed items.
+    pub fn rename_label(&mut self, old: &str, new: &str) -> usize {
+        let mut count = 0;
+        for it in self.items.iter_mut().filter(|it| it.label == old) {
+            it.label = new.to_string();
+            count += 1;
+        }
+        if let Some(r) = self.class_radii.remove(old) {
+            self.class_radii.entry(new.to_string()).or_insert(r);
+        }
+        count
+    }
+    /// Function to relabel the items accepted by the predicate
+    ///
+    /// Returns `(index, old label)` of every item whose label changes.
+    /// With `dry_run`, nothing is changed and only the report is returned.
+    pub fn relabel_where(&mut self, predicate: impl Fn(&KnnItem) -> bool, new_label: &str, dry_run: bool) -> Vec<(usize, String)> {
+        let ids: Vec<usize> = self.items.iter().enumerate()
+            .filter(|(_, it)| predicate(it))
+            .map(|(i, _)| i)
+            .collect();
+        self.relabel(&ids, new_label, dry_run)
+    }
+    /// Function to relabel the items at the indices (in `items`), see `relabel_where`
+    ///
+    /// Fails without changing anything when an index is out of range.
+    pub fn relabel_by_ids(&mut self, ids: &[usize], new_label: &str, dry_run: bool) -> Result<Vec<(usize, String)>, KnnError> {
+        if let Some(i) = ids.iter().find(|i| **i >= self.items.len()) {
+            return Err(KnnError::Invalid(format!("item index out of range: {}", i)));
+        }
+        Ok(self.relabel(ids, new_label, dry_run))
+    }
+    fn relabel(&mut self, ids: &[usize], new_label: &str, dry_run: bool) -> Vec<(usize, String)> {
+        let mut changed = vec![];
+        for &i in ids {
+            let it = &mut self.items[i];
+            if it.label == new_label || changed.iter().any(|(j, _)| *j == i) { continue; }
+            changed.push((i, it.label.clone()));
+            if !dry_run {
+                it.label = new_label.to_string();
+            }
+        }
+        changed
+    }
+    /// Function to register an alias, so items learned with the `alias` label get `label` instead
+    ///
+    /// Aliases are applied by `fit`, `fit_one`, `from_csv`, `from_csv_reader`, `from_arff`, ...
+    /// Items learned before are not changed; use `apply_label_aliases` for them.
+    pub fn add_label_alias(&mut self, alias: &str, label: &str) {
+        self.label_aliases.insert(alias.to_string(), label.to_string());
+    }
+    /// Function to apply `label_aliases` to the learned items, returns the number of renamed items
+    pub fn apply_label_aliases(&mut self) -> usize {
+        let aliases: Vec<(String, String)> = self.label_aliases.iter().map(|(a, l)| (a.clone(), l.clone())).collect();
+        aliases.iter().map(|(alias, label)| self.rename_label(alias, label)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_label() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[1.], &[2.], &[3.]], &["obese", "Normal", "Obesity"]);
+        c.class_radii.insert("obese".to_string(), 1.5);
+        assert_eq!(c.rename_label("obese", "Obesity"), 1);
+        assert_eq!(c.class_radii["Obesity"], 1.5);
+        assert_eq!(c.predict_one(&[1.]), "Obesity");
+        // aliases applied when loading
+        c.add_label_alias("normal", "Normal");
+        c.from_csv("normal,4\nOBESITY,5\n", ',', 0, false);
+        assert_eq!(c.items[3].label, "Normal");
+        c.add_label_alias("OBESITY", "Obesity");
+        assert_eq!(c.apply_label_aliases(), 1);
+        assert_eq!(c.items[4].label, "Obesity");
+    }
+    #[test]
+    fn test_relabel() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[1.], &[20.], &[30.], &[2.]], &["a", "a", "b", "b"]);
+        let report = c.relabel_where(|it| it.data[0] > 10.0, "c", true);
+        assert_eq!(report, [(1, "a".to_string()), (2, "b".to_string())]);
+        assert_eq!(c.items[1].label, "a");
+        assert_eq!(c.relabel_where(|it| it.data[0] > 10.0, "c", false).len(), 2);
+        assert_eq!(c.items[2].label, "c");
+        assert_eq!(c.relabel_by_ids(&[0, 3], "a", false).unwrap(), [(3, "b".to_string())]);
+        assert!(c.relabel_by_ids(&[4], "a", false).is_err());
+    }
+}
@@ -21,6 +21,151 @@
 //! }
 //! ```
 //!
+//! ## Many classes
+//!
+//! `ShardedClassifier` stores items per class, with the distance settings of the classifier, and can restrict a query to candidate classes.
+//!
+//! ```rs
+//! let sc = ShardedClassifier::from_classifier(clf);
+//! let label = sc.predict_one_among(&[159., 85.], &["Normal", "Obesity"]);
+//! ```
+//!
+//! `KnnClassifier::predict_one_among` does the same on a single model, without copying it (and votes with `voting`).
+//!
+//! `CoarseToFineClassifier` picks candidate classes by their nearest centroids first, then runs k-nn over those classes only.
+//!
+//! ## Weighted voting
+//!
+//! `Vote::Distance` weights each neighbor by `1 / distance` instead of counting all k neighbors equally,
+//! and the kernels `Vote::Gaussian` and `Vote::Epanechnikov` weight them smoothly by the distance relative to a bandwidth.
+//! Custom weightings implement `VoteStrategy` and are set with `set_vote_strategy`.
+//!
+//! ```rs
+//! clf.voting = Vote::Distance;
+//! ```
+//!
+//! ## Multi-label classification
+//!
+//! `fit_multi_label` learns items with several labels, and `predict_labels` returns the labels
+//! carried by more than a share of the neighbors.
+//!
+//! ```rs
+//! let tags = clf.predict_labels(&[0.25, 0.65], 0.5);
+//! ```
+//!
+//! ## Regression
+//!
+//! `KnnRegressor` predicts the mean (or the distance-weighted mean) of the numeric targets of the k nearest items.
+//!
+//! ```rs
+//! let mut r = KnnRegressor::new(3);
+//! r.fit(&[&[150., 50.], &[160., 60.], &[170., 65.]], &[22.2, 23.4, 22.5]);
+//! let bmi = r.predict_one(&[165., 62.]);
+//! ```
+//!
+//! ## Hierarchical labels
+//!
+//! Labels like `"animal/dog/beagle"` form a hierarchy. `predict_hierarchical` backs off to a parent label
+//! when the vote share of the finer label is lower than the threshold.
+//!
+//! ```rs
+//! let label = clf.predict_hierarchical(&[1.0, 2.0], 0.6); // "animal/dog"
+//! ```
+//!
+//! ## Explanations
+//!
+//! `explain_one` returns the neighbors (features, labels, distances) and the vote breakdown of a prediction.
+//! `Explanation::to_json` makes a payload for "why this prediction" views.
+//!
+//! ```rs
+//! let e = clf.explain_one(&[159., 85.])?;
+//! println!("{}", e.to_json());
+//! ```
+//!
+//! ## Standardization
+//!
+//! With `standardize`, `fit` learns the mean and standard deviation of every feature and distances compare z-scores.
+//!
+//! ```rs
+//! clf.standardize = true;
+//! clf.fit(&data, &labels);
+//! ```
+//!
+//! ## Dimensionality reduction
+//!
+//! `fit_pca(n)` appends a PCA projection onto `n` components to `preprocess`, so queries are projected the same way.
+//!
+//! ```rs
+//! clf.fit(&data, &labels);
+//! let explained = clf.fit_pca(10);
+//! ```
+//!
+//! `project(n, seed)` stores a seeded random projection of the items onto `n` dimensions, so distances get cheaper.
+//!
+//! ## Choosing a distance
+//!
+//! The distance metric is Euclidean by default. Use `with_metric` (or set `metric`) for another one.
+//!
+//! ```rs
+//! let mut clf = KnnClassifier::with_metric(3, Metric::Manhattan);
+//! let mut clf = KnnClassifier::with_metric(3, Metric::Minkowski(1.5));
+//! let mut clf = KnnClassifier::with_metric(3, Metric::Cosine); // embedding vectors
+//! let mut clf = KnnClassifier::with_metric(3, Metric::Mahalanobis); // covariance estimated by `fit`
+//! let mut clf = KnnClassifier::with_metric(3, Metric::Haversine); // [latitude, longitude] in degrees
+//! ```
+//!
+//! `compare_metrics` cross-validates the training data under several distance functions and ranks them.
+//!
+//! ```rs
+//! let scores = clf.compare_metrics(&[("euclidean", calc_distance), ("manhattan", manhattan_distance)], 5);
+//! println!("best: {}", scores[0].name);
+//! ```
+//!
+//! ## Unknown classes
+//!
+//! `fit_class_radii` learns how far from its class a query may be. `predict_one_open` returns `None` (unknown)
+//! for queries outside the radius of the predicted class.
+//!
+//! ```rs
+//! clf.fit_class_radii(0.95, 1.5);
+//! let label = clf.predict_one_open(&[300., 10.]); // None
+//! ```
+//!
+//! ## Parallel prediction
+//!
+//! `predict` runs serially by default. Set `parallelism` to use threads.
+//!
+//! ```rs
+//! clf.parallelism = Parallelism::threads(4);
+//! let labels = clf.predict(&queries);
+//! ```
+//!
+//! `predict_batched` shares pruning bounds between similar queries of a batch,
+//! which saves most distance computations for correlated queries.
+//!
+//! `Backend::KdTree` searches the neighbors of low-dimensional data with a k-d tree instead of a full scan,
+//! `Backend::BallTree` with a ball tree, which also suits higher dimensions and other metrics,
+//! and `Backend::VpTree` with a vantage-point tree, which works with every metric including cosine.
+//!
+//! ```rs
+//! clf.backend = Backend::KdTree;
+//! clf.backend = Backend::BallTree;
+//! clf.backend = Backend::VpTree;
+//! ```
+//!
+//! `Backend::Hnsw` (graph) and `Backend::Lsh` (locality-sensitive hashing) are approximate searches
+//! for very large or very high-dimensional training sets.
+//! Other search structures implement `NeighborIndex` and are plugged in with `set_index`.
+//!
+//! ## Serving
+//!
+//! `freeze` makes an immutable `FrozenKnnClassifier` with the index built, to share between threads without locks.
+//!
+//! ```rs
+//! let model = std::sync::Arc::new(clf.freeze());
+//! let label = model.predict_one(&[159., 85.]);
+//! ```
+//!
 //! ## Support CSV format
 //!
 //! The classifier can be converted to and from CSV format.
@@ -38,78 +183,639 @@
 //! assert_eq!(label, "Obesity");
 //! ```
 //!
+//! ## Online learning with a model file
+//!
+//! `AppendOnlyModel` appends every `fit_one` to a CSV file, so the model survives restarts.
+//!
+//! ```rs
+//! let mut model = AppendOnlyModel::open("model.csv", 3).unwrap();
+//! model.fit_one(&[170., 60.], "Normal").unwrap();
+//! let label = model.classifier().predict_one(&[165., 55.]);
+//! ```
+//!
+//! `AppendOnlyModel::open_with_journal` writes to a checksummed write-ahead journal instead,
+//! so a crash never corrupts the model file and the journal is replayed on the next open.
+//!
+//! ## Many models
+//!
+//! `KnnRegistry` keeps one classifier per tenant or model name with a shared configuration,
+//! and saves or loads all of them at once.
+//!
+//! ```rs
+//! let mut reg = KnnRegistry::new(3);
+//! reg.fit_one("customer-1", &[170., 60.], "Normal");
+//! reg.save_dir("models").unwrap();
+//! let reg = KnnRegistry::load_dir("models").unwrap();
+//! ```
+//!
+//! ## Support ARFF format
+//!
+//! ARFF files (used by Weka) can be read with `from_arff`. Nominal features are encoded as the index of the value.
+//!
+//! ```rs
+//! // Label column is the last attribute
+//! clf.from_arff(&text, 4).unwrap();
+//! ```
+//!
+//! ## Support MessagePack format
+//!
+//! With the `msgpack` feature, the classifier (including `k`) can be saved to and loaded from MessagePack bytes.
+//!
+//! ```rs
+//! let bytes = clf.to_msgpack();
+//! let clf = KnnClassifier::from_msgpack(&bytes).unwrap();
+//! ```
+//!
+//! ## Import scikit-learn models
+//!
+//! A fitted `KNeighborsClassifier` dumped as JSON can be loaded with `KnnClassifier::from_sklearn_json`.
+//! See the `sklearn` module source for the expected JSON layout.
+//!
 //! # Reference
 //! - [k-NN algorithm](https://en.wikipedia.org/wiki/K-nearest_neighbors_algorithm)
 //! - [k-NN algorithm (ja)](https://ja.wikipedia.org/wiki/K%E8%BF%91%E5%82%8D%E6%B3%95)
 //!
 
+mod active;
+mod among;
+mod arff;
+mod auto;
+mod balltree;
+mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+mod cascade;
+mod coincident;
+mod cv;
+mod deadline;
+mod dedup;
+#[cfg(feature = "encryption")]
+mod encryption;
+mod error;
+mod explain;
+mod frozen;
+mod hierarchy;
+mod hnsw;
+mod imputer;
+mod index;
+mod instrument;
+mod json;
+mod kdtree;
+mod labels;
+mod lsh;
+mod mahalanobis;
+mod merge;
+mod metrics;
+#[cfg(feature = "msgpack")]
+mod msgpack;
+mod multilabel;
+mod openset;
+mod parallel;
+mod pca;
+mod persist;
+mod preprocess;
+mod proba;
+mod projection;
+mod quota;
+mod reduce;
+mod registry;
+mod regressor;
+mod reject;
+mod shard;
+mod sklearn;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod source;
+mod standardize;
+mod stats;
+#[cfg(test)]
+mod testutil;
+mod tombstone;
+#[cfg(feature = "url")]
+mod url;
+mod validate;
+mod view;
+mod vote;
+mod vptree;
+#[cfg(feature = "xlsx")]
+mod xlsx;
+
+pub use auto::{AutoConfig, AUTO_CONFIGURE_SAMPLE};
+pub use cascade::CoarseToFineClassifier;
+pub use coincident::CoincidentVote;
+pub use cv::{CalibrationBin, DistanceCache, DistanceFn, MetricScore};
+pub use dedup::approx_eq;
+pub use error::KnnError;
+pub use explain::{DistanceUnit, ExplainedNeighbor, Explanation};
+pub use frozen::FrozenKnnClassifier;
+use index::BruteForce;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::panic::RefUnwindSafe;
+pub use hierarchy::HIERARCHY_SEPARATOR;
+pub use hnsw::HnswParams;
+pub use imputer::KnnImputer;
+pub use index::{Backend, NeighborIndex};
+#[cfg(feature = "instrument")]
+pub use instrument::{instrumented, QueryStats};
+pub use kdtree::KD_TREE_MAX_FEATURES;
+pub use lsh::LshParams;
+pub use metrics::{top_k_accuracy, ClassReport, Report};
+pub use multilabel::LABEL_SEPARATOR;
+pub use parallel::Parallelism;
+pub use pca::Pca;
+pub use persist::AppendOnlyModel;
+pub use preprocess::{MinMaxScaler, Preprocess, RobustScaler};
+pub use proba::Prediction;
+pub use projection::RandomProjection;
+pub use quota::Eviction;
+pub use registry::{KnnRegistry, ModelStats};
+pub use regressor::KnnRegressor;
+pub use reject::Reject;
+pub use shard::ShardedClassifier;
+pub use standardize::StandardScaler;
+pub use stats::{ClassSummary, HistogramBin, NeighborhoodStats};
+pub use view::FeatureView;
+pub use vote::{TieBreak, Vote, VoteStrategy};
+
 // Define data type for k-nearest neighbor (k-nn) algorithm
+// (non-exhaustive, so adding a field does not break callers: create items with `KnnItem::new`)
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct KnnItem {
     pub label: String,
     pub data: Vec<f64>,
+    /// voting weight (1.0 by default, see `merge_weighted`)
+    pub weight: f64,
+    /// data source the item was learned from (see `fit_source`)
+    pub source: Option<String>,
+    /// time the item was learned, in units of the caller's choice (e.g. Unix seconds, see `fit_one_at`)
+    pub timestamp: Option<u64>,
+    /// soft-deleted: searches skip the item until `compact` removes it (set it with `KnnClassifier::delete`)
+    pub deleted: bool,
+}
+
+impl KnnItem {
+    /// new item with weight 1.0
+    pub fn new(label: &str, data: &[f64]) -> KnnItem {
+        KnnItem { label: label.to_string(), data: data.to_vec(), weight: 1.0, source: None, timestamp: None, deleted: false }
+    }
 }
 // Define the classifier for k-nn
 #[derive(Debug, Clone)]
 pub struct KnnClassifier {
     pub k: usize,
+    /// learned items; after editing them in place (same number of items), call `update_feature_ranges`,
+    /// which also drops the search index and the cached transformed items
     pub items: Vec<KnnItem>,
+    /// acceptance radius per class for `predict_one_open` (see `fit_class_radii`)
+    pub class_radii: HashMap<String, f64>,
+    /// (min, max) of each feature seen at fit time (see `update_feature_ranges`)
+    pub feature_ranges: Vec<(f64, f64)>,
+    /// how queries outside `feature_ranges` are handled
+    pub range_check: RangeCheck,
+    /// thread settings of `predict` (serial by default)
+    pub parallelism: Parallelism,
+    /// label spellings replaced when items are learned (alias -> label, see `rename_label`)
+    pub label_aliases: HashMap<String, String>,
+    /// distance metric (Euclidean by default)
+    pub metric: Metric,
+    /// pseudo-count added to the votes of every class in `predict_proba_one` (0.0 = no smoothing)
+    pub smoothing: f64,
+    /// inverse covariance matrix of the features for `Metric::Mahalanobis` (see `fit_covariance`)
+    pub inverse_covariance: Vec<Vec<f64>>,
+    /// random projection of the features, applied to the items when they are learned (see `project`)
+    pub projection: Option<RandomProjection>,
+    /// transformations applied to both points of every distance, in order (see `transform_point`)
+    pub preprocess: Vec<Preprocess>,
+    /// compare the features in z-score units (after `preprocess`): `fit` learns `scaler` and distances use it
+    pub standardize: bool,
+    /// mean and standard deviation of every feature (see `standardize` and `fit_scaler`)
+    pub scaler: Option<StandardScaler>,
+    /// weight of each feature in the distance (empty = all 1.0, see `set_feature_weights`)
+    pub feature_weights: Vec<f64>,
+    /// trust weight of each data source, multiplied into the item weights in votes (missing = 1.0, see `set_source_weight`)
+    pub source_weights: HashMap<String, f64>,
+    /// decision when all k nearest items coincide with the query (see `CoincidentVote`)
+    pub coincident: CoincidentVote,
+    /// how the k nearest neighbors vote (uniform by default, see `Vote`)
+    pub voting: Vote,
+    /// winner of a vote when labels tie (the nearest neighbor's label by default)
+    pub tie_break: TieBreak,
+    /// label predicted when no item can vote (empty model, or all items deleted or filtered out);
+    /// `None`: `try_predict_one` returns `KnnError::NoNeighbors` and `predict_one` panics
+    pub fallback: Option<String>,
+    /// maximum number of items per class (see `set_class_quota`)
+    pub class_quotas: HashMap<String, usize>,
+    /// item removed when a class reaches its quota (the oldest by default)
+    pub eviction: Eviction,
+    /// thresholds below which predictions are rejected (none by default, see `predict_one_or_reject`)
+    pub reject: Reject,
+    // strategy given to `set_vote_strategy`, used with `Vote::Custom`
+    vote_strategy: Option<std::sync::Arc<dyn VoteStrategy>>,
+    // handler given to `set_range_warning`, called by `RangeCheck::Warn`
+    range_warning: Option<RangeWarning>,
+    /// neighbor search (brute force by default)
+    pub backend: Backend,
+    /// with `Backend::Hnsw` and `Backend::Lsh`, fetch `rerank` times k approximate candidates
+    /// and keep the k nearest by their exact distances (1 = no re-ranking, the default)
+    pub rerank: usize,
+    // search structure of `backend`, rebuilt when the items change
+    index: index::IndexCell,
+    // number of deleted items counted by `delete` (items edited directly are counted by `update_feature_ranges`)
+    deleted: usize,
+}
+
+/// Handling of query features outside the training range
+///
+/// `slack` widens the allowed range by that fraction of the range width on both sides
+/// (0.1 allows values up to 10% of the width below the minimum or above the maximum).
+/// This catches unit mix-ups (meters vs centimeters) at the API boundary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RangeCheck {
+    /// no check (default)
+    Off,
+    /// `predict_one`, `try_predict_one` and `predict_batched` pass `KnnError::OutOfRange` to the handler
+    /// given to `set_range_warning` (e.g. a logger) and predict anyway; nothing is reported without a handler
+    Warn { slack: f64 },
+    /// `try_predict_one` returns `KnnError::OutOfRange`
+    Error { slack: f64 },
 }
+// handler of the warnings of `RangeCheck::Warn`
+#[derive(Clone)]
+struct RangeWarning(std::sync::Arc<dyn Fn(&KnnError) + Send + Sync + RefUnwindSafe>);
+
+impl std::fmt::Debug for RangeWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "RangeWarning")
+    }
+}
+
 impl KnnClassifier {
     /// new classifier with k (0 or odd number)
     pub fn new(k: usize) -> KnnClassifier {
         // check k, should be odd number
         let k = if k > 0 { k } else { 5 };
         let k = if k % 2 == 1 { k } else { k + 1 };
-        KnnClassifier { k, items: vec![] }
+        KnnClassifier { k, items: vec![], class_radii: HashMap::new(), feature_ranges: vec![], range_check: RangeCheck::Off, parallelism: Parallelism::serial(), label_aliases: HashMap::new(), metric: Metric::Euclidean, smoothing: 0.0, inverse_covariance: vec![], projection: None, preprocess: vec![], standardize: false, scaler: None, feature_weights: vec![], source_weights: HashMap::new(), coincident: CoincidentVote::Majority, voting: Vote::Uniform, tie_break: TieBreak::Nearest, fallback: None, reject: Reject::default(), class_quotas: HashMap::new(), eviction: Eviction::Oldest, vote_strategy: None, range_warning: None, backend: Backend::BruteForce, rerank: 1, index: index::IndexCell::default(), deleted: 0 }
+    }
+    /// new classifier with k and a distance metric
+    pub fn with_metric(k: usize, metric: Metric) -> KnnClassifier {
+        KnnClassifier { metric, ..KnnClassifier::new(k) }
+    }
+    /// Function to calculate the distance between two points with `metric` (and `feature_weights`)
+    ///
+    /// Features missing (NaN) on either side are left out, and the distance over the observed
+    /// features is scaled up as if the missing features differed like the observed ones.
+    /// The points are compared after `preprocess` and `standardize` (see `transform_point`).
+    /// Searches transform the items once and only the query per prediction.
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let (a, b) = (&*self.projected(a), &*self.projected(b));
+        if a.iter().chain(b.iter()).any(|v| v.is_nan()) {
+            return self.masked_distance(a, b, |x, y| self.distance(x, y));
+        }
+        instrument::count_distance();
+        if self.transforms_points() {
+            return self.scaled_distance(&self.transform_point(a), &self.transform_point(b));
+        }
+        self.scaled_distance(a, b)
+    }
+    // distance of points already transformed by `transform_point` (missing features are left out as in `distance`)
+    pub(crate) fn point_distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        if a.iter().chain(b.iter()).any(|v| v.is_nan()) {
+            return self.masked_distance(a, b, |x, y| self.point_distance(x, y));
+        }
+        instrument::count_distance();
+        self.scaled_distance(a, b)
+    }
+    // distance of transformed points, with the feature weights and the metric
+    fn scaled_distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        if let Some(scale) = self.feature_scale(a.len()) {
+            return self.weighted_distance(a, b, &scale);
+        }
+        match self.metric {
+            Metric::Mahalanobis => self.mahalanobis_distance(a, b),
+            _ => self.metric.distance(a, b),
+        }
+    }
+    /// Function to set the weight of each feature in the distance
+    ///
+    /// A weight scales the contribution of the feature: with Euclidean distance
+    /// `sqrt(sum w[i] * (a[i] - b[i])^2)`, with Manhattan `sum w[i] * |a[i] - b[i]|`.
+    /// 0.0 ignores a feature. Canberra and haversine distances are not affected.
+    /// The number of weights must match the number of features of the items. An empty slice removes the weights.
+    pub fn set_feature_weights(&mut self, weights: &[f64]) -> Result<(), KnnError> {
+        if !weights.is_empty() && !self.items.is_empty() && weights.len() != self.feature_ranges.len() {
+            return Err(KnnError::Invalid(format!("expected {} feature weights, found {}", self.feature_ranges.len(), weights.len())));
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+            return Err(KnnError::Invalid(format!("feature weight should be a non-negative number: {}", w)));
+        }
+        self.feature_weights = weights.to_vec();
+        Ok(())
+    }
+    // factor of each coordinate that multiplies the terms of the metric by the weights,
+    // None when `feature_weights` do not apply (no weights, another number of features, Canberra or haversine)
+    pub(crate) fn feature_scale(&self, dim: usize) -> Option<Vec<f64>> {
+        if self.feature_weights.is_empty() || self.feature_weights.len() != dim {
+            return None;
+        }
+        let p = match self.metric {
+            Metric::Manhattan | Metric::Chebyshev => 1.0,
+            Metric::Minkowski(p) => p,
+            Metric::Canberra | Metric::Haversine => return None,
+            _ => 2.0,
+        };
+        Some(self.feature_weights.iter().map(|w| w.powf(1.0 / p)).collect())
+    }
+    // distance of the coordinates scaled by `feature_scale`
+    fn weighted_distance(&self, a: &[f64], b: &[f64], scale: &[f64]) -> f64 {
+        let sa: Vec<f64> = a.iter().zip(scale).map(|(v, s)| v * s).collect();
+        let sb: Vec<f64> = b.iter().zip(scale).map(|(v, s)| v * s).collect();
+        match self.metric {
+            Metric::Mahalanobis => self.mahalanobis_distance(&sa, &sb),
+            m => m.distance(&sa, &sb),
+        }
+    }
+    fn masked_distance(&self, a: &[f64], b: &[f64], distance: impl Fn(&[f64], &[f64]) -> f64) -> f64 {
+        // missing coordinates are set to 0 on both sides, so they add nothing
+        let observed = a.iter().zip(b.iter()).filter(|(x, y)| !x.is_nan() && !y.is_nan()).count();
+        if observed == 0 { return f64::INFINITY; }
+        let mask = |v: &[f64], w: &[f64]| -> Vec<f64> {
+            v.iter().zip(w.iter()).map(|(x, y)| if x.is_nan() || y.is_nan() { 0.0 } else { *x }).collect()
+        };
+        let (a2, b2) = (mask(a, b), mask(b, a));
+        let d = distance(&a2, &b2);
+        let ratio = a.len().min(b.len()) as f64 / observed as f64;
+        match self.metric {
+            Metric::Euclidean | Metric::Mahalanobis => d * ratio.sqrt(),
+            Metric::Manhattan | Metric::Canberra => d * ratio,
+            Metric::Minkowski(p) => d * ratio.powf(1.0 / p),
+            Metric::Chebyshev | Metric::Cosine | Metric::Haversine => d,
+        }
     }
     /// Function to learn from data
     pub fn fit(&mut self, data: &[&[f64]], labels: &[&str]) {
         // Append learning data and labels together into items
         data.iter().zip(labels.iter()).for_each(|(it, label)| {
-            let item = KnnItem { label: label.to_string(), data: it.to_vec() };
-            self.items.push(item);
+            let item = KnnItem::new(label, it);
+            self.push_item(item);
         });
+        self.fit_statistics();
     }
-    /// Function to add a single data point
+    /// Function to add a single data point (not learned when a class quota rejects it, see `set_class_quota`)
+    ///
+    /// With `standardize` or `Metric::Mahalanobis`, the statistics are learned again from all items,
+    /// so learn many items at once with `fit`.
     pub fn fit_one(&mut self, data: &[f64], label: &str) {
-        let item = KnnItem { label: label.to_string(), data: data.to_vec() };
-        self.items.push(item);
+        self.learn_one(KnnItem::new(label, data));
+    }
+    /// Function to learn a single data point with the time it was observed (see `predict_one_where`)
+    pub fn fit_one_at(&mut self, data: &[f64], label: &str, timestamp: u64) {
+        let mut item = KnnItem::new(label, data);
+        item.timestamp = Some(timestamp);
+        self.learn_one(item);
+    }
+    // Function to add an item and refresh the statistics (false when a class quota rejects it)
+    pub(crate) fn learn_one(&mut self, item: KnnItem) -> bool {
+        let kept = self.push_item(item);
+        if kept {
+            self.fit_statistics();
+        }
+        kept
+    }
+    // Function to learn the statistics of all items the distance needs (covariance, scaler)
+    // after the items changed (every way of learning and loading calls it)
+    pub(crate) fn fit_statistics(&mut self) {
+        if self.needs_covariance() {
+            self.fit_covariance();
+        }
+        if self.standardize {
+            self.fit_scaler();
+        }
+    }
+    // Function to add an item, keeping the feature ranges up to date (false when a class quota rejects it)
+    pub(crate) fn push_item(&mut self, mut it: KnnItem) -> bool {
+        if let Some(label) = self.label_aliases.get(&it.label) {
+            it.label = label.clone();
+        }
+        if !self.make_room(&it.label) {
+            return false;
+        }
+        if let Cow::Owned(data) = self.projected(&it.data) {
+            it.data = data;
+        }
+        for (i, d) in it.data.iter().enumerate() {
+            if d.is_nan() { continue; }
+            match self.feature_ranges.get_mut(i) {
+                Some((min, max)) => {
+                    *min = min.min(*d);
+                    *max = max.max(*d);
+                },
+                None => self.feature_ranges.push((*d, *d)),
+            }
+        }
+        self.deleted += it.deleted as usize;
+        self.items.push(it);
+        self.index.clear();
+        true
+    }
+    /// Function to recompute `feature_ranges` and the statistics (see `standardize`) from the items (after editing `items` directly)
+    ///
+    /// The search index is built again on the next query (an index given to `set_index` is dropped).
+    pub fn update_feature_ranges(&mut self) {
+        self.index.invalidate();
+        self.feature_ranges.clear();
+        self.deleted = 0;
+        for it in std::mem::take(&mut self.items) {
+            self.push_item(it);
+        }
+        self.fit_statistics();
+    }
+    /// Function to learn from an iterator of results (rows from a CSV reader, DB driver, ...)
+    ///
+    /// Every successful row is learned. Failed rows are collected with their row index,
+    /// so the returned error lists all problems at once instead of stopping at the first one.
+    /// Returns the number of learned rows when no error occurred.
+    pub fn try_fit_from_iter<E>(&mut self, iter: impl Iterator<Item = Result<(Vec<f64>, String), E>>) -> Result<usize, Vec<(usize, E)>> {
+        let mut count = 0;
+        let mut errors = vec![];
+        for (i, row) in iter.enumerate() {
+            match row {
+                Ok((data, label)) => {
+                    self.push_item(KnnItem { label, data, ..KnnItem::new("", &[]) });
+                    count += 1;
+                },
+                Err(e) => errors.push((i, e)),
+            }
+        }
+        self.fit_statistics();
+        if errors.is_empty() { Ok(count) } else { Err(errors) }
+    }
+    /// Function to learn from owned rows and labels (e.g. columns read by a database driver), returns the number of items
+    ///
+    /// The vectors are moved into the items without copying. Nothing is learned when the lengths differ.
+    pub fn fit_columns(&mut self, data: Vec<Vec<f64>>, labels: Vec<String>) -> Result<usize, KnnError> {
+        if data.len() != labels.len() {
+            return Err(KnnError::Invalid(format!("{} rows of data but {} labels", data.len(), labels.len())));
+        }
+        let count = data.len();
+        self.items.reserve(count);
+        for (data, label) in data.into_iter().zip(labels) {
+            self.push_item(KnnItem { label, data, ..KnnItem::new("", &[]) });
+        }
+        self.fit_statistics();
+        Ok(count)
     }
     /// Function to predict based on a single data point
+    ///
+    /// The result is deterministic: items at equal distances are taken in learning order (the order of
+    /// `items`, e.g. the rows of a CSV file), and when labels tie in the vote, the label of the nearest neighbor wins (see `tie_break`).
+    /// When all k nearest items coincide with the query, `coincident` decides.
+    /// A model rebuilt from the same data predicts identically on every platform, as long as the distance
+    /// only uses `+ - * /` and square roots (Euclidean, Manhattan, Chebyshev, Canberra, cosine);
+    /// `powf` (Minkowski) and trigonometry (haversine) may differ in the last bit between platforms.
     pub fn predict_one(&self, item: &[f64]) -> String {
-        // Calculate distances between the data to predict and the learned data
-        let mut distances: Vec<(usize, f64)> = self.items.iter().enumerate().map(|(i, it)| {
-            (i, calc_distance(&it.data, &item))
-        }).collect();
-        // Sort by distance
-        distances.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+        self.warn_range(item);
+        self.predict_one_unchecked(item).unwrap_or_else(|e| panic!("{}", e))
+    }
+    fn predict_one_unchecked(&self, item: &[f64]) -> Result<String, KnnError> {
+        let distances = self.nearest(item, self.k);
+        self.decide_or_reject(item, &distances)
+    }
+    // Function to decide the label from the k nearest neighbors, replaced by `reject.label` when rejected
+    pub(crate) fn decide_or_reject(&self, item: &[f64], neighbors: &[(usize, f64)]) -> Result<String, KnnError> {
         // Take k nearest neighbors and perform a majority vote
-        let mut counter_map = std::collections::HashMap::new();
-        for (i, _) in distances.iter().take(self.k) {
-            let label = &self.items[*i].label;
-            *counter_map.entry(label).or_insert(0) += 1;
+        // (on a tie, the label of the nearest neighbor among the tied labels wins)
+        let label = self.decide(item, neighbors).ok_or(KnnError::NoNeighbors)?;
+        if let Some(reject) = &self.reject.label {
+            let labels: Vec<&str> = neighbors.iter().map(|(i, _)| self.items[*i].label.as_str()).collect();
+            if self.rejects(neighbors, &labels, label) {
+                return Ok(reject.clone());
+            }
+        }
+        Ok(label.to_string())
+    }
+    // Function to vote among the neighbors with the item and source weights
+    pub(crate) fn vote(&self, neighbors: &[(usize, f64)]) -> Option<&str> {
+        let weights = self.neighbor_weights(neighbors);
+        self.tally(neighbors.iter().zip(weights).map(|((i, _), w)| (self.items[*i].label.as_str(), w)))
+    }
+    /// Function to set the handler of `RangeCheck::Warn`, called with `KnnError::OutOfRange` for every query out of range
+    ///
+    /// ```rs
+    /// clf.range_check = RangeCheck::Warn { slack: 0.1 };
+    /// clf.set_range_warning(|e| log::warn!("{}", e));
+    /// ```
+    pub fn set_range_warning(&mut self, handler: impl Fn(&KnnError) + Send + Sync + RefUnwindSafe + 'static) {
+        self.range_warning = Some(RangeWarning(std::sync::Arc::new(handler)));
+    }
+    // Function to report a query out of range to the handler of `RangeCheck::Warn`
+    pub(crate) fn warn_range(&self, item: &[f64]) {
+        if let (RangeCheck::Warn { slack }, Some(handler)) = (self.range_check, &self.range_warning) {
+            if let Err(e) = self.check_query(item, slack) { (handler.0)(&e); }
+        }
+    }
+    /// Function to check that every feature of the query is within the training range (with `slack`)
+    ///
+    /// Missing features (NaN) are not checked.
+    pub fn check_query(&self, item: &[f64], slack: f64) -> Result<(), KnnError> {
+        let item = &*self.projected(item);
+        for (feature, (v, (min, max))) in item.iter().zip(self.feature_ranges.iter()).enumerate() {
+            let margin = (max - min) * slack;
+            if *v < min - margin || *v > max + margin {
+                return Err(KnnError::OutOfRange { feature, value: *v, min: *min, max: *max });
+            }
+        }
+        Ok(())
+    }
+    /// Function to predict based on a single data point, applying `range_check`
+    ///
+    /// Returns `KnnError::NoNeighbors` when no item can vote and there is no `fallback`.
+    pub fn try_predict_one(&self, item: &[f64]) -> Result<String, KnnError> {
+        match self.range_check {
+            RangeCheck::Off => {},
+            RangeCheck::Warn { .. } => self.warn_range(item),
+            RangeCheck::Error { slack } => self.check_query(item, slack)?,
         }
-        // Return the most common label
-        let label = counter_map.into_iter().max_by_key(|&(_, count)| count).unwrap().0;
-        label.clone()
+        self.predict_one_unchecked(item)
     }
-    // Function to predict based on multiple data points
+    // Function to find the n nearest items as (index, distance), nearest first, skipping deleted items
+    pub(crate) fn nearest(&self, item: &[f64], n: usize) -> Vec<(usize, f64)> {
+        let item = &*self.projected(item);
+        // the n nearest live items are among the n + deleted nearest items
+        let wanted = n.saturating_add(self.deleted);
+        let mut found = self.nearest_indexed(item, wanted)
+            .or_else(|| BruteForce.nearest(self, item, wanted))
+            .unwrap_or_default();
+        let searched = found.len();
+        found.retain(|(i, _)| !self.items[*i].deleted);
+        if found.len() < n && searched == wanted && searched < self.items.len() {
+            // items marked deleted in `items` directly are not counted: search them all
+            found = BruteForce.nearest(self, item, self.items.len()).unwrap_or_default();
+            found.retain(|(i, _)| !self.items[*i].deleted);
+        }
+        found.truncate(n);
+        found
+    }
+    /// Function to find the n nearest training items as (index, label, distance), nearest first
+    ///
+    /// Ties are in learning order, and deleted items are skipped. The index is the position in `items`,
+    /// e.g. to look up other data of a recommended item.
+    pub fn kneighbors(&self, item: &[f64], n: usize) -> Vec<(usize, &str, f64)> {
+        self.nearest(item, n).into_iter().map(|(i, d)| (i, self.items[i].label.as_str(), d)).collect()
+    }
+    /// Function to predict based on multiple data points
+    ///
+    /// Runs on multiple threads when `parallelism` allows it. The results are always in input order
+    /// and identical to the serial results.
     pub fn predict(&self, items: &[Vec<f64>]) -> Vec<String> {
-        items.iter().map(|it| self.predict_one(&it.to_vec())).collect()
+        self.map_queries(items, |it| self.predict_one(it))
+    }
+    /// content hash of the model (k, labels and data of the live items)
+    ///
+    /// Weights other than 1.0, sources and timestamps of the items are hashed too.
+    /// The value is stable across platforms and Rust versions (64-bit FNV-1a),
+    /// so it can be stored next to a saved model and compared after loading.
+    pub fn checksum(&self) -> u64 {
+        let mut h = Fnv64::new();
+        h.write(&(self.k as u64).to_le_bytes());
+        h.write(&(self.live_items().count() as u64).to_le_bytes());
+        for (_, it) in self.live_items() {
+            h.write(&(it.label.len() as u64).to_le_bytes());
+            h.write(it.label.as_bytes());
+            h.write(&(it.data.len() as u64).to_le_bytes());
+            for d in &it.data {
+                h.write(&d.to_bits().to_le_bytes());
+            }
+            // only hashed when set, so the checksums of models without them stay the same
+            if it.weight != 1.0 {
+                h.write(b"w");
+                h.write(&it.weight.to_bits().to_le_bytes());
+            }
+            if let Some(source) = &it.source {
+                h.write(b"s");
+                h.write(&(source.len() as u64).to_le_bytes());
+                h.write(source.as_bytes());
+            }
+            if let Some(t) = it.timestamp {
+                h.write(b"t");
+                h.write(&t.to_le_bytes());
+            }
+        }
+        h.finish()
     }
     /// convert to csv
     pub fn to_csv(&self, delimiter: char) -> String {
+        self.to_csv_with_format(delimiter, FloatFormat::RoundTrip)
+    }
+    /// convert to csv with the specified number format (deleted items are left out)
+    ///
+    /// Only labels and data are written: weights, sources and timestamps are kept by `to_msgpack` and `AppendOnlyModel`.
+    pub fn to_csv_with_format(&self, delimiter: char, format: FloatFormat) -> String {
         let mut s = String::new();
-        for it in &self.items {
-            s.push_str(&it.label);
-            s.push(delimiter);
-            for d in &it.data {
-                s.push_str(&d.to_string());
-                s.push(delimiter);
-            }
-            s.pop();
-            s.push('\n');
+        for (_, it) in self.live_items() {
+            push_csv_line(&mut s, it, delimiter, format);
         }
         s
     }
@@ -118,18 +824,171 @@ impl KnnClassifier {
         // read csv line
         for (i, line) in s.lines().enumerate() {
             if skip_header && i == 0 { continue; }
-            let line = line.trim();
-            if line == "" { continue; }
-            let mut it = KnnItem { label: "".to_string(), data: vec![] };
-            let columns_iter = line.split(delimiter);
-            for (i, d) in columns_iter.enumerate() {
-                if i == label_col {
-                    it.label = d.trim().to_string();
-                } else {
-                    it.data.push(d.trim().parse().unwrap());
-                }
+            if let Some(it) = parse_csv_line(line, delimiter, label_col).unwrap() {
+                self.push_item(it);
+            }
+        }
+        self.fit_statistics();
+    }
+    /// read csv from a stream (file, stdin, ...) without loading the whole text into memory
+    ///
+    /// `progress` is called with the number of rows read so far, every `CSV_CHUNK_ROWS` rows and at the end.
+    /// Returns the number of rows read.
+    pub fn from_csv_reader(&mut self, reader: impl std::io::BufRead, delimiter: char, label_col: usize, skip_header: bool, mut progress: impl FnMut(usize)) -> Result<usize, KnnError> {
+        let mut count = 0;
+        for (i, line) in reader.lines().enumerate() {
+            let line = line.map_err(|e| KnnError::Io(e.to_string()))?;
+            if skip_header && i == 0 { continue; }
+            let it = parse_csv_line(&line, delimiter, label_col).map_err(|msg| KnnError::Parse { line: i + 1, msg })?;
+            if let Some(it) = it {
+                self.push_item(it);
+                count += 1;
+                if count % CSV_CHUNK_ROWS == 0 { progress(count); }
             }
-            self.items.push(it);
+        }
+        self.fit_statistics();
+        progress(count);
+        Ok(count)
+    }
+}
+
+/// Number format used when writing data to csv
+///
+/// All formats can be read back by `from_csv` (plain and scientific notation are both accepted).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FloatFormat {
+    /// shortest text that parses back to exactly the same value (`0.1`, `150`, `1e-7`)
+    RoundTrip,
+    /// at most `n` digits after the decimal point, trailing zeros removed (lossy, but compact)
+    Precision(usize),
+    /// shortest round-trip text in scientific notation (`1.5e2`)
+    Scientific,
+}
+
+impl FloatFormat {
+    fn format(&self, v: f64) -> String {
+        match self {
+            FloatFormat::RoundTrip => v.to_string(),
+            FloatFormat::Precision(n) => {
+                let s = format!("{:.*}", n, v);
+                let s = if s.contains('.') { s.trim_end_matches('0').trim_end_matches('.') } else { &s };
+                // avoid "-0"
+                if s == "-0" { "0".to_string() } else { s.to_string() }
+            },
+            FloatFormat::Scientific => format!("{:e}", v),
+        }
+    }
+}
+
+/// Number of rows between progress reports of `KnnClassifier::from_csv_reader`
+pub const CSV_CHUNK_ROWS: usize = 10000;
+
+// Function to write an item as a csv line
+pub(crate) fn push_csv_line(s: &mut String, it: &KnnItem, delimiter: char, format: FloatFormat) {
+    s.push_str(&it.label);
+    s.push(delimiter);
+    for d in &it.data {
+        s.push_str(&format.format(*d));
+        s.push(delimiter);
+    }
+    s.pop();
+    s.push('\n');
+}
+
+// Function to parse a csv line (None for an empty line)
+pub(crate) fn parse_csv_line(line: &str, delimiter: char, label_col: usize) -> Result<Option<KnnItem>, String> {
+    let line = line.trim();
+    if line.is_empty() { return Ok(None); }
+    let mut it = KnnItem::new("", &[]);
+    let columns_iter = line.split(delimiter);
+    for (i, d) in columns_iter.enumerate() {
+        if i == label_col {
+            it.label = d.trim().to_string();
+        } else {
+            let v = d.trim().parse().map_err(|_| format!("invalid number: {}", d.trim()))?;
+            it.data.push(v);
+        }
+    }
+    Ok(Some(it))
+}
+
+// Function to return the most common label (ties go to the label that appears first)
+pub(crate) fn majority<'a>(labels: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    weighted_majority(labels.map(|l| (l, 1.0)))
+}
+
+// Function to return the label with the largest total weight (ties go to the label that appears first)
+pub(crate) fn weighted_majority<'a>(votes: impl Iterator<Item = (&'a str, f64)>) -> Option<&'a str> {
+    let mut counts: Vec<(&str, f64)> = vec![];
+    for (label, w) in votes {
+        match counts.iter_mut().find(|(l, _)| *l == label) {
+            Some((_, n)) => *n += w,
+            None => counts.push((label, w)),
+        }
+    }
+    let mut best: Option<(&str, f64)> = None;
+    for (label, n) in counts {
+        if best.is_none_or(|(_, m)| n > m) { best = Some((label, n)); }
+    }
+    best.map(|(label, _)| label)
+}
+
+// 64-bit FNV-1a hash (std's DefaultHasher is not stable between Rust versions)
+pub(crate) struct Fnv64(u64);
+impl Fnv64 {
+    pub(crate) fn new() -> Self { Fnv64(0xcbf29ce484222325) }
+    pub(crate) fn write(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.0 ^= *b as u64;
+            self.0 = self.0.wrapping_mul(0x100000001b3);
+        }
+    }
+    pub(crate) fn finish(&self) -> u64 { self.0 }
+}
+
+/// Distance metric of a classifier
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Metric {
+    /// straight-line (L2) distance (default)
+    #[default]
+    Euclidean,
+    /// sum of absolute differences (L1), often better for high-dimensional tabular data
+    Manhattan,
+    /// generalized distance `(sum |a - b|^p)^(1/p)` with p >= 1 (1: Manhattan, 2: Euclidean)
+    Minkowski(f64),
+    /// largest absolute difference of a feature (L∞), for bounded readings where the worst feature matters
+    Chebyshev,
+    /// `1 - cosine similarity` (0: same direction, 2: opposite), for embedding vectors; not a true metric
+    Cosine,
+    /// Euclidean distance after decorrelating and scaling the features with their covariance
+    /// (`KnnClassifier::inverse_covariance`); without a covariance matrix it is the Euclidean distance
+    Mahalanobis,
+    /// great-circle distance in kilometers between `[latitude, longitude]` points in degrees
+    Haversine,
+    /// `sum |a - b| / (|a| + |b|)`, robust for features of very different magnitudes near zero
+    Canberra,
+}
+
+impl Metric {
+    /// Function to calculate distance between two points
+    pub fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        match self {
+            Metric::Euclidean => calc_distance(a, b),
+            Metric::Manhattan => manhattan_distance(a, b),
+            Metric::Minkowski(p) => minkowski_distance(a, b, *p),
+            Metric::Chebyshev => chebyshev_distance(a, b),
+            Metric::Cosine => cosine_distance(a, b),
+            Metric::Mahalanobis => calc_distance(a, b),
+            Metric::Haversine => haversine_distance(a, b),
+            Metric::Canberra => canberra_distance(a, b),
+        }
+    }
+    // true when the triangle inequality holds, so search can prune by distance bounds
+    pub(crate) fn is_metric(&self) -> bool {
+        match self {
+            Metric::Minkowski(p) => *p >= 1.0,
+            Metric::Cosine => false,
+            _ => true,
         }
     }
 }
@@ -139,11 +998,69 @@ pub fn calc_distance(a: &[f64], b: &[f64]) -> f64 {
     a.iter().zip(b.iter()).map(|(x, y)| (x - y).powi(2)).sum::<f64>().sqrt()
 }
 
+/// Function to calculate Manhattan (L1) distance between two points
+pub fn manhattan_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs()).sum()
+}
+
+/// Function to calculate Chebyshev (L∞) distance between two points
+pub fn chebyshev_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs()).fold(0.0, f64::max)
+}
+
+/// Function to calculate cosine distance (`1 - cosine similarity`) between two points
+///
+/// A zero vector has no direction; its distance to any point is 1.0.
+pub fn cosine_distance(a: &[f64], b: &[f64]) -> f64 {
+    let dot: f64 = a.iter().zip(b.iter()).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f64]| v.iter().map(|x| x * x).sum::<f64>().sqrt();
+    let n = norm(a) * norm(b);
+    if n > 0.0 { 1.0 - (dot / n).clamp(-1.0, 1.0) } else { 1.0 }
+}
+
+/// Function to calculate Canberra distance between two points (terms with `a = b = 0` count as 0)
+pub fn canberra_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| {
+        let s = x.abs() + y.abs();
+        if s > 0.0 { (x - y).abs() / s } else { 0.0 }
+    }).sum()
+}
+
+/// mean radius of the earth in kilometers (for `Metric::Haversine`)
+pub const EARTH_RADIUS_KM: f64 = 6371.0088;
+
+/// Function to calculate great-circle distance in kilometers between `[latitude, longitude]` points in degrees
+///
+/// Correct across the antimeridian and near the poles. Features after the first two are ignored.
+/// NaN when a point has fewer than two features (`check_config` reports such items).
+pub fn haversine_distance(a: &[f64], b: &[f64]) -> f64 {
+    if a.len() < 2 || b.len() < 2 {
+        return f64::NAN;
+    }
+    let (lat1, lon1) = (a[0].to_radians(), a[1].to_radians());
+    let (lat2, lon2) = (b[0].to_radians(), b[1].to_radians());
+    let h = ((lat2 - lat1) / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * ((lon2 - lon1) / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_KM * h.clamp(0.0, 1.0).sqrt().asin()
+}
+
+/// Function to calculate Minkowski distance with exponent `p` between two points
+pub fn minkowski_distance(a: &[f64], b: &[f64], p: f64) -> f64 {
+    a.iter().zip(b.iter()).map(|(x, y)| (x - y).abs().powf(p)).sum::<f64>().powf(1.0 / p)
+}
+
 // test code
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_unwind_safe() {
+        // a panicking prediction can be caught with the classifier borrowed
+        fn assert_unwind_safe<T: std::panic::UnwindSafe + RefUnwindSafe>() {}
+        assert_unwind_safe::<KnnClassifier>();
+        let c = KnnClassifier::new(1);
+        assert!(std::panic::catch_unwind(|| c.predict_one(&[1.0])).is_err());
+    }
     #[test]
     fn test_knn1() {
         // Obesity: 肥満 > normal: 標準 > thin: 痩せ
@@ -209,6 +1126,70 @@ mod tests {
         assert_eq!(labels, ["肥満", "標準", "痩せ"]);
     }
     #[test]
+    fn test_metric() {
+        assert_eq!(Metric::Euclidean.distance(&[0., 0.], &[3., 4.]), 5.);
+        assert_eq!(Metric::Manhattan.distance(&[0., 0.], &[3., -4.]), 7.);
+        // the origin is nearer to "a" with L2 (4.24 < 5) and to "b" with L1 (5 < 6)
+        let data: &[&[f64]] = &[&[3., 3.], &[5., 0.]];
+        let mut c = KnnClassifier::new(1);
+        c.fit(data, &["a", "b"]);
+        assert_eq!(c.predict_one(&[0., 0.]), "a");
+        let mut c = KnnClassifier::with_metric(1, Metric::Manhattan);
+        c.fit(data, &["a", "b"]);
+        assert_eq!(c.predict_one(&[0., 0.]), "b");
+        // Minkowski generalizes both
+        assert_eq!(Metric::Minkowski(1.0).distance(&[0., 0.], &[3., -4.]), 7.);
+        assert!((Metric::Minkowski(2.0).distance(&[0., 0.], &[3., 4.]) - 5.).abs() < 1e-12);
+        assert!((Metric::Minkowski(3.0).distance(&[0., 0.], &[1., 1.]) - 2f64.cbrt()).abs() < 1e-12);
+        assert_eq!(Metric::Chebyshev.distance(&[0., 5.], &[3., -4.]), 9.);
+        assert_eq!(Metric::Cosine.distance(&[1., 0.], &[5., 0.]), 0.);
+        assert_eq!(Metric::Cosine.distance(&[1., 0.], &[0., 2.]), 1.);
+        assert!((Metric::Cosine.distance(&[1., 1.], &[-1., -1.]) - 2.).abs() < 1e-12);
+        assert_eq!(Metric::Cosine.distance(&[0., 0.], &[1., 1.]), 1.);
+        assert_eq!(Metric::Canberra.distance(&[0., 1., -2.], &[0., 3., 2.]), 0.5 + 1.);
+        // Tokyo station - Osaka station is about 403 km; across the antimeridian and over the pole
+        assert!((Metric::Haversine.distance(&[35.6812, 139.7671], &[34.7025, 135.4959]) - 403.).abs() < 1.);
+        assert!((Metric::Haversine.distance(&[0., 179.5], &[0., -179.5]) - 111.2).abs() < 0.1);
+        assert!((Metric::Haversine.distance(&[89.5, 0.], &[89.5, 180.]) - 111.2).abs() < 0.1);
+        // a point without a longitude has no distance, and predicting does not panic
+        assert!(Metric::Haversine.distance(&[35.], &[34., 135.]).is_nan());
+        let mut c = KnnClassifier::with_metric(1, Metric::Haversine);
+        c.fit(&[&[35.], &[34.]], &["a", "b"]);
+        assert_eq!(c.predict_one(&[35.]), "a");
+        assert!(c.check_config().is_err());
+        // direction matters, not length
+        let mut c = KnnClassifier::with_metric(1, Metric::Cosine);
+        c.fit(&[&[10., 0.], &[0.1, 0.1]], &["x", "diagonal"]);
+        assert_eq!(c.predict_one(&[3., 3.5]), "diagonal");
+    }
+    #[test]
+    fn test_missing_query_features() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0., 0.], &[10., 10., 0.]], &["a", "b"]);
+        // the second feature is missing: 2 observed features, scaled to 3
+        assert!((c.distance(&[3., f64::NAN, 4.], &[0., 0., 0.]) - 5. * 1.5f64.sqrt()).abs() < 1e-12);
+        assert_eq!(c.predict_one(&[9., f64::NAN, 0.]), "b");
+        assert_eq!(c.predict(&[vec![f64::NAN, 1., f64::NAN]]), ["a"]);
+        c.metric = Metric::Manhattan;
+        assert_eq!(c.distance(&[3., f64::NAN, 4.], &[0., 0., 0.]), 10.5);
+        assert_eq!(c.distance(&[f64::NAN; 3], &[0., 0., 0.]), f64::INFINITY);
+    }
+    #[test]
+    fn test_feature_weights() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[3., 10.]], &["a", "b"]);
+        assert_eq!(c.predict_one(&[2., 4.]), "a");
+        c.set_feature_weights(&[1., 0.01]).unwrap();
+        assert!((c.distance(&[0., 0.], &[3., 40.]) - 5.).abs() < 1e-12);
+        assert_eq!(c.predict_one(&[2., 4.]), "b");
+        c.metric = Metric::Manhattan;
+        assert!((c.distance(&[0., 0.], &[3., 40.]) - 3.4).abs() < 1e-12);
+        assert!(c.set_feature_weights(&[1.]).is_err());
+        assert!(c.set_feature_weights(&[1., -1.]).is_err());
+        c.set_feature_weights(&[]).unwrap();
+        assert_eq!(c.distance(&[0., 0.], &[3., 40.]), 43.);
+    }
+    #[test]
     fn test_to_csv() {
         //
         let mut c = KnnClassifier::new(5);
@@ -226,5 +1207,147 @@ mod tests {
         c.from_csv("肥満, 150, 80\n肥満 , 153, 69.0\n 肥満, 153, 94.0\n", ',', 0, false);
         assert_eq!(&c.to_csv(','), "肥満,150,80\n肥満,153,69\n肥満,153,94\n");
     }
+    #[test]
+    fn test_csv_float_format() {
+        let mut c = KnnClassifier::new(1);
+        c.fit_one(&[0.1 + 0.2, 150.0, -0.0001, 1e-7], "a");
+        assert_eq!(c.to_csv(','), "a,0.30000000000000004,150,-0.0001,0.0000001\n");
+        assert_eq!(c.to_csv_with_format(',', FloatFormat::Precision(3)), "a,0.3,150,0,0\n");
+        let s = c.to_csv_with_format(',', FloatFormat::Scientific);
+        assert_eq!(s, "a,3.0000000000000004e-1,1.5e2,-1e-4,1e-7\n");
+        // round-trip
+        let mut c2 = KnnClassifier::new(1);
+        c2.from_csv(&s, ',', 0, false);
+        assert_eq!(c2.items[0].data, c.items[0].data);
+        c2.from_csv("b,1E3,2.5e+1", ',', 0, false);
+        assert_eq!(c2.items[1].data, [1000.0, 25.0]);
+    }
+    #[test]
+    fn test_from_csv_reader() {
+        let text = "label,h,w\n肥満,150,80\n\n標準,169,64\n".to_string() + &"標準,170,60\n".repeat(CSV_CHUNK_ROWS);
+        let mut c = KnnClassifier::new(5);
+        let mut reports = vec![];
+        let n = c.from_csv_reader(text.as_bytes(), ',', 0, true, |n| reports.push(n)).unwrap();
+        assert_eq!(n, CSV_CHUNK_ROWS + 2);
+        assert_eq!(reports, [CSV_CHUNK_ROWS, CSV_CHUNK_ROWS + 2]);
+        assert_eq!(c.items[1].data, [169.0, 64.0]);
+        //
+        let r = c.from_csv_reader("肥満,150,80\n肥満,x,80\n".as_bytes(), ',', 0, false, |_| {});
+        assert_eq!(r, Err(KnnError::Parse { line: 2, msg: "invalid number: x".to_string() }));
+    }
+    #[test]
+    fn test_feature_ranges() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[150., 60.], &[190., 90.]], &["a", "b"]);
+        c.fit_one(&[170., 40.], "a");
+        assert_eq!(c.feature_ranges, [(150., 190.), (40., 90.)]);
+        assert!(c.check_query(&[1.7, 60.], 0.1).is_err());
+        assert!(c.check_query(&[194., f64::NAN], 0.1).is_ok());
+        c.range_check = RangeCheck::Error { slack: 0.1 };
+        assert_eq!(c.try_predict_one(&[1.7, 60.]), Err(KnnError::OutOfRange { feature: 0, value: 1.7, min: 150., max: 190. }));
+        assert_eq!(c.try_predict_one(&[185., 85.]).unwrap(), "b");
+        // warnings go to the handler
+        let warnings = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let w = warnings.clone();
+        c.set_range_warning(move |e| w.lock().unwrap().push(e.clone()));
+        c.range_check = RangeCheck::Warn { slack: 0.1 };
+        assert_eq!(c.try_predict_one(&[1.7, 60.]).unwrap(), "a");
+        c.predict_one(&[185., 85.]);
+        c.predict_batched(&[vec![185., 6000.]]);
+        assert_eq!(*warnings.lock().unwrap(), [
+            KnnError::OutOfRange { feature: 0, value: 1.7, min: 150., max: 190. },
+            KnnError::OutOfRange { feature: 1, value: 6000., min: 40., max: 90. },
+        ]);
+        // after editing items directly
+        c.items.pop();
+        c.update_feature_ranges();
+        assert_eq!(c.feature_ranges, [(150., 190.), (60., 90.)]);
+    }
+    #[test]
+    fn test_stable_order() {
+        let mut c = KnnClassifier::new(3);
+        // four items at distance 1 from the query, and one at an infinite distance
+        c.fit(&[&[f64::INFINITY], &[1.], &[-1.], &[1.], &[-1.]], &["x", "a", "b", "b", "a"]);
+        assert_eq!(c.nearest(&[0.], 5).iter().map(|n| n.0).collect::<Vec<_>>(), [1, 2, 3, 4, 0]);
+        assert_eq!(c.predict_one(&[0.]), "b");
+        // undefined (NaN) distances come last
+        c.metric = Metric::Cosine;
+        assert_eq!(c.nearest(&[1.], 5).iter().map(|n| n.0).collect::<Vec<_>>(), [1, 3, 2, 4, 0]);
+        // the same rows in another order give the first three of that order
+        let mut r = KnnClassifier::new(3);
+        r.fit(&[&[-1.], &[1.], &[1.], &[-1.]], &["a", "a", "b", "b"]);
+        assert_eq!(r.predict_one(&[0.]), "a");
+        for backend in [Backend::KdTree, Backend::BallTree, Backend::VpTree] {
+            r.backend = backend;
+            assert_eq!(r.nearest(&[0.], 3), [(0, 1.), (1, 1.), (2, 1.)]);
+        }
+    }
+    #[test]
+    fn test_checksum() {
+        let mut c = KnnClassifier::new(3);
+        c.fit_one(&[150.0, 80.0], "肥満");
+        let sum = c.checksum();
+        assert_eq!(sum, c.clone().checksum());
+        c.items[0].data[1] = 80.5;
+        assert_ne!(sum, c.checksum());
+        c.items[0].data[1] = 80.0;
+        c.k = 5;
+        assert_ne!(sum, c.checksum());
+        c.k = 3;
+        c.items[0].weight = 0.5;
+        assert_ne!(sum, c.checksum());
+    }
+    #[test]
+    fn test_kneighbors() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[3., 4.], &[1., 0.], &[0., 1.]], &["a", "b", "c", "d"]);
+        assert_eq!(c.kneighbors(&[0., 0.], 3), [(0, "a", 0.), (2, "c", 1.), (3, "d", 1.)]);
+        assert_eq!(c.kneighbors(&[0., 0.], 10).len(), 4);
+        c.delete(2);
+        c.backend = Backend::KdTree;
+        assert_eq!(c.kneighbors(&[3., 3.], 2), [(1, "b", 1.), (3, "d", 13f64.sqrt())]);
+    }
+    #[test]
+    fn test_try_fit_from_iter() {
+        let rows: Vec<Result<(Vec<f64>, String), String>> = vec![
+            Ok((vec![170.0, 60.0], "Normal".to_string())),
+            Err("bad row".to_string()),
+            Ok((vec![152.0, 99.0], "Obesity".to_string())),
+        ];
+        let mut c = KnnClassifier::new(1);
+        let errors = c.try_fit_from_iter(rows.into_iter()).unwrap_err();
+        assert_eq!(errors, [(1, "bad row".to_string())]);
+        assert_eq!(c.items.len(), 2);
+        assert_eq!(c.predict_one(&[150.0, 95.0]), "Obesity");
+        //
+        let rows = vec![Ok::<_, String>((vec![166.0, 58.0], "Normal".to_string()))];
+        assert_eq!(c.try_fit_from_iter(rows.into_iter()), Ok(1));
+    }
+    #[test]
+    fn test_fallback() {
+        let mut c = KnnClassifier::new(3);
+        assert_eq!(c.try_predict_one(&[1.0]), Err(KnnError::NoNeighbors));
+        c.fallback = Some("unknown".to_string());
+        assert_eq!(c.predict_one(&[1.0]), "unknown");
+        assert_eq!(c.predict_one_among(&[1.0], &["a"]).unwrap(), "unknown");
+        c.fit_one(&[0.0], "a");
+        assert_eq!(c.predict_one(&[1.0]), "a");
+        c.delete(0);
+        assert_eq!(c.try_predict_one(&[1.0]).unwrap(), "unknown");
+        assert_eq!(c.freeze().predict_one(&[1.0]), "unknown");
+    }
+    #[test]
+    fn test_fit_columns() {
+        let mut c = KnnClassifier::new(1);
+        let data = vec![vec![170.0, 60.0], vec![152.0, 99.0]];
+        let first = data[0].as_ptr();
+        assert_eq!(c.fit_columns(data, vec!["Normal".to_string(), "Obesity".to_string()]).unwrap(), 2);
+        // the rows are moved, not copied
+        assert_eq!(c.items[0].data.as_ptr(), first);
+        assert_eq!(c.predict_one(&[150.0, 95.0]), "Obesity");
+        assert_eq!(c.feature_ranges, [(152.0, 170.0), (60.0, 99.0)]);
+        assert!(matches!(c.fit_columns(vec![vec![1.0, 2.0]], vec![]), Err(KnnError::Invalid(_))));
+        assert_eq!(c.items.len(), 2);
+    }
 }
 
@@ -0,0 +1,208 @@
+//! Locality-sensitive hashing for approximate neighbor search (see `Backend::Lsh`).
+
+use crate::index::{insert_neighbor, BuiltIndex, NeighborIndex};
+#[cfg(feature = "msgpack")]
+use crate::index::{IndexParts, PartsReader};
+use crate::instrument;
+use crate::KnnClassifier;
+use std::collections::{HashMap, HashSet};
+
+/// Parameters of `Backend::Lsh`
+///
+/// More tables find the true neighbors more often (higher recall), more bits per hash
+/// make smaller buckets (faster queries, lower recall).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LshParams {
+    /// number of hash tables
+    pub tables: usize,
+    /// length of a hash in bits (random hyperplanes per table, 1 to 64)
+    pub bits: usize,
+}
+
+impl Default for LshParams {
+    fn default() -> Self {
+        LshParams { tables: 8, bits: 12 }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct Lsh {
+    // mean of the items; the hyperplanes go through it
+    mean: Vec<f64>,
+    // normal vectors of the hyperplanes, `bits` per table
+    planes: Vec<Vec<Vec<f64>>>,
+    // hash -> items, per table
+    buckets: Vec<HashMap<u64, Vec<usize>>>,
+}
+
+// deterministic pseudo-random numbers (splitmix64)
+pub(crate) struct Rng(pub(crate) u64);
+
+impl Rng {
+    fn next(&mut self) -> f64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut x = self.0;
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        ((x >> 11) as f64 + 0.5) / (1u64 << 53) as f64
+    }
+    // standard normal number (Box-Muller)
+    pub(crate) fn normal(&mut self) -> f64 {
+        (-2.0 * self.next().ln()).sqrt() * (2.0 * std::f64::consts::PI * self.next()).cos()
+    }
+}
+
+impl Lsh {
+    // Function to hash every item, None when the items can not be indexed
+    pub(crate) fn build(clf: &KnnClassifier, params: &LshParams) -> Option<Lsh> {
+        let features = clf.items.first()?.data.len();
+        if clf.items.iter().any(|it| it.data.len() != features) {
+            return None;
+        }
+        let mut mean = vec![0.0; features];
+        let mut counts = vec![0usize; features];
+        for it in &clf.items {
+            for (f, v) in it.data.iter().enumerate().filter(|(_, v)| !v.is_nan()) {
+                mean[f] += v;
+                counts[f] += 1;
+            }
+        }
+        for (m, c) in mean.iter_mut().zip(counts) {
+            *m /= c.max(1) as f64;
+        }
+        let mut rng = Rng(0x5eed);
+        let planes: Vec<Vec<Vec<f64>>> = (0..params.tables.max(1)).map(|_| {
+            (0..params.bits.clamp(1, 64)).map(|_| (0..features).map(|_| rng.normal()).collect()).collect()
+        }).collect();
+        let mut lsh = Lsh { mean, planes, buckets: vec![] };
+        lsh.buckets = (0..lsh.planes.len()).map(|t| {
+            let mut table: HashMap<u64, Vec<usize>> = HashMap::new();
+            for (i, it) in clf.items.iter().enumerate() {
+                table.entry(lsh.hash(t, &it.data)).or_default().push(i);
+            }
+            table
+        }).collect();
+        Some(lsh)
+    }
+    // one bit per hyperplane: on which side of it the point is (missing features count as the mean)
+    fn hash(&self, table: usize, data: &[f64]) -> u64 {
+        self.planes[table].iter().enumerate().fold(0, |h, (b, plane)| {
+            let dot: f64 = plane.iter().zip(data).zip(&self.mean)
+                .map(|((p, v), m)| if v.is_nan() { 0.0 } else { p * (v - m) })
+                .sum();
+            if dot >= 0.0 { h | 1 << b } else { h }
+        })
+    }
+}
+
+impl NeighborIndex for Lsh {
+    // approximately the n nearest items: the nearest among the items sharing a bucket with the query,
+    // None when there are less than n such items
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        if query.len() != self.mean.len() {
+            return None;
+        }
+        let mut candidates: HashSet<usize> = HashSet::new();
+        for (t, table) in self.buckets.iter().enumerate() {
+            instrument::count_node();
+            if let Some(ids) = table.get(&self.hash(t, query)) {
+                candidates.extend(ids);
+            }
+        }
+        if candidates.len() < n.min(clf.items.len()) {
+            return None;
+        }
+        instrument::count_pruned(clf.items.len() - candidates.len());
+        let query = clf.query_point(query);
+        let mut found = vec![];
+        for i in candidates {
+            insert_neighbor(&mut found, n, i, query.distance_to(i));
+        }
+        Some(found)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl Lsh {
+    // Function to read the tables written by `to_parts`, None when they are invalid
+    pub(crate) fn from_parts(r: &mut PartsReader, items: usize) -> Option<Lsh> {
+        let features = r.int()? as usize;
+        let tables = r.int()?;
+        let bits = r.int()?;
+        if tables == 0 || !(1..=64).contains(&bits) {
+            return None;
+        }
+        let mean: Vec<f64> = (0..features).map(|_| r.float()).collect::<Option<_>>()?;
+        let mut buckets = vec![];
+        for _ in 0..tables {
+            let mut table = HashMap::new();
+            for _ in 0..r.int()? {
+                table.insert(r.int()?, r.ids(items)?);
+            }
+            buckets.push(table);
+        }
+        let planes = (0..tables).map(|_| {
+            (0..bits).map(|_| (0..features).map(|_| r.float()).collect::<Option<Vec<f64>>>()).collect::<Option<Vec<_>>>()
+        }).collect::<Option<_>>()?;
+        Some(Lsh { mean, planes, buckets })
+    }
+}
+
+impl BuiltIndex for Lsh {
+    // [features, tables, bits, then the buckets of every table as (hash, ids), in hash order], and the mean and the planes
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts {
+        let bits = self.planes[0].len();
+        let mut parts = IndexParts { ints: vec![self.mean.len() as u64, self.planes.len() as u64, bits as u64], floats: self.mean.clone() };
+        for table in &self.buckets {
+            let mut buckets: Vec<_> = table.iter().collect();
+            buckets.sort_by_key(|(hash, _)| **hash);
+            parts.ints.push(buckets.len() as u64);
+            for (hash, ids) in buckets {
+                parts.ints.push(*hash);
+                parts.push_ids(ids);
+            }
+        }
+        parts.floats.extend(self.planes.iter().flatten().flatten());
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, LshParams};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_lsh() {
+        let mut c = KnnClassifier::new(5);
+        let mut rng = Rng(11u64);
+        let mut rand = || rng.uniform();
+        // 20 clusters in 64 dimensions, most features zero
+        let centers: Vec<Vec<f64>> = (0..20).map(|_| (0..64).map(|_| if rand() < 0.2 { rand() * 10.0 } else { 0.0 }).collect()).collect();
+        for i in 0..1000 {
+            let p: Vec<f64> = centers[i % 20].iter().map(|v| if *v != 0.0 { v + rand() } else { 0.0 }).collect();
+            c.fit_one(&p, &format!("c{}", i % 20));
+        }
+        let queries: Vec<Vec<f64>> = (0..40).map(|i| centers[i % 20].iter().map(|v| if *v != 0.0 { v + rand() } else { 0.0 }).collect()).collect();
+        let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 5)).collect();
+        c.backend = Backend::Lsh(LshParams::default());
+        let mut hits = 0;
+        for (i, (q, e)) in queries.iter().zip(&expected).enumerate() {
+            let found = c.nearest(q, 5);
+            hits += found.iter().filter(|f| e.contains(f)).count();
+            assert_eq!(c.predict_one(q), format!("c{}", i % 20));
+        }
+        assert!(hits as f64 / (40 * 5) as f64 > 0.8);
+        // long hashes make tiny buckets; too few candidates fall back to exact search
+        c.backend = Backend::Lsh(LshParams { tables: 1, bits: 64 });
+        assert_eq!(c.nearest(&[50.; 64], 3).len(), 3);
+        // so does asking for more candidates than the buckets hold
+        c.backend = Backend::Lsh(LshParams::default());
+        c.rerank = 1000;
+        for (q, e) in queries.iter().zip(&expected) {
+            assert_eq!(c.nearest(q, 5), *e);
+        }
+    }
+}
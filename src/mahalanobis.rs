@@ -0,0 +1,132 @@
+//! Mahalanobis distance: covariance estimation and matrix inversion.
+
+use crate::{calc_distance, KnnClassifier, KnnError, Metric};
+
+impl KnnClassifier {
+    /// Function to estimate the covariance of the features from the live items and store its inverse
+    ///
+    /// Called by every way of learning (`fit`, `fit_one`, `from_csv`, ...) when `metric` is `Metric::Mahalanobis`.
+    /// Call it after editing `items` directly or switching the metric. A tiny ridge is added to the diagonal, so constant or perfectly
+    /// correlated features do not make the matrix singular. Missing features (NaN) are skipped.
+    pub fn fit_covariance(&mut self) {
+        let dim = self.feature_ranges.len();
+        let mut mean = vec![0.0; dim];
+        let mut count = vec![0usize; dim];
+        for (_, it) in self.live_items() {
+            for (f, v) in it.data.iter().enumerate().filter(|(_, v)| !v.is_nan()) {
+                mean[f] += v;
+                count[f] += 1;
+            }
+        }
+        mean.iter_mut().zip(&count).for_each(|(m, c)| *m /= (*c).max(1) as f64);
+        let mut cov = vec![vec![0.0; dim]; dim];
+        let mut pairs = vec![vec![0usize; dim]; dim];
+        for (_, it) in self.live_items() {
+            for i in 0..dim.min(it.data.len()) {
+                for j in 0..dim.min(it.data.len()) {
+                    let (a, b) = (it.data[i], it.data[j]);
+                    if a.is_nan() || b.is_nan() { continue; }
+                    cov[i][j] += (a - mean[i]) * (b - mean[j]);
+                    pairs[i][j] += 1;
+                }
+            }
+        }
+        let trace: f64 = (0..dim).map(|i| cov[i][i] / pairs[i][i].max(1) as f64).sum();
+        let ridge = 1e-9 * (trace / dim.max(1) as f64).max(1.0);
+        for i in 0..dim {
+            for j in 0..dim {
+                cov[i][j] /= pairs[i][j].max(1) as f64;
+            }
+            cov[i][i] += ridge;
+        }
+        self.inverse_covariance = invert(&cov).unwrap_or_default();
+    }
+    /// Function to set the covariance matrix of the features (instead of estimating it)
+    pub fn set_covariance(&mut self, cov: &[Vec<f64>]) -> Result<(), KnnError> {
+        if cov.iter().any(|row| row.len() != cov.len()) {
+            return Err(KnnError::Invalid("covariance matrix should be square".to_string()));
+        }
+        self.inverse_covariance = invert(cov).ok_or_else(|| KnnError::Invalid("covariance matrix is singular".to_string()))?;
+        Ok(())
+    }
+    // Function to calculate Mahalanobis distance with `inverse_covariance`
+    // (Euclidean when no matching matrix is set, e.g. in a `FeatureView`)
+    pub(crate) fn mahalanobis_distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let inv = &self.inverse_covariance;
+        if inv.len() != a.len() || a.len() != b.len() {
+            return calc_distance(a, b);
+        }
+        let diff: Vec<f64> = a.iter().zip(b).map(|(x, y)| x - y).collect();
+        let mut sum = 0.0;
+        for (i, row) in inv.iter().enumerate() {
+            sum += diff[i] * row.iter().zip(&diff).map(|(m, d)| m * d).sum::<f64>();
+        }
+        sum.max(0.0).sqrt()
+    }
+    // true when `fit` should estimate the covariance
+    pub(crate) fn needs_covariance(&self) -> bool {
+        self.metric == Metric::Mahalanobis
+    }
+}
+
+// Gauss-Jordan elimination with partial pivoting, None when singular
+fn invert(m: &[Vec<f64>]) -> Option<Vec<Vec<f64>>> {
+    let n = m.len();
+    let mut a: Vec<Vec<f64>> = m.to_vec();
+    let mut inv: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+    let scale = m.iter().flatten().fold(0.0, |s: f64, v| s.max(v.abs()));
+    for col in 0..n {
+        let pivot = (col..n).max_by(|x, y| a[*x][col].abs().total_cmp(&a[*y][col].abs()))?;
+        if a[pivot][col].is_nan() || a[pivot][col].abs() <= scale * 1e-14 { return None; }
+        a.swap(col, pivot);
+        inv.swap(col, pivot);
+        let p = a[col][col];
+        a[col].iter_mut().for_each(|v| *v /= p);
+        inv[col].iter_mut().for_each(|v| *v /= p);
+        for row in 0..n {
+            if row == col { continue; }
+            let f = a[row][col];
+            if f == 0.0 { continue; }
+            for j in 0..n {
+                a[row][j] -= f * a[col][j];
+                inv[row][j] -= f * inv[col][j];
+            }
+        }
+    }
+    Some(inv)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mahalanobis() {
+        let inv = invert(&[vec![4., 0.], vec![0., 0.25]]).unwrap();
+        assert_eq!(inv, [vec![0.25, 0.], vec![0., 4.]]);
+        assert!(invert(&[vec![1., 2.], vec![2., 4.]]).is_none());
+        // feature 1 varies 10 times more than feature 0
+        let mut c = KnnClassifier::with_metric(1, Metric::Mahalanobis);
+        c.fit(&[&[0., 0.], &[1., 0.], &[0., 10.], &[1., 10.], &[5., 30.]], &["a", "a", "a", "b", "b"]);
+        assert_eq!(c.inverse_covariance.len(), 2);
+        c.set_covariance(&[vec![1., 0.], vec![0., 100.]]).unwrap();
+        assert!((c.mahalanobis_distance(&[0., 0.], &[3., 40.]) - 5.).abs() < 1e-12);
+        // (2, 4) is nearer to "a" in raw units, but nearer to "b" once feature 1 is scaled down
+        let mut e = KnnClassifier::new(1);
+        e.fit(&[&[0., 0.], &[3., 10.]], &["a", "b"]);
+        assert_eq!(e.predict_one(&[2., 4.]), "a");
+        let mut m = KnnClassifier::with_metric(1, Metric::Mahalanobis);
+        m.fit(&[&[0., 0.], &[3., 10.]], &["a", "b"]);
+        m.set_covariance(&[vec![1., 0.], vec![0., 100.]]).unwrap();
+        assert_eq!(m.predict_one(&[2., 4.]), "b");
+        assert!(m.set_covariance(&[vec![1., 1.], vec![1., 1.]]).is_err());
+        // a deleted outlier does not shift the covariance
+        let mut d = KnnClassifier::with_metric(1, Metric::Mahalanobis);
+        d.fit(&[&[0., 0.], &[1., 0.], &[0., 10.], &[100., -50.]], &["a", "a", "a", "b"]);
+        d.delete(3);
+        d.fit_covariance();
+        let mut live = KnnClassifier::with_metric(1, Metric::Mahalanobis);
+        live.fit(&[&[0., 0.], &[1., 0.], &[0., 10.]], &["a", "a", "a"]);
+        assert_eq!(d.inverse_covariance, live.inverse_covariance);
+    }
+}
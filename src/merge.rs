@@ -0,0 +1,45 @@
+//! Merging models for periodic retraining.
+
+use crate::KnnClassifier;
+
+impl KnnClassifier {
+    /// Function to merge a newly trained model, down-weighting the items learned before
+    ///
+    /// The weight of every existing item is multiplied by `weight` (e.g. 0.5), then the items of `other`
+    /// are appended with their own weights. Votes add up item weights, so repeated merges let old data
+    /// fade out without a full retrain. Items whose weight becomes 0 are removed.
+    /// Weights are kept in memory only; CSV and MessagePack files do not store them.
+    pub fn merge_weighted(&mut self, other: KnnClassifier, weight: f64) {
+        for it in self.items.iter_mut() {
+            it.weight *= weight;
+        }
+        if self.items.iter().any(|it| it.weight <= 0.0) {
+            self.items.retain(|it| it.weight > 0.0);
+            self.update_feature_ranges();
+        }
+        for it in other.items {
+            self.push_item(it);
+        }
+        self.fit_statistics();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merge_weighted() {
+        let mut old = KnnClassifier::new(3);
+        old.fit(&[&[0.], &[1.], &[2.]], &["a", "a", "b"]);
+        let mut new = KnnClassifier::new(3);
+        new.fit(&[&[1.5]], &["b"]);
+        old.merge_weighted(new, 0.4);
+        assert_eq!(old.items.len(), 4);
+        assert_eq!(old.items[0].weight, 0.4);
+        // b: 0.4 + 1.0 outweighs a: 0.4 + 0.4
+        assert_eq!(old.predict_one(&[1.]), "b");
+        old.merge_weighted(KnnClassifier::new(3), 0.0);
+        assert!(old.items.is_empty());
+    }
+}
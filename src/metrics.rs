@@ -0,0 +1,220 @@
+//! Evaluation reports: accuracy, per-class precision/recall/F1 and confusion matrix.
+
+use crate::{parse_csv_line, KnnClassifier, KnnError};
+use std::fmt;
+use std::path::Path;
+
+/// Scores of one class in a `Report`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassReport {
+    pub label: String,
+    pub precision: f64,
+    pub recall: f64,
+    pub f1: f64,
+    /// number of items with this true label
+    pub support: usize,
+    /// total weight of the items with this true label (`support` without weights)
+    pub weight: f64,
+}
+
+/// Result of evaluating a classifier on labeled data
+///
+/// With sample weights (see `Report::weighted`), every item counts its weight in the scores,
+/// so a test set sampled non-uniformly can be reweighted to match the production traffic.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// fraction of correct predictions
+    pub accuracy: f64,
+    /// mean F1 over the classes
+    pub macro_f1: f64,
+    /// scores per class, in the order of `labels`
+    pub classes: Vec<ClassReport>,
+    /// labels of the confusion matrix (true labels first, then labels only predicted)
+    pub labels: Vec<String>,
+    /// `confusion[true][predicted]`: number of items, indexed like `labels`
+    pub confusion: Vec<Vec<usize>>,
+    /// `weighted_confusion[true][predicted]`: total weight of the items (`confusion` without weights)
+    pub weighted_confusion: Vec<Vec<f64>>,
+}
+
+impl Report {
+    /// Function to make a report from true and predicted labels
+    pub fn new(actual: &[&str], predicted: &[&str]) -> Report {
+        Report::tally(actual, predicted, &vec![1.0; actual.len()])
+    }
+    /// Function to make a report from true and predicted labels with a (non-negative) weight per item
+    ///
+    /// Returns `KnnError::Invalid` when the numbers of labels and weights differ, or a weight is negative or not finite.
+    pub fn weighted(actual: &[&str], predicted: &[&str], weights: &[f64]) -> Result<Report, KnnError> {
+        if actual.len() != predicted.len() || actual.len() != weights.len() {
+            return Err(KnnError::Invalid(format!("{} true labels, {} predicted labels and {} weights", actual.len(), predicted.len(), weights.len())));
+        }
+        if let Some(w) = weights.iter().find(|w| !w.is_finite() || **w < 0.0) {
+            return Err(KnnError::Invalid(format!("sample weight should be a non-negative number: {}", w)));
+        }
+        Ok(Report::tally(actual, predicted, weights))
+    }
+    fn tally(actual: &[&str], predicted: &[&str], weights: &[f64]) -> Report {
+        let mut labels: Vec<String> = vec![];
+        for l in actual.iter().chain(predicted.iter()) {
+            if !labels.iter().any(|x| x == l) { labels.push(l.to_string()); }
+        }
+        let index = |l: &str| labels.iter().position(|x| x == l).unwrap();
+        let mut confusion = vec![vec![0; labels.len()]; labels.len()];
+        let mut weighted_confusion = vec![vec![0.0; labels.len()]; labels.len()];
+        for ((a, p), w) in actual.iter().zip(predicted.iter()).zip(weights) {
+            confusion[index(a)][index(p)] += 1;
+            weighted_confusion[index(a)][index(p)] += w;
+        }
+        let ratio = |a: f64, b: f64| if b > 0.0 { a / b } else { 0.0 };
+        let classes: Vec<ClassReport> = labels.iter().enumerate().map(|(i, label)| {
+            let tp = weighted_confusion[i][i];
+            let weight: f64 = weighted_confusion[i].iter().sum();
+            let predicted: f64 = weighted_confusion.iter().map(|row| row[i]).sum();
+            let (precision, recall) = (ratio(tp, predicted), ratio(tp, weight));
+            let f1 = if precision + recall > 0.0 { 2.0 * precision * recall / (precision + recall) } else { 0.0 };
+            ClassReport { label: label.clone(), precision, recall, f1, support: confusion[i].iter().sum(), weight }
+        }).collect();
+        let correct: f64 = (0..labels.len()).map(|i| weighted_confusion[i][i]).sum();
+        let total: f64 = weighted_confusion.iter().flatten().sum();
+        Report {
+            accuracy: ratio(correct, total),
+            macro_f1: if classes.is_empty() { 0.0 } else { classes.iter().map(|c| c.f1).sum::<f64>() / classes.len() as f64 },
+            classes,
+            labels,
+            confusion,
+            weighted_confusion,
+        }
+    }
+}
+
+// text table like scikit-learn's classification_report, followed by the confusion matrix
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self.labels.iter().map(|l| l.chars().count()).max().unwrap_or(0).max(8);
+        writeln!(f, "{:>width$} {:>9} {:>9} {:>9} {:>9}", "", "precision", "recall", "f1", "support")?;
+        for c in &self.classes {
+            writeln!(f, "{:>width$} {:>9.3} {:>9.3} {:>9.3} {:>9}", c.label, c.precision, c.recall, c.f1, c.support)?;
+        }
+        writeln!(f, "{:>width$} {:>9.3}", "accuracy", self.accuracy)?;
+        writeln!(f, "{:>width$} {:>9.3}", "macro f1", self.macro_f1)?;
+        writeln!(f)?;
+        writeln!(f, "confusion matrix (rows: true, columns: predicted)")?;
+        for (label, row) in self.labels.iter().zip(&self.confusion) {
+            write!(f, "{:>width$}", label)?;
+            for n in row { write!(f, " {:>6}", n)?; }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Function to calculate the fraction of items whose true label is among their ranked predictions
+///
+/// `predictions[i]` holds the suggestions for item i (e.g. the labels of `predict_top_n`);
+/// pass the first k of them to measure the top-k accuracy.
+pub fn top_k_accuracy<S: AsRef<str>>(actual: &[&str], predictions: &[Vec<S>]) -> f64 {
+    let n = actual.len().min(predictions.len());
+    if n == 0 {
+        return 0.0;
+    }
+    let hits = actual.iter().zip(predictions).filter(|(a, p)| p.iter().any(|l| l.as_ref() == **a)).count();
+    hits as f64 / n as f64
+}
+
+impl KnnClassifier {
+    /// Function to measure the top-k accuracy: the fraction of items whose true label is among the first k of `predict_top_n`
+    pub fn evaluate_top_k(&self, items: &[Vec<f64>], labels: &[&str], k: usize) -> f64 {
+        let predictions = self.map_queries(items, |it| {
+            self.predict_top_n(it, k).into_iter().map(|(l, _)| l).collect::<Vec<String>>()
+        });
+        top_k_accuracy(labels, &predictions)
+    }
+    /// Function to predict the items and compare with their true labels
+    pub fn evaluate(&self, items: &[Vec<f64>], labels: &[&str]) -> Report {
+        let predicted = self.predict(items);
+        Report::new(labels, &predicted.iter().map(|s| s.as_str()).collect::<Vec<_>>())
+    }
+    /// Function to predict the items and compare with their true labels, weighting each item (see `Report::weighted`)
+    pub fn evaluate_weighted(&self, items: &[Vec<f64>], labels: &[&str], weights: &[f64]) -> Result<Report, KnnError> {
+        if items.len() != labels.len() {
+            return Err(KnnError::Invalid(format!("{} items but {} labels", items.len(), labels.len())));
+        }
+        let predicted = self.predict(items);
+        Report::weighted(labels, &predicted.iter().map(|s| s.as_str()).collect::<Vec<_>>(), weights)
+    }
+    /// Function to evaluate on labeled CSV text (same layout as `from_csv`)
+    pub fn evaluate_csv(&self, s: &str, delimiter: char, label_col: usize, skip_header: bool) -> Result<Report, KnnError> {
+        let mut items = vec![];
+        let mut labels = vec![];
+        for (i, line) in s.lines().enumerate() {
+            if skip_header && i == 0 { continue; }
+            let parsed = parse_csv_line(line, delimiter, label_col).map_err(|msg| KnnError::Parse { line: i + 1, msg })?;
+            if let Some(it) = parsed {
+                items.push(it.data);
+                labels.push(it.label);
+            }
+        }
+        Ok(self.evaluate(&items, &labels.iter().map(|s| s.as_str()).collect::<Vec<_>>()))
+    }
+    /// Function to evaluate on a labeled CSV file
+    pub fn evaluate_csv_file(&self, path: impl AsRef<Path>, delimiter: char, label_col: usize, skip_header: bool) -> Result<Report, KnnError> {
+        let s = std::fs::read_to_string(path).map_err(|e| KnnError::Io(e.to_string()))?;
+        self.evaluate_csv(&s, delimiter, label_col, skip_header)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_evaluate_csv() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[10.], &[20.]], &["a", "b", "c"]);
+        let r = c.evaluate_csv("label,x\na,1\na,9\nb,11\nc,19\nd,21\n", ',', 0, true).unwrap();
+        assert_eq!(r.accuracy, 0.6);
+        assert_eq!(r.labels, ["a", "b", "c", "d"]);
+        assert_eq!(r.confusion, [vec![1, 1, 0, 0], vec![0, 1, 0, 0], vec![0, 0, 1, 0], vec![0, 0, 1, 0]]);
+        assert_eq!(r.classes[1], ClassReport { label: "b".to_string(), precision: 0.5, recall: 1.0, f1: 2. / 3., support: 1, weight: 1.0 });
+        assert_eq!(r.classes[3].f1, 0.0);
+        assert!(r.to_string().contains("accuracy     0.600"));
+        assert_eq!(c.evaluate_csv("a,x\n", ',', 0, false), Err(KnnError::Parse { line: 1, msg: "invalid number: x".to_string() }));
+    }
+    #[test]
+    fn test_top_k_accuracy() {
+        assert_eq!(top_k_accuracy(&["a", "b", "c", "d"], &[vec!["a", "b"], vec!["a", "b"], vec!["a", "b"], vec![]]), 0.5);
+        assert_eq!(top_k_accuracy::<&str>(&[], &[]), 0.0);
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.]], &["a", "b", "b", "c"]);
+        let items = [vec![0.], vec![0.5], vec![10.]];
+        assert_eq!(c.evaluate_top_k(&items, &["a", "a", "a"], 1), 0.0);
+        assert_eq!(c.evaluate_top_k(&items, &["a", "a", "a"], 2), 2. / 3.);
+    }
+    #[test]
+    fn test_weighted_report() {
+        // the "b" items are undersampled 4:1
+        let r = Report::weighted(&["a", "a", "b", "b"], &["a", "b", "b", "a"], &[1.0, 1.0, 4.0, 4.0]).unwrap();
+        assert_eq!(r.accuracy, 0.5);
+        assert_eq!(r.confusion, [vec![1, 1], vec![1, 1]]);
+        assert_eq!(r.weighted_confusion, [vec![1.0, 1.0], vec![4.0, 4.0]]);
+        assert_eq!(r.classes[0].precision, 0.2);
+        assert_eq!((r.classes[1].support, r.classes[1].weight), (2, 8.0));
+        assert_eq!(r.classes[1].precision, 0.8);
+        assert_eq!(Report::weighted(&["a", "b"], &["a", "a"], &[1.0, 3.0]).unwrap().accuracy, 0.25);
+        assert_eq!(Report::weighted(&["a", "b"], &["a", "a"], &[1.0, 1.0]), Ok(Report::new(&["a", "b"], &["a", "a"])));
+    }
+    #[test]
+    fn test_weighted_report_invalid() {
+        // every sample needs a weight and a prediction
+        assert!(matches!(Report::weighted(&["a", "b"], &["a", "a"], &[1.0]), Err(KnnError::Invalid(_))));
+        assert!(matches!(Report::weighted(&["a", "b"], &["a"], &[1.0, 1.0]), Err(KnnError::Invalid(_))));
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.]], &["a"]);
+        assert!(matches!(c.evaluate_weighted(&[vec![0.]], &["a", "a"], &[1.0, 1.0]), Err(KnnError::Invalid(_))));
+        // weights are non-negative numbers
+        for w in [-1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(Report::weighted(&["a", "b"], &["a", "a"], &[1.0, w]), Err(KnnError::Invalid(_))), "{}", w);
+        }
+    }
+}
@@ -0,0 +1,753 @@
+//! MessagePack serialization of the classifier (feature `msgpack`).
+//!
+//! The model is stored as a map, which can be read by any MessagePack library:
+//!
+//! ```text
+//! { "k": 5, "items": [ ["label", [1.0, 2.0, ...]], ... ], "checksum": 1234..., "config": { "version": 1, ... },
+//!   "index": { "version": 1, ... } }
+//! ```
+//!
+//! `checksum` is the 64-bit FNV-1a hash of `KnnClassifier::checksum` (8 bytes, little endian) followed by
+//! the encoded `config` and `index` maps, so it covers the items, the settings and the index. Without them (or with
+//! a version 1 `config`, which was not hashed) it is `KnnClassifier::checksum` alone. It is verified on load, so a corrupted
+//! or modified file fails with `KnnError::Checksum`. Writers in other languages may omit it.
+//!
+//! `config` holds the distance and vote settings:
+//!
+//! ```text
+//! { "version": 3, "metric": "minkowski", "p": 1.5, "feature_weights": [1.0, ...],
+//!   "voting": "gaussian", "bandwidth": 2.0, "preprocess": [ ["minmax", [mins], [maxs]], ... ],
+//!   "standardize": true, "scaler": [[means], [stds]] or nil, "projection": [seed, features, components] or nil,
+//!   "reject": { "min_confidence": 0.6, "max_distance": 2.5 or nil, "label": "unknown" or nil },
+//!   "tie_break": "lexicographic", "coincident": "first", "fallback": "unknown" or nil, "smoothing": 1.0,
+//!   "source_weights": { "survey": 0.5, ... }, "label_aliases": { "fat": "obese", ... },
+//!   "class_quotas": { "a": 100, ... }, "eviction": "lightest" }
+//! ```
+//!
+//! Items with a weight other than 1.0, a source or a timestamp are written as `[label, data, weight, source or nil, timestamp or nil]`
+//! (see `KnnItem`); `[label, data]` items load with the defaults.
+//!
+//! `p` is only written for `minkowski` and `bandwidth` only for `gaussian` and `epanechnikov`.
+//! `custom` voting is written but refused on load, since the strategy given to `set_vote_strategy` is not stored.
+//! The keys from `tie_break` on were added in version 3; older files load with their defaults.
+//! The items are stored projected; `projection` is drawn again from its seed, so queries with the original features still work.
+//! Preprocessing steps are `["standard", [means], [stds]]`, `["minmax", [mins], [maxs]]`,
+//! `["robust", [medians], [iqrs]]` and `["pca", [means], [[component], ...], [variance], [ratio]]`.
+//! Files without `config` (written before it existed, or by other writers) load with the default settings.
+//!
+//! `index` is only written for the backends that build an index (all but brute force and a custom index):
+//!
+//! ```text
+//! { "version": 1, "backend": "hnsw", "params": [16, 100, 50], "rerank": 1, "items": 1000, "settings": 1234...,
+//!   "data": [[integers], [floats]] or nil }
+//! ```
+//!
+//! `backend` is `kdtree`, `balltree`, `vptree`, `hnsw` (params `[m, ef_construction, ef_search]`) or `lsh`
+//! (params `[tables, bits]`); the trees have no params. `rerank` is `KnnClassifier::rerank` (1 when missing). `items` is the number of items and `settings` a hash of
+//! the distance settings the index was built with. `data` is the flattened index, nil when the items can not be
+//! indexed or some are deleted. The backend is restored on load, and the saved index is used as it is (no rebuild)
+//! when it was built over the same items with the same settings. Otherwise, and when the `index` version is newer
+//! than this library reads or `data` is invalid, the index is built again on the first query.
+//!
+//! For example, in Python: `msgpack.unpackb(data)["items"][0]` is `["label", [1.0, 2.0]]`.
+//!
+//! The values are encoded and decoded with rmp-serde. The top-level map is walked with rmp, so the checksum
+//! covers the `config` and `index` maps exactly as they are stored, also in files written by other libraries.
+
+use crate::index::{index_from_parts, IndexParts};
+use crate::{Backend, CoincidentVote, Eviction, Fnv64, HnswParams, KnnClassifier, KnnError, KnnItem, LshParams, Metric, MinMaxScaler, Pca, Preprocess, RandomProjection, Reject, RobustScaler, StandardScaler, TieBreak, Vote};
+use serde::de::{DeserializeOwned, IgnoredAny, SeqAccess, Visitor};
+use serde::ser::{SerializeSeq, Serializer};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Cursor;
+use std::ops::Range;
+
+// version of the `config` map written by `to_msgpack`
+const CONFIG_VERSION: u64 = 3;
+// version of the `index` map (the layout of `data`) written by `to_msgpack`
+const INDEX_VERSION: u64 = 1;
+// largest random matrix (features * components of a projection, or LSH hyperplanes) a file may ask for
+const MAX_PROJECTION_SIZE: usize = 1 << 28;
+// deepest nesting a value may have (a file written by `to_msgpack` needs 4), so broken input can not exhaust the stack
+const MAX_DEPTH: usize = 16;
+
+// the file written by `to_msgpack`
+#[derive(Serialize)]
+struct SavedModel<'a> {
+    k: usize,
+    items: SavedItems<'a>,
+    checksum: u64,
+    config: &'a SavedConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    index: Option<&'a SavedIndex<IndexData>>,
+}
+
+// the live items of a classifier, written as `[label, data]` or `[label, data, weight, source, timestamp]`
+struct SavedItems<'a>(&'a KnnClassifier);
+
+impl Serialize for SavedItems<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.live_items().count()))?;
+        for (_, it) in self.0.live_items() {
+            if it.weight != 1.0 || it.source.is_some() || it.timestamp.is_some() {
+                seq.serialize_element(&(&it.label, &it.data, it.weight, &it.source, it.timestamp))?;
+            } else {
+                seq.serialize_element(&(&it.label, &it.data))?;
+            }
+        }
+        seq.end()
+    }
+}
+
+// an item read from a file
+struct SavedItem(KnnItem);
+
+impl<'de> Deserialize<'de> for SavedItem {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ItemVisitor;
+        impl<'de> Visitor<'de> for ItemVisitor {
+            type Value = SavedItem;
+            fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str("item [label, data] or [label, data, weight, source, timestamp]")
+            }
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<SavedItem, A::Error> {
+                let missing = |n| serde::de::Error::invalid_length(n, &self);
+                let label = seq.next_element()?.ok_or_else(|| missing(0))?;
+                let data = seq.next_element()?.ok_or_else(|| missing(1))?;
+                let mut it = KnnItem { label, data, ..KnnItem::new("", &[]) };
+                if let Some(weight) = seq.next_element()? {
+                    it.weight = weight;
+                    it.source = seq.next_element()?.ok_or_else(|| missing(3))?;
+                    it.timestamp = seq.next_element()?.ok_or_else(|| missing(4))?;
+                }
+                Ok(SavedItem(it))
+            }
+        }
+        deserializer.deserialize_seq(ItemVisitor)
+    }
+}
+
+// the `config` map; the settings missing in older files keep their defaults
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedConfig {
+    #[serde(default = "first_version")]
+    version: u64,
+    metric: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    p: Option<f64>,
+    #[serde(default)]
+    feature_weights: Vec<f64>,
+    voting: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bandwidth: Option<f64>,
+    #[serde(default)]
+    preprocess: Vec<SavedStep>,
+    #[serde(default)]
+    standardize: bool,
+    // [means, stds]
+    scaler: Option<(Vec<f64>, Vec<f64>)>,
+    // [seed, features, components]
+    projection: Option<(u64, usize, usize)>,
+    #[serde(default)]
+    reject: SavedReject,
+    tie_break: Option<String>,
+    coincident: Option<String>,
+    fallback: Option<String>,
+    #[serde(default)]
+    smoothing: f64,
+    // sorted maps, so equal settings are written as equal bytes (the checksum and the index settings hash them)
+    #[serde(default)]
+    source_weights: BTreeMap<String, f64>,
+    #[serde(default)]
+    label_aliases: BTreeMap<String, String>,
+    #[serde(default)]
+    class_quotas: BTreeMap<String, usize>,
+    eviction: Option<String>,
+}
+
+// only the version of the `config` map, read before the rest so a newer file fails with a clear message
+#[derive(Deserialize)]
+struct ConfigVersion {
+    #[serde(default = "first_version")]
+    version: u64,
+}
+
+fn first_version() -> u64 {
+    1
+}
+
+// a preprocessing step: `[name, a, b]` for the scalers, `[name, means, components, variance, ratio]` for PCA
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum SavedStep {
+    Scaler(String, Vec<f64>, Vec<f64>),
+    Pca(String, Vec<f64>, Vec<Vec<f64>>, Vec<f64>, Vec<f64>),
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct SavedReject {
+    min_confidence: f64,
+    max_distance: Option<f64>,
+    label: Option<String>,
+}
+
+// the `index` map; `data` is read as `IgnoredAny` until its version is known
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SavedIndex<D> {
+    version: Option<u64>,
+    backend: String,
+    #[serde(default)]
+    params: Vec<usize>,
+    #[serde(default = "first_version")]
+    rerank: u64,
+    items: Option<usize>,
+    settings: Option<u64>,
+    data: Option<D>,
+}
+
+// `data` of the `index` map: [integers, floats]
+type IndexData = (Vec<u64>, Vec<f64>);
+
+impl KnnClassifier {
+    /// convert to MessagePack bytes (deleted items are left out)
+    pub fn to_msgpack(&self) -> Vec<u8> {
+        let config = self.saved_config();
+        let config_bytes = encode(&config);
+        let index = self.saved_index(&config_bytes);
+        let index_bytes = index.as_ref().map_or(vec![], encode);
+        encode(&SavedModel {
+            k: self.k,
+            items: SavedItems(self),
+            checksum: file_checksum(self.checksum(), &[&config_bytes, &index_bytes]),
+            config: &config,
+            index: index.as_ref(),
+        })
+    }
+    // Function to describe the backend and its built index as the `index` map, None for brute force and a custom index
+    fn saved_index(&self, config: &[u8]) -> Option<SavedIndex<IndexData>> {
+        let (backend, params) = match self.backend {
+            Backend::KdTree => ("kdtree", vec![]),
+            Backend::BallTree => ("balltree", vec![]),
+            Backend::VpTree => ("vptree", vec![]),
+            Backend::Hnsw(p) => ("hnsw", vec![p.m, p.ef_construction, p.ef_search]),
+            Backend::Lsh(p) => ("lsh", vec![p.tables, p.bits]),
+            Backend::BruteForce | Backend::Custom => return None,
+        };
+        Some(SavedIndex {
+            version: Some(INDEX_VERSION),
+            backend: backend.to_string(),
+            params,
+            rerank: self.rerank as u64,
+            items: Some(self.items.len()),
+            settings: Some(self.index_settings(config)),
+            // the index numbers the items including the deleted ones, which are not saved
+            data: self.index_parts().filter(|_| self.live_items().count() == self.items.len()).map(|parts| (parts.ints, parts.floats)),
+        })
+    }
+    // Function to hash the settings an index is built with: the `config` map and the inverse covariance
+    fn index_settings(&self, config: &[u8]) -> u64 {
+        let mut h = Fnv64::new();
+        h.write(config);
+        for v in self.inverse_covariance.iter().flatten() {
+            h.write(&v.to_bits().to_le_bytes());
+        }
+        h.finish()
+    }
+    // Function to describe the settings as the `config` map
+    fn saved_config(&self) -> SavedConfig {
+        let (metric, p) = match self.metric {
+            Metric::Euclidean => ("euclidean", None),
+            Metric::Manhattan => ("manhattan", None),
+            Metric::Minkowski(p) => ("minkowski", Some(p)),
+            Metric::Chebyshev => ("chebyshev", None),
+            Metric::Cosine => ("cosine", None),
+            Metric::Mahalanobis => ("mahalanobis", None),
+            Metric::Haversine => ("haversine", None),
+            Metric::Canberra => ("canberra", None),
+        };
+        let (voting, bandwidth) = match self.voting {
+            Vote::Uniform => ("uniform", None),
+            Vote::Distance => ("distance", None),
+            Vote::Gaussian { bandwidth } => ("gaussian", Some(bandwidth)),
+            Vote::Epanechnikov { bandwidth } => ("epanechnikov", Some(bandwidth)),
+            Vote::Dudani => ("dudani", None),
+            Vote::Rank => ("rank", None),
+            Vote::Custom => ("custom", None),
+        };
+        let preprocess = self.preprocess.iter().map(|step| match step {
+            Preprocess::Standard(s) => SavedStep::Scaler("standard".to_string(), s.means.clone(), s.stds.clone()),
+            Preprocess::MinMax(s) => SavedStep::Scaler("minmax".to_string(), s.mins.clone(), s.maxs.clone()),
+            Preprocess::Robust(s) => SavedStep::Scaler("robust".to_string(), s.medians.clone(), s.iqrs.clone()),
+            Preprocess::Pca(p) => SavedStep::Pca("pca".to_string(), p.means.clone(), p.components.clone(),
+                p.explained_variance.clone(), p.explained_variance_ratio.clone()),
+        }).collect();
+        SavedConfig {
+            version: CONFIG_VERSION,
+            metric: Some(metric.to_string()),
+            p,
+            feature_weights: self.feature_weights.clone(),
+            voting: Some(voting.to_string()),
+            bandwidth,
+            preprocess,
+            standardize: self.standardize,
+            scaler: self.scaler.as_ref().map(|s| (s.means.clone(), s.stds.clone())),
+            projection: self.projection.as_ref().map(|proj| (proj.seed, proj.features, proj.matrix.len())),
+            reject: SavedReject { min_confidence: self.reject.min_confidence, max_distance: self.reject.max_distance, label: self.reject.label.clone() },
+            tie_break: Some(match self.tie_break {
+                TieBreak::Nearest => "nearest",
+                TieBreak::Lexicographic => "lexicographic",
+            }.to_string()),
+            coincident: Some(match self.coincident {
+                CoincidentVote::Majority => "majority",
+                CoincidentVote::First => "first",
+                CoincidentVote::Neighbors => "neighbors",
+            }.to_string()),
+            fallback: self.fallback.clone(),
+            smoothing: self.smoothing,
+            source_weights: self.source_weights.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            label_aliases: self.label_aliases.iter().map(|(k, v)| (k.clone(), v.clone())).collect(),
+            class_quotas: self.class_quotas.iter().map(|(k, v)| (k.clone(), *v)).collect(),
+            eviction: Some(match self.eviction {
+                Eviction::Oldest => "oldest",
+                Eviction::Lightest => "lightest",
+                Eviction::Newest => "newest",
+            }.to_string()),
+        }
+    }
+    /// convert from MessagePack bytes
+    pub fn from_msgpack(bytes: &[u8]) -> Result<KnnClassifier, KnnError> {
+        let mut cur = Cursor::new(bytes);
+        let mut clf = KnnClassifier::new(0);
+        let (mut k, mut checksum) = (None, None);
+        // byte ranges of the `config` and `index` maps, which the checksum covers and which are read after the items
+        let (mut config, mut index) = (None, None);
+        let len = rmp::decode::read_map_len(&mut cur).map_err(|_| decode_err("expected map"))?;
+        for _ in 0..len {
+            let key: String = decode(&mut cur)?;
+            match key.as_str() {
+                "k" => k = Some(decode::<u64>(&mut cur)? as usize),
+                "checksum" => checksum = Some(decode::<u64>(&mut cur)?),
+                "items" => {
+                    for SavedItem(it) in decode::<Vec<SavedItem>>(&mut cur)? {
+                        clf.push_item(it);
+                    }
+                },
+                "config" => config = Some(skip(&mut cur)?),
+                "index" => index = Some(skip(&mut cur)?),
+                key => return Err(decode_err(&format!("unknown key: {}", key))),
+            }
+        }
+        clf.k = k.ok_or_else(|| decode_err("missing key: k"))?;
+        if clf.k == 0 {
+            return Err(decode_err("k should be at least 1"));
+        }
+        let config_version = match &config {
+            Some(span) => Some(clf.read_config(&bytes[span.clone()])?),
+            None => None,
+        };
+        let saved = match &index {
+            Some(span) => read_index(&bytes[span.clone()])?,
+            None => None,
+        };
+        if let Some(expected) = checksum {
+            let actual = match config_version {
+                Some(version) if version < 2 => clf.checksum(),
+                _ if config.is_none() && index.is_none() => clf.checksum(),
+                _ => {
+                    let config = config.map_or(&[][..], |span| &bytes[span]);
+                    let index = index.map_or(&[][..], |span| &bytes[span]);
+                    file_checksum(clf.checksum(), &[config, index])
+                },
+            };
+            if expected != actual {
+                return Err(KnnError::Checksum { expected, actual });
+            }
+        }
+        if clf.needs_covariance() {
+            clf.fit_covariance();
+        }
+        if clf.standardize && clf.scaler.is_none() {
+            clf.fit_scaler();
+        }
+        if let Some(saved) = saved {
+            if let Backend::Lsh(p) = saved.backend {
+                let features = clf.items.first().map_or(0, |it| it.data.len());
+                if p.tables.saturating_mul(p.bits.clamp(1, 64)).saturating_mul(features) > MAX_PROJECTION_SIZE {
+                    return Err(decode_err("too many LSH hyperplanes"));
+                }
+            }
+            clf.backend = saved.backend;
+            clf.rerank = saved.rerank;
+            // warm start with the saved index, when it fits the loaded items and settings
+            if let Some(parts) = saved.parts {
+                let config = encode(&clf.saved_config());
+                if saved.items == clf.items.len() && saved.settings == clf.index_settings(&config) {
+                    if let Some(built) = index_from_parts(saved.backend, &parts, clf.items.len()) {
+                        clf.index.store(&clf, Some(built));
+                    }
+                }
+            }
+        }
+        Ok(clf)
+    }
+    // Function to read the `config` map into the settings, returns its version
+    fn read_config(&mut self, bytes: &[u8]) -> Result<u64, KnnError> {
+        let ConfigVersion { version } = decode(&mut Cursor::new(bytes))?;
+        if version > CONFIG_VERSION {
+            return Err(decode_err(&format!("unsupported config version: {}", version)));
+        }
+        let config: SavedConfig = decode(&mut Cursor::new(bytes))?;
+        let param = |v: Option<f64>, name: &str| v.ok_or_else(|| decode_err(&format!("missing key: config.{}", name)));
+        if let Some(metric) = config.metric {
+            self.metric = match metric.as_str() {
+                "euclidean" => Metric::Euclidean,
+                "manhattan" => Metric::Manhattan,
+                "minkowski" => Metric::Minkowski(param(config.p, "p")?),
+                "chebyshev" => Metric::Chebyshev,
+                "cosine" => Metric::Cosine,
+                "mahalanobis" => Metric::Mahalanobis,
+                "haversine" => Metric::Haversine,
+                "canberra" => Metric::Canberra,
+                m => return Err(decode_err(&format!("unknown metric: {}", m))),
+            };
+        }
+        if let Some(voting) = config.voting {
+            self.voting = match voting.as_str() {
+                "uniform" => Vote::Uniform,
+                "distance" => Vote::Distance,
+                "gaussian" => Vote::Gaussian { bandwidth: param(config.bandwidth, "bandwidth")? },
+                "epanechnikov" => Vote::Epanechnikov { bandwidth: param(config.bandwidth, "bandwidth")? },
+                "dudani" => Vote::Dudani,
+                "rank" => Vote::Rank,
+                // the strategy is not stored: loading it as uniform would change the predictions
+                "custom" => return Err(decode_err("custom voting can not be loaded: the vote strategy is not stored")),
+                v => return Err(decode_err(&format!("unknown voting: {}", v))),
+            };
+        }
+        self.feature_weights = config.feature_weights;
+        self.preprocess = config.preprocess.into_iter().map(|step| Ok(match step {
+            SavedStep::Scaler(name, a, b) => match name.as_str() {
+                "standard" => StandardScaler { means: a, stds: b }.into(),
+                "minmax" => MinMaxScaler { mins: a, maxs: b }.into(),
+                "robust" => RobustScaler { medians: a, iqrs: b }.into(),
+                name => return Err(decode_err(&format!("unknown preprocess step: {}", name))),
+            },
+            SavedStep::Pca(name, means, components, explained_variance, explained_variance_ratio) => match name.as_str() {
+                "pca" => Pca { means, components, explained_variance, explained_variance_ratio }.into(),
+                name => return Err(decode_err(&format!("unknown preprocess step: {}", name))),
+            },
+        })).collect::<Result<Vec<Preprocess>, KnnError>>()?;
+        self.standardize = config.standardize;
+        self.scaler = config.scaler.map(|(means, stds)| StandardScaler { means, stds });
+        self.projection = match config.projection {
+            None => None,
+            // the matrix is drawn again: bound its size before allocating it
+            Some((_, features, components)) if components == 0 || components >= features || features.saturating_mul(components) > MAX_PROJECTION_SIZE => {
+                return Err(decode_err("projection should reduce the features"));
+            },
+            Some((seed, features, components)) => Some(RandomProjection::new(features, components, seed)),
+        };
+        self.reject = Reject { min_confidence: config.reject.min_confidence, max_distance: config.reject.max_distance, label: config.reject.label };
+        if let Some(tie_break) = config.tie_break {
+            self.tie_break = match tie_break.as_str() {
+                "nearest" => TieBreak::Nearest,
+                "lexicographic" => TieBreak::Lexicographic,
+                t => return Err(decode_err(&format!("unknown tie_break: {}", t))),
+            };
+        }
+        if let Some(coincident) = config.coincident {
+            self.coincident = match coincident.as_str() {
+                "majority" => CoincidentVote::Majority,
+                "first" => CoincidentVote::First,
+                "neighbors" => CoincidentVote::Neighbors,
+                c => return Err(decode_err(&format!("unknown coincident: {}", c))),
+            };
+        }
+        self.fallback = config.fallback;
+        self.smoothing = config.smoothing;
+        self.source_weights = config.source_weights.into_iter().collect();
+        self.label_aliases = config.label_aliases.into_iter().collect();
+        self.class_quotas = config.class_quotas.into_iter().collect();
+        if let Some(eviction) = config.eviction {
+            self.eviction = match eviction.as_str() {
+                "oldest" => Eviction::Oldest,
+                "lightest" => Eviction::Lightest,
+                "newest" => Eviction::Newest,
+                e => return Err(decode_err(&format!("unknown eviction: {}", e))),
+            };
+        }
+        Ok(version)
+    }
+}
+
+// checksum stored in the file: the model checksum extended with the bytes of the `config` and `index` maps
+fn file_checksum(model: u64, maps: &[&[u8]]) -> u64 {
+    let mut h = Fnv64::new();
+    h.write(&model.to_le_bytes());
+    for map in maps {
+        h.write(map);
+    }
+    h.finish()
+}
+
+// the `index` map of a file (see `read_index`)
+struct LoadedIndex {
+    backend: Backend,
+    rerank: usize,
+    // number of items and hash of the settings the index was built for
+    items: usize,
+    settings: u64,
+    // None when the index is built again on the first query
+    parts: Option<IndexParts>,
+}
+
+// Function to read the `index` map (None for a backend this library does not know)
+fn read_index(bytes: &[u8]) -> Result<Option<LoadedIndex>, KnnError> {
+    let saved: SavedIndex<IgnoredAny> = decode(&mut Cursor::new(bytes))?;
+    let backend = match (saved.backend.as_str(), saved.params.as_slice()) {
+        ("kdtree", []) => Backend::KdTree,
+        ("balltree", []) => Backend::BallTree,
+        ("vptree", []) => Backend::VpTree,
+        ("hnsw", [m, ef_construction, ef_search]) => Backend::Hnsw(HnswParams { m: *m, ef_construction: *ef_construction, ef_search: *ef_search }),
+        ("lsh", [tables, bits]) => Backend::Lsh(LshParams { tables: *tables, bits: *bits }),
+        ("kdtree" | "balltree" | "vptree" | "hnsw" | "lsh", _) => return Err(decode_err(&format!("invalid params of index backend {}", saved.backend))),
+        _ => return Ok(None),
+    };
+    let rerank = saved.rerank as usize;
+    // without the version, the settings or the data, or with a newer layout, the index is built again
+    let (Some(version), Some(items), Some(settings), Some(_)) = (saved.version, saved.items, saved.settings, saved.data) else {
+        return Ok(Some(LoadedIndex { backend, rerank, items: 0, settings: 0, parts: None }));
+    };
+    if version > INDEX_VERSION {
+        return Ok(Some(LoadedIndex { backend, rerank, items, settings, parts: None }));
+    }
+    let saved: SavedIndex<IndexData> = decode(&mut Cursor::new(bytes))?;
+    let parts = saved.data.map(|(ints, floats)| IndexParts { ints, floats });
+    Ok(Some(LoadedIndex { backend, rerank, items, settings, parts }))
+}
+
+fn decode_err(msg: &str) -> KnnError {
+    KnnError::Decode(msg.to_string())
+}
+
+// Function to encode a value (maps for the structs); writing to memory can not fail
+fn encode<T: Serialize>(value: &T) -> Vec<u8> {
+    rmp_serde::to_vec_named(value).expect("MessagePack encoding of the model")
+}
+
+// Function to decode the next value
+fn decode<T: DeserializeOwned>(cur: &mut Cursor<&[u8]>) -> Result<T, KnnError> {
+    let mut de = rmp_serde::Deserializer::new(cur);
+    de.set_max_depth(MAX_DEPTH);
+    T::deserialize(&mut de).map_err(|e| decode_err(&e.to_string()))
+}
+
+// Function to skip the next value, returns its byte range
+fn skip(cur: &mut Cursor<&[u8]>) -> Result<Range<usize>, KnnError> {
+    let start = cur.position() as usize;
+    decode::<IgnoredAny>(cur)?;
+    Ok(start..cur.position() as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_msgpack() {
+        let mut c = KnnClassifier::new(3);
+        c.fit_one(&[150.0, 80.5], "肥満");
+        c.fit_one(&[169.0, -64.0], "標準");
+        let bytes = c.to_msgpack();
+        let c2 = KnnClassifier::from_msgpack(&bytes).unwrap();
+        assert_eq!(c2.k, 3);
+        assert_eq!(c2.to_csv(','), c.to_csv(','));
+        // integers and float32 written by other languages
+        let bytes = [0x82, 0xa1, b'k', 0x01, 0xa5, b'i', b't', b'e', b'm', b's',
+            0x91, 0x92, 0xa1, b'a', 0x92, 0xd0, 0xfe, 0xca, 0x3f, 0xc0, 0x00, 0x00];
+        let c3 = KnnClassifier::from_msgpack(&bytes).unwrap();
+        assert_eq!(c3.to_csv(','), "a,-2,1.5\n");
+        // broken data
+        assert!(KnnClassifier::from_msgpack(&bytes[..10]).is_err());
+        // a huge array length with no data behind it fails instead of allocating
+        let huge = [0x81, 0xa5, b'i', b't', b'e', b'm', b's', 0x91, 0x92, 0xa1, b'a', 0xdd, 0xff, 0xff, 0xff, 0xff, 0xcb, 0, 0];
+        assert!(matches!(KnnClassifier::from_msgpack(&huge), Err(KnnError::Decode(_))));
+        // deeply nested input fails instead of exhausting the stack
+        let mut deep = b"\x81\xa6config".to_vec();
+        deep.extend([0x91; 100000]);
+        assert!(matches!(KnnClassifier::from_msgpack(&deep), Err(KnnError::Decode(_))));
+        let mut zero_k = bytes;
+        zero_k[3] = 0x00;
+        assert!(matches!(KnnClassifier::from_msgpack(&zero_k), Err(KnnError::Decode(_))));
+        // tampered data
+        let mut bytes = c.to_msgpack();
+        let pos = bytes.iter().position(|b| *b == 0xcb).unwrap();
+        bytes[pos + 8] ^= 1;
+        assert!(matches!(KnnClassifier::from_msgpack(&bytes), Err(KnnError::Checksum { .. })));
+        // weights, sources and timestamps are kept
+        let mut m = c.clone();
+        let mut other = KnnClassifier::new(3);
+        other.fit_source(&[&[160.0, 70.0]], &["標準"], "survey");
+        other.fit_one_at(&[180.0, 90.0], "肥満", 1700000000);
+        m.merge_weighted(other, 0.5);
+        let m2 = KnnClassifier::from_msgpack(&m.to_msgpack()).unwrap();
+        let meta = |c: &KnnClassifier| c.items.iter().map(|it| (it.weight, it.source.clone(), it.timestamp)).collect::<Vec<_>>();
+        assert_eq!(meta(&m2), meta(&m));
+        assert_eq!(meta(&m2)[2], (1.0, Some("survey".to_string()), None));
+        assert_eq!(m2.checksum(), m.checksum());
+        // the checksum covers the settings too
+        let mut bytes = c.to_msgpack();
+        let pos = bytes.windows(10).position(|w| w == b"\xa9euclidean").unwrap();
+        bytes[pos + 1..pos + 10].copy_from_slice(b"manhattan");
+        assert!(matches!(KnnClassifier::from_msgpack(&bytes), Err(KnnError::Checksum { .. })));
+        // files with a version 1 `config` have the checksum of the items only
+        let mut bytes = c.to_msgpack();
+        let pos = bytes.windows(8).position(|w| w == b"\xa7version").unwrap();
+        bytes[pos + 8] = 0x01;
+        let start = bytes.windows(9).position(|w| w == b"\xa8checksum").unwrap() + 9;
+        let end = start + skip(&mut Cursor::new(&bytes[start..])).unwrap().end;
+        let mut sum = vec![];
+        rmp::encode::write_uint(&mut sum, c.checksum()).unwrap();
+        bytes.splice(start..end, sum);
+        assert!(KnnClassifier::from_msgpack(&bytes).is_ok());
+    }
+    #[test]
+    fn test_msgpack_config() {
+        let mut c = KnnClassifier::with_metric(3, Metric::Minkowski(1.5));
+        for i in 0..20 {
+            c.fit_one(&[i as f64, (i * 7 % 5) as f64, (i % 3) as f64 * 10.0], ["a", "b"][i % 2]);
+        }
+        let rows: Vec<Vec<f64>> = c.items.iter().map(|it| it.data.clone()).collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        c.feature_weights = vec![1.0, 2.0, 0.5];
+        c.voting = Vote::Gaussian { bandwidth: 2.0 };
+        c.preprocess.push(MinMaxScaler::fit(&data).into());
+        c.fit_pca(2);
+        c.standardize = true;
+        c.fit_scaler();
+        c.reject = Reject { min_confidence: 0.6, max_distance: Some(1.5), label: Some("unknown".to_string()) };
+        let c2 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert_eq!((c2.metric, c2.voting, c2.standardize), (c.metric, c.voting, true));
+        assert_eq!((&c2.feature_weights, &c2.preprocess, &c2.scaler, &c2.reject), (&c.feature_weights, &c.preprocess, &c.scaler, &c.reject));
+        for q in [[3.0, 1.0, 0.0], [10.5, 4.0, 20.0], [40.0, 0.0, 0.0]] {
+            assert_eq!(c2.predict_one(&q), c.predict_one(&q));
+        }
+        // Mahalanobis needs no stored covariance: it is estimated from the items again
+        c.metric = Metric::Mahalanobis;
+        c.fit_covariance();
+        let c3 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert_eq!(c3.inverse_covariance, c.inverse_covariance);
+        // the projection is drawn again from its seed, so queries with the original features still work
+        let mut c = KnnClassifier::new(1);
+        for i in 0..40 {
+            let row: Vec<f64> = (0..20).map(|f| ((i % 2) * 3 + (i * f) % 7) as f64).collect();
+            c.fit_one(&row, ["a", "b"][i % 2]);
+        }
+        c.project(4, 1).unwrap();
+        let c4 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert_eq!(c4.projection, c.projection);
+        for i in 0..10 {
+            let q: Vec<f64> = (0..20).map(|f| ((i % 2) * 3 + (i * f) % 5) as f64).collect();
+            assert_eq!(c4.predict_one(&q), c.predict_one(&q));
+        }
+        // a newer config version is refused
+        let mut bytes = KnnClassifier::new(1).to_msgpack();
+        let pos = bytes.windows(8).position(|w| w == b"\xa7version").unwrap();
+        bytes[pos + 8] = 0x04;
+        assert!(matches!(KnnClassifier::from_msgpack(&bytes), Err(KnnError::Decode(_))));
+    }
+    #[test]
+    fn test_msgpack_vote_settings() {
+        let mut c = KnnClassifier::new(1);
+        c.k = 2;
+        c.fit(&[&[1.0], &[-1.0]], &["z", "a"]);
+        c.tie_break = TieBreak::Lexicographic;
+        c.coincident = CoincidentVote::First;
+        c.fallback = Some("none".to_string());
+        c.smoothing = 1.0;
+        c.set_source_weight("survey", 0.25).unwrap();
+        c.add_label_alias("fat", "obese");
+        c.set_class_quota("a", 3);
+        c.eviction = Eviction::Lightest;
+        assert_eq!(c.predict_one(&[0.0]), "a");
+        let c2 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert_eq!(c2.predict_one(&[0.0]), "a");
+        assert_eq!(c2.predict_proba_one(&[0.0]), c.predict_proba_one(&[0.0]));
+        assert_eq!((c2.tie_break, c2.coincident, &c2.fallback, c2.smoothing), (c.tie_break, c.coincident, &c.fallback, c.smoothing));
+        assert_eq!((&c2.source_weights, &c2.label_aliases, &c2.class_quotas, c2.eviction), (&c.source_weights, &c.label_aliases, &c.class_quotas, c.eviction));
+        // the maps are written in key order, so the bytes do not depend on the hash order
+        assert_eq!(c2.to_msgpack(), c.to_msgpack());
+        // a custom vote strategy is not stored, so the file is refused rather than loaded as uniform
+        c.voting = Vote::Custom;
+        assert!(matches!(KnnClassifier::from_msgpack(&c.to_msgpack()), Err(KnnError::Decode(_))));
+    }
+    // Function to remove the checksum of a file written by `to_msgpack`, so its content can be edited
+    fn remove_checksum(bytes: &mut Vec<u8>) {
+        let start = bytes.windows(9).position(|w| w == b"\xa8checksum").unwrap();
+        let end = start + 9 + skip(&mut Cursor::new(&bytes[start + 9..])).unwrap().end;
+        bytes.drain(start..end);
+        bytes[0] -= 1;
+    }
+    #[test]
+    fn test_msgpack_index() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..300 {
+            c.fit_one(&[(i * 37 % 101) as f64, (i * 11 % 29) as f64 * 3.0], ["a", "b", "c"][i % 3]);
+        }
+        let queries = [[10.5, 20.0], [50.0, 3.0], [99.0, 80.0]];
+        let backends = [Backend::KdTree, Backend::BallTree, Backend::VpTree,
+            Backend::Hnsw(HnswParams { m: 8, ef_construction: 40, ef_search: 20 }), Backend::Lsh(LshParams { tables: 4, bits: 6 })];
+        c.rerank = 2;
+        for backend in backends {
+            c.backend = backend;
+            let bytes = c.to_msgpack();
+            let c2 = KnnClassifier::from_msgpack(&bytes).unwrap();
+            assert_eq!((c2.backend, c2.rerank), (backend, c.rerank));
+            // the saved index is used without building it again
+            assert_eq!(format!("{:?}", c2.index), format!("IndexCell({:?}, 300 items)", backend));
+            assert!(c2.index_built());
+            for q in queries {
+                assert_eq!(c2.nearest(&q, 5), c.nearest(&q, 5), "{:?}", backend);
+            }
+            // the index is covered by the checksum
+            let mut broken = bytes.clone();
+            let n = broken.len();
+            broken[n - 2] ^= 1;
+            assert!(matches!(KnnClassifier::from_msgpack(&broken), Err(KnnError::Checksum { .. })), "{:?}", backend);
+        }
+        // an index built with other settings is built again
+        c.standardize = true;
+        let c2 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert!(c2.scaler.is_some());
+        assert_eq!(format!("{:?}", c2.index), "IndexCell(empty)");
+        assert!(!c2.index_built());
+        let mut brute = c2.clone();
+        brute.backend = Backend::BruteForce;
+        assert_eq!(c2.nearest(&queries[0], 5), brute.nearest(&queries[0], 5));
+        c.standardize = false;
+        // brute force writes no index
+        c.backend = Backend::BruteForce;
+        assert!(!c.to_msgpack().windows(6).any(|w| w == b"\xa5index"));
+        // without the index data (deleted items), the index is built again
+        c.backend = Backend::VpTree;
+        c.delete(0);
+        let c2 = KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap();
+        assert_eq!(c2.backend, Backend::VpTree);
+        assert_eq!(c2.nearest(&queries[0], 5).len(), 5);
+        // a newer index version or an unknown backend falls back to building the index again
+        let mut bytes = c.to_msgpack();
+        remove_checksum(&mut bytes);
+        let index = bytes.windows(6).position(|w| w == b"\xa5index").unwrap();
+        let version = index + bytes[index..].windows(8).position(|w| w == b"\xa7version").unwrap() + 8;
+        bytes[version] = 0x7f;
+        assert_eq!(KnnClassifier::from_msgpack(&bytes).unwrap().backend, Backend::VpTree);
+        let name = index + bytes[index..].windows(7).position(|w| w == b"\xa6vptree").unwrap();
+        bytes[name + 1..name + 7].copy_from_slice(b"mytree");
+        assert_eq!(KnnClassifier::from_msgpack(&bytes).unwrap().backend, Backend::BruteForce);
+    }
+}
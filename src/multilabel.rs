@@ -0,0 +1,79 @@
+//! Multi-label classification (items with several labels, e.g. tags).
+
+use crate::{KnnClassifier, KnnItem};
+
+/// Separator of the labels of a multi-label item ("news|sports"), also in CSV files
+pub const LABEL_SEPARATOR: char = '|';
+
+impl KnnItem {
+    /// labels of a multi-label item (the label split by `LABEL_SEPARATOR`, none for an empty label)
+    pub fn labels(&self) -> Vec<&str> {
+        self.label.split(LABEL_SEPARATOR).filter(|l| !l.is_empty()).collect()
+    }
+}
+
+impl KnnClassifier {
+    /// Function to learn items with several labels each
+    ///
+    /// The labels of an item are stored joined by `LABEL_SEPARATOR` (duplicates removed), so CSV and
+    /// MessagePack files keep them as one label column. `predict_one` still votes on the whole label set.
+    pub fn fit_multi_label(&mut self, data: &[&[f64]], labels: &[&[&str]]) {
+        for (it, labels) in data.iter().zip(labels) {
+            let mut unique: Vec<&str> = vec![];
+            for l in labels.iter().filter(|l| !l.is_empty()) {
+                if !unique.contains(l) { unique.push(l); }
+            }
+            self.push_item(KnnItem::new(&unique.join(&LABEL_SEPARATOR.to_string()), it));
+        }
+        self.fit_statistics();
+    }
+    /// Function to score every label of the k nearest neighbors, highest first
+    ///
+    /// The score of a label is the (weighted, see `voting`) share of the neighbors that have it,
+    /// so the scores of several labels can add up to more than 1.0. Ties are in order of the nearest neighbor.
+    pub fn predict_label_scores(&self, item: &[f64]) -> Vec<(String, f64)> {
+        let neighbors = self.nearest(item, self.k);
+        let weights = self.neighbor_weights(&neighbors);
+        let total: f64 = weights.iter().sum();
+        let mut scores: Vec<(&str, f64)> = vec![];
+        for ((i, _), w) in neighbors.iter().zip(&weights) {
+            for label in self.items[*i].labels() {
+                match scores.iter_mut().find(|(l, _)| *l == label) {
+                    Some((_, s)) => *s += w,
+                    None => scores.push((label, *w)),
+                }
+            }
+        }
+        // stable sort: ties stay in order of the nearest neighbor
+        scores.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scores.into_iter().map(|(l, s)| (l.to_string(), if total > 0.0 { s / total } else { 0.0 })).collect()
+    }
+    /// Function to predict every label whose score (see `predict_label_scores`) exceeds `threshold`
+    pub fn predict_labels(&self, item: &[f64], threshold: f64) -> Vec<String> {
+        self.predict_label_scores(item).into_iter().filter(|(_, s)| *s > threshold).map(|(l, _)| l).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predict_labels() {
+        let mut c = KnnClassifier::new(3);
+        c.fit_multi_label(
+            &[&[0.0], &[0.1], &[0.2], &[5.0]],
+            &[&["news", "sports"], &["sports", "sports"], &["news", "politics"], &[]]);
+        assert_eq!(c.items[1].label, "sports");
+        assert_eq!(c.items[2].labels(), ["news", "politics"]);
+        assert!(c.items[3].labels().is_empty());
+        let scores = c.predict_label_scores(&[0.0]);
+        assert_eq!(scores[0], ("news".to_string(), 2.0 / 3.0));
+        assert_eq!(scores[1], ("sports".to_string(), 2.0 / 3.0));
+        assert_eq!(c.predict_labels(&[0.0], 0.5), ["news", "sports"]);
+        // the labels survive a CSV round trip
+        let mut d = KnnClassifier::new(3);
+        d.from_csv(&c.to_csv(','), ',', 0, false);
+        assert_eq!(d.predict_labels(&[0.0], 0.5), ["news", "sports"]);
+    }
+}
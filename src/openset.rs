@@ -0,0 +1,80 @@
+//! Open-set recognition with per-class acceptance radii.
+
+use crate::stats::quantile_sorted;
+use crate::KnnClassifier;
+use std::collections::HashMap;
+
+impl KnnClassifier {
+    /// Function to set the acceptance radius of every class from the training data
+    ///
+    /// For each live item, the distance to the nearest other live item of the same class is measured.
+    /// The radius of a class is the `quantile` (0.0 to 1.0) of those distances, multiplied by `scale`.
+    /// Classes with a single item get no radius (they accept every query) unless set by hand.
+    pub fn fit_class_radii(&mut self, quantile: f64, scale: f64) {
+        let mut nearest_same: HashMap<&str, Vec<f64>> = HashMap::new();
+        for (i, a) in self.live_items() {
+            let d = self.live_items()
+                .filter(|(j, b)| *j != i && b.label == a.label)
+                .map(|(_, b)| self.distance(&a.data, &b.data))
+                .fold(f64::INFINITY, f64::min);
+            if d.is_finite() {
+                nearest_same.entry(&a.label).or_default().push(d);
+            }
+        }
+        let radii: Vec<(String, f64)> = nearest_same.into_iter().map(|(label, mut ds)| {
+            ds.sort_by(|a, b| a.total_cmp(b));
+            (label.to_string(), quantile_sorted(&ds, quantile) * scale)
+        }).collect();
+        self.class_radii.extend(radii);
+    }
+    /// Function to predict, returning `None` (unknown) when the query is not close enough to the predicted class
+    ///
+    /// The query is accepted when its distance to the nearest item of the predicted class
+    /// is within the radius of the class in `class_radii`. Classes without a radius always accept.
+    pub fn predict_one_open(&self, item: &[f64]) -> Option<String> {
+        self.live_items().next()?;
+        let label = self.predict_one(item);
+        let Some(radius) = self.class_radii.get(&label) else { return Some(label) };
+        let d = self.live_items()
+            .filter(|(_, it)| it.label == label)
+            .map(|(_, it)| self.distance(&it.data, item))
+            .fold(f64::INFINITY, f64::min);
+        (d <= *radius).then_some(label)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predict_one_open() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(
+            &[&[0.0, 0.0], &[1.0, 0.0], &[0.0, 2.0], &[10.0, 10.0], &[10.0, 11.0], &[50.0, 50.0]],
+            &["a", "a", "a", "b", "b", "c"]);
+        c.fit_class_radii(1.0, 1.5);
+        assert_eq!(c.class_radii["a"], 3.0);
+        assert_eq!(c.class_radii["b"], 1.5);
+        assert!(!c.class_radii.contains_key("c"));
+        assert_eq!(c.predict_one_open(&[0.5, 0.5]).unwrap(), "a");
+        assert_eq!(c.predict_one_open(&[10.0, 13.0]), None);
+        assert_eq!(c.predict_one_open(&[90.0, 90.0]).unwrap(), "c");
+        c.class_radii.insert("c".to_string(), 5.0);
+        assert_eq!(c.predict_one_open(&[90.0, 90.0]), None);
+    }
+    #[test]
+    fn test_class_radii_deleted() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.0], &[1.0], &[20.0], &[50.0]], &["a", "a", "a", "b"]);
+        c.delete(2);
+        c.fit_class_radii(1.0, 1.0);
+        assert_eq!(c.class_radii["a"], 1.0);
+        // the deleted item at 20 does not accept the query
+        assert_eq!(c.predict_one_open(&[19.0]), None);
+        c.delete(0);
+        c.delete(1);
+        c.delete(3);
+        assert_eq!(c.predict_one_open(&[0.0]), None);
+    }
+}
@@ -0,0 +1,98 @@
+//! Parallel batch prediction with std threads.
+
+use crate::KnnClassifier;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Thread settings of batch prediction (`KnnClassifier::predict`)
+///
+/// The default is serial, so the crate never starts threads unless asked to,
+/// which keeps it predictable inside host applications that are already parallel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Parallelism {
+    /// number of worker threads (0 = number of available CPUs, 1 = serial)
+    pub threads: usize,
+    /// number of queries handed to a worker at a time
+    pub chunk_size: usize,
+}
+
+impl Parallelism {
+    /// no threads (default)
+    pub fn serial() -> Parallelism {
+        Parallelism { threads: 1, chunk_size: 256 }
+    }
+    /// run on `threads` threads (0 = number of available CPUs)
+    pub fn threads(threads: usize) -> Parallelism {
+        Parallelism { threads, ..Parallelism::serial() }
+    }
+    // actual number of threads to use for `n` queries
+    fn worker_count(&self, n: usize) -> usize {
+        let threads = match self.threads {
+            0 => std::thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+            t => t,
+        };
+        let chunks = n.div_ceil(self.chunk_size.max(1));
+        threads.min(chunks).max(1)
+    }
+}
+
+impl Default for Parallelism {
+    fn default() -> Self {
+        Parallelism::serial()
+    }
+}
+
+impl KnnClassifier {
+    // Function to map the queries with the thread settings of `parallelism`, keeping the input order
+    pub(crate) fn map_queries<T: Send>(&self, items: &[Vec<f64>], f: impl Fn(&[f64]) -> T + Sync) -> Vec<T> {
+        let workers = self.parallelism.worker_count(items.len());
+        if workers <= 1 {
+            return items.iter().map(|it| f(it)).collect();
+        }
+        let chunks: Vec<&[Vec<f64>]> = items.chunks(self.parallelism.chunk_size.max(1)).collect();
+        let next = AtomicUsize::new(0);
+        let mut done: Vec<(usize, Vec<T>)> = std::thread::scope(|scope| {
+            let handles: Vec<_> = (0..workers).map(|_| scope.spawn(|| {
+                let mut results = vec![];
+                loop {
+                    let i = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(chunk) = chunks.get(i) else { break };
+                    results.push((i, chunk.iter().map(|it| f(it)).collect()));
+                }
+                results
+            })).collect();
+            handles.into_iter().flat_map(|h| h.join().unwrap()).collect()
+        });
+        // put the chunks back in input order
+        done.sort_by_key(|(i, _)| *i);
+        done.into_iter().flat_map(|(_, results)| results).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parallel_predict() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(
+            &[&[170., 60.], &[166., 58.], &[152., 99.], &[163., 95.], &[150., 90.]],
+            &["Normal", "Normal", "Obesity", "Obesity", "Obesity"]);
+        let queries: Vec<Vec<f64>> = (0..1000).map(|i| vec![150. + (i % 20) as f64, 50. + (i % 50) as f64]).collect();
+        let serial = c.predict(&queries);
+        c.parallelism = Parallelism { threads: 4, chunk_size: 7 };
+        assert_eq!(c.predict(&queries), serial);
+        // ties are broken the same way on every thread
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0., 0.], &[2., 0.], &[0., 2.], &[2., 2.]], &["a", "b", "c", "d"]);
+        let queries: Vec<Vec<f64>> = (0..500).map(|i| vec![(i % 21) as f64 / 10., (i % 17) as f64 / 8.]).collect();
+        let serial = c.predict(&queries);
+        assert_eq!(serial[0], "a");
+        for _ in 0..5 {
+            c.parallelism = Parallelism { threads: 0, chunk_size: 3 };
+            assert_eq!(c.predict(&queries), serial);
+        }
+        assert_eq!(Parallelism { threads: 8, chunk_size: 100 }.worker_count(250), 3);
+        assert_eq!(Parallelism::serial().worker_count(250), 1);
+    }
+}
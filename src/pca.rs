@@ -0,0 +1,213 @@
+//! Principal component analysis, to reduce the number of features before the neighbor search.
+
+use crate::KnnClassifier;
+
+/// Projection of the features onto their principal components (directions of largest variance)
+///
+/// With 100+ features, most of the variance is often in a few directions; keeping only those makes the
+/// distances less noisy, and faster: searches compare the query projected once with the items projected
+/// when they were learned (items with missing features are projected per distance).
+/// Features with different units should be scaled first (e.g. `StandardScaler`).
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Pca {
+    /// mean of every feature, subtracted before projecting
+    pub means: Vec<f64>,
+    /// unit vectors of the components, largest variance first
+    pub components: Vec<Vec<f64>>,
+    /// variance along each component
+    pub explained_variance: Vec<f64>,
+    /// share of the total variance along each component
+    pub explained_variance_ratio: Vec<f64>,
+}
+
+impl Pca {
+    /// Function to learn the first `n_components` principal components of the rows (all of them when there are fewer features)
+    ///
+    /// Missing values (NaN) are replaced with the mean of the feature. The covariance matrix is
+    /// decomposed with Jacobi rotations, which takes `features^3` time: fine for hundreds of features.
+    pub fn fit(data: &[&[f64]], n_components: usize) -> Pca {
+        let dim = data.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut means = vec![0.0; dim];
+        let mut counts = vec![0usize; dim];
+        for row in data {
+            for (f, v) in row.iter().enumerate().filter(|(_, v)| !v.is_nan()) {
+                means[f] += v;
+                counts[f] += 1;
+            }
+        }
+        means.iter_mut().zip(&counts).for_each(|(m, c)| *m /= (*c).max(1) as f64);
+        let mut cov = vec![vec![0.0; dim]; dim];
+        for row in data {
+            let centered = center(&means, row);
+            for (cov_row, a) in cov.iter_mut().zip(&centered) {
+                cov_row.iter_mut().zip(&centered).for_each(|(c, b)| *c += a * b);
+            }
+        }
+        let n = data.len().max(1) as f64;
+        cov.iter_mut().flatten().for_each(|c| *c /= n);
+        let (values, vectors) = symmetric_eigen(cov);
+        let mut order: Vec<usize> = (0..dim).collect();
+        order.sort_by(|a, b| values[*b].total_cmp(&values[*a]));
+        order.truncate(n_components);
+        let total: f64 = values.iter().map(|v| v.max(0.0)).sum();
+        let components = order.iter().map(|c| {
+            let mut u: Vec<f64> = vectors.iter().map(|row| row[*c]).collect();
+            // the largest coordinate is positive, so the signs do not depend on rounding
+            let largest = u.iter().copied().fold(0.0, |a: f64, x| if x.abs() > a.abs() { x } else { a });
+            if largest < 0.0 {
+                u.iter_mut().for_each(|x| *x = -*x);
+            }
+            u
+        }).collect();
+        let explained_variance: Vec<f64> = order.iter().map(|c| values[*c].max(0.0)).collect();
+        let explained_variance_ratio = explained_variance.iter().map(|v| if total > 0.0 { v / total } else { 0.0 }).collect();
+        Pca { means, components, explained_variance, explained_variance_ratio }
+    }
+    /// Function to project a row onto the components (missing values count as the mean)
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        let centered = center(&self.means, row);
+        self.components.iter().map(|u| u.iter().zip(&centered).map(|(a, b)| a * b).sum()).collect()
+    }
+    /// Function to map projected coordinates back to the features (the part of the row the components keep)
+    pub fn inverse_transform(&self, projected: &[f64]) -> Vec<f64> {
+        let mut row = self.means.clone();
+        for (u, p) in self.components.iter().zip(projected) {
+            row.iter_mut().zip(u).for_each(|(r, x)| *r += p * x);
+        }
+        row
+    }
+}
+
+// row minus the means, with 0 for missing values and missing features
+fn center(means: &[f64], row: &[f64]) -> Vec<f64> {
+    means.iter().enumerate().map(|(f, m)| match row.get(f) {
+        Some(v) if !v.is_nan() => v - m,
+        _ => 0.0,
+    }).collect()
+}
+
+// Function to decompose a symmetric matrix with cyclic Jacobi rotations,
+// returns the eigenvalues and the eigenvectors as the columns of a matrix
+fn symmetric_eigen(mut a: Vec<Vec<f64>>) -> (Vec<f64>, Vec<Vec<f64>>) {
+    let n = a.len();
+    let mut v: Vec<Vec<f64>> = (0..n).map(|i| (0..n).map(|j| if i == j { 1.0 } else { 0.0 }).collect()).collect();
+    let scale: f64 = a.iter().flatten().map(|x| x * x).sum();
+    for _ in 0..100 {
+        let off: f64 = (0..n).flat_map(|p| (p + 1..n).map(move |q| (p, q))).map(|(p, q)| a[p][q] * a[p][q]).sum();
+        if off <= 1e-30 * scale {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                if a[p][q] == 0.0 {
+                    continue;
+                }
+                let theta = (a[q][q] - a[p][p]) / (2.0 * a[p][q]);
+                let t = theta.signum() / (theta.abs() + (theta * theta + 1.0).sqrt());
+                let c = 1.0 / (t * t + 1.0).sqrt();
+                let s = t * c;
+                for m in [&mut a, &mut v] {
+                    for row in m.iter_mut() {
+                        let (x, y) = (row[p], row[q]);
+                        row[p] = c * x - s * y;
+                        row[q] = s * x + c * y;
+                    }
+                }
+                let (upper, lower) = a.split_at_mut(q);
+                for (x, y) in upper[p].iter_mut().zip(lower[0].iter_mut()) {
+                    (*x, *y) = (c * *x - s * *y, s * *x + c * *y);
+                }
+            }
+        }
+    }
+    ((0..n).map(|i| a[i][i]).collect(), v)
+}
+
+impl KnnClassifier {
+    /// Function to learn a `Pca` from the live items after `preprocess` and append it to `preprocess`
+    ///
+    /// Predictions then compare the queries in the same projection. `scaler` is learned again when
+    /// `standardize` is set. Returns the share of the variance the kept components explain.
+    pub fn fit_pca(&mut self, n_components: usize) -> f64 {
+        let rows: Vec<Vec<f64>> = self.live_items()
+            .map(|(_, it)| self.preprocess.iter().fold(it.data.clone(), |r, step| step.transform(&r)))
+            .collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        let pca = Pca::fit(&data, n_components);
+        let explained = pca.explained_variance_ratio.iter().sum();
+        self.preprocess.push(pca.into());
+        if self.standardize {
+            self.fit_scaler();
+        }
+        explained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, Preprocess, Reject};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_pca() {
+        // points along the diagonal (1, 1, 0) with a little noise in the other directions
+        let mut rng = Rng(7u64);
+        let mut rand = || rng.uniform() - 0.5;
+        let rows: Vec<Vec<f64>> = (0..200).map(|i| {
+            let t = i as f64 / 10.0;
+            vec![t + rand() * 0.1, t + rand() * 0.1 + 3.0, rand() * 0.1]
+        }).collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        let pca = Pca::fit(&data, 1);
+        let u = &pca.components[0];
+        let s = 0.5f64.sqrt();
+        assert!((u[0] - s).abs() < 1e-3 && (u[1] - s).abs() < 1e-3 && u[2].abs() < 1e-2, "{:?}", u);
+        assert!(pca.explained_variance_ratio[0] > 0.999);
+        let p = pca.transform(&[10.0, 13.0, 0.0]);
+        assert_eq!(p.len(), 1);
+        let back = pca.inverse_transform(&p);
+        assert!((back[0] - 10.0).abs() < 0.1 && (back[1] - 13.0).abs() < 0.1);
+        // all components of a known covariance: diag(4, 1) rotated by 45 degrees
+        let full = Pca::fit(&[&[2.0, 2.0], &[-2.0, -2.0], &[1.0, -1.0], &[-1.0, 1.0]], 5);
+        assert_eq!(full.components.len(), 2);
+        assert!((full.explained_variance[0] - 4.0).abs() < 1e-12 && (full.explained_variance[1] - 1.0).abs() < 1e-12);
+        assert!((full.transform(&[f64::NAN, 2.0])[0] - 2.0 * s).abs() < 1e-12);
+        // 50 features, 3 of which separate the classes
+        let mut c = KnnClassifier::new(3);
+        for i in 0..100 {
+            let label = ["a", "b"][i % 2];
+            let row: Vec<f64> = (0..50).map(|f| if f < 3 && i % 2 == 1 { 5.0 } else { 0.0 } + rand()).collect();
+            c.fit_one(&row, label);
+        }
+        let query: Vec<f64> = (0..50).map(|f| if f < 3 { 4.5 } else { 0.0 }).collect();
+        let expected = c.predict_one(&query).to_string();
+        assert!(c.fit_pca(2) > 0.5);
+        assert_eq!(c.transform_point(&query).len(), 2);
+        // the searches compare the projected items
+        assert!(c.item_points().unwrap().rows.iter().all(|r| r.as_ref().is_some_and(|r| r.len() == 2)));
+        assert_eq!(c.predict_one(&query), expected);
+        c.backend = Backend::BallTree;
+        assert_eq!(c.predict_one(&query), "b");
+    }
+    #[test]
+    fn test_fit_pca_edges() {
+        // empty model: no components
+        let mut c = KnnClassifier::new(1);
+        assert_eq!(c.fit_pca(2), 0.0);
+        assert!(c.nearest(&[1.0, 2.0], 1).is_empty());
+        // the components are learned from the live items only
+        let rows: [&[f64]; 5] = [&[0., 0.], &[1., 1.], &[2., 2.], &[3., 3.], &[50., -50.]];
+        let mut c = KnnClassifier::new(1);
+        c.fit(&rows, &["a", "a", "b", "b", "outlier"]);
+        c.delete(4);
+        c.fit_pca(1);
+        let Preprocess::Pca(pca) = &c.preprocess[0] else { panic!() };
+        assert_eq!(pca, &Pca::fit(&rows[..4], 1));
+        // a NaN feature takes the mean of the feature, and reject applies in the projection
+        assert_eq!(c.predict_one(&[3.0, f64::NAN]), "b");
+        c.reject = Reject { max_distance: Some(1.0), label: Some("unknown".to_string()), ..Default::default() };
+        assert_eq!(c.predict_one(&[0.1, 0.0]), "a");
+        assert_eq!(c.predict_one(&[30.0, 30.0]), "unknown");
+    }
+}
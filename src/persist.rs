@@ -0,0 +1,396 @@
+//! Incremental persistence for online learning.
+
+use crate::{parse_csv_line, push_csv_line, FloatFormat, Fnv64, KnnClassifier, KnnError, KnnItem};
+use std::fs::{File, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Classifier backed by an append-only CSV file (label in column 0)
+///
+/// `fit_one` appends one record to the file, so learned items survive a process restart
+/// without rewriting the whole model. Changes made through `classifier_mut` (relabeling,
+/// removing items, ...) are written by `compact`, which rewrites the file from memory.
+/// Compaction also runs automatically every `compact_every` appends.
+///
+/// Items with a weight other than 1.0, a source or a timestamp get extra `@weight=`, `@source=`
+/// and `@timestamp=` columns after their data, so `merge_weighted`, `fit_source` and `fit_one_at`
+/// survive a restart too. Labels and sources are written as is, so they must not contain `,`, `"` or line breaks.
+/// `fit_one` and `compact` refuse them with `KnnError::Invalid`.
+///
+/// With `open_with_journal`, records are appended to a write-ahead journal (`<path>.wal`)
+/// instead, and the model file is only ever replaced atomically by `compact`.
+/// Each journal record carries a checksum, and `open_with_journal` replays the valid records
+/// on top of the model file, so a crash at any point never corrupts the model.
+#[derive(Debug)]
+pub struct AppendOnlyModel {
+    clf: KnnClassifier,
+    path: PathBuf,
+    file: File,
+    appended: usize,
+    // generation of the model file when a journal is used
+    journal: Option<u64>,
+    /// compact the file after this many appends (0 = never)
+    pub compact_every: usize,
+}
+
+impl AppendOnlyModel {
+    /// open the model file (created when missing) and load its records
+    ///
+    /// A record torn by a crash during writing (last line without newline) is discarded.
+    pub fn open(path: impl AsRef<Path>, k: usize) -> Result<AppendOnlyModel, KnnError> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = read_or_empty(&path)?;
+        let valid_len = bytes.iter().rposition(|b| *b == b'\n').map(|i| i + 1).unwrap_or(0);
+        let (_, body) = split_header(&bytes[..valid_len]);
+        let mut clf = KnnClassifier::new(k);
+        read_records(&mut clf, body)?;
+        clf.fit_statistics();
+        let file = open_truncated(&path, valid_len as u64)?;
+        Ok(AppendOnlyModel { clf, path, file, appended: 0, journal: None, compact_every: 1000 })
+    }
+    /// open the model file with a write-ahead journal, replaying the journal records
+    pub fn open_with_journal(path: impl AsRef<Path>, k: usize) -> Result<AppendOnlyModel, KnnError> {
+        let path = path.as_ref().to_path_buf();
+        let bytes = read_or_empty(&path)?;
+        let (generation, body) = split_header(&bytes);
+        let mut clf = KnnClassifier::new(k);
+        read_records(&mut clf, body)?;
+        // replay the journal written for this generation of the model file
+        let wal_path = journal_path(&path);
+        let wal = read_or_empty(&wal_path)?;
+        let (wal_generation, records) = split_header(&wal);
+        let mut valid_len = wal.len() - records.len();
+        if wal.is_empty() || wal_generation != generation {
+            // no journal, or the model file was compacted after it was written
+            replace_file(&wal_path, header(generation).as_bytes())?;
+            valid_len = header(generation).len();
+        } else {
+            for line in records.split_inclusive(|b| *b == b'\n') {
+                match parse_record(line) {
+                    Some(record) => read_records(&mut clf, record)?,
+                    None => break,
+                };
+                valid_len += line.len();
+            }
+        }
+        clf.fit_statistics();
+        let file = open_truncated(&wal_path, valid_len as u64)?;
+        Ok(AppendOnlyModel { clf, path, file, appended: 0, journal: Some(generation), compact_every: 1000 })
+    }
+    /// learn a single data point and append it to the file, returns `false` when a class quota rejects it
+    ///
+    /// When a quota evicts other items to make room, the file is compacted instead, so the evicted items
+    /// do not come back on the next `open`.
+    pub fn fit_one(&mut self, data: &[f64], label: &str) -> Result<bool, KnnError> {
+        check_field("label", self.clf.label_aliases.get(label).map_or(label, |l| l.as_str()))?;
+        let before = self.clf.items.len();
+        if !self.clf.learn_one(KnnItem::new(label, data)) {
+            return Ok(false);
+        }
+        if self.clf.items.len() != before + 1 {
+            self.compact()?;
+            return Ok(true);
+        }
+        let mut line = String::new();
+        push_record(&mut line, self.clf.items.last().unwrap())?;
+        if self.journal.is_some() {
+            line = format!("{:016x}\t{}", record_hash(line.as_bytes()), line);
+        }
+        self.file.write_all(line.as_bytes()).map_err(io_err)?;
+        self.file.flush().map_err(io_err)?;
+        if self.journal.is_some() {
+            self.file.sync_data().map_err(io_err)?;
+        }
+        self.appended += 1;
+        if self.compact_every > 0 && self.appended >= self.compact_every {
+            self.compact()?;
+        }
+        Ok(true)
+    }
+    /// rewrite the file from the model in memory
+    ///
+    /// The new content is written to a temporary file which then replaces the model file,
+    /// so the old file stays intact if writing fails. With a journal, the journal is reset
+    /// after the model file has been replaced.
+    pub fn compact(&mut self) -> Result<(), KnnError> {
+        let mut text = String::new();
+        if let Some(generation) = self.journal {
+            text.push_str(&header(generation + 1));
+        }
+        for (_, it) in self.clf.live_items() {
+            push_record(&mut text, it)?;
+        }
+        replace_file(&self.path, text.as_bytes())?;
+        match self.journal.as_mut() {
+            Some(generation) => {
+                *generation += 1;
+                // a crash here is safe: the old journal has a different generation and is ignored
+                let wal_path = journal_path(&self.path);
+                replace_file(&wal_path, header(*generation).as_bytes())?;
+                self.file = OpenOptions::new().append(true).open(&wal_path).map_err(io_err)?;
+            },
+            None => {
+                self.file = OpenOptions::new().append(true).open(&self.path).map_err(io_err)?;
+            },
+        }
+        self.appended = 0;
+        Ok(())
+    }
+    /// the classifier (for prediction)
+    pub fn classifier(&self) -> &KnnClassifier {
+        &self.clf
+    }
+    /// the classifier for changes other than appending; call `compact` to save them
+    pub fn classifier_mut(&mut self) -> &mut KnnClassifier {
+        &mut self.clf
+    }
+}
+
+// Function to write an item as a csv line, followed by the columns of its weight, source and timestamp when set
+fn push_record(s: &mut String, it: &KnnItem) -> Result<(), KnnError> {
+    check_field("label", &it.label)?;
+    push_csv_line(s, it, ',', FloatFormat::RoundTrip);
+    s.pop();
+    if it.weight != 1.0 {
+        s.push_str(&format!(",@weight={}", it.weight));
+    }
+    if let Some(source) = &it.source {
+        check_field("source", source)?;
+        s.push_str(&format!(",@source={}", source));
+    }
+    if let Some(t) = it.timestamp {
+        s.push_str(&format!(",@timestamp={}", t));
+    }
+    s.push('\n');
+    Ok(())
+}
+
+// Function to refuse text that would break a record
+fn check_field(name: &str, s: &str) -> Result<(), KnnError> {
+    if s.contains([',', '"', '\n', '\r']) {
+        return Err(KnnError::Invalid(format!("{} cannot be saved in the model file: {:?}", name, s)));
+    }
+    Ok(())
+}
+
+// Function to learn the records of a model file or journal (see `push_record`)
+fn read_records(clf: &mut KnnClassifier, body: &[u8]) -> Result<(), KnnError> {
+    for (i, line) in body.split(|b| *b == b'\n').enumerate() {
+        let parse_err = |msg: String| KnnError::Parse { line: i + 1, msg };
+        let line = std::str::from_utf8(line).map_err(|_| parse_err("invalid UTF-8".to_string()))?.trim();
+        let (csv, columns) = line.split_once(",@").map_or((line, None), |(csv, rest)| (csv, Some(rest)));
+        let Some(mut it) = parse_csv_line(csv, ',', 0).map_err(parse_err)? else { continue };
+        for column in columns.into_iter().flat_map(|c| c.split(",@")) {
+            match column.split_once('=') {
+                Some(("weight", v)) => it.weight = v.parse().map_err(|_| parse_err(format!("invalid weight: {}", v)))?,
+                Some(("source", v)) => it.source = Some(v.to_string()),
+                Some(("timestamp", v)) => it.timestamp = Some(v.parse().map_err(|_| parse_err(format!("invalid timestamp: {}", v)))?),
+                _ => return Err(parse_err(format!("invalid column: @{}", column))),
+            }
+        }
+        clf.push_item(it);
+    }
+    Ok(())
+}
+
+fn io_err(e: std::io::Error) -> KnnError {
+    KnnError::Io(e.to_string())
+}
+
+// read as bytes: a torn record may end in the middle of a UTF-8 character
+fn read_or_empty(path: &Path) -> Result<Vec<u8>, KnnError> {
+    match std::fs::read(path) {
+        Ok(bytes) => Ok(bytes),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(io_err(e)),
+    }
+}
+
+fn open_truncated(path: &Path, len: u64) -> Result<File, KnnError> {
+    let mut file = OpenOptions::new().create(true).truncate(false).write(true).open(path).map_err(io_err)?;
+    file.set_len(len).map_err(io_err)?;
+    file.seek(SeekFrom::End(0)).map_err(io_err)?;
+    Ok(file)
+}
+
+pub(crate) fn replace_file(path: &Path, bytes: &[u8]) -> Result<(), KnnError> {
+    let tmp = path_with_suffix(path, ".tmp");
+    {
+        let mut f = File::create(&tmp).map_err(io_err)?;
+        f.write_all(bytes).map_err(io_err)?;
+        f.sync_all().map_err(io_err)?;
+    }
+    std::fs::rename(&tmp, path).map_err(io_err)?;
+    sync_parent(path)
+}
+
+// Function to flush the directory entry of a renamed file, so the rename itself survives a power loss
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<(), KnnError> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    File::open(dir).and_then(|d| d.sync_all()).map_err(io_err)
+}
+
+// directories cannot be opened as files here; the rename is flushed by the file system
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<(), KnnError> {
+    Ok(())
+}
+
+fn path_with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut s = path.as_os_str().to_os_string();
+    s.push(suffix);
+    PathBuf::from(s)
+}
+
+fn journal_path(path: &Path) -> PathBuf {
+    path_with_suffix(path, ".wal")
+}
+
+fn header(generation: u64) -> String {
+    format!("#generation={}\n", generation)
+}
+
+// split the "#generation=N" header line (generation 0 when missing)
+fn split_header(bytes: &[u8]) -> (u64, &[u8]) {
+    if bytes.starts_with(b"#generation=") {
+        if let Some(end) = bytes.iter().position(|b| *b == b'\n') {
+            let generation = std::str::from_utf8(&bytes[12..end]).ok().and_then(|s| s.parse().ok()).unwrap_or(0);
+            return (generation, &bytes[end + 1..]);
+        }
+    }
+    (0, bytes)
+}
+
+fn record_hash(line: &[u8]) -> u64 {
+    let mut h = Fnv64::new();
+    h.write(line);
+    h.finish()
+}
+
+// "hash\tcsv line\n" -> csv line, None for a torn or corrupted record
+fn parse_record(line: &[u8]) -> Option<&[u8]> {
+    if line.len() < 18 || line[16] != b'\t' || !line.ends_with(b"\n") {
+        return None;
+    }
+    let hash = u64::from_str_radix(std::str::from_utf8(&line[..16]).ok()?, 16).ok()?;
+    let record = &line[17..];
+    (record_hash(record) == hash).then_some(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_append_only_model() {
+        let path = std::env::temp_dir().join(format!("knn_append_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+            m.fit_one(&[170.0, 60.0], "Normal").unwrap();
+            m.fit_one(&[152.0, 99.0], "Obesity").unwrap();
+        }
+        // torn record from a crash
+        OpenOptions::new().append(true).open(&path).unwrap().write_all("肥満".as_bytes().split_at(4).0).unwrap();
+        {
+            let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+            assert_eq!(m.classifier().items.len(), 2);
+            m.fit_one(&[166.0, 58.0], "Normal").unwrap();
+            m.classifier_mut().items[1].label = "Fat".to_string();
+            m.compact().unwrap();
+            m.fit_one(&[150.0, 90.0], "Fat").unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Normal,170,60\nFat,152,99\nNormal,166,58\nFat,150,90\n");
+        let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+        assert_eq!(m.classifier().predict_one(&[151.0, 95.0]), "Fat");
+        // items rejected or evicted by a quota are not kept on disk
+        m.classifier_mut().set_class_quota("Fat", 2);
+        m.classifier_mut().set_class_quota("Tall", 0);
+        assert!(!m.fit_one(&[190.0, 70.0], "Tall").unwrap());
+        assert!(m.fit_one(&[155.0, 92.0], "Fat").unwrap());
+        drop(m);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Normal,170,60\nNormal,166,58\nFat,150,90\nFat,155,92\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_item_metadata() {
+        let path = std::env::temp_dir().join(format!("knn_metadata_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        {
+            let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+            m.fit_one(&[170.0, 60.0], "Normal").unwrap();
+            let mut other = KnnClassifier::new(1);
+            other.fit_source(&[&[152.0, 99.0]], &["Obesity"], "survey");
+            other.fit_one_at(&[166.0, 58.0], "Normal", 1700000000);
+            m.classifier_mut().merge_weighted(other, 0.5);
+            m.compact().unwrap();
+            m.fit_one(&[150.0, 90.0], "Obesity").unwrap();
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(),
+            "Normal,170,60,@weight=0.5\nObesity,152,99,@source=survey\nNormal,166,58,@timestamp=1700000000\nObesity,150,90\n");
+        let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+        let meta: Vec<_> = m.classifier().items.iter().map(|it| (it.weight, it.source.clone(), it.timestamp)).collect();
+        assert_eq!(meta, [(0.5, None, None), (1.0, Some("survey".to_string()), None), (1.0, None, Some(1700000000)), (1.0, None, None)]);
+        // a source that would break the record is refused
+        m.classifier_mut().items[0].source = Some("a,b".to_string());
+        assert!(matches!(m.compact(), Err(KnnError::Invalid(_))));
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_invalid_label() {
+        let path = std::env::temp_dir().join(format!("knn_label_{}.csv", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let mut m = AppendOnlyModel::open(&path, 1).unwrap();
+        m.fit_one(&[170.0, 60.0], "Normal").unwrap();
+        for label in ["Fat,Tall", "\"Fat\"", "Fat\nTall"] {
+            assert!(matches!(m.fit_one(&[150.0, 90.0], label), Err(KnnError::Invalid(_))));
+        }
+        m.classifier_mut().label_aliases.insert("fat".to_string(), "Fat,Tall".to_string());
+        assert!(matches!(m.fit_one(&[150.0, 90.0], "fat"), Err(KnnError::Invalid(_))));
+        m.classifier_mut().items[0].label = "Normal\n".to_string();
+        assert!(matches!(m.compact(), Err(KnnError::Invalid(_))));
+        drop(m);
+        // nothing was learned or written
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "Normal,170,60\n");
+        std::fs::remove_file(&path).unwrap();
+    }
+    #[test]
+    fn test_journal() {
+        let path = std::env::temp_dir().join(format!("knn_journal_{}.csv", std::process::id()));
+        let wal_path = journal_path(&path);
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(&wal_path);
+        {
+            let mut m = AppendOnlyModel::open_with_journal(&path, 1).unwrap();
+            m.fit_one(&[170.0, 60.0], "Normal").unwrap();
+            m.compact().unwrap();
+            m.fit_one(&[152.0, 99.0], "Obesity").unwrap();
+            m.fit_one(&[166.0, 58.0], "Normal").unwrap();
+        }
+        // corrupt the last record
+        let mut wal = std::fs::read(&wal_path).unwrap();
+        let n = wal.len();
+        wal[n - 3] = b'9';
+        std::fs::write(&wal_path, &wal).unwrap();
+        {
+            let mut m = AppendOnlyModel::open_with_journal(&path, 1).unwrap();
+            assert_eq!(m.classifier().to_csv(','), "Normal,170,60\nObesity,152,99\n");
+            m.fit_one(&[150.0, 90.0], "Obesity").unwrap();
+        }
+        // crash after the model file was compacted, before the journal was reset
+        let old_wal = std::fs::read(&wal_path).unwrap();
+        {
+            let mut m = AppendOnlyModel::open_with_journal(&path, 1).unwrap();
+            m.compact().unwrap();
+        }
+        std::fs::write(&wal_path, old_wal).unwrap();
+        let m = AppendOnlyModel::open_with_journal(&path, 1).unwrap();
+        assert_eq!(m.classifier().to_csv(','), "Normal,170,60\nObesity,152,99\nObesity,150,90\n");
+        std::fs::remove_file(&path).unwrap();
+        std::fs::remove_file(&wal_path).unwrap();
+    }
+}
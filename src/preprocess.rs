@@ -0,0 +1,185 @@
+//! Preprocessing of the features: scalers and the preprocessing chain of a classifier.
+
+use crate::stats::quantile_sorted;
+use crate::{KnnClassifier, Pca, StandardScaler};
+
+/// Minimum and maximum of every feature, to scale the features to the range 0.0 to 1.0
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MinMaxScaler {
+    pub mins: Vec<f64>,
+    pub maxs: Vec<f64>,
+}
+
+impl MinMaxScaler {
+    /// Function to learn the minimum and maximum of every feature (missing values (NaN) are skipped)
+    pub fn fit(data: &[&[f64]]) -> MinMaxScaler {
+        let dim = data.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut s = MinMaxScaler { mins: vec![f64::INFINITY; dim], maxs: vec![f64::NEG_INFINITY; dim] };
+        for row in data {
+            for (i, v) in row.iter().enumerate().filter(|(_, v)| !v.is_nan()) {
+                s.mins[i] = s.mins[i].min(*v);
+                s.maxs[i] = s.maxs[i].max(*v);
+            }
+        }
+        s
+    }
+    /// Function to make a scaler from (min, max) ranges, e.g. `KnnClassifier::feature_ranges`
+    pub fn from_ranges(ranges: &[(f64, f64)]) -> MinMaxScaler {
+        MinMaxScaler { mins: ranges.iter().map(|r| r.0).collect(), maxs: ranges.iter().map(|r| r.1).collect() }
+    }
+    /// Function to scale a row: `(x - min) / (max - min)`
+    ///
+    /// Constant features are only shifted, and features beyond the fitted ones are kept as they are.
+    /// Values outside the fitted range fall below 0.0 or above 1.0.
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        row.iter().enumerate().map(|(i, v)| match (self.mins.get(i), self.maxs.get(i)) {
+            (Some(min), Some(max)) if max > min => (v - min) / (max - min),
+            (Some(min), Some(_)) if min.is_finite() => v - min,
+            _ => *v,
+        }).collect()
+    }
+}
+
+/// Median and interquartile range of every feature, a scaling that outliers barely move
+///
+/// `transform` gives `(x - median) / (q3 - q1)`. Unlike the mean and the standard deviation,
+/// a few extreme values do not change the median and the quartiles much.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RobustScaler {
+    pub medians: Vec<f64>,
+    /// q3 - q1 of every feature
+    pub iqrs: Vec<f64>,
+}
+
+impl RobustScaler {
+    /// Function to learn the median and the interquartile range of every feature (nearest-rank quantiles)
+    ///
+    /// Missing values (NaN) are skipped.
+    pub fn fit(data: &[&[f64]]) -> RobustScaler {
+        let dim = data.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut s = RobustScaler::default();
+        for i in 0..dim {
+            let mut values: Vec<f64> = data.iter().filter_map(|row| row.get(i).copied()).filter(|v| !v.is_nan()).collect();
+            values.sort_by(f64::total_cmp);
+            let median = if values.is_empty() { 0.0 } else { quantile_sorted(&values, 0.5) };
+            let iqr = if values.is_empty() { 0.0 } else { quantile_sorted(&values, 0.75) - quantile_sorted(&values, 0.25) };
+            s.medians.push(median);
+            s.iqrs.push(iqr);
+        }
+        s
+    }
+    /// Function to scale a row: `(x - median) / iqr`
+    ///
+    /// Features with an interquartile range of 0 are only centered, and features beyond the fitted ones are kept as they are.
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        row.iter().enumerate().map(|(i, v)| match (self.medians.get(i), self.iqrs.get(i)) {
+            (Some(m), Some(iqr)) if *iqr > 0.0 => (v - m) / iqr,
+            (Some(m), _) => v - m,
+            _ => *v,
+        }).collect()
+    }
+}
+
+/// Step of the preprocessing chain of a classifier (see `KnnClassifier::preprocess`)
+#[derive(Debug, Clone, PartialEq)]
+pub enum Preprocess {
+    Standard(StandardScaler),
+    MinMax(MinMaxScaler),
+    Robust(RobustScaler),
+    Pca(Pca),
+}
+
+impl Preprocess {
+    /// Function to transform a row with the step
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        match self {
+            Preprocess::Standard(s) => s.transform(row),
+            Preprocess::MinMax(s) => s.transform(row),
+            Preprocess::Robust(s) => s.transform(row),
+            Preprocess::Pca(p) => p.transform(row),
+        }
+    }
+}
+
+impl From<StandardScaler> for Preprocess {
+    fn from(s: StandardScaler) -> Preprocess {
+        Preprocess::Standard(s)
+    }
+}
+
+impl From<MinMaxScaler> for Preprocess {
+    fn from(s: MinMaxScaler) -> Preprocess {
+        Preprocess::MinMax(s)
+    }
+}
+
+impl From<RobustScaler> for Preprocess {
+    fn from(s: RobustScaler) -> Preprocess {
+        Preprocess::Robust(s)
+    }
+}
+
+impl From<Pca> for Preprocess {
+    fn from(p: Pca) -> Preprocess {
+        Preprocess::Pca(p)
+    }
+}
+
+impl KnnClassifier {
+    /// Function to transform a point as the distances see it: `projection` (for a point with the original features),
+    /// the `preprocess` steps in order, then `standardize`
+    pub fn transform_point(&self, row: &[f64]) -> Vec<f64> {
+        let mut row = self.preprocess.iter().fold(self.projected(row).into_owned(), |r, step| step.transform(&r));
+        if let Some(s) = self.active_scaler() {
+            row = s.transform(&row);
+        }
+        row
+    }
+    // true when the distances compare transformed points
+    pub(crate) fn transforms_points(&self) -> bool {
+        !self.preprocess.is_empty() || self.active_scaler().is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backend;
+
+    #[test]
+    fn test_min_max_scaler() {
+        let s = MinMaxScaler::fit(&[&[1.0, 5.0], &[3.0, 5.0], &[f64::NAN, 5.0]]);
+        assert_eq!(s, MinMaxScaler { mins: vec![1.0, 5.0], maxs: vec![3.0, 5.0] });
+        assert_eq!(s.transform(&[2.0, 6.0, 7.0]), [0.5, 1.0, 7.0]);
+        // height (mm) and weight (kg)
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[1500.0, 60.0], &[1900.0, 62.0], &[1700.0, 90.0], &[1720.0, 95.0]], &["light", "light", "heavy", "heavy"]);
+        assert_eq!(c.predict_one(&[1710.0, 64.0]), "heavy");
+        let scaler = MinMaxScaler::from_ranges(&c.feature_ranges);
+        c.preprocess.push(scaler.clone().into());
+        assert_eq!(c.transform_point(&[1700.0, 77.5]), [0.5, 0.5]);
+        assert_eq!(c.predict_one(&[1710.0, 64.0]), "light");
+        c.backend = Backend::BallTree;
+        assert_eq!(c.predict_one(&[1710.0, 64.0]), "light");
+        // standardization applies after the chain
+        c.standardize = true;
+        c.fit_scaler();
+        assert_eq!(c.scaler, Some(StandardScaler::fit(&[&[0.0, 0.0], &[1.0, 2.0 / 35.0], &[0.5, 30.0 / 35.0], &[0.55, 1.0]])));
+    }
+    #[test]
+    fn test_robust_scaler() {
+        let s = RobustScaler::fit(&[&[1.0, 7.0], &[2.0, 7.0], &[3.0, 7.0], &[4.0, 7.0], &[1000.0, f64::NAN]]);
+        assert_eq!(s, RobustScaler { medians: vec![3.0, 7.0], iqrs: vec![2.0, 0.0] });
+        assert_eq!(s.transform(&[5.0, 8.0, 9.0]), [1.0, 1.0, 9.0]);
+        // an outlier squeezes the other values in a min-max scaling, but not in a robust scaling
+        let rows: Vec<Vec<f64>> = (0..20).map(|i| vec![i as f64]).chain([vec![10000.0]]).collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        let spread = |step: Preprocess| step.transform(&[15.0])[0] - step.transform(&[5.0])[0];
+        assert_eq!(spread(MinMaxScaler::fit(&data).into()), 0.001);
+        assert_eq!(spread(RobustScaler::fit(&data).into()), 1.0);
+        let mut c = KnnClassifier::new(1);
+        c.fit(&data, &vec!["x"; 21]);
+        c.preprocess.push(RobustScaler::fit(&data).into());
+        assert_eq!(c.distance(&[5.0], &[15.0]), 1.0);
+    }
+}
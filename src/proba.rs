@@ -0,0 +1,207 @@
+//! Class probabilities from neighbor votes.
+
+use crate::{KnnClassifier, KnnError, TieBreak};
+
+/// Prediction with its confidence (see `KnnClassifier::predict_one_detailed`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Prediction {
+    /// predicted label (the same as `predict_one`, so `reject.label` when the vote is rejected)
+    pub label: String,
+    /// vote share of the voted label among the k nearest neighbors (0.0 to 1.0)
+    pub confidence: f64,
+    /// confidence minus the vote share of the runner-up label (equal to `confidence` when no other label got votes)
+    pub margin: f64,
+    /// true when the vote does not meet the thresholds of `reject` (see `predict_one_or_reject`)
+    pub rejected: bool,
+}
+
+impl KnnClassifier {
+    /// labels of all classes of the live items, in order of first appearance
+    pub fn classes(&self) -> Vec<&str> {
+        let mut classes: Vec<&str> = vec![];
+        for (_, it) in self.live_items() {
+            if !classes.contains(&it.label.as_str()) {
+                classes.push(&it.label);
+            }
+        }
+        classes
+    }
+    /// Function to estimate the probability of every class (in the order of `classes`)
+    ///
+    /// The probability of a class is its share of the (weighted, see `voting`) votes of the k nearest neighbors.
+    /// With `smoothing` (Laplace smoothing), `(votes + smoothing) / (total + smoothing * classes)`
+    /// gives classes absent from the neighborhood a small nonzero probability, as log-loss needs.
+    /// When all k neighbors coincide with the query, the votes are those of the coincident items (see `coincident`),
+    /// so the most probable class is the one `predict_one` returns (up to ties).
+    /// The classes are collected from all items on every call; `predict_proba` collects them once for all queries.
+    pub fn predict_proba_one(&self, item: &[f64]) -> Vec<(String, f64)> {
+        self.proba_among(item, &self.classes())
+    }
+    /// Function to estimate the class probabilities of multiple data points (see `predict_proba_one`)
+    ///
+    /// Runs on multiple threads when `parallelism` allows it; the results are in input order.
+    pub fn predict_proba(&self, items: &[Vec<f64>]) -> Vec<Vec<(String, f64)>> {
+        let classes = self.classes();
+        self.map_queries(items, |it| self.proba_among(it, &classes))
+    }
+    // Function to estimate the probabilities of `classes` (all classes of the live items, see `predict_proba_one`)
+    fn proba_among(&self, item: &[f64], classes: &[&str]) -> Vec<(String, f64)> {
+        let mut votes = vec![0.0; classes.len()];
+        let neighbors = self.nearest(item, self.k);
+        for (i, w) in self.voters(item, &neighbors) {
+            votes[classes.iter().position(|c| *c == self.items[i].label).unwrap()] += w;
+        }
+        let alpha = self.smoothing.max(0.0);
+        let total = votes.iter().sum::<f64>() + alpha * classes.len() as f64;
+        classes.iter().zip(votes).map(|(c, v)| {
+            (c.to_string(), if total > 0.0 { (v + alpha) / total } else { 0.0 })
+        }).collect()
+    }
+    /// Function to predict a single data point with the vote share of the label and its margin over the runner-up
+    ///
+    /// Downstream systems can gate predictions with a low confidence or a small margin (e.g. send them to a human).
+    /// Vote shares are weighted like the vote (see `voting`); smoothing is not applied. When all k neighbors
+    /// coincide with the query, the shares are those of the coincident items (see `coincident`).
+    /// Returns `KnnError::NoNeighbors` when no item can vote and there is no `fallback`
+    /// (the fallback label has confidence and margin 0 and is not rejected).
+    pub fn predict_one_detailed(&self, item: &[f64]) -> Result<Prediction, KnnError> {
+        let neighbors = self.nearest(item, self.k);
+        let label = self.decide(item, &neighbors).ok_or(KnnError::NoNeighbors)?;
+        let voters = if self.coincident_decides(&neighbors) {
+            self.coincident_votes(item, |_| true)
+        } else {
+            neighbors.iter().map(|(i, _)| *i).zip(self.neighbor_weights(&neighbors)).collect()
+        };
+        let total: f64 = voters.iter().map(|(_, w)| w).sum();
+        let share = |same: bool| {
+            let mut shares: Vec<(&str, f64)> = vec![];
+            for (i, w) in &voters {
+                let l = self.items[*i].label.as_str();
+                if (l == label) != same { continue; }
+                match shares.iter_mut().find(|(x, _)| *x == l) {
+                    Some((_, v)) => *v += w,
+                    None => shares.push((l, *w)),
+                }
+            }
+            let best = shares.iter().map(|(_, w)| *w).fold(0.0, f64::max);
+            if total > 0.0 { best / total } else { 0.0 }
+        };
+        let confidence = share(true);
+        let labels: Vec<&str> = neighbors.iter().map(|(i, _)| self.items[*i].label.as_str()).collect();
+        let rejected = self.rejects(&neighbors, &labels, label);
+        let label = match &self.reject.label {
+            Some(reject) if rejected => reject.clone(),
+            _ => label.to_string(),
+        };
+        Ok(Prediction { label, confidence, margin: confidence - share(false), rejected })
+    }
+    /// Function to rank the labels of the k nearest neighbors by their vote share, best first (at most n)
+    ///
+    /// Ties are ordered like the vote (see `tie_break`), so the first label is the one `predict_one` returns
+    /// (unless all k neighbors coincide with the query, see `coincident`). Labels without votes are not listed.
+    pub fn predict_top_n(&self, item: &[f64], n: usize) -> Vec<(String, f64)> {
+        let neighbors = self.nearest(item, self.k);
+        let mut shares: Vec<(&str, f64)> = vec![];
+        for ((i, _), w) in neighbors.iter().zip(self.neighbor_weights(&neighbors)) {
+            let label = self.items[*i].label.as_str();
+            match shares.iter_mut().find(|(l, _)| *l == label) {
+                Some((_, v)) => *v += w,
+                None => shares.push((label, w)),
+            }
+        }
+        if self.tie_break == TieBreak::Lexicographic {
+            shares.sort_by(|a, b| a.0.cmp(b.0));
+        }
+        // stable sort: equal shares keep the tie order
+        shares.sort_by(|a, b| b.1.total_cmp(&a.1));
+        let total: f64 = shares.iter().map(|(_, w)| w).sum();
+        shares.into_iter().take(n).map(|(l, w)| (l.to_string(), if total > 0.0 { w / total } else { 0.0 })).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predict_proba_one() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.]], &["a", "a", "b", "c"]);
+        let p = c.predict_proba_one(&[0.5]);
+        assert_eq!(p, [("a".to_string(), 2. / 3.), ("b".to_string(), 1. / 3.), ("c".to_string(), 0.)]);
+        c.smoothing = 1.0;
+        let p = c.predict_proba_one(&[0.5]);
+        assert_eq!(p.iter().map(|(_, v)| *v).collect::<Vec<_>>(), [0.5, 2. / 6., 1. / 6.]);
+        // a deleted item adds no class
+        c.delete(3);
+        assert_eq!(c.classes(), ["a", "b"]);
+        assert_eq!(c.predict_proba_one(&[0.5]).len(), 2);
+    }
+    #[test]
+    fn test_predict_proba() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[10.]], &["a", "a", "b", "c"]);
+        let items = vec![vec![0.5], vec![9.]];
+        let p = c.predict_proba(&items);
+        assert_eq!(p[0], c.predict_proba_one(&items[0]));
+        assert_eq!(p[1], [("a".to_string(), 1. / 3.), ("b".to_string(), 1. / 3.), ("c".to_string(), 1. / 3.)]);
+        c.parallelism = crate::Parallelism::threads(2);
+        assert_eq!(c.predict_proba(&items), p);
+    }
+    #[test]
+    fn test_predict_proba_coincident() {
+        // 2 "a" then 3 "b" at the query: the 3 nearest are "a", "a", "b" but the coincident majority is "b"
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[1.], &[1.], &[1.], &[1.], &[1.], &[1.5]], &["a", "a", "b", "b", "b", "c"]);
+        let argmax = |p: Vec<(String, f64)>| p.into_iter().max_by(|a, b| a.1.total_cmp(&b.1)).unwrap().0;
+        assert_eq!(c.predict_proba_one(&[1.]), [("a".to_string(), 0.4), ("b".to_string(), 0.6), ("c".to_string(), 0.)]);
+        assert_eq!(argmax(c.predict_proba_one(&[1.])), c.predict_one(&[1.]));
+        c.coincident = crate::CoincidentVote::First;
+        assert_eq!(c.predict_proba_one(&[1.])[0].1, 1.0);
+        assert_eq!(argmax(c.predict_proba_one(&[1.])), c.predict_one(&[1.]));
+        c.coincident = crate::CoincidentVote::Neighbors;
+        assert_eq!(argmax(c.predict_proba_one(&[1.])), c.predict_one(&[1.]));
+        assert_eq!(c.predict_proba(&[vec![1.]])[0][0].1, 2. / 3.);
+    }
+    #[test]
+    fn test_predict_one_detailed() {
+        let mut c = KnnClassifier::new(5);
+        assert!(matches!(c.predict_one_detailed(&[0.]), Err(KnnError::NoNeighbors)));
+        c.fallback = Some("z".to_string());
+        assert_eq!(c.predict_one_detailed(&[0.]).unwrap(), Prediction { label: "z".to_string(), confidence: 0.0, margin: 0.0, rejected: false });
+        c.reject.max_distance = Some(1.0);
+        assert!(!c.predict_one_detailed(&[0.]).unwrap().rejected);
+        c.reject.max_distance = None;
+        c.fit(&[&[0.], &[1.], &[2.], &[3.], &[4.], &[9.]], &["a", "a", "b", "a", "c", "c"]);
+        let p = c.predict_one_detailed(&[0.]).unwrap();
+        assert_eq!(p.label, "a");
+        assert_eq!(p.confidence, 0.6);
+        assert!((p.margin - 0.4).abs() < 1e-12);
+        let p = c.predict_one_detailed(&[9.]).unwrap();
+        assert_eq!((p.label.as_str(), p.confidence), ("c", 0.4));
+        assert_eq!(p.label, c.predict_one(&[9.]));
+        c.fit(&[&[20.], &[20.], &[20.], &[20.], &[20.]], &["d", "d", "d", "d", "d"]);
+        assert_eq!(c.predict_one_detailed(&[20.]).unwrap().margin, 1.0);
+        // 5 "e" learned after the "d" at the same point: the coincident majority decides
+        c.fit(&[&[20.], &[20.], &[20.], &[20.], &[20.], &[20.]], &["e", "e", "e", "e", "e", "e"]);
+        let p = c.predict_one_detailed(&[20.]).unwrap();
+        assert_eq!((p.label.as_str(), p.confidence), ("e", 6.0 / 11.0));
+        assert!((p.margin - 1.0 / 11.0).abs() < 1e-12);
+        // rejected votes keep their shares
+        c.reject = crate::Reject { max_distance: Some(0.5), label: Some("unknown".to_string()), ..Default::default() };
+        let p = c.predict_one_detailed(&[6.5]).unwrap();
+        assert_eq!((p.label.as_str(), p.confidence, p.rejected), ("unknown", 0.4, true));
+        assert_eq!(p.label, c.predict_one(&[6.5]));
+        assert!(!c.predict_one_detailed(&[9.]).unwrap().rejected);
+    }
+    #[test]
+    fn test_predict_top_n() {
+        let mut c = KnnClassifier::new(5);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.], &[4.], &[9.]], &["b", "a", "c", "a", "c", "d"]);
+        let top = c.predict_top_n(&[0.], 3);
+        assert_eq!(top, [("a".to_string(), 0.4), ("c".to_string(), 0.4), ("b".to_string(), 0.2)]);
+        assert_eq!(top[0].0, c.predict_one(&[0.]));
+        assert_eq!(c.predict_top_n(&[0.], 1).len(), 1);
+        assert_eq!(c.predict_top_n(&[0.], 10).len(), 3);
+    }
+}
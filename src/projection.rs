@@ -0,0 +1,141 @@
+//! Johnson-Lindenstrauss random projection, to reduce the number of features of the stored items.
+
+use crate::lsh::Rng;
+use crate::{KnnClassifier, KnnError};
+use std::borrow::Cow;
+
+/// Random linear map to fewer dimensions that roughly keeps the distances (Johnson-Lindenstrauss)
+///
+/// The entries of the matrix are normal with variance `1 / components`, drawn from `seed`,
+/// so the same seed always gives the same projection.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct RandomProjection {
+    pub seed: u64,
+    /// number of features of the rows it projects
+    pub features: usize,
+    /// one row per output dimension
+    pub matrix: Vec<Vec<f64>>,
+}
+
+impl RandomProjection {
+    /// new projection of `features` dimensions onto `components` dimensions
+    pub fn new(features: usize, components: usize, seed: u64) -> RandomProjection {
+        let mut rng = Rng(seed);
+        let scale = 1.0 / (components.max(1) as f64).sqrt();
+        let matrix = (0..components).map(|_| (0..features).map(|_| rng.normal() * scale).collect()).collect();
+        RandomProjection { seed, features, matrix }
+    }
+    /// Function to calculate the number of components that keeps the distances between `samples` points
+    /// within a factor `1 ± eps` with high probability: `4 ln(samples) / (eps^2 / 2 - eps^3 / 3)`
+    ///
+    /// The bound does not depend on the number of features, and it is conservative in practice.
+    pub fn min_components(samples: usize, eps: f64) -> usize {
+        let denominator = eps * eps / 2.0 - eps * eps * eps / 3.0;
+        (4.0 * (samples.max(2) as f64).ln() / denominator).ceil() as usize
+    }
+    /// Function to project a row (missing values (NaN) count as 0)
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        self.matrix.iter().map(|m| m.iter().zip(row).filter(|(_, v)| !v.is_nan()).map(|(a, b)| a * b).sum()).collect()
+    }
+    // true when the row has the input features (projected rows are shorter)
+    fn applies_to(&self, row: &[f64]) -> bool {
+        row.len() == self.features && self.matrix.len() < self.features
+    }
+}
+
+impl KnnClassifier {
+    /// Function to replace the features of every item with a random projection onto `components` dimensions
+    ///
+    /// Distances then cost `components` instead of the number of features, and a low enough dimension
+    /// lets `Backend::KdTree` prune again. Queries with the original features are projected once per search,
+    /// and items learned later are projected too. The items keep only the projected features (as exported),
+    /// so keep the original data to project again. Fails when the items are already projected, when
+    /// `preprocess` is set (it was learned on the original features), or when `components` does not reduce
+    /// the features. See `RandomProjection::min_components` for a number of components.
+    pub fn project(&mut self, components: usize, seed: u64) -> Result<(), KnnError> {
+        let features = self.feature_ranges.len();
+        if self.projection.is_some() {
+            return Err(KnnError::Invalid("the items are already projected".to_string()));
+        }
+        if !self.preprocess.is_empty() {
+            return Err(KnnError::Invalid("set preprocess after projecting".to_string()));
+        }
+        if components == 0 || components >= features {
+            return Err(KnnError::Invalid(format!("{} components do not reduce {} features", components, features)));
+        }
+        self.projection = Some(RandomProjection::new(features, components, seed));
+        self.update_feature_ranges();
+        Ok(())
+    }
+    // the point in the projected features, when `projection` applies to it
+    pub(crate) fn projected<'a>(&self, row: &'a [f64]) -> Cow<'a, [f64]> {
+        match &self.projection {
+            Some(p) if p.applies_to(row) => Cow::Owned(p.transform(row)),
+            _ => Cow::Borrowed(row),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Backend, Reject};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_random_projection() {
+        assert_eq!(RandomProjection::min_components(1000, 0.5), 332);
+        let p = RandomProjection::new(300, 40, 9);
+        assert_eq!(p, RandomProjection::new(300, 40, 9));
+        assert_ne!(p, RandomProjection::new(300, 40, 10));
+        // two clusters of 300 features, 2.0 apart on every feature
+        let mut rng = Rng(3u64);
+        let mut rand = || rng.uniform();
+        let mut c = KnnClassifier::new(5);
+        for i in 0..200 {
+            let row: Vec<f64> = (0..300).map(|_| (i % 2) as f64 * 2.0 + rand()).collect();
+            c.fit_one(&row, ["a", "b"][i % 2]);
+        }
+        let queries: Vec<Vec<f64>> = (0..20).map(|i| (0..300).map(|_| (i % 2) as f64 * 2.0 + rand()).collect()).collect();
+        let expected = c.predict(&queries);
+        let d = c.distance(&c.items[0].data, &c.items[1].data);
+        let original = c.items[..2].to_vec();
+        assert!(c.project(300, 1).is_err());
+        c.project(12, 1).unwrap();
+        assert!(c.project(6, 1).is_err());
+        assert_eq!((c.items[0].data.len(), c.feature_ranges.len()), (12, 12));
+        // the distances are roughly kept, and a query is projected like the items
+        let pd = c.distance(&c.items[0].data, &c.items[1].data);
+        assert!((pd / d - 1.0).abs() < 0.3, "{} {}", d, pd);
+        assert_eq!(c.distance(&original[0].data, &original[1].data), pd);
+        assert_eq!(c.predict(&queries), expected);
+        assert_eq!(c.kneighbors(&original[0].data, 1)[0].2, 0.0);
+        // learned later, projected too
+        c.fit_one(&original[1].data, "c");
+        assert_eq!(c.items[200].data, c.items[1].data);
+        c.backend = Backend::KdTree;
+        assert_eq!(c.predict(&queries), expected);
+        assert_eq!(c.check_config().map_err(|e| e.to_string()), Ok(()));
+    }
+    #[test]
+    fn test_project_edges() {
+        // nothing to reduce in an empty model
+        let mut c = KnnClassifier::new(1);
+        assert!(c.project(1, 7).is_err());
+        let mut c = KnnClassifier::new(1);
+        for i in 0..20 {
+            let row: Vec<f64> = (0..10).map(|f| (i % 2 * 10 + f % 2) as f64).collect();
+            c.fit_one(&row, ["a", "b"][i % 2]);
+        }
+        c.fit_one(&[100.0; 10], "far");
+        c.project(4, 7).unwrap();
+        // a deleted item is never a neighbor, and NaN features count as 0 in the projection
+        c.delete(20);
+        assert_eq!(c.predict_one(&[100.0; 10]), "b");
+        let mut q = vec![0.0; 10];
+        q[3] = f64::NAN;
+        assert_eq!(c.predict_one(&q), "a");
+        c.reject = Reject { max_distance: Some(1.0), label: Some("unknown".to_string()), ..Default::default() };
+        assert_eq!(c.predict_one(&[100.0; 10]), "unknown");
+    }
+}
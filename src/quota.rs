@@ -0,0 +1,108 @@
+//! Per-class limits on the number of items.
+
+use crate::KnnClassifier;
+
+/// Item removed to make room when a class reaches its quota (see `KnnClassifier::set_class_quota`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Eviction {
+    /// the item of the class learned first (default), so the class keeps its latest items
+    #[default]
+    Oldest,
+    /// the item with the smallest `weight` (the one learned first on a tie)
+    Lightest,
+    /// none: the new item is not learned
+    Newest,
+}
+
+impl KnnClassifier {
+    /// Function to limit the number of items of a class (0 = the class is not learned)
+    ///
+    /// Every way of learning (`fit`, `fit_one`, `from_csv`, ...) keeps the class within the quota,
+    /// removing an item chosen by `eviction` before adding a new one, so a single busy source can not let one class
+    /// crowd out the others. The indexes of the items after a removed one shift. Items learned before the quota
+    /// was set are kept; `update_feature_ranges` learns the items again and applies it.
+    pub fn set_class_quota(&mut self, label: &str, max_items: usize) {
+        self.class_quotas.insert(label.to_string(), max_items);
+    }
+    // Function to make room for a new item of the class, returns false when the item should not be learned
+    pub(crate) fn make_room(&mut self, label: &str) -> bool {
+        let Some(&quota) = self.class_quotas.get(label) else { return true };
+        let live: Vec<usize> = (0..self.items.len()).filter(|i| !self.items[*i].deleted && self.items[*i].label == label).collect();
+        if live.len() < quota {
+            return true;
+        }
+        if quota == 0 || self.eviction == Eviction::Newest {
+            return false;
+        }
+        // remove the surplus (more than one item when the quota was lowered), latest indexes first
+        let mut victims: Vec<usize> = match self.eviction {
+            Eviction::Lightest => {
+                let mut by_weight = live.clone();
+                by_weight.sort_by(|a, b| self.items[*a].weight.total_cmp(&self.items[*b].weight).then(a.cmp(b)));
+                by_weight
+            },
+            _ => live.clone(),
+        };
+        victims.truncate(live.len() + 1 - quota);
+        victims.sort_unstable_by(|a, b| b.cmp(a));
+        for i in victims {
+            self.items.remove(i);
+        }
+        // the indexes shifted, even when the number of items is the same again after the new one
+        self.index.invalidate();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, Eviction, KnnClassifier, NeighborIndex};
+
+    #[test]
+    fn test_class_quota() {
+        let mut c = KnnClassifier::new(1);
+        c.set_class_quota("spam", 2);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.]], &["spam", "ham", "spam", "spam"]);
+        let data: Vec<(f64, &str)> = c.items.iter().map(|it| (it.data[0], it.label.as_str())).collect();
+        assert_eq!(data, [(1., "ham"), (2., "spam"), (3., "spam")]);
+        c.eviction = Eviction::Newest;
+        c.fit_one(&[4.], "spam");
+        assert_eq!(c.items.len(), 3);
+        c.eviction = Eviction::Lightest;
+        c.items[2].weight = 0.5;
+        c.fit_one(&[5.], "spam");
+        assert_eq!(c.items.iter().map(|it| it.data[0]).collect::<Vec<_>>(), [1., 2., 5.]);
+        assert_eq!(c.predict_one(&[3.]), "spam");
+        // lowering the quota applies to the items learned again
+        c.set_class_quota("spam", 1);
+        c.update_feature_ranges();
+        assert_eq!(c.items.iter().map(|it| it.data[0]).collect::<Vec<_>>(), [1., 5.]);
+        c.set_class_quota("ham", 0);
+        c.fit_one(&[6.], "ham");
+        assert_eq!(c.items.len(), 2);
+    }
+    // returns the first item, to see whether the index answered
+    #[derive(Debug)]
+    struct FirstItem;
+
+    impl NeighborIndex for FirstItem {
+        fn nearest(&self, clf: &KnnClassifier, query: &[f64], _n: usize) -> Option<Vec<(usize, f64)>> {
+            Some(vec![(0, clf.distance(&clf.items[0].data, query))])
+        }
+    }
+
+    #[test]
+    fn test_class_quota_custom_index() {
+        let mut c = KnnClassifier::new(1);
+        c.set_class_quota("a", 1);
+        c.fit(&[&[0.], &[10.]], &["a", "b"]);
+        c.set_index(FirstItem);
+        assert_eq!(c.predict_one(&[9.]), "a");
+        // the eviction shifts the items, so the index is dropped
+        c.fit_one(&[20.], "a");
+        assert_eq!(c.items.len(), 2);
+        assert_eq!(c.backend, Backend::Custom);
+        assert_eq!(c.predict_one(&[19.]), "a");
+        assert!(!c.index_built());
+    }
+}
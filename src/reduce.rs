@@ -0,0 +1,92 @@
+//! Instance reduction: removing noisy and redundant training items.
+
+use crate::KnnClassifier;
+
+impl KnnClassifier {
+    /// Function to remove noisy items (Wilson's edited nearest neighbor rule)
+    ///
+    /// An item is removed when the vote of its k nearest other items disagrees with its label.
+    /// All items are judged against the original set (deleted items do not vote). Returns the number of removed items.
+    pub fn edit(&mut self) -> usize {
+        let mut remove = vec![false; self.items.len()];
+        for (i, n) in self.training_neighbors(self.k) {
+            remove[i] = self.vote(&n).is_some_and(|l| l != self.items[i].label);
+        }
+        self.remove_flagged(&remove)
+    }
+    /// Function to keep only the items needed to classify the others with 1-nn (Hart's condensed nearest neighbor rule)
+    ///
+    /// Starting from the first live item, every item misclassified by its nearest kept item is kept too,
+    /// until no more item is added. Items inside a class are removed, items near class borders are kept.
+    /// Deleted items are never kept. Run `edit` first, otherwise noisy items are kept as borders.
+    /// Returns the number of removed items.
+    pub fn condense(&mut self) -> usize {
+        let Some((first, _)) = self.live_items().next() else { return 0; };
+        let mut kept = vec![false; self.items.len()];
+        kept[first] = true;
+        let mut kept_ids = vec![first];
+        loop {
+            let mut added = false;
+            for (i, it) in self.live_items() {
+                if kept[i] { continue; }
+                let nearest = kept_ids.iter()
+                    .map(|j| (*j, self.distance(&self.items[*j].data, &it.data)))
+                    .fold(None, |best: Option<(usize, f64)>, (j, d)| if best.is_none_or(|b| d < b.1) { Some((j, d)) } else { best });
+                if nearest.is_some_and(|(j, _)| self.items[j].label != it.label) {
+                    kept[i] = true;
+                    kept_ids.push(i);
+                    added = true;
+                }
+            }
+            if !added { break; }
+        }
+        let remove: Vec<bool> = kept.iter().map(|k| !k).collect();
+        self.remove_flagged(&remove)
+    }
+    fn remove_flagged(&mut self, remove: &[bool]) -> usize {
+        let before = self.items.len();
+        let mut flags = remove.iter();
+        self.items.retain(|_| !flags.next().unwrap());
+        let removed = before - self.items.len();
+        if removed > 0 {
+            self.update_feature_ranges();
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_edit_condense() {
+        let mut c = KnnClassifier::new(3);
+        // "b" at 1.5 is noise inside class "a"
+        c.fit(&[&[0.], &[1.], &[1.5], &[2.], &[3.], &[10.], &[11.], &[12.], &[13.]],
+            &["a", "a", "b", "a", "a", "b", "b", "b", "b"]);
+        assert_eq!(c.edit(), 1);
+        assert_eq!(c.items.len(), 8);
+        assert!(c.items.iter().all(|it| it.data[0] != 1.5));
+        // one item per class is enough for the remaining data
+        assert_eq!(c.condense(), 6);
+        assert_eq!(c.to_csv(','), "a,0\nb,10\n");
+        assert_eq!(c.predict_one(&[4.]), "a");
+        assert_eq!(c.condense(), 0);
+    }
+    #[test]
+    fn test_condense_deleted() {
+        // a deleted first item is not a prototype
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[1.], &[2.]], &["a", "a", "a"]);
+        c.delete(0);
+        assert_eq!(c.condense(), 2);
+        assert_eq!(c.to_csv(','), "a,1\n");
+        assert_eq!(c.try_predict_one(&[0.]).unwrap(), "a");
+        // a model with only deleted items is left alone
+        let mut c = KnnClassifier::new(1);
+        c.fit_one(&[0.], "a");
+        c.delete(0);
+        assert_eq!(c.condense(), 0);
+    }
+}
@@ -0,0 +1,239 @@
+//! Registry of many small classifiers (e.g. one model per customer).
+
+use crate::persist::replace_file;
+use crate::{KnnClassifier, KnnError, Parallelism, RangeCheck};
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// name of the index file of a saved registry
+const INDEX_FILE: &str = "registry.txt";
+
+#[derive(Debug)]
+struct Entry {
+    clf: KnnClassifier,
+    queries: AtomicUsize,
+}
+
+/// Statistics of one model of a `KnnRegistry`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelStats {
+    pub name: String,
+    /// number of learned items (deleted items are not counted)
+    pub items: usize,
+    /// number of distinct labels of the live items
+    pub classes: usize,
+    /// number of features of the first live item
+    pub features: usize,
+    /// number of `predict_one` calls through the registry since it was created or loaded
+    pub queries: usize,
+}
+
+/// Independent classifiers keyed by tenant or model name, sharing one configuration
+///
+/// `k`, `range_check` and `parallelism` are applied to every model the registry creates.
+/// After changing them, call `apply_config` to update the existing models too.
+/// Models are kept in name order.
+#[derive(Debug)]
+pub struct KnnRegistry {
+    pub k: usize,
+    pub range_check: RangeCheck,
+    pub parallelism: Parallelism,
+    models: BTreeMap<String, Entry>,
+}
+
+impl KnnRegistry {
+    /// new empty registry (`k` is adjusted like `KnnClassifier::new`)
+    pub fn new(k: usize) -> KnnRegistry {
+        let clf = KnnClassifier::new(k);
+        KnnRegistry { k: clf.k, range_check: clf.range_check, parallelism: clf.parallelism, models: BTreeMap::new() }
+    }
+    /// names of all models
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.models.keys().map(|s| s.as_str())
+    }
+    /// number of models
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+    /// true when there are no models
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+    /// the model with the name
+    pub fn get(&self, name: &str) -> Option<&KnnClassifier> {
+        self.models.get(name).map(|e| &e.clf)
+    }
+    /// the model with the name, created with the shared configuration when missing
+    pub fn model_mut(&mut self, name: &str) -> &mut KnnClassifier {
+        if !self.models.contains_key(name) {
+            let clf = self.configured(KnnClassifier::new(self.k));
+            self.insert(name, clf);
+        }
+        &mut self.models.get_mut(name).unwrap().clf
+    }
+    /// add or replace a model (the shared configuration is applied to it)
+    pub fn insert(&mut self, name: &str, clf: KnnClassifier) {
+        let clf = self.configured(clf);
+        self.models.insert(name.to_string(), Entry { clf, queries: AtomicUsize::new(0) });
+    }
+    /// remove a model
+    pub fn remove(&mut self, name: &str) -> Option<KnnClassifier> {
+        self.models.remove(name).map(|e| e.clf)
+    }
+    /// apply the shared configuration to all models
+    pub fn apply_config(&mut self) {
+        let (k, range_check, parallelism) = (self.k, self.range_check, self.parallelism);
+        for e in self.models.values_mut() {
+            e.clf.k = k;
+            e.clf.range_check = range_check;
+            e.clf.parallelism = parallelism;
+        }
+    }
+    fn configured(&self, mut clf: KnnClassifier) -> KnnClassifier {
+        clf.k = self.k;
+        clf.range_check = self.range_check;
+        clf.parallelism = self.parallelism;
+        clf
+    }
+    /// Function to add a single data point to a model (created when missing)
+    pub fn fit_one(&mut self, name: &str, data: &[f64], label: &str) {
+        self.model_mut(name).fit_one(data, label);
+    }
+    /// Function to predict with a model, `None` when the model does not exist or is empty
+    pub fn predict_one(&self, name: &str, item: &[f64]) -> Option<String> {
+        let e = self.models.get(name).filter(|e| e.clf.live_items().next().is_some())?;
+        e.queries.fetch_add(1, Ordering::Relaxed);
+        Some(e.clf.predict_one(item))
+    }
+    /// statistics of every model, in name order
+    pub fn stats(&self) -> Vec<ModelStats> {
+        self.models.iter().map(|(name, e)| {
+            let mut labels: Vec<&str> = e.clf.live_items().map(|(_, it)| it.label.as_str()).collect();
+            labels.sort_unstable();
+            labels.dedup();
+            ModelStats {
+                name: name.clone(),
+                items: e.clf.live_items().count(),
+                classes: labels.len(),
+                features: e.clf.live_items().next().map(|(_, it)| it.data.len()).unwrap_or(0),
+                queries: e.queries.load(Ordering::Relaxed),
+            }
+        }).collect()
+    }
+    /// Function to save all models to a directory (created when missing)
+    ///
+    /// Every model is written as a CSV file (label in column 0), and `registry.txt` records `k`
+    /// and the model names. Each file is replaced atomically. Files of removed models are left in place
+    /// but are not loaded again, because they are not listed in the index.
+    pub fn save_dir(&self, dir: impl AsRef<Path>) -> Result<(), KnnError> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).map_err(|e| KnnError::Io(e.to_string()))?;
+        let mut index = format!("k={}\n", self.k);
+        for (name, e) in &self.models {
+            let file = file_name(name);
+            replace_file(&dir.join(&file), e.clf.to_csv(',').as_bytes())?;
+            index.push_str(&file);
+            index.push('\n');
+        }
+        // the index is written last, so an interrupted save still loads the previous set of models
+        replace_file(&dir.join(INDEX_FILE), index.as_bytes())
+    }
+    /// Function to load the models saved by `save_dir`
+    pub fn load_dir(dir: impl AsRef<Path>) -> Result<KnnRegistry, KnnError> {
+        let dir = dir.as_ref();
+        let read = |file: &str| std::fs::read_to_string(dir.join(file)).map_err(|e| KnnError::Io(format!("{}: {}", file, e)));
+        let index = read(INDEX_FILE)?;
+        let mut lines = index.lines();
+        let k = lines.next().and_then(|l| l.strip_prefix("k=")).and_then(|k| k.parse().ok()).filter(|k| *k >= 1)
+            .ok_or_else(|| KnnError::Parse { line: 1, msg: "expected k=N (N >= 1)".to_string() })?;
+        let mut reg = KnnRegistry::new(k);
+        reg.k = k; // keep a positive k as saved, even when it is not odd
+        for (no, file) in lines.enumerate() {
+            // only names written by `save_dir` (a single file in `dir`, so `../x.csv` or `/x.csv` can not escape it)
+            let name = model_name(file).filter(|name| file_name(name) == file)
+                .ok_or_else(|| KnnError::Parse { line: no + 2, msg: format!("invalid file name: {}", file) })?;
+            let mut clf = KnnClassifier::new(k);
+            clf.from_csv_reader(read(file)?.as_bytes(), ',', 0, false, |_| {})?;
+            reg.insert(&name, clf);
+        }
+        Ok(reg)
+    }
+}
+
+// model name -> file name, with characters other than [A-Za-z0-9_-] percent-encoded
+fn file_name(name: &str) -> String {
+    let mut s = String::new();
+    for b in name.bytes() {
+        if b.is_ascii_alphanumeric() || b == b'_' || b == b'-' {
+            s.push(b as char);
+        } else {
+            s.push_str(&format!("%{:02X}", b));
+        }
+    }
+    s + ".csv"
+}
+
+fn model_name(file: &str) -> Option<String> {
+    let s = file.strip_suffix(".csv")?.as_bytes();
+    let mut bytes = vec![];
+    let mut i = 0;
+    while i < s.len() {
+        if s[i] == b'%' {
+            bytes.push(u8::from_str_radix(std::str::from_utf8(s.get(i + 1..i + 3)?).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            bytes.push(s[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_registry() {
+        let mut reg = KnnRegistry::new(1);
+        reg.fit_one("acme", &[170., 60.], "Normal");
+        reg.fit_one("acme", &[152., 99.], "Obesity");
+        reg.fit_one("株式会社/x", &[1., 2.], "a");
+        assert_eq!(reg.names().collect::<Vec<_>>(), ["acme", "株式会社/x"]);
+        assert_eq!(reg.predict_one("acme", &[151., 95.]).unwrap(), "Obesity");
+        assert_eq!(reg.predict_one("unknown", &[1., 2.]), None);
+        assert_eq!(reg.stats()[0], ModelStats { name: "acme".to_string(), items: 2, classes: 2, features: 2, queries: 1 });
+        reg.k = 3;
+        reg.apply_config();
+        assert_eq!(reg.get("acme").unwrap().k, 3);
+        // save and load
+        let dir = std::env::temp_dir().join(format!("knn_registry_{}", std::process::id()));
+        reg.save_dir(&dir).unwrap();
+        let loaded = KnnRegistry::load_dir(&dir).unwrap();
+        assert_eq!(loaded.k, 3);
+        assert_eq!(loaded.names().collect::<Vec<_>>(), ["acme", "株式会社/x"]);
+        assert_eq!(loaded.get("株式会社/x").unwrap().to_csv(','), "a,1,2\n");
+        for index in ["k=0\n", "k=x\n", "acme.csv\n"] {
+            std::fs::write(dir.join(INDEX_FILE), index).unwrap();
+            assert!(matches!(KnnRegistry::load_dir(&dir), Err(KnnError::Parse { line: 1, .. })), "{}", index);
+        }
+        // file names outside the directory are refused
+        std::fs::write(dir.join("x.csv"), "a,1,2\n").unwrap();
+        for file in ["../x.csv", "/tmp/x.csv", "sub/x.csv", "a.b.csv"] {
+            std::fs::write(dir.join(INDEX_FILE), format!("k=3\n{}\n", file)).unwrap();
+            assert!(matches!(KnnRegistry::load_dir(&dir), Err(KnnError::Parse { line: 2, .. })), "{}", file);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn test_registry_deleted() {
+        let mut reg = KnnRegistry::new(1);
+        reg.fit_one("acme", &[170., 60.], "Normal");
+        reg.fit_one("acme", &[152., 99.], "Obesity");
+        reg.model_mut("acme").delete(1);
+        assert_eq!(reg.stats()[0], ModelStats { name: "acme".to_string(), items: 1, classes: 1, features: 2, queries: 0 });
+        reg.model_mut("acme").delete(0);
+        assert_eq!(reg.predict_one("acme", &[151., 95.]), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! k-nn regression of numeric targets.
+
+use crate::{KnnClassifier, KnnItem};
+
+/// k-nn regressor: predicts the (weighted) mean of the targets of the k nearest items
+///
+/// The neighbor search is the one of `KnnClassifier`, so `knn` holds the features and all settings
+/// (`metric`, `backend`, `feature_weights`, `parallelism`, ...). Its `voting` weights the neighbors:
+/// `Vote::Uniform` averages their targets and `Vote::Distance` weights them by `1 / distance`.
+/// A target is a vector (e.g. (x, y) coordinates), averaged per dimension; `fit` and `predict_one` handle single numbers.
+#[derive(Debug, Clone)]
+pub struct KnnRegressor {
+    /// neighbor search; its items hold the features (their labels are empty)
+    pub knn: KnnClassifier,
+    /// target of each item of `knn.items` (kept in step by the fit functions)
+    pub targets: Vec<Vec<f64>>,
+}
+
+impl KnnRegressor {
+    /// new regressor with k (0 = 5; unlike the classifier, an even k is kept as there is no vote to tie)
+    pub fn new(k: usize) -> KnnRegressor {
+        let knn = KnnClassifier { k: if k > 0 { k } else { 5 }, ..KnnClassifier::new(k) };
+        KnnRegressor { knn, targets: vec![] }
+    }
+    /// Function to learn data and targets
+    pub fn fit(&mut self, data: &[&[f64]], targets: &[f64]) {
+        let targets: Vec<&[f64]> = targets.iter().map(std::slice::from_ref).collect();
+        self.fit_multi(data, &targets);
+    }
+    /// Function to learn data and vector targets
+    pub fn fit_multi(&mut self, data: &[&[f64]], targets: &[&[f64]]) {
+        for (it, target) in data.iter().zip(targets) {
+            self.knn.push_item(KnnItem::new("", it));
+            self.targets.push(target.to_vec());
+        }
+        self.knn.fit_statistics();
+    }
+    /// Function to add a single data point
+    pub fn fit_one(&mut self, data: &[f64], target: f64) {
+        self.fit_one_multi(data, &[target]);
+    }
+    /// Function to add a single data point with a vector target
+    pub fn fit_one_multi(&mut self, data: &[f64], target: &[f64]) {
+        self.knn.push_item(KnnItem::new("", data));
+        self.targets.push(target.to_vec());
+        self.knn.fit_statistics();
+    }
+    /// Function to predict the target of a single data point (the first dimension; NaN when there are no items)
+    pub fn predict_one(&self, item: &[f64]) -> f64 {
+        self.predict_one_multi(item).first().copied().unwrap_or(f64::NAN)
+    }
+    /// Function to predict the vector target of a single data point (empty when there are no items)
+    ///
+    /// Each dimension is the weighted mean over the neighbors whose target has it (missing or NaN values are left out).
+    /// When every weight is 0 (e.g. a kernel with no neighbor within the bandwidth),
+    /// the value of the nearest neighbor is returned.
+    pub fn predict_one_multi(&self, item: &[f64]) -> Vec<f64> {
+        let neighbors = self.knn.nearest(item, self.knn.k);
+        let weights = self.knn.neighbor_weights(&neighbors);
+        let width = neighbors.iter().map(|(i, _)| self.targets[*i].len()).max().unwrap_or(0);
+        (0..width).map(|d| {
+            let value = |i: usize| self.targets[i].get(d).copied().filter(|v| !v.is_nan());
+            let (sum, total) = neighbors.iter().zip(&weights)
+                .filter_map(|((i, _), w)| value(*i).map(|v| (v * w, *w)))
+                .fold((0.0, 0.0), |(s, t), (v, w)| (s + v, t + w));
+            if total > 0.0 {
+                sum / total
+            } else {
+                neighbors.iter().find_map(|(i, _)| value(*i)).unwrap_or(f64::NAN)
+            }
+        }).collect()
+    }
+    /// Function to predict the targets of multiple data points (on multiple threads when `parallelism` allows it)
+    pub fn predict(&self, items: &[Vec<f64>]) -> Vec<f64> {
+        self.knn.map_queries(items, |it| self.predict_one(it))
+    }
+    /// Function to predict the vector targets of multiple data points
+    pub fn predict_multi(&self, items: &[Vec<f64>]) -> Vec<Vec<f64>> {
+        self.knn.map_queries(items, |it| self.predict_one_multi(it))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnRegressor, Vote};
+
+    #[test]
+    fn test_regressor() {
+        let mut r = KnnRegressor::new(2);
+        r.fit(&[&[0.], &[1.], &[2.], &[4.]], &[10., 20., 30., 50.]);
+        assert_eq!(r.predict_one(&[0.4]), 15.0);
+        // 1/0.8 for 50 and 1/1.2 for 30
+        r.knn.voting = Vote::Distance;
+        assert!((r.predict_one(&[3.2]) - (50.0 / 0.8 + 30.0 / 1.2) / (1.0 / 0.8 + 1.0 / 1.2)).abs() < 1e-9);
+        assert_eq!(r.predict_one(&[4.]), 50.0);
+        r.knn.backend = Backend::KdTree;
+        let p = r.predict(&[vec![0.5], vec![4.]]);
+        assert_eq!(p, [15.0, 50.0]);
+        assert!(KnnRegressor::new(3).predict_one(&[1.]).is_nan());
+    }
+    #[test]
+    fn test_multi_output() {
+        let mut r = KnnRegressor::new(2);
+        r.fit_multi(&[&[0.], &[1.], &[5.]], &[&[0., 10.], &[2., f64::NAN], &[100., 100.]]);
+        assert_eq!(r.predict_one_multi(&[0.4]), [1., 10.]);
+        assert_eq!(r.predict_one(&[0.4]), 1.);
+        r.fit_one_multi(&[0.5], &[4.]);
+        // the second dimension of the neighbors at 0.5 and 0
+        assert_eq!(r.predict_multi(&[vec![0.5]]), [vec![2., 10.]]);
+        assert_eq!(KnnRegressor::new(1).predict_one_multi(&[0.]), Vec::<f64>::new());
+    }
+}
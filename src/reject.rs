@@ -0,0 +1,91 @@
+//! Rejection of uncertain predictions.
+
+use crate::KnnClassifier;
+
+/// Thresholds a prediction must meet to be accepted (see `KnnClassifier::predict_one_or_reject`)
+///
+/// The default accepts every prediction.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Reject {
+    /// minimum vote share of the predicted label among the k nearest neighbors (0.0 = no minimum)
+    pub min_confidence: f64,
+    /// maximum distance of the nearest neighbor (`None` = no maximum)
+    pub max_distance: Option<f64>,
+    /// label `predict_one` returns for rejected predictions (`None`: `predict_one` still guesses)
+    pub label: Option<String>,
+}
+
+impl Reject {
+    // true when some threshold is set
+    fn is_active(&self) -> bool {
+        self.min_confidence > 0.0 || self.max_distance.is_some()
+    }
+}
+
+impl KnnClassifier {
+    /// Function to predict, returning `None` when the prediction does not meet the thresholds of `reject`
+    ///
+    /// Rather than forcing a guess, a query is rejected when the vote share of the predicted label is below
+    /// `min_confidence` (weighted like the vote, see `voting`) or its nearest neighbor is farther than `max_distance`.
+    /// Only votes are judged: when no item can vote, `fallback` is returned as is (and `predict_one` returns
+    /// the fallback rather than `reject.label`).
+    pub fn predict_one_or_reject(&self, item: &[f64]) -> Option<String> {
+        let neighbors = self.nearest(item, self.k);
+        let label = self.decide(item, &neighbors)?;
+        let labels: Vec<&str> = neighbors.iter().map(|(i, _)| self.items[*i].label.as_str()).collect();
+        (!self.rejects(&neighbors, &labels, label)).then(|| label.to_string())
+    }
+    // Function to check the thresholds of `reject` for a label decided from the neighbors (with their labels)
+    // (the `fallback` of a query without neighbors is never rejected)
+    pub(crate) fn rejects(&self, neighbors: &[(usize, f64)], labels: &[&str], label: &str) -> bool {
+        if !self.reject.is_active() || neighbors.is_empty() {
+            return false;
+        }
+        if let Some(max) = self.reject.max_distance {
+            if neighbors.first().is_none_or(|n| n.1.is_nan() || n.1 > max) {
+                return true;
+            }
+        }
+        let weights = self.labeled_neighbor_weights(neighbors, labels);
+        let total: f64 = weights.iter().sum();
+        let votes: f64 = labels.iter().zip(&weights).filter(|(l, _)| **l == label).map(|(_, w)| w).sum();
+        if total > 0.0 { votes / total < self.reject.min_confidence } else { self.reject.min_confidence > 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KnnClassifier, Reject};
+
+    #[test]
+    fn test_reject() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.], &[4.], &[10.]], &["a", "a", "b", "b", "b", "b"]);
+        assert_eq!(c.predict_one_or_reject(&[0.5]).unwrap(), "a");
+        c.reject = Reject { min_confidence: 0.7, ..Default::default() };
+        // 2 of 3 votes
+        assert_eq!(c.predict_one_or_reject(&[0.5]), None);
+        assert_eq!(c.predict_one_or_reject(&[3.5]).unwrap(), "b");
+        assert_eq!(c.predict_one(&[0.5]), "a");
+        c.reject = Reject { max_distance: Some(2.0), label: Some("unknown".to_string()), ..Default::default() };
+        assert_eq!(c.predict_one_or_reject(&[6.5]), None);
+        assert_eq!(c.predict_one(&[6.5]), "unknown");
+        assert_eq!(c.predict_one(&[8.5]), "b");
+        assert_eq!(c.clone().freeze().predict_one(&[6.5]), "unknown");
+        assert_eq!(KnnClassifier::new(3).predict_one_or_reject(&[0.]), None);
+    }
+    #[test]
+    fn test_reject_fallback() {
+        // the fallback is not a vote, so it is not rejected
+        let mut c = KnnClassifier::new(1);
+        c.fallback = Some("default".to_string());
+        c.reject = Reject { min_confidence: 0.9, max_distance: Some(1.0), label: Some("unknown".to_string()) };
+        assert_eq!(c.predict_one(&[0.]), "default");
+        assert_eq!(c.predict_one_or_reject(&[0.]).unwrap(), "default");
+        assert_eq!(c.clone().freeze().predict_one(&[0.]), "default");
+        c.fit_one(&[0.], "a");
+        assert_eq!(c.predict_one(&[5.]), "unknown");
+        c.delete(0);
+        assert_eq!(c.predict_one(&[5.]), "default");
+    }
+}
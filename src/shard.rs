@@ -0,0 +1,175 @@
+//! Per-class sharded storage for datasets with many classes.
+
+use crate::index::neighbor_order;
+use crate::{majority, KnnClassifier, KnnError};
+
+// All items of one class, stored as a flat array
+#[derive(Debug, Clone)]
+struct Shard {
+    label: String,
+    data: Vec<f64>,
+    dim: usize,
+}
+
+impl Shard {
+    fn rows(&self) -> impl Iterator<Item = &[f64]> {
+        self.data.chunks(self.dim.max(1))
+    }
+}
+
+/// k-nn classifier that stores items per class
+///
+/// Each label is stored once per class (not once per item) and the data of a class is kept
+/// in one flat array, which saves memory when there are thousands of classes.
+/// Queries can be restricted to candidate classes, so only those shards are scanned and
+/// irrelevant classes can not add noise to the vote.
+///
+/// The distance settings of the classifier given to `from_classifier` (`metric`, `feature_weights`,
+/// `projection`, `preprocess` and `standardize`) are kept, and the items are stored as the distance sees them
+/// (see `KnnClassifier::transform_point`), so only the query is transformed. The vote is uniform.
+#[derive(Debug, Clone)]
+pub struct ShardedClassifier {
+    pub k: usize,
+    shards: Vec<Shard>,
+    // distance settings (a classifier without items)
+    settings: KnnClassifier,
+}
+
+impl ShardedClassifier {
+    /// new empty classifier with k (0 or odd number, see `KnnClassifier::new`) and Euclidean distance
+    pub fn new(k: usize) -> ShardedClassifier {
+        let settings = KnnClassifier::new(k);
+        ShardedClassifier { k: settings.k, shards: vec![], settings }
+    }
+    /// build from a classifier with its distance settings (the items are moved into per-class shards, deleted items are left out)
+    ///
+    /// Items with another number of features than the first item are left out.
+    pub fn from_classifier(mut clf: KnnClassifier) -> ShardedClassifier {
+        let items = std::mem::take(&mut clf.items);
+        clf.deleted = 0;
+        clf.index.clear();
+        let mut sc = ShardedClassifier { k: clf.k, shards: vec![], settings: clf };
+        for it in items.into_iter().filter(|it| !it.deleted) {
+            let _ = sc.fit_one(&it.data, &it.label);
+        }
+        sc
+    }
+    /// Function to add a single data point
+    ///
+    /// All items should have the same number of features (`KnnError::Invalid` otherwise).
+    pub fn fit_one(&mut self, data: &[f64], label: &str) -> Result<(), KnnError> {
+        let point = self.point(data);
+        if let Some(dim) = self.shards.first().map(|s| s.dim).filter(|dim| *dim != point.len()) {
+            return Err(KnnError::Invalid(format!("expected {} features, found {}", dim, point.len())));
+        }
+        match self.shards.iter_mut().find(|s| s.label == label) {
+            Some(shard) => shard.data.extend_from_slice(&point),
+            None => self.shards.push(Shard { label: label.to_string(), dim: point.len(), data: point }),
+        }
+        Ok(())
+    }
+    // Function to transform a point as the distance sees it (see `KnnClassifier::transform_point`)
+    pub(crate) fn point(&self, row: &[f64]) -> Vec<f64> {
+        self.settings.transform_point(row)
+    }
+    // distance of two points from `point`
+    pub(crate) fn point_distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        self.settings.point_distance(a, b)
+    }
+    /// labels of all classes
+    pub fn classes(&self) -> impl Iterator<Item = &str> {
+        self.shards.iter().map(|s| s.label.as_str())
+    }
+    /// number of items of the class
+    pub fn class_len(&self, label: &str) -> usize {
+        self.shards.iter().find(|s| s.label == label).map(|s| s.rows().count()).unwrap_or(0)
+    }
+    /// mean of the items of each class as the distance sees them (see `KnnClassifier::transform_point`), in the order of `classes`
+    pub fn centroids(&self) -> Vec<Vec<f64>> {
+        self.shards.iter().map(|shard| {
+            let mut sum = vec![0.0; shard.dim];
+            let mut n = 0;
+            for row in shard.rows() {
+                sum.iter_mut().zip(row).for_each(|(s, v)| *s += v);
+                n += 1;
+            }
+            sum.iter().map(|s| s / n as f64).collect()
+        }).collect()
+    }
+    /// Function to predict based on a single data point
+    ///
+    /// Panics when there are no items, like `KnnClassifier::predict_one`.
+    pub fn predict_one(&self, item: &[f64]) -> String {
+        self.predict_one_filtered(item, |_| true).unwrap_or_else(|| panic!("{}", KnnError::NoNeighbors))
+    }
+    /// Function to predict, only voting among the candidate classes
+    ///
+    /// Returns `None` when no candidate class has items.
+    pub fn predict_one_among(&self, item: &[f64], classes: &[&str]) -> Option<String> {
+        self.predict_one_filtered(item, |label| classes.contains(&label))
+    }
+    /// Function to predict, only scanning the classes accepted by the filter
+    pub fn predict_one_filtered(&self, item: &[f64], filter: impl Fn(&str) -> bool) -> Option<String> {
+        let item = &self.point(item);
+        let mut distances: Vec<(usize, f64)> = vec![];
+        for (si, shard) in self.shards.iter().enumerate() {
+            if !filter(&shard.label) { continue; }
+            distances.extend(shard.rows().map(|row| (si, self.point_distance(row, item))));
+        }
+        distances.sort_by(neighbor_order);
+        let labels = distances.iter().take(self.k).map(|(si, _)| self.shards[*si].label.as_str());
+        majority(labels).map(|l| l.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sharded() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(
+            &[&[170., 60.], &[166., 58.], &[152., 99.], &[163., 95.], &[150., 90.], &[175., 45.]],
+            &["Normal", "Normal", "Obesity", "Obesity", "Obesity", "Thin"]);
+        let sc = ShardedClassifier::from_classifier(c);
+        assert_eq!(sc.classes().collect::<Vec<_>>(), ["Normal", "Obesity", "Thin"]);
+        assert_eq!(sc.class_len("Obesity"), 3);
+        assert_eq!(sc.predict_one(&[159., 85.]), "Obesity");
+        assert_eq!(sc.predict_one_among(&[159., 85.], &["Normal", "Thin"]).unwrap(), "Normal");
+        assert_eq!(sc.predict_one_among(&[159., 85.], &["Unknown"]), None);
+        let mut sc = sc;
+        assert!(sc.fit_one(&[150.], "Thin").is_err());
+        assert!(sc.fit_one(&[150., 40., 1.], "New").is_err());
+        assert_eq!(sc.class_len("Thin"), 1);
+        assert!(sc.fit_one(&[150., 40.], "Thin").is_ok());
+    }
+    #[test]
+    fn test_sharded_settings() {
+        // the origin is nearer to "a" with L2 and to "b" with L1 (see `test_metric`)
+        let mut c = KnnClassifier::with_metric(1, crate::Metric::Manhattan);
+        c.fit(&[&[3., 3.], &[5., 0.]], &["a", "b"]);
+        assert_eq!(ShardedClassifier::from_classifier(c.clone()).predict_one(&[0., 0.]), "b");
+        c.metric = crate::Metric::Euclidean;
+        c.set_feature_weights(&[1., 0.]).unwrap();
+        assert_eq!(ShardedClassifier::from_classifier(c.clone()).predict_one(&[0., 0.]), "a");
+        // standardized, with a missing feature
+        let mut c = KnnClassifier::new(1);
+        c.standardize = true;
+        c.fit(&[&[1500., 60.], &[1900., 62.], &[1700., 90.]], &["a", "b", "c"]);
+        let sc = ShardedClassifier::from_classifier(c.clone());
+        for q in [[1550., 70.], [1850., 85.], [1700., f64::NAN]] {
+            assert_eq!(sc.predict_one(&q), c.predict_one(&q));
+        }
+    }
+    #[test]
+    fn test_from_classifier_deleted() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[9.], &[10.]], &["a", "b", "a"]);
+        c.delete(2);
+        assert_eq!(c.predict_one(&[10.]), "b");
+        let sc = ShardedClassifier::from_classifier(c);
+        assert_eq!(sc.predict_one(&[10.]), "b");
+        assert_eq!(sc.class_len("a"), 1);
+    }
+}
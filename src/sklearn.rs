@@ -0,0 +1,125 @@
+//! Import of scikit-learn `KNeighborsClassifier` models.
+//!
+//! The fitted model is exchanged as JSON. It can be written from Python like this:
+//!
+//! ```python
+//! import json
+//! json.dump({
+//!     "n_neighbors": clf.n_neighbors,
+//!     "metric": clf.effective_metric_,
+//!     "p": clf.p,
+//!     "weights": clf.weights,
+//!     "X": clf._fit_X.tolist(),
+//!     "y": clf.classes_[clf._y].tolist(),
+//! }, open("model.json", "w"))
+//! ```
+//!
+//! Labels may be strings or numbers. Numeric labels are converted to strings (`1.0` becomes `"1"`).
+//! Tied votes are decided by comparing the labels as strings, so for numeric labels of different lengths
+//! (`9` and `10`) the order can differ from the numeric order of `classes_`.
+//!
+//! The metrics `euclidean`, `manhattan` (`cityblock`, `l1`), `chebyshev` and `minkowski` with p >= 1 are supported,
+//! with `weights` `"uniform"` or `"distance"` (`Vote::Distance`).
+
+use crate::json::Json;
+use crate::{KnnClassifier, KnnError, KnnItem, Metric, TieBreak, Vote};
+
+impl KnnClassifier {
+    /// load a fitted scikit-learn `KNeighborsClassifier` from its JSON dump
+    ///
+    /// `k` is taken as is from `n_neighbors` (even values are not adjusted),
+    /// so the imported model votes over the same neighbors as the original one.
+    /// Tied votes go to the smallest label (`TieBreak::Lexicographic`), like in scikit-learn.
+    pub fn from_sklearn_json(s: &str) -> Result<KnnClassifier, KnnError> {
+        let root = Json::parse(s)?;
+        let field = |key: &str| root.get(key).ok_or_else(|| sklearn_err(&format!("missing field: {}", key)));
+        let k = field("n_neighbors")?.as_f64().filter(|v| *v >= 1.0 && v.fract() == 0.0)
+            .ok_or_else(|| sklearn_err("n_neighbors should be a positive integer"))?;
+        // check the hyperparameters supported by this crate
+        let metric = root.get("metric").and_then(|m| m.as_str()).unwrap_or("minkowski");
+        let p = root.get("p").and_then(|p| p.as_f64()).unwrap_or(2.0);
+        let metric = match metric {
+            "euclidean" | "l2" => Metric::Euclidean,
+            "manhattan" | "cityblock" | "l1" => Metric::Manhattan,
+            "chebyshev" | "infinity" => Metric::Chebyshev,
+            "minkowski" if p == 2.0 => Metric::Euclidean,
+            "minkowski" if p == 1.0 => Metric::Manhattan,
+            "minkowski" if p > 1.0 => Metric::Minkowski(p),
+            _ => return Err(sklearn_err(&format!("unsupported metric: {} (p={})", metric, p))),
+        };
+        let voting = match root.get("weights").and_then(|w| w.as_str()).unwrap_or("uniform") {
+            "uniform" => Vote::Uniform,
+            "distance" => Vote::Distance,
+            weights => return Err(sklearn_err(&format!("unsupported weights: {}", weights))),
+        };
+        // training data
+        let x = field("X")?.as_array().ok_or_else(|| sklearn_err("X should be an array"))?;
+        let y = field("y")?.as_array().ok_or_else(|| sklearn_err("y should be an array"))?;
+        if x.len() != y.len() {
+            return Err(sklearn_err("X and y have different lengths"));
+        }
+        let mut clf = KnnClassifier::with_metric(0, metric);
+        clf.k = k as usize;
+        clf.voting = voting;
+        // scikit-learn gives a tied vote to the first label in `classes_`, which is sorted
+        clf.tie_break = TieBreak::Lexicographic;
+        for (row, label) in x.iter().zip(y.iter()) {
+            let data = row.as_array()
+                .and_then(|r| r.iter().map(|v| v.as_f64()).collect::<Option<Vec<f64>>>())
+                .ok_or_else(|| sklearn_err("X should be an array of number arrays"))?;
+            let label = match label {
+                Json::Str(s) => s.clone(),
+                Json::Num(v) => v.to_string(),
+                Json::Bool(b) => b.to_string(),
+                _ => return Err(sklearn_err("y should contain strings or numbers")),
+            };
+            clf.push_item(KnnItem { label, data, ..KnnItem::new("", &[]) });
+        }
+        Ok(clf)
+    }
+}
+
+fn sklearn_err(msg: &str) -> KnnError {
+    KnnError::Decode(format!("sklearn: {}", msg))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_sklearn_json() {
+        let s = r#"{"n_neighbors": 2, "metric": "minkowski", "p": 2, "weights": "uniform",
+            "X": [[170.0, 60.0], [166.0, 58.0], [152.0, 99.0]], "y": ["Normal", "Normal", 1.0]}"#;
+        let c = KnnClassifier::from_sklearn_json(s).unwrap();
+        assert_eq!(c.k, 2);
+        assert_eq!(c.to_csv(','), "Normal,170,60\nNormal,166,58\n1,152,99\n");
+        assert_eq!((c.metric, c.voting), (Metric::Euclidean, Vote::Uniform));
+        // distance weights and the other Minkowski metrics
+        let s = r#"{"n_neighbors": 3, "weights": "distance", "X": [[0.0], [1.0], [5.0]], "y": ["a", "a", "b"]}"#;
+        let c = KnnClassifier::from_sklearn_json(s).unwrap();
+        assert_eq!(c.voting, Vote::Distance);
+        assert_eq!(c.predict_one(&[4.5]), "b");
+        // a tie goes to the first of the sorted labels, not to the nearest neighbor
+        let s = r#"{"n_neighbors": 2, "X": [[0.0], [2.0]], "y": ["b", "a"]}"#;
+        let c = KnnClassifier::from_sklearn_json(s).unwrap();
+        assert_eq!((c.tie_break, c.predict_one(&[0.9]).as_str()), (TieBreak::Lexicographic, "a"));
+        // n_neighbors is a positive integer
+        for n in ["0", "-1", "3.7", "\"3\""] {
+            let s = format!(r#"{{"n_neighbors": {}, "X": [], "y": []}}"#, n);
+            assert!(matches!(KnnClassifier::from_sklearn_json(&s), Err(KnnError::Decode(_))), "{}", n);
+        }
+        let metric = |m: &str, p: f64| {
+            let s = format!(r#"{{"n_neighbors": 3, "metric": "{}", "p": {}, "X": [], "y": []}}"#, m, p);
+            KnnClassifier::from_sklearn_json(&s).map(|c| c.metric)
+        };
+        assert_eq!(metric("manhattan", 2.0), Ok(Metric::Manhattan));
+        assert_eq!(metric("minkowski", 1.0), Ok(Metric::Manhattan));
+        assert_eq!(metric("minkowski", 3.0), Ok(Metric::Minkowski(3.0)));
+        // unsupported settings are rejected
+        assert!(metric("minkowski", 0.5).is_err());
+        assert!(metric("jaccard", 2.0).is_err());
+        let s = r#"{"n_neighbors": 3, "weights": "custom", "X": [], "y": []}"#;
+        assert!(KnnClassifier::from_sklearn_json(s).is_err());
+    }
+}
@@ -0,0 +1,57 @@
+//! Trust weights of data sources, for training sets combined from data of different quality.
+
+use crate::{KnnClassifier, KnnError, KnnItem};
+
+impl KnnClassifier {
+    /// Function to learn from data of a source (e.g. "human", "heuristic")
+    ///
+    /// The items remember their source, and their votes are multiplied by the trust weight
+    /// of the source (`set_source_weight`), so a less reliable dataset can be added without duplicating
+    /// the reliable one. The weights can be changed at any time without learning the items again.
+    /// Sources are kept in memory only; CSV and MessagePack files do not store them.
+    pub fn fit_source(&mut self, data: &[&[f64]], labels: &[&str], source: &str) {
+        for (it, label) in data.iter().zip(labels.iter()) {
+            let mut item = KnnItem::new(label, it);
+            item.source = Some(source.to_string());
+            self.push_item(item);
+        }
+        self.fit_statistics();
+    }
+    /// Function to set the trust weight of a source (1.0 by default, 0.0 = the source does not vote)
+    pub fn set_source_weight(&mut self, source: &str, weight: f64) -> Result<(), KnnError> {
+        if !weight.is_finite() || weight < 0.0 {
+            return Err(KnnError::Invalid(format!("source weight should be a non-negative number: {}", weight)));
+        }
+        self.source_weights.insert(source.to_string(), weight);
+        Ok(())
+    }
+    // voting weight of an item: its own weight times the trust weight of its source
+    pub(crate) fn vote_weight(&self, i: usize) -> f64 {
+        let it = &self.items[i];
+        let trust = it.source.as_ref().and_then(|s| self.source_weights.get(s)).copied().unwrap_or(1.0);
+        it.weight * trust
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fit_source() {
+        let mut c = KnnClassifier::new(3);
+        c.fit_source(&[&[0.], &[1.]], &["a", "a"], "human");
+        c.fit_source(&[&[0.5], &[1.5], &[2.]], &["b", "b", "b"], "heuristic");
+        assert_eq!(c.items[2].source.as_deref(), Some("heuristic"));
+        // 1 human vote against 2 heuristic votes
+        assert_eq!(c.predict_one(&[1.2]), "b");
+        c.set_source_weight("heuristic", 0.3).unwrap();
+        assert_eq!(c.predict_one(&[1.2]), "a");
+        let proba = c.predict_proba_one(&[1.2]);
+        assert!((proba[0].1 - 1.0 / 1.6).abs() < 1e-12);
+        assert!(c.set_source_weight("heuristic", -1.0).is_err());
+        // items without a source are not affected
+        c.fit_one(&[1.2], "c");
+        assert_eq!(c.vote_weight(5), 1.0);
+    }
+}
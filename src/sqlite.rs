@@ -0,0 +1,287 @@
+//! SQLite persistence of the classifier (feature `sqlite`).
+//!
+//! The model is stored in three tables, so it can be queried and edited with SQL tools:
+//!
+//! ```text
+//! config(key TEXT PRIMARY KEY, value TEXT NOT NULL)
+//! items(id INTEGER PRIMARY KEY, label TEXT NOT NULL, weight REAL NOT NULL, source TEXT, timestamp INTEGER)
+//! features(item INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE, position INTEGER NOT NULL, value REAL,
+//!          PRIMARY KEY (item, position))
+//! ```
+//!
+//! `config` holds `version`, `k`, `metric` (with `p` for minkowski), `voting` (with `bandwidth` for gaussian and
+//! epanechnikov), `tie_break`, `coincident`, `fallback` (missing when None), `smoothing`, `standardize`
+//! and `feature_weights` (comma-separated, empty for none). Items are loaded in `id` order; a missing feature (NaN)
+//! is stored as a NULL `value`. Deleted items are left out.
+//!
+//! Preprocessing steps, a random projection and custom voting are not stored: `save_sqlite` refuses them with
+//! `KnnError::Config` (use `to_msgpack`). The reject rule, source weights, label aliases and class quotas are not stored either.
+
+use crate::{CoincidentVote, KnnClassifier, KnnError, KnnItem, Metric, TieBreak, Vote};
+use rusqlite::{params, Connection, OpenFlags};
+use std::collections::HashMap;
+use std::path::Path;
+
+// version of the schema written by `save_sqlite`
+const SCHEMA_VERSION: u64 = 1;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS config (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+CREATE TABLE IF NOT EXISTS items (id INTEGER PRIMARY KEY, label TEXT NOT NULL, weight REAL NOT NULL, source TEXT, timestamp INTEGER);
+CREATE TABLE IF NOT EXISTS features (item INTEGER NOT NULL REFERENCES items(id) ON DELETE CASCADE,
+    position INTEGER NOT NULL, value REAL, PRIMARY KEY (item, position));
+";
+
+impl KnnClassifier {
+    /// Function to save the classifier to an SQLite database (created when missing, its model tables replaced)
+    pub fn save_sqlite(&self, path: impl AsRef<Path>) -> Result<(), KnnError> {
+        let config = self.sqlite_config()?;
+        let path = path.as_ref();
+        let err = |e: rusqlite::Error| KnnError::Io(format!("{}: {}", path.display(), e));
+        let mut conn = Connection::open(path).map_err(err)?;
+        // one transaction, so a reader never sees half a model
+        let tx = conn.transaction().map_err(err)?;
+        tx.execute_batch(SCHEMA).map_err(err)?;
+        tx.execute_batch("DELETE FROM features; DELETE FROM items; DELETE FROM config;").map_err(err)?;
+        {
+            let mut insert = tx.prepare("INSERT INTO config (key, value) VALUES (?1, ?2)").map_err(err)?;
+            for (key, value) in &config {
+                insert.execute(params![key, value]).map_err(err)?;
+            }
+            let mut item = tx.prepare("INSERT INTO items (id, label, weight, source, timestamp) VALUES (?1, ?2, ?3, ?4, ?5)").map_err(err)?;
+            let mut feature = tx.prepare("INSERT INTO features (item, position, value) VALUES (?1, ?2, ?3)").map_err(err)?;
+            for (id, (_, it)) in self.live_items().enumerate() {
+                // SQLite integers are signed
+                let timestamp = it.timestamp.map(|t| i64::try_from(t).map_err(|_| KnnError::Invalid(format!("timestamp too large: {}", t)))).transpose()?;
+                item.execute(params![id as i64, it.label, it.weight, it.source, timestamp]).map_err(err)?;
+                for (position, v) in it.data.iter().enumerate() {
+                    feature.execute(params![id as i64, position as i64, (!v.is_nan()).then_some(*v)]).map_err(err)?;
+                }
+            }
+        }
+        tx.commit().map_err(err)
+    }
+    /// Function to load a classifier saved by `save_sqlite`
+    pub fn load_sqlite(path: impl AsRef<Path>) -> Result<KnnClassifier, KnnError> {
+        let path = path.as_ref();
+        let err = |e: rusqlite::Error| KnnError::Io(format!("{}: {}", path.display(), e));
+        // do not create a database that is not there
+        let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(err)?;
+        let config: HashMap<String, String> = conn.prepare("SELECT key, value FROM config").map_err(err)?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?))).map_err(err)?
+            .collect::<Result<_, _>>().map_err(err)?;
+        let mut clf = KnnClassifier::new(1);
+        clf.read_sqlite_config(&config)?;
+        let mut data: HashMap<i64, Vec<f64>> = HashMap::new();
+        let mut features = conn.prepare("SELECT item, position, value FROM features ORDER BY item, position").map_err(err)?;
+        let mut rows = features.query([]).map_err(err)?;
+        while let Some(row) = rows.next().map_err(err)? {
+            let (item, position, value): (i64, i64, Option<f64>) = (row.get(0).map_err(err)?, row.get(1).map_err(err)?, row.get(2).map_err(err)?);
+            let values = data.entry(item).or_default();
+            if position != values.len() as i64 {
+                return Err(decode_err(&format!("item {} misses feature {}", item, values.len())));
+            }
+            values.push(value.unwrap_or(f64::NAN));
+        }
+        let mut items = conn.prepare("SELECT id, label, weight, source, timestamp FROM items ORDER BY id").map_err(err)?;
+        let mut rows = items.query([]).map_err(err)?;
+        while let Some(row) = rows.next().map_err(err)? {
+            let id: i64 = row.get(0).map_err(err)?;
+            let timestamp: Option<i64> = row.get(4).map_err(err)?;
+            let it = KnnItem {
+                label: row.get(1).map_err(err)?,
+                data: data.remove(&id).unwrap_or_default(),
+                weight: row.get(2).map_err(err)?,
+                source: row.get(3).map_err(err)?,
+                timestamp: timestamp.map(|t| u64::try_from(t).map_err(|_| decode_err(&format!("negative timestamp of item {}", id)))).transpose()?,
+                deleted: false,
+            };
+            clf.push_item(it);
+        }
+        if let Some(item) = data.keys().min() {
+            return Err(decode_err(&format!("features of a missing item: {}", item)));
+        }
+        clf.fit_statistics();
+        Ok(clf)
+    }
+    // Function to write the settings as the rows of the `config` table
+    fn sqlite_config(&self) -> Result<Vec<(&'static str, String)>, KnnError> {
+        let unsupported = |what: &str| KnnError::Config {
+            problem: format!("{} can not be stored in SQLite", what),
+            fix: "save the model with to_msgpack".to_string(),
+        };
+        if !self.preprocess.is_empty() {
+            return Err(unsupported("preprocess"));
+        }
+        if self.projection.is_some() {
+            return Err(unsupported("a random projection"));
+        }
+        let mut config = vec![("version", SCHEMA_VERSION.to_string()), ("k", self.k.to_string())];
+        let metric = match self.metric {
+            Metric::Euclidean => "euclidean",
+            Metric::Manhattan => "manhattan",
+            Metric::Minkowski(p) => {
+                config.push(("p", p.to_string()));
+                "minkowski"
+            },
+            Metric::Chebyshev => "chebyshev",
+            Metric::Cosine => "cosine",
+            Metric::Mahalanobis => "mahalanobis",
+            Metric::Haversine => "haversine",
+            Metric::Canberra => "canberra",
+        };
+        config.push(("metric", metric.to_string()));
+        let voting = match self.voting {
+            Vote::Uniform => "uniform",
+            Vote::Distance => "distance",
+            Vote::Gaussian { bandwidth } | Vote::Epanechnikov { bandwidth } => {
+                config.push(("bandwidth", bandwidth.to_string()));
+                if matches!(self.voting, Vote::Gaussian { .. }) { "gaussian" } else { "epanechnikov" }
+            },
+            Vote::Dudani => "dudani",
+            Vote::Rank => "rank",
+            Vote::Custom => return Err(unsupported("custom voting")),
+        };
+        config.push(("voting", voting.to_string()));
+        config.push(("tie_break", match self.tie_break {
+            TieBreak::Nearest => "nearest",
+            TieBreak::Lexicographic => "lexicographic",
+        }.to_string()));
+        config.push(("coincident", match self.coincident {
+            CoincidentVote::Majority => "majority",
+            CoincidentVote::First => "first",
+            CoincidentVote::Neighbors => "neighbors",
+        }.to_string()));
+        if let Some(fallback) = &self.fallback {
+            config.push(("fallback", fallback.clone()));
+        }
+        config.push(("smoothing", self.smoothing.to_string()));
+        config.push(("standardize", self.standardize.to_string()));
+        let weights: Vec<String> = self.feature_weights.iter().map(|w| w.to_string()).collect();
+        config.push(("feature_weights", weights.join(",")));
+        Ok(config)
+    }
+    // Function to read the settings from the rows of the `config` table
+    fn read_sqlite_config(&mut self, config: &HashMap<String, String>) -> Result<(), KnnError> {
+        let float = |key: &str| -> Result<f64, KnnError> {
+            let value = config.get(key).ok_or_else(|| decode_err(&format!("missing config: {}", key)))?;
+            value.parse().map_err(|_| decode_err(&format!("invalid config {}: {}", key, value)))
+        };
+        let text = |key: &str| config.get(key).map(|s| s.as_str());
+        if let Some(key) = config.keys().find(|key| !CONFIG_KEYS.contains(&key.as_str())) {
+            return Err(decode_err(&format!("unknown config: {}", key)));
+        }
+        let version = float("version")?;
+        if version > SCHEMA_VERSION as f64 {
+            return Err(decode_err(&format!("unsupported schema version: {}", version)));
+        }
+        let k = float("k")?;
+        if k < 1.0 || k.fract() != 0.0 {
+            return Err(decode_err(&format!("k should be a positive integer: {}", k)));
+        }
+        self.k = k as usize;
+        self.metric = match text("metric") {
+            None | Some("euclidean") => Metric::Euclidean,
+            Some("manhattan") => Metric::Manhattan,
+            Some("minkowski") => Metric::Minkowski(float("p")?),
+            Some("chebyshev") => Metric::Chebyshev,
+            Some("cosine") => Metric::Cosine,
+            Some("mahalanobis") => Metric::Mahalanobis,
+            Some("haversine") => Metric::Haversine,
+            Some("canberra") => Metric::Canberra,
+            Some(m) => return Err(decode_err(&format!("unknown metric: {}", m))),
+        };
+        self.voting = match text("voting") {
+            None | Some("uniform") => Vote::Uniform,
+            Some("distance") => Vote::Distance,
+            Some("gaussian") => Vote::Gaussian { bandwidth: float("bandwidth")? },
+            Some("epanechnikov") => Vote::Epanechnikov { bandwidth: float("bandwidth")? },
+            Some("dudani") => Vote::Dudani,
+            Some("rank") => Vote::Rank,
+            Some(v) => return Err(decode_err(&format!("unknown voting: {}", v))),
+        };
+        self.tie_break = match text("tie_break") {
+            None | Some("nearest") => TieBreak::Nearest,
+            Some("lexicographic") => TieBreak::Lexicographic,
+            Some(t) => return Err(decode_err(&format!("unknown tie_break: {}", t))),
+        };
+        self.coincident = match text("coincident") {
+            None | Some("majority") => CoincidentVote::Majority,
+            Some("first") => CoincidentVote::First,
+            Some("neighbors") => CoincidentVote::Neighbors,
+            Some(c) => return Err(decode_err(&format!("unknown coincident: {}", c))),
+        };
+        self.fallback = text("fallback").map(|s| s.to_string());
+        if config.contains_key("smoothing") {
+            self.smoothing = float("smoothing")?;
+        }
+        self.standardize = match text("standardize") {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(s) => return Err(decode_err(&format!("invalid config standardize: {}", s))),
+        };
+        self.feature_weights = match text("feature_weights") {
+            None | Some("") => vec![],
+            Some(s) => s.split(',').map(|w| w.trim().parse().map_err(|_| decode_err(&format!("invalid feature weight: {}", w))))
+                .collect::<Result<_, _>>()?,
+        };
+        Ok(())
+    }
+}
+
+// keys of the `config` table
+const CONFIG_KEYS: [&str; 12] = ["version", "k", "metric", "p", "voting", "bandwidth", "tie_break", "coincident", "fallback",
+    "smoothing", "standardize", "feature_weights"];
+
+fn decode_err(msg: &str) -> KnnError {
+    KnnError::Decode(msg.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite() {
+        let path = std::env::temp_dir().join(format!("knn_sqlite_{}.db", std::process::id()));
+        let mut c = KnnClassifier::with_metric(3, Metric::Minkowski(1.5));
+        c.fit(&[&[150., 80.], &[170., 60.], &[160., 70.], &[155., f64::NAN]], &["肥満", "標準", "標準", "肥満"]);
+        c.fit_one_at(&[180., 90.], "肥満", 1700000000);
+        c.voting = Vote::Gaussian { bandwidth: 2.0 };
+        c.tie_break = TieBreak::Lexicographic;
+        c.fallback = Some("unknown".to_string());
+        c.set_feature_weights(&[1.0, 0.5]).unwrap();
+        c.delete(2);
+        c.save_sqlite(&path).unwrap();
+        let c2 = KnnClassifier::load_sqlite(&path).unwrap();
+        assert_eq!((c2.k, c2.metric, c2.voting, c2.tie_break), (3, c.metric, c.voting, c.tie_break));
+        assert_eq!((&c2.fallback, &c2.feature_weights), (&c.fallback, &c.feature_weights));
+        assert_eq!(c2.items.len(), 4);
+        assert_eq!(c2.checksum(), c.checksum());
+        assert_eq!(c2.items[3].timestamp, Some(1700000000));
+        assert!(c2.items[2].data[1].is_nan());
+        assert_eq!(c2.predict_one(&[158., 72.]), c.predict_one(&[158., 72.]));
+        // the tables can be edited with SQL
+        let conn = Connection::open(&path).unwrap();
+        conn.execute("UPDATE items SET label = 'normal' WHERE label = '標準'", []).unwrap();
+        conn.execute("UPDATE config SET value = '5' WHERE key = 'k'", []).unwrap();
+        let c3 = KnnClassifier::load_sqlite(&path).unwrap();
+        assert_eq!(c3.k, 5);
+        assert_eq!(c3.items[1].label, "normal");
+        // saving again replaces the model
+        KnnClassifier::new(1).save_sqlite(&path).unwrap();
+        assert_eq!(KnnClassifier::load_sqlite(&path).unwrap().items.len(), 0);
+        // broken tables
+        c.save_sqlite(&path).unwrap();
+        conn.execute("DELETE FROM features WHERE item = 0 AND position = 0", []).unwrap();
+        assert!(matches!(KnnClassifier::load_sqlite(&path), Err(KnnError::Decode(_))));
+        conn.execute("INSERT INTO config (key, value) VALUES ('colour', 'blue')", []).unwrap();
+        assert!(matches!(KnnClassifier::load_sqlite(&path), Err(KnnError::Decode(_))));
+        drop(conn);
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(KnnClassifier::load_sqlite(&path), Err(KnnError::Io(_))));
+        // settings SQLite does not store are refused
+        c.voting = Vote::Custom;
+        assert!(matches!(c.save_sqlite(&path), Err(KnnError::Config { .. })));
+    }
+}
@@ -0,0 +1,105 @@
+//! Standardization of the features (z-scores).
+
+use crate::KnnClassifier;
+
+/// Mean and standard deviation of every feature, to compare the features in z-score units
+///
+/// Without scaling, a feature with large values (height in cm) dominates the distance over one
+/// with small values (weight in kg). After `transform`, every feature has mean 0 and standard deviation 1.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct StandardScaler {
+    pub means: Vec<f64>,
+    pub stds: Vec<f64>,
+}
+
+impl StandardScaler {
+    /// Function to learn the mean and the (population) standard deviation of every feature
+    ///
+    /// Missing values (NaN) are skipped.
+    pub fn fit(data: &[&[f64]]) -> StandardScaler {
+        let dim = data.iter().map(|row| row.len()).max().unwrap_or(0);
+        let mut sums = vec![(0.0, 0.0, 0usize); dim];
+        for row in data {
+            for ((sum, squares, count), v) in sums.iter_mut().zip(row.iter()).filter(|(_, v)| !v.is_nan()) {
+                *sum += v;
+                *squares += v * v;
+                *count += 1;
+            }
+        }
+        let means: Vec<f64> = sums.iter().map(|(s, _, c)| if *c > 0 { s / *c as f64 } else { 0.0 }).collect();
+        let stds = sums.iter().zip(&means).map(|((_, sq, c), m)| {
+            if *c > 0 { (sq / *c as f64 - m * m).max(0.0).sqrt() } else { 0.0 }
+        }).collect();
+        StandardScaler { means, stds }
+    }
+    /// Function to convert a row to z-scores `(x - mean) / std`
+    ///
+    /// Constant features (std 0) are only centered, and features beyond the fitted ones are kept as they are.
+    pub fn transform(&self, row: &[f64]) -> Vec<f64> {
+        row.iter().enumerate().map(|(i, v)| match (self.means.get(i), self.stds.get(i)) {
+            (Some(m), Some(s)) if *s > 0.0 => (v - m) / s,
+            (Some(m), _) => v - m,
+            _ => *v,
+        }).collect()
+    }
+}
+
+impl KnnClassifier {
+    /// Function to learn `scaler` from the live items after `preprocess`
+    ///
+    /// Every way of learning (`fit`, `fit_one`, `from_csv`, ...) calls it when `standardize` is set.
+    /// Call it after setting `standardize` on a trained model or changing `preprocess`.
+    pub fn fit_scaler(&mut self) {
+        let rows: Vec<Vec<f64>> = self.live_items()
+            .map(|(_, it)| self.preprocess.iter().fold(it.data.clone(), |r, step| step.transform(&r)))
+            .collect();
+        let data: Vec<&[f64]> = rows.iter().map(|r| r.as_slice()).collect();
+        self.scaler = Some(StandardScaler::fit(&data));
+    }
+    // scaler applied to the distances, when `standardize` is set and it was learned
+    pub(crate) fn active_scaler(&self) -> Option<&StandardScaler> {
+        self.scaler.as_ref().filter(|_| self.standardize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Backend;
+
+    #[test]
+    fn test_standardize() {
+        let s = StandardScaler::fit(&[&[1.0, 5.0], &[3.0, 5.0], &[f64::NAN, 5.0]]);
+        assert_eq!(s, StandardScaler { means: vec![2.0, 5.0], stds: vec![1.0, 0.0] });
+        assert_eq!(s.transform(&[4.0, 6.0, 7.0]), [2.0, 1.0, 7.0]);
+        // height (mm) and weight (kg): the height difference dominates without scaling
+        let mut c = KnnClassifier::new(1);
+        let (data, labels): (Vec<&[f64]>, Vec<&str>) = (
+            vec![&[1500.0, 60.0], &[1900.0, 62.0], &[1700.0, 90.0], &[1720.0, 95.0]],
+            vec!["light", "light", "heavy", "heavy"]);
+        c.fit(&data, &labels);
+        assert_eq!(c.predict_one(&[1710.0, 64.0]), "heavy");
+        c.standardize = true;
+        c.fit_scaler();
+        assert_eq!(c.predict_one(&[1710.0, 64.0]), "light");
+        for backend in [Backend::KdTree, Backend::BallTree] {
+            c.backend = backend;
+            assert_eq!(c.predict_one(&[1710.0, 64.0]), "light");
+        }
+        let mut d = KnnClassifier::new(1);
+        d.standardize = true;
+        d.fit(&data, &labels);
+        assert_eq!(d.scaler, c.scaler);
+        assert_eq!(d.predict_one(&[1710.0, 64.0]), "light");
+        // every way of learning refreshes the scaler
+        d.fit_one(&[1600.0, 70.0], "light");
+        let rows = [[1500.0, 60.0], [1900.0, 62.0], [1700.0, 90.0], [1720.0, 95.0], [1600.0, 70.0]];
+        let fitted = |rows: &[[f64; 2]]| StandardScaler::fit(&rows.iter().map(|r| r.as_slice()).collect::<Vec<_>>());
+        assert_eq!(d.scaler, Some(fitted(&rows)));
+        d.from_csv("heavy,1650,100\n", ',', 0, false);
+        assert_eq!(d.scaler.as_ref().unwrap().means[1], 79.5);
+        d.delete(5);
+        d.compact();
+        assert_eq!(d.scaler, Some(fitted(&rows)));
+    }
+}
@@ -0,0 +1,312 @@
+//! Statistics of the training set (neighbor distances, class separability, ...).
+
+use crate::index::neighbor_order;
+use crate::{KnnClassifier, KnnItem};
+
+/// Distribution of the distance from each training item to its k-th nearest other item
+#[derive(Debug, Clone, PartialEq)]
+pub struct NeighborhoodStats {
+    pub k: usize,
+    /// k-th neighbor distance of every live item, in item order (large values are outliers)
+    pub kth_distances: Vec<f64>,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    pub std_dev: f64,
+    // sorted copy of kth_distances
+    sorted: Vec<f64>,
+}
+
+/// A bin of a histogram: values in `lower..upper` (the last bin includes `upper`)
+#[derive(Debug, Clone, PartialEq)]
+pub struct HistogramBin {
+    pub lower: f64,
+    pub upper: f64,
+    pub count: usize,
+}
+
+/// Summary of one class of the training data
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClassSummary {
+    pub label: String,
+    pub count: usize,
+    /// mean of the items
+    pub centroid: Vec<f64>,
+    /// variance of each feature (diagonal of the covariance matrix)
+    pub variance: Vec<f64>,
+    /// mean distance from the items to the centroid
+    pub mean_radius: f64,
+    /// median distance from the items to the centroid
+    pub median_radius: f64,
+    /// largest distance from an item to the centroid
+    pub max_radius: f64,
+}
+
+impl NeighborhoodStats {
+    /// quantile of the k-th neighbor distances (`q` from 0.0 to 1.0, nearest rank)
+    pub fn quantile(&self, q: f64) -> f64 {
+        quantile_sorted(&self.sorted, q)
+    }
+    /// histogram of the k-th neighbor distances with `bins` equal-width bins from min to max
+    pub fn histogram(&self, bins: usize) -> Vec<HistogramBin> {
+        let bins = bins.max(1);
+        let width = (self.max - self.min) / bins as f64;
+        let mut hist: Vec<HistogramBin> = (0..bins).map(|b| HistogramBin {
+            lower: self.min + width * b as f64,
+            upper: if b + 1 == bins { self.max } else { self.min + width * (b + 1) as f64 },
+            count: 0,
+        }).collect();
+        for d in &self.sorted {
+            let b = if width > 0.0 { (((d - self.min) / width) as usize).min(bins - 1) } else { 0 };
+            hist[b].count += 1;
+        }
+        hist
+    }
+}
+
+impl KnnClassifier {
+    // Function to calculate the distances from the item at `i` to all live items (including itself): (index, distance)
+    pub(crate) fn distance_row(&self, i: usize) -> Vec<(usize, f64)> {
+        self.live_items().map(|(j, it)| (j, self.distance(&self.items[i].data, &it.data))).collect()
+    }
+    // Function to find the k nearest other live items of every live item: (index, neighbors), neighbors nearest first
+    pub(crate) fn training_neighbors(&self, k: usize) -> Vec<(usize, Vec<(usize, f64)>)> {
+        self.live_items().map(|(i, _)| {
+            let mut ds: Vec<(usize, f64)> = self.distance_row(i).into_iter()
+                .filter(|(j, _)| *j != i)
+                .collect();
+            ds.sort_by(neighbor_order);
+            ds.truncate(k);
+            (i, ds)
+        }).collect()
+    }
+    /// Function to measure the distribution of k-th nearest neighbor distances over the training set
+    ///
+    /// Useful to choose radius thresholds (e.g. `quantile(0.95)`), to find outliers
+    /// (items with a large `kth_distances` value) and to tune approximate search.
+    /// Deleted items are left out. Returns `None` when there are not more than `k` live items.
+    pub fn neighborhood_stats(&self, k: usize) -> Option<NeighborhoodStats> {
+        let k = k.max(1);
+        if self.live_items().count() <= k { return None; }
+        let kth_distances: Vec<f64> = self.training_neighbors(k).iter().map(|(_, ns)| ns[k - 1].1).collect();
+        let mut sorted = kth_distances.clone();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        let n = sorted.len() as f64;
+        let mean = sorted.iter().sum::<f64>() / n;
+        let std_dev = (sorted.iter().map(|d| (d - mean).powi(2)).sum::<f64>() / n).sqrt();
+        Some(NeighborhoodStats {
+            k,
+            min: sorted[0],
+            max: sorted[sorted.len() - 1],
+            mean,
+            median: quantile_sorted(&sorted, 0.5),
+            std_dev,
+            kth_distances,
+            sorted,
+        })
+    }
+    /// Function to summarize every class of the live items (in order of first appearance)
+    ///
+    /// A quick separability check: classes whose centroids are closer than their radii overlap.
+    pub fn class_summaries(&self) -> Vec<ClassSummary> {
+        let mut groups: Vec<(&str, Vec<&[f64]>)> = vec![];
+        for (_, it) in self.live_items() {
+            match groups.iter_mut().find(|(l, _)| *l == it.label) {
+                Some((_, rows)) => rows.push(&it.data),
+                None => groups.push((&it.label, vec![&it.data])),
+            }
+        }
+        groups.into_iter().map(|(label, rows)| {
+            let n = rows.len() as f64;
+            let dim = rows[0].len();
+            let centroid: Vec<f64> = (0..dim).map(|f| rows.iter().map(|r| r[f]).sum::<f64>() / n).collect();
+            let variance = (0..dim).map(|f| rows.iter().map(|r| (r[f] - centroid[f]).powi(2)).sum::<f64>() / n).collect();
+            let mut radii: Vec<f64> = rows.iter().map(|r| self.distance(r, &centroid)).collect();
+            radii.sort_by(|a, b| a.total_cmp(b));
+            ClassSummary {
+                label: label.to_string(),
+                count: rows.len(),
+                centroid,
+                variance,
+                mean_radius: radii.iter().sum::<f64>() / n,
+                median_radius: quantile_sorted(&radii, 0.5),
+                max_radius: radii[radii.len() - 1],
+            }
+        }).collect()
+    }
+    /// approximate memory used by the classifier and its items in bytes (search indexes not included)
+    ///
+    /// Deleted items still use memory until `compact` removes them, so they are counted.
+    pub fn memory_usage(&self) -> usize {
+        let items: usize = self.items.iter().map(|it| {
+            it.data.capacity() * std::mem::size_of::<f64>() + it.label.capacity() + it.source.as_ref().map_or(0, |s| s.capacity())
+        }).sum();
+        let radii: usize = self.class_radii.keys().map(|l| l.len() + std::mem::size_of::<(String, f64)>()).sum();
+        std::mem::size_of::<KnnClassifier>() + self.items.capacity() * std::mem::size_of::<KnnItem>() + items + radii
+            + self.feature_ranges.capacity() * std::mem::size_of::<(f64, f64)>()
+            + self.feature_weights.capacity() * std::mem::size_of::<f64>()
+            + self.inverse_covariance.iter().map(|r| r.capacity() * std::mem::size_of::<f64>()).sum::<usize>()
+    }
+    /// Function to calculate the silhouette of every live item, in item order
+    ///
+    /// `(b - a) / max(a, b)`, where `a` is the mean distance to the other items of the same class
+    /// and `b` the mean distance to the items of the nearest other class.
+    /// Values near 1 mean well separated, near 0 on a class border, below 0 closer to another class.
+    /// Items that are alone in their class get 0. Deleted items are left out.
+    pub fn silhouette_samples(&self) -> Vec<f64> {
+        let mut classes: Vec<&str> = vec![];
+        // class of every item (deleted items are never looked up)
+        let class_of: Vec<usize> = self.items.iter().map(|it| {
+            if it.deleted { return 0; }
+            classes.iter().position(|l| *l == it.label).unwrap_or_else(|| {
+                classes.push(&it.label);
+                classes.len() - 1
+            })
+        }).collect();
+        let mut sizes = vec![0usize; classes.len()];
+        self.live_items().for_each(|(i, _)| sizes[class_of[i]] += 1);
+        self.live_items().map(|(i, _)| {
+            let own = class_of[i];
+            if sizes[own] < 2 { return 0.0; }
+            let mut sums = vec![0.0; classes.len()];
+            for (j, d) in self.distance_row(i) {
+                sums[class_of[j]] += d;
+            }
+            let a = sums[own] / (sizes[own] - 1) as f64;
+            let b = (0..classes.len()).filter(|c| *c != own)
+                .map(|c| sums[c] / sizes[c] as f64)
+                .fold(f64::INFINITY, f64::min);
+            if !b.is_finite() { return 0.0; }
+            let m = a.max(b);
+            if m > 0.0 { (b - a) / m } else { 0.0 }
+        }).collect()
+    }
+    /// Function to calculate the mean silhouette over the training set
+    ///
+    /// Returns `None` when the live items have less than two classes.
+    pub fn silhouette(&self) -> Option<f64> {
+        let first = &self.live_items().next()?.1.label;
+        if self.live_items().all(|(_, it)| it.label == *first) { return None; }
+        let s = self.silhouette_samples();
+        Some(s.iter().sum::<f64>() / s.len() as f64)
+    }
+    /// Function to estimate the intrinsic dimension of the training data (Levina-Bickel MLE)
+    ///
+    /// Uses the distances to the `k` nearest neighbors of every item (`k` around 10 to 20 is typical)
+    /// and averages the inverse local estimates (MacKay-Ghahramani). Data lying on a low-dimensional
+    /// surface gives a value well below the number of features: dimensionality reduction may pay off,
+    /// and tree indexes work well. Items with duplicate neighbors (zero distances) are skipped.
+    /// Returns `None` when there is not enough data.
+    pub fn intrinsic_dimension(&self, k: usize) -> Option<f64> {
+        let k = k.max(2);
+        if self.live_items().count() <= k { return None; }
+        let mut inv_sum = 0.0;
+        let mut n = 0;
+        for (_, ns) in self.training_neighbors(k) {
+            let tk = ns[k - 1].1;
+            if ns[0].1 <= 0.0 { continue; }
+            // inverse of the local estimate: mean of ln(T_k / T_j)
+            inv_sum += ns[..k - 1].iter().map(|(_, tj)| (tk / tj).ln()).sum::<f64>() / (k - 1) as f64;
+            n += 1;
+        }
+        if n == 0 || inv_sum <= 0.0 { return None; }
+        Some(n as f64 / inv_sum)
+    }
+}
+
+// nearest-rank quantile of sorted values
+pub(crate) fn quantile_sorted(sorted: &[f64], q: f64) -> f64 {
+    if sorted.is_empty() { return f64::NAN; }
+    let pos = ((sorted.len() - 1) as f64 * q.clamp(0.0, 1.0)).round() as usize;
+    sorted[pos]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_neighborhood_stats() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.], &[10.]], &["a", "a", "a", "a", "b"]);
+        let s = c.neighborhood_stats(1).unwrap();
+        assert_eq!(s.kth_distances, [1., 1., 1., 1., 7.]);
+        assert_eq!((s.min, s.max, s.mean, s.median), (1., 7., 2.2, 1.));
+        assert_eq!(s.quantile(1.0), 7.);
+        let h = s.histogram(3);
+        assert_eq!(h.iter().map(|b| b.count).collect::<Vec<_>>(), [4, 0, 1]);
+        assert_eq!((h[2].lower, h[2].upper), (5., 7.));
+        assert_eq!(c.neighborhood_stats(2).unwrap().kth_distances, [2., 1., 1., 2., 8.]);
+        assert!(c.neighborhood_stats(5).is_none());
+        // the deleted outlier is neither measured nor a neighbor
+        c.delete(4);
+        assert_eq!(c.neighborhood_stats(1).unwrap().kth_distances, [1., 1., 1., 1.]);
+        assert!(c.neighborhood_stats(4).is_none());
+        // items at infinity are at a NaN distance from each other
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[f64::INFINITY], &[f64::INFINITY]], &["c", "c"]);
+        assert!(c.neighborhood_stats(1).unwrap().kth_distances.iter().all(|d| d.is_nan()));
+    }
+    #[test]
+    fn test_class_summaries() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 0.], &[2., 0.], &[10., 10.], &[1., 3.]], &["a", "a", "b", "a"]);
+        let s = c.class_summaries();
+        assert_eq!(s.len(), 2);
+        assert_eq!((s[0].label.as_str(), s[0].count), ("a", 3));
+        assert_eq!(s[0].centroid, [1., 1.]);
+        assert_eq!(s[0].variance, [2. / 3., 2.]);
+        assert_eq!(s[0].max_radius, 2.);
+        assert_eq!(s[0].median_radius, 2f64.sqrt());
+        assert_eq!((s[1].count, s[1].max_radius), (1, 0.));
+        let mut d = c.clone();
+        d.delete(2);
+        assert_eq!(d.class_summaries().len(), 1);
+        // an item at infinity gives an infinite centroid and NaN radii
+        d.fit_one(&[f64::INFINITY, 0.], "b");
+        assert!(d.class_summaries()[1].max_radius.is_nan());
+        assert!(c.memory_usage() >= std::mem::size_of::<KnnClassifier>() + 4 * (std::mem::size_of::<KnnItem>() + 16 + 1));
+    }
+    #[test]
+    fn test_silhouette() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0.], &[2.], &[10.], &[12.], &[50.]], &["a", "a", "b", "b", "c"]);
+        let s = c.silhouette_samples();
+        // item 0: a = 2, b = mean(10, 12) = 11
+        assert_eq!(s[0], 9. / 11.);
+        assert_eq!(s[4], 0.);
+        assert!(c.silhouette().unwrap() > 0.6);
+        // overlapping classes score lower
+        c.fit(&[&[11.]], &["a"]);
+        assert!(c.silhouette_samples()[5] < 0.);
+        let mut d = c.clone();
+        d.delete(5);
+        assert_eq!(d.silhouette_samples(), s);
+        for i in 2..5 {
+            d.delete(i);
+        }
+        assert_eq!(d.silhouette(), None);
+        c.items.retain(|it| it.label == "a");
+        assert_eq!(c.silhouette(), None);
+    }
+    #[test]
+    fn test_intrinsic_dimension() {
+        // random points on a line in 3D and on a plane in 3D
+        let mut line = KnnClassifier::new(1);
+        let mut plane = KnnClassifier::new(1);
+        let mut rng = Rng(12345u64);
+        let mut rand = || rng.uniform();
+        for _ in 0..400 {
+            let (t, u) = (rand(), rand());
+            line.fit_one(&[t, 2.0 * t, -t], "a");
+            plane.fit_one(&[t, u, t + u], "a");
+        }
+        let d1 = line.intrinsic_dimension(10).unwrap();
+        let d2 = plane.intrinsic_dimension(10).unwrap();
+        assert!((0.8..1.3).contains(&d1), "{}", d1);
+        assert!((1.7..2.4).contains(&d2), "{}", d2);
+        assert!(KnnClassifier::new(1).intrinsic_dimension(10).is_none());
+    }
+}
@@ -0,0 +1,101 @@
+//! Soft deletion of items (tombstones) and compaction.
+
+use crate::{KnnClassifier, KnnItem};
+
+impl KnnClassifier {
+    /// Function to mark an item deleted without rebuilding the search index
+    ///
+    /// Searches skip deleted items, so predictions are as if the item was removed, while the index of
+    /// `backend` stays valid and the indexes of the other items do not change. Returns `false` when
+    /// there is no such item or it is already deleted. Many deleted items slow searches down;
+    /// `compact` removes them. Exports (`to_csv`, `to_msgpack`, ...) leave deleted items out.
+    pub fn delete(&mut self, i: usize) -> bool {
+        match self.items.get_mut(i) {
+            Some(it) if !it.deleted => {
+                it.deleted = true;
+                self.deleted += 1;
+                true
+            },
+            _ => false,
+        }
+    }
+    /// Function to restore a deleted item, returns `false` when there is no such deleted item
+    pub fn undelete(&mut self, i: usize) -> bool {
+        match self.items.get_mut(i) {
+            Some(it) if it.deleted => {
+                it.deleted = false;
+                self.deleted = self.deleted.saturating_sub(1);
+                true
+            },
+            _ => false,
+        }
+    }
+    /// number of deleted items
+    pub fn deleted_count(&self) -> usize {
+        self.items.len() - self.live_items().count()
+    }
+    // Function to iterate over the items that are not deleted, with their index in `items`
+    // (everything learned from the items skips deleted ones through it)
+    pub(crate) fn live_items(&self) -> impl Iterator<Item = (usize, &KnnItem)> {
+        self.items.iter().enumerate().filter(|(_, it)| !it.deleted)
+    }
+    /// Function to remove the deleted items (the indexes of the later items shift), returns the number of removed items
+    ///
+    /// The search index is rebuilt on the next query.
+    pub fn compact(&mut self) -> usize {
+        let before = self.items.len();
+        self.items.retain(|it| !it.deleted);
+        let removed = before - self.items.len();
+        if removed > 0 || self.deleted > 0 {
+            self.update_feature_ranges();
+        }
+        removed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier};
+
+    #[test]
+    fn test_delete() {
+        let mut c = KnnClassifier::new(3);
+        for i in 0..200 {
+            c.fit_one(&[(i % 20) as f64, (i / 20) as f64], if i % 20 < 10 { "a" } else { "b" });
+        }
+        c.backend = Backend::KdTree;
+        let q = [9.4, 3.0];
+        assert_eq!(c.nearest(&q, 1)[0].0, 69);
+        assert_eq!(c.predict_one(&q), "a");
+        // the items at (7, 3), (8, 3) and (9, 3)
+        for i in [67, 68, 69] {
+            assert!(c.delete(i));
+        }
+        assert!(!c.delete(69));
+        assert!(!c.delete(1000));
+        assert_eq!(c.deleted_count(), 3);
+        // a save and load does not bring deleted items back
+        assert_eq!(c.to_csv(',').lines().count(), 197);
+        let mut loaded = KnnClassifier::new(3);
+        loaded.from_csv(&c.to_csv(','), ',', 0, false);
+        assert_eq!(loaded.checksum(), c.checksum());
+        let found = c.nearest(&q, 3);
+        assert!(found.iter().all(|(i, _)| ![67, 68, 69].contains(i)));
+        assert_eq!(found[0].0, 70);
+        let mut brute = c.clone();
+        brute.backend = Backend::BruteForce;
+        assert_eq!(brute.nearest(&q, 5), c.nearest(&q, 5));
+        assert!(c.undelete(69));
+        assert!(!c.undelete(69));
+        assert_eq!(c.nearest(&q, 1)[0].0, 69);
+        assert_eq!(c.compact(), 2);
+        assert_eq!((c.items.len(), c.deleted_count()), (198, 0));
+        assert_eq!(c.nearest(&q, 1)[0].0, 67);
+        // the items edited directly are counted as they are
+        c.delete(1);
+        c.items.clear();
+        assert_eq!((c.deleted_count(), c.checksum()), (0, KnnClassifier::new(3).checksum()));
+        #[cfg(feature = "msgpack")]
+        assert!(KnnClassifier::from_msgpack(&c.to_msgpack()).unwrap().items.is_empty());
+    }
+}
@@ -0,0 +1,53 @@
+//! Training data over HTTP(S) (feature `url`).
+
+use crate::{KnnClassifier, KnnError};
+use std::io::BufReader;
+
+impl KnnClassifier {
+    /// read csv from an `http://` or `https://` URL (blocking), streamed like `from_csv_reader`
+    ///
+    /// Redirects are followed. A status other than 2xx fails with `KnnError::Io`. Returns the number of rows read.
+    pub fn from_csv_url(&mut self, url: &str, delimiter: char, label_col: usize, skip_header: bool) -> Result<usize, KnnError> {
+        let response = ureq::get(url).call().map_err(|e| KnnError::Io(format!("{}: {}", url, e)))?;
+        self.from_csv_reader(BufReader::new(response.into_reader()), delimiter, label_col, skip_header, |_| {})
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, Write};
+    use std::net::TcpListener;
+
+    // Function to serve one response per connection on a local port, returns the base URL
+    fn serve(responses: Vec<String>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            for response in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = std::io::BufReader::new(stream);
+                // read the request head
+                let mut line = String::new();
+                while reader.read_line(&mut line).unwrap() > 2 {
+                    line.clear();
+                }
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn test_from_csv_url() {
+        let csv = "label,height,weight\n肥満,150,80\n標準,170,60\n";
+        let ok = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", csv.len(), csv);
+        let missing = "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n".to_string();
+        let url = serve(vec![ok, missing]);
+        let mut c = KnnClassifier::new(1);
+        assert_eq!(c.from_csv_url(&format!("{}/data.csv", url), ',', 0, true).unwrap(), 2);
+        assert_eq!(c.predict_one(&[152., 78.]), "肥満");
+        assert!(matches!(c.from_csv_url(&format!("{}/missing.csv", url), ',', 0, true), Err(KnnError::Io(_))));
+        assert!(matches!(c.from_csv_url("not a url", ',', 0, true), Err(KnnError::Io(_))));
+    }
+}
@@ -0,0 +1,266 @@
+//! Invariant checks that can be run on any model, e.g. in the tests of downstream crates.
+
+use crate::{Backend, KnnClassifier, KnnError, Metric, Parallelism, RangeCheck, Vote, KD_TREE_MAX_FEATURES};
+
+impl KnnClassifier {
+    /// Function to check the invariants of the model
+    ///
+    /// - `k` is at least 1
+    /// - every item has the same number of features, and every feature is finite
+    /// - labels are not empty and contain no line breaks
+    /// - `feature_ranges` covers every item (call `update_feature_ranges` after editing `items`)
+    pub fn validate(&self) -> Result<(), KnnError> {
+        if self.k == 0 {
+            return Err(invalid("k should be at least 1"));
+        }
+        let dim = self.items.first().map(|it| it.data.len()).unwrap_or(0);
+        for (i, it) in self.items.iter().enumerate() {
+            if it.data.len() != dim {
+                return Err(invalid(&format!("item {} has {} features, expected {}", i, it.data.len(), dim)));
+            }
+            if let Some(f) = it.data.iter().position(|d| !d.is_finite()) {
+                return Err(invalid(&format!("item {} has a non-finite value in feature {}", i, f)));
+            }
+            if it.label.is_empty() || it.label.contains(['\n', '\r']) {
+                return Err(invalid(&format!("item {} has an empty label or a label with a line break", i)));
+            }
+            let in_range = it.data.iter().enumerate().all(|(f, d)| {
+                self.feature_ranges.get(f).is_some_and(|(min, max)| min <= d && d <= max)
+            });
+            if !in_range {
+                return Err(invalid(&format!("feature_ranges does not cover item {}", i)));
+            }
+        }
+        Ok(())
+    }
+    /// Function to check that the settings fit each other and the items
+    ///
+    /// Most conflicts do not fail at prediction time but quietly change the result
+    /// (a Mahalanobis distance without a covariance matrix is Euclidean, mismatched feature weights are ignored,
+    /// a k-d tree falls back to brute force, ...). The error describes the first conflict and how to fix it.
+    pub fn check_config(&self) -> Result<(), KnnError> {
+        let dim = self.feature_ranges.len();
+        if self.k == 0 {
+            return Err(config("k is 0, so no neighbor votes", "set k to at least 1 (an odd number avoids ties)".to_string()));
+        }
+        if !self.items.is_empty() && self.k > self.items.len() {
+            return Err(config(&format!("k is {} but there are only {} items", self.k, self.items.len()),
+                format!("set k to at most {} or add more items", self.items.len())));
+        }
+        match self.metric {
+            Metric::Minkowski(p) if p.is_nan() || p <= 0.0 => {
+                return Err(config(&format!("Metric::Minkowski({}) is not a distance", p),
+                    "use p >= 1 (1: Manhattan, 2: Euclidean)".to_string()));
+            },
+            Metric::Haversine if !self.items.is_empty() && dim != 2 => {
+                return Err(config(&format!("Metric::Haversine needs [latitude, longitude] items, but the items have {} features", dim),
+                    "use two features in degrees, or another metric".to_string()));
+            },
+            Metric::Haversine if self.feature_ranges.first().is_some_and(|(min, max)| *min < -90.0 || *max > 90.0) => {
+                let (min, max) = self.feature_ranges[0];
+                return Err(config(&format!("feature 0 ranges from {} to {}, which is not a latitude in degrees", min, max),
+                    "put the latitude first and the longitude second, in degrees".to_string()));
+            },
+            Metric::Mahalanobis if !self.items.is_empty() && self.inverse_covariance.len() != dim => {
+                return Err(config("Metric::Mahalanobis has no covariance matrix for the features, so the distance is Euclidean",
+                    "call fit_covariance after adding the items".to_string()));
+            },
+            _ => {},
+        }
+        if !self.feature_weights.is_empty() && !self.items.is_empty() && self.feature_weights.len() != dim {
+            return Err(config(&format!("there are {} feature weights for {} features, so they are ignored", self.feature_weights.len(), dim),
+                format!("call set_feature_weights with {} weights, or with an empty slice", dim)));
+        }
+        if self.standardize && !self.items.is_empty() && self.scaler.is_none() {
+            return Err(config("standardize is set but no scaler was learned, so the features are not scaled",
+                "call fit_scaler after adding the items".to_string()));
+        }
+        if self.transforms_points() && matches!(self.metric, Metric::Mahalanobis | Metric::Haversine) {
+            return Err(config(&format!("preprocessing changes the coordinates {:?} expects", self.metric),
+                "turn standardize off and clear preprocess (Mahalanobis already scales the features, haversine needs degrees)".to_string()));
+        }
+        if !self.smoothing.is_finite() || self.smoothing < 0.0 {
+            return Err(config(&format!("smoothing is {}", self.smoothing), "set smoothing to 0.0 or a positive pseudo-count".to_string()));
+        }
+        if let Vote::Gaussian { bandwidth } | Vote::Epanechnikov { bandwidth } = self.voting {
+            if bandwidth.is_nan() || bandwidth <= 0.0 {
+                return Err(config(&format!("the bandwidth of the vote kernel is {}", bandwidth),
+                    "use a positive bandwidth, about the typical distance between neighbors".to_string()));
+            }
+        }
+        if self.voting == Vote::Custom && self.vote_strategy.is_none() {
+            return Err(config("voting is Vote::Custom without a strategy, so the vote is uniform",
+                "call set_vote_strategy, or choose another Vote".to_string()));
+        }
+        if self.reject.min_confidence.is_nan() || self.reject.min_confidence > 1.0 {
+            return Err(config(&format!("reject.min_confidence is {}, so every prediction is rejected", self.reject.min_confidence),
+                "use a vote share from 0.0 to 1.0".to_string()));
+        }
+        if let Some(max) = self.reject.max_distance.filter(|d| d.is_nan() || *d < 0.0) {
+            return Err(config(&format!("reject.max_distance is {}, so every prediction is rejected", max), "use a distance of 0.0 or more".to_string()));
+        }
+        if let RangeCheck::Warn { slack } | RangeCheck::Error { slack } = self.range_check {
+            if slack.is_nan() || slack < 0.0 {
+                return Err(config(&format!("the slack of range_check is {}", slack), "use a slack of 0.0 or more (0.1 = 10% of the range)".to_string()));
+            }
+        }
+        match self.backend {
+            Backend::KdTree if dim > KD_TREE_MAX_FEATURES => {
+                return Err(config(&format!("Backend::KdTree supports up to {} features, but the items have {}, so brute force is used", KD_TREE_MAX_FEATURES, dim),
+                    "use Backend::BallTree for more features".to_string()));
+            },
+            Backend::KdTree if !self.feature_weights.is_empty() || self.transforms_points() || !matches!(self.metric, Metric::Euclidean | Metric::Manhattan | Metric::Chebyshev | Metric::Minkowski(1.0..)) => {
+                return Err(config(&format!("Backend::KdTree does not support {:?}, feature weights or preprocessing, so brute force is used", self.metric),
+                    "use Backend::BallTree, or Backend::VpTree for every metric".to_string()));
+            },
+            Backend::BallTree if !self.metric.is_metric() => {
+                return Err(config(&format!("Backend::BallTree needs a true metric, but {:?} is not, so brute force is used", self.metric),
+                    "use Backend::VpTree".to_string()));
+            },
+            _ => {},
+        }
+        Ok(())
+    }
+    /// Function to check that `to_csv` and `from_csv` restore exactly the same live items (deleted items are not exported)
+    pub fn check_csv_round_trip(&self, delimiter: char) -> Result<(), KnnError> {
+        let s = self.to_csv(delimiter);
+        let mut c = KnnClassifier::new(self.k);
+        c.from_csv_reader(s.as_bytes(), delimiter, 0, false, |_| {})?;
+        let live = self.live_items().count();
+        if c.items.len() != live {
+            return Err(invalid(&format!("csv round trip restored {} items, expected {}", c.items.len(), live)));
+        }
+        for ((i, a), b) in self.live_items().zip(c.items.iter()) {
+            // compare bits, so that 0.0 and -0.0 are different
+            let same_data = a.data.len() == b.data.len() && a.data.iter().zip(b.data.iter()).all(|(x, y)| x.to_bits() == y.to_bits());
+            if a.label != b.label || !same_data {
+                return Err(invalid(&format!("csv round trip changed item {}", i)));
+            }
+        }
+        Ok(())
+    }
+    /// Function to check that predictions are deterministic: repeated, cloned and parallel predictions agree
+    pub fn check_deterministic(&self, queries: &[Vec<f64>]) -> Result<(), KnnError> {
+        let mut c = self.clone();
+        c.parallelism = Parallelism::serial();
+        let expected = c.predict(queries);
+        if c.predict(queries) != expected {
+            return Err(invalid("repeated predictions differ"));
+        }
+        c.parallelism = Parallelism { threads: 4, chunk_size: 1 };
+        if c.predict(queries) != expected {
+            return Err(invalid("parallel predictions differ from serial predictions"));
+        }
+        Ok(())
+    }
+}
+
+fn invalid(msg: &str) -> KnnError {
+    KnnError::Invalid(msg.to_string())
+}
+
+fn config(problem: &str, fix: String) -> KnnError {
+    KnnError::Config { problem: problem.to_string(), fix }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::collection::vec;
+    use proptest::prelude::*;
+
+    // mix of ordinary values, tiny/huge magnitudes and negative zero for property tests
+    fn value() -> impl Strategy<Value = f64> {
+        prop_oneof![
+            prop::num::f64::NORMAL | prop::num::f64::SUBNORMAL | prop::num::f64::ZERO,
+            Just(-0.0),
+            (0..1000u32).prop_map(f64::from),
+            (-0.5..0.5f64, -20..20i32).prop_map(|(m, e)| m * 10f64.powi(e)),
+        ]
+    }
+    // rows of (features, label index)
+    type Rows = Vec<(Vec<f64>, u8)>;
+    // rows and integer queries, all with the same number of features
+    fn dataset() -> impl Strategy<Value = (Rows, Vec<Vec<f64>>)> {
+        (1..=5usize).prop_flat_map(|dim| (
+            vec((vec(value(), dim), 0..4u8), 1..=30),
+            vec(vec((0..100u32).prop_map(f64::from), dim), 10),
+        ))
+    }
+
+    proptest! {
+        #[test]
+        fn test_properties(k in 0..7usize, (rows, queries) in dataset()) {
+            let mut c = KnnClassifier::new(k);
+            for (data, label) in &rows {
+                c.fit_one(data, &format!("label{}", label));
+            }
+            c.validate()?;
+            c.check_csv_round_trip(',')?;
+            c.check_csv_round_trip('\t')?;
+            c.check_deterministic(&queries)?;
+        }
+    }
+    #[test]
+    fn test_validate() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[1., 2.], &[3., 4.]], &["a", "b"]);
+        assert!(c.validate().is_ok());
+        c.items[1].data[0] = 10.0;
+        assert!(c.validate().is_err());
+        c.update_feature_ranges();
+        assert!(c.validate().is_ok());
+        c.fit_one(&[1.], "c");
+        assert!(c.validate().is_err());
+        c.items.pop();
+        c.fit_one(&[1., f64::NAN], "c");
+        assert!(c.validate().is_err());
+    }
+    #[test]
+    fn test_check_config() {
+        let mut c = KnnClassifier::new(3);
+        assert!(c.check_config().is_ok());
+        c.fit(&[&[35.7, 139.7], &[34.7, 135.5], &[43.1, 141.4]], &["east", "west", "north"]);
+        c.metric = Metric::Haversine;
+        assert!(c.check_config().is_ok());
+        c.k = 5;
+        let e = c.check_config().unwrap_err();
+        assert_eq!(e.to_string(), "invalid configuration: k is 5 but there are only 3 items (fix: set k to at most 3 or add more items)");
+        c.k = 3;
+        // longitude first
+        c.items.iter_mut().for_each(|it| it.data.reverse());
+        c.update_feature_ranges();
+        assert!(matches!(c.check_config(), Err(KnnError::Config { .. })));
+        c.metric = Metric::Mahalanobis;
+        assert!(c.check_config().is_err());
+        c.fit_covariance();
+        assert!(c.check_config().is_ok());
+        c.metric = Metric::Cosine;
+        c.backend = Backend::KdTree;
+        assert!(c.check_config().is_err());
+        c.backend = Backend::VpTree;
+        assert!(c.check_config().is_ok());
+        c.feature_weights = vec![1.0];
+        assert!(c.check_config().is_err());
+        c.feature_weights = vec![];
+        c.voting = Vote::Gaussian { bandwidth: 0.0 };
+        assert!(c.check_config().is_err());
+        c.voting = Vote::Uniform;
+        c.reject.min_confidence = 1.5;
+        assert!(c.check_config().is_err());
+        c.reject.min_confidence = 0.0;
+        c.standardize = true;
+        assert!(c.check_config().is_err());
+        c.fit_scaler();
+        assert!(c.check_config().is_ok());
+    }
+    #[test]
+    fn test_csv_round_trip_deleted() {
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[1., 2.], &[-0., 4.], &[5., 6.]], &["a", "b", "c"]);
+        c.delete(1);
+        assert!(c.check_csv_round_trip(',').is_ok());
+        c.items[2].data[0] = 0.1 + 0.2;
+        assert!(c.check_csv_round_trip(',').is_ok());
+    }
+}
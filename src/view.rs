@@ -0,0 +1,68 @@
+//! Views of a classifier restricted to some features.
+
+use crate::index::neighbor_order;
+use crate::KnnClassifier;
+
+/// Classifier restricted to a subset of the feature columns (see `KnnClassifier::view`)
+///
+/// The view borrows the items of the classifier, so any number of feature subsets can be
+/// compared (ablation studies) without copying the training data.
+/// Queries are given with all features; only the selected columns are used.
+#[derive(Debug, Clone)]
+pub struct FeatureView<'a> {
+    clf: &'a KnnClassifier,
+    features: Vec<usize>,
+}
+
+impl KnnClassifier {
+    /// Function to make a view that only uses the feature columns in `features`
+    pub fn view(&self, features: &[usize]) -> FeatureView<'_> {
+        FeatureView { clf: self, features: features.to_vec() }
+    }
+}
+
+impl FeatureView<'_> {
+    /// the selected feature columns
+    pub fn features(&self) -> &[usize] {
+        &self.features
+    }
+    // distance over the selected features (missing columns count as NaN)
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        let pick = |v: &[f64]| self.features.iter().map(|f| v.get(*f).copied().unwrap_or(f64::NAN)).collect::<Vec<f64>>();
+        self.clf.distance(&pick(a), &pick(b))
+    }
+    /// Function to predict based on a single data point (with all features)
+    pub fn predict_one(&self, item: &[f64]) -> String {
+        let mut distances: Vec<(usize, f64)> = self.clf.live_items()
+            .map(|(i, it)| (i, self.distance(&it.data, item)))
+            .collect();
+        distances.sort_by(neighbor_order);
+        distances.truncate(self.clf.k);
+        self.clf.vote(&distances).unwrap().to_string()
+    }
+    /// Function to predict based on multiple data points
+    pub fn predict(&self, items: &[Vec<f64>]) -> Vec<String> {
+        self.clf.map_queries(items, |it| self.predict_one(it))
+    }
+    /// Function to measure the accuracy with k-fold cross-validation (see `KnnClassifier::cross_validate`)
+    pub fn cross_validate(&self, folds: usize) -> Option<f64> {
+        self.clf.cross_validate_by(folds, |a, b| self.distance(a, b))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_feature_view() {
+        // feature 0 separates the classes, feature 1 is noise
+        let mut c = KnnClassifier::new(1);
+        c.fit(&[&[0., 50.], &[1., 0.], &[10., 0.], &[11., 50.]], &["a", "a", "b", "b"]);
+        assert_eq!(c.view(&[0]).cross_validate(4), Some(1.0));
+        assert_eq!(c.view(&[1]).cross_validate(4), Some(0.0));
+        assert_eq!(c.view(&[0]).predict_one(&[2., 0.]), "a");
+        assert_eq!(c.view(&[1]).predict(&[vec![2., 50.]]), ["a"]);
+        assert_eq!(c.view(&[1, 0]).features(), [1, 0]);
+    }
+}
@@ -0,0 +1,213 @@
+//! Weighting of the neighbor votes.
+
+use crate::{weighted_majority, KnnClassifier};
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
+/// How the k nearest neighbors vote
+///
+/// The kernels weight a neighbor by its distance `d` relative to the bandwidth `h`, so the weight decays smoothly
+/// instead of dropping from 1 to 0 after the k-th neighbor. When every weight is 0, the label of the nearest neighbor wins.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Vote {
+    /// every neighbor counts equally (times its item weight) (default)
+    #[default]
+    Uniform,
+    /// a neighbor counts `1 / distance` (times its item weight), so closer neighbors count more;
+    /// when neighbors coincide with the query (distance 0), only they vote
+    Distance,
+    /// Gaussian kernel `exp(-(d / h)^2 / 2)`
+    Gaussian { bandwidth: f64 },
+    /// Epanechnikov kernel `max(0, 1 - (d / h)^2)`; neighbors farther than `h` do not vote
+    Epanechnikov { bandwidth: f64 },
+    /// Dudani's weights `(d_k - d) / (d_k - d_1)`: 1 for the nearest neighbor, 0 for the k-th,
+    /// linear in between (all 1 when the distances are equal)
+    Dudani,
+    /// weight `1 / rank`: 1 for the nearest neighbor, 1/2 for the second, ... (ignores the distances)
+    Rank,
+    /// the strategy given to `set_vote_strategy`; uniform without one
+    Custom,
+}
+
+/// Winner of a vote when labels have the same total weight
+///
+/// Both policies are deterministic, so predictions are reproducible between runs and platforms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TieBreak {
+    /// the label of the nearest neighbor among the tied labels (default)
+    #[default]
+    Nearest,
+    /// the lexicographically smallest of the tied labels
+    Lexicographic,
+}
+
+/// Weighting of the k nearest neighbors in a vote
+///
+/// `Vote` implements it. To aggregate the neighbors another way, implement it and pass it to
+/// `KnnClassifier::set_vote_strategy`. The label with the largest total weight wins, and the weights
+/// also make the probabilities of `predict_proba_one`. Item and source weights are multiplied in afterwards.
+pub trait VoteStrategy: Send + Sync + RefUnwindSafe + std::fmt::Debug {
+    /// Function to weight each neighbor given as (label, distance), nearest first (one weight per neighbor, not negative)
+    fn weights(&self, neighbors: &[(&str, f64)]) -> Vec<f64>;
+}
+
+impl Vote {
+    // weight of a neighbor at distance d for the kernels (1.0 for the other schemes)
+    fn kernel(&self, d: f64) -> f64 {
+        match self {
+            Vote::Gaussian { bandwidth } => (-(d / bandwidth).powi(2) / 2.0).exp(),
+            Vote::Epanechnikov { bandwidth } => (1.0 - (d / bandwidth).powi(2)).max(0.0),
+            _ => 1.0,
+        }
+    }
+}
+
+impl VoteStrategy for Vote {
+    // NaN distances get no vote, except with `Uniform` and `Rank`
+    fn weights(&self, neighbors: &[(&str, f64)]) -> Vec<f64> {
+        match self {
+            Vote::Uniform | Vote::Custom => vec![1.0; neighbors.len()],
+            Vote::Distance => {
+                let coincident = neighbors.iter().any(|(_, d)| *d == 0.0);
+                neighbors.iter().map(|(_, d)| {
+                    if coincident {
+                        if *d == 0.0 { 1.0 } else { 0.0 }
+                    } else if d.is_nan() {
+                        0.0
+                    } else {
+                        1.0 / d
+                    }
+                }).collect()
+            },
+            Vote::Dudani => {
+                let finite = neighbors.iter().map(|(_, d)| *d).filter(|d| d.is_finite());
+                let (nearest, farthest) = finite.fold((f64::INFINITY, f64::NEG_INFINITY), |(a, b), d| (a.min(d), b.max(d)));
+                neighbors.iter().map(|(_, d)| {
+                    if !d.is_finite() {
+                        0.0
+                    } else if farthest > nearest {
+                        (farthest - d) / (farthest - nearest)
+                    } else {
+                        1.0
+                    }
+                }).collect()
+            },
+            Vote::Rank => (1..=neighbors.len()).map(|r| 1.0 / r as f64).collect(),
+            kernel => neighbors.iter().map(|(_, d)| if d.is_nan() { 0.0 } else { kernel.kernel(*d) }).collect(),
+        }
+    }
+}
+
+impl KnnClassifier {
+    /// Function to aggregate the neighbor votes with another strategy (sets `voting` to `Vote::Custom`)
+    pub fn set_vote_strategy(&mut self, strategy: impl VoteStrategy + 'static) {
+        self.vote_strategy = Some(Arc::new(strategy));
+        self.voting = Vote::Custom;
+    }
+    // Function to return the label with the largest total weight, breaking ties with `tie_break`
+    // (the votes are given nearest first)
+    pub(crate) fn tally<'a>(&self, votes: impl Iterator<Item = (&'a str, f64)>) -> Option<&'a str> {
+        match self.tie_break {
+            TieBreak::Nearest => weighted_majority(votes),
+            TieBreak::Lexicographic => {
+                let mut votes: Vec<(&str, f64)> = votes.collect();
+                votes.sort_by(|a, b| a.0.cmp(b.0));
+                weighted_majority(votes.into_iter())
+            },
+        }
+    }
+    // Function to calculate the vote of each neighbor (sorted by distance) with `voting` and the item weights
+    pub(crate) fn neighbor_weights(&self, neighbors: &[(usize, f64)]) -> Vec<f64> {
+        let labels: Vec<&str> = neighbors.iter().map(|(i, _)| self.items[*i].label.as_str()).collect();
+        self.labeled_neighbor_weights(neighbors, &labels)
+    }
+    // same as `neighbor_weights` with the labels of the neighbors given
+    pub(crate) fn labeled_neighbor_weights(&self, neighbors: &[(usize, f64)], labels: &[&str]) -> Vec<f64> {
+        let pairs: Vec<(&str, f64)> = labels.iter().zip(neighbors).map(|(l, (_, d))| (*l, *d)).collect();
+        let weights = match (&self.voting, &self.vote_strategy) {
+            (Vote::Custom, Some(strategy)) => strategy.weights(&pairs),
+            (vote, _) => vote.weights(&pairs),
+        };
+        neighbors.iter().zip(weights).map(|((i, _), w)| w * self.vote_weight(*i)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{KnnClassifier, TieBreak, Vote, VoteStrategy};
+
+    #[test]
+    fn test_distance_vote() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[3.], &[3.5]], &["a", "b", "b"]);
+        assert_eq!(c.predict_one(&[0.5]), "b");
+        c.voting = Vote::Distance;
+        // 1/0.5 = 2 for "a" against 1/2.5 + 1/3 for "b"
+        assert_eq!(c.predict_one(&[0.5]), "a");
+        let p = c.predict_proba_one(&[0.5]);
+        assert!((p[0].1 - 2.0 / (2.0 + 0.4 + 1.0 / 3.0)).abs() < 1e-12);
+        // a coincident neighbor decides alone
+        assert_eq!(c.predict_one(&[3.0]), "b");
+        c.fit_one(&[3.0], "a");
+        c.fit_one(&[3.0], "a");
+        c.k = 4;
+        assert_eq!(c.predict_one(&[3.0]), "a");
+    }
+    #[test]
+    fn test_kernel_vote() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[2.], &[2.2]], &["a", "b", "b"]);
+        c.voting = Vote::Gaussian { bandwidth: 0.5 };
+        // exp(-0.5) for "a" against exp(-4.5) + exp(-5.78) for "b"
+        assert_eq!(c.predict_one(&[0.5]), "a");
+        let p = c.predict_proba_one(&[0.5]);
+        let (a, b) = ((-0.5f64).exp(), (-4.5f64).exp() + (-5.78f64).exp());
+        assert!((p[0].1 - a / (a + b)).abs() < 1e-12);
+        // a wide kernel counts the neighbors almost equally
+        c.voting = Vote::Gaussian { bandwidth: 100.0 };
+        assert_eq!(c.predict_one(&[0.5]), "b");
+        c.voting = Vote::Epanechnikov { bandwidth: 1.0 };
+        assert_eq!(c.predict_proba_one(&[0.5])[0].1, 1.0);
+        // no neighbor within the bandwidth: the nearest one wins
+        assert_eq!(c.predict_one(&[1.9]), "b");
+        assert_eq!(c.predict_one(&[-5.0]), "a");
+    }
+    // rank-based weights where the two nearest neighbors count double
+    #[derive(Debug)]
+    struct NearestTwice;
+
+    impl VoteStrategy for NearestTwice {
+        fn weights(&self, neighbors: &[(&str, f64)]) -> Vec<f64> {
+            (0..neighbors.len()).map(|r| if r < 2 { 2.0 } else { 1.0 }).collect()
+        }
+    }
+
+    #[test]
+    fn test_vote_strategy() {
+        let mut c = KnnClassifier::new(5);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.], &[4.]], &["a", "b", "c", "c", "c"]);
+        assert_eq!(Vote::Dudani.weights(&[("a", 1.0), ("b", 2.0), ("c", 5.0)]), [1.0, 0.75, 0.0]);
+        assert_eq!(Vote::Dudani.weights(&[("a", 1.0), ("b", 1.0)]), [1.0, 1.0]);
+        assert_eq!(Vote::Rank.weights(&[("a", 1.0), ("b", 2.0), ("c", 2.0), ("d", 7.0)]), [1.0, 0.5, 1.0 / 3.0, 0.25]);
+        assert_eq!(c.predict_one(&[0.]), "c");
+        // 1 + 1/2 for "a" and "b" against 1/3 + 1/4 + 1/5 for "c"
+        c.voting = Vote::Rank;
+        assert_eq!(c.predict_one(&[0.]), "a");
+        // 2 + 2 against 3
+        c.set_vote_strategy(NearestTwice);
+        assert_eq!(c.voting, Vote::Custom);
+        assert_eq!(c.predict_proba_one(&[0.5])[2].1, 3.0 / 7.0);
+    }
+    #[test]
+    fn test_tie_break() {
+        let mut c = KnnClassifier::new(3);
+        c.fit(&[&[0.], &[1.], &[2.], &[3.]], &["b", "a", "c", "c"]);
+        c.k = 2;
+        assert_eq!(c.predict_one(&[0.]), "b");
+        c.tie_break = TieBreak::Lexicographic;
+        assert_eq!(c.predict_one(&[0.]), "a");
+        // no tie: the majority wins
+        c.k = 4;
+        assert_eq!(c.predict_one(&[0.]), "c");
+    }
+}
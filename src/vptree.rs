@@ -0,0 +1,190 @@
+//! Vantage-point tree for neighbor search that only needs distances (see `Backend::VpTree`).
+
+use crate::index::{insert_neighbor, BuiltIndex, NeighborIndex, QueryPoint};
+#[cfg(feature = "msgpack")]
+use crate::index::{leaves_cover_items, IndexParts, PartsReader, TreeNode};
+use crate::instrument;
+use crate::{KnnClassifier, Metric};
+
+// largest number of items in a leaf
+const LEAF_SIZE: usize = 16;
+// relative slack for rounding errors of the triangle inequality bound
+const BOUND_SLACK: f64 = 1e-9;
+
+#[derive(Debug)]
+enum Node {
+    Leaf(Vec<usize>),
+    // items of `inside` are within `mu` of the vantage point `vp`, items of `outside` are at `mu` or farther
+    Split { vp: usize, mu: f64, inside: usize, outside: usize },
+}
+
+#[derive(Debug)]
+pub(crate) struct VpTree {
+    features: usize,
+    // nodes[0] is the root
+    nodes: Vec<Node>,
+}
+
+// true when `transform` makes the distance of the metric a true metric
+fn is_transformable(metric: Metric) -> bool {
+    !matches!(metric, Metric::Minkowski(p) if p <= 0.0 || p.is_nan())
+}
+
+// Function to map a distance of the metric to a true metric with the same order of neighbors
+//
+// Cosine distance `1 - cos` becomes the Euclidean distance of the normalized vectors `sqrt(2 (1 - cos))`,
+// and Minkowski with p < 1 becomes `sum |a - b|^p`. Other metrics are already true metrics.
+fn transform(metric: Metric, d: f64) -> f64 {
+    match metric {
+        Metric::Cosine => (2.0 * d.max(0.0)).sqrt(),
+        Metric::Minkowski(p) if p < 1.0 => d.powf(p),
+        _ => d,
+    }
+}
+
+impl VpTree {
+    // Function to build the tree with the distance of the classifier, None when the items can not be indexed
+    pub(crate) fn build(clf: &KnnClassifier) -> Option<VpTree> {
+        let features = clf.items.first()?.data.len();
+        if !is_transformable(clf.metric) {
+            return None;
+        }
+        if clf.items.iter().any(|it| it.data.len() != features || it.data.iter().any(|v| v.is_nan())) {
+            return None;
+        }
+        let mut tree = VpTree { features, nodes: vec![] };
+        let mut ids: Vec<usize> = (0..clf.items.len()).collect();
+        tree.build_node(clf, &mut ids);
+        Some(tree)
+    }
+    fn build_node(&mut self, clf: &KnnClassifier, ids: &mut [usize]) -> usize {
+        let node = self.nodes.len();
+        self.nodes.push(Node::Leaf(ids.to_vec()));
+        if ids.len() <= LEAF_SIZE {
+            return node;
+        }
+        let t = |i: usize, j: usize| self.transformed(clf, &clf.items[i].data, &clf.items[j].data);
+        // the vantage point is the item farthest from the first item, a point on the rim of the set
+        let vp = *ids.iter().max_by(|a, b| t(ids[0], **a).total_cmp(&t(ids[0], **b))).unwrap();
+        let mid = ids.len() / 2;
+        ids.select_nth_unstable_by(mid, |a, b| t(vp, *a).total_cmp(&t(vp, *b)));
+        let mu = t(vp, ids[mid]);
+        let (l, r) = ids.split_at_mut(mid);
+        let inside = self.build_node(clf, l);
+        let outside = self.build_node(clf, r);
+        self.nodes[node] = Node::Split { vp, mu, inside, outside };
+        node
+    }
+    fn transformed(&self, clf: &KnnClassifier, a: &[f64], b: &[f64]) -> f64 {
+        transform(clf.metric, clf.distance(a, b))
+    }
+    fn search(&self, node: usize, clf: &KnnClassifier, query: &QueryPoint, n: usize, found: &mut Vec<(usize, f64)>) {
+        instrument::count_node();
+        match &self.nodes[node] {
+            Node::Leaf(ids) => {
+                for &i in ids {
+                    insert_neighbor(found, n, i, query.distance_to(i));
+                }
+            },
+            Node::Split { vp, mu, inside, outside } => {
+                let tq = transform(clf.metric, query.distance_to(*vp));
+                // lower bounds by the triangle inequality: mu - tq for `outside`, tq - mu for `inside`
+                let (near, far, lb) = if tq <= *mu { (*inside, *outside, mu - tq) } else { (*outside, *inside, tq - mu) };
+                self.search(near, clf, query, n, found);
+                // the limit in the transformed distance (results are kept in the original distance)
+                let limit = if found.len() < n { f64::INFINITY } else { transform(clf.metric, found[n - 1].1) };
+                if lb - BOUND_SLACK * (1.0 + tq + limit) <= limit {
+                    self.search(far, clf, query, n, found);
+                } else {
+                    instrument::count_pruned(1);
+                }
+            },
+        }
+    }
+}
+
+impl NeighborIndex for VpTree {
+    // same result as brute force
+    fn nearest(&self, clf: &KnnClassifier, query: &[f64], n: usize) -> Option<Vec<(usize, f64)>> {
+        if !is_transformable(clf.metric) || query.len() != self.features {
+            return None;
+        }
+        let mut found = vec![];
+        if n > 0 {
+            self.search(0, clf, &clf.query_point(query), n, &mut found);
+        }
+        Some(found)
+    }
+}
+
+#[cfg(feature = "msgpack")]
+impl VpTree {
+    // Function to read the tree written by `to_parts`, None when it is invalid
+    pub(crate) fn from_parts(r: &mut PartsReader, items: usize) -> Option<VpTree> {
+        let features = r.int()? as usize;
+        let count = r.int()? as usize;
+        let mut nodes = vec![];
+        for node in 0..count {
+            nodes.push(match r.int()? {
+                0 => Node::Leaf(r.ids(items)?),
+                1 => Node::Split { vp: r.below(items)?, inside: r.child(node, count)?, outside: r.child(node, count)?, mu: r.float()? },
+                _ => return None,
+            });
+        }
+        let covered = leaves_cover_items(nodes.len(), items, |i| match &nodes[i] {
+            Node::Leaf(ids) => TreeNode::Leaf(ids),
+            Node::Split { inside, outside, .. } => TreeNode::Split(*inside, *outside),
+        });
+        covered.then_some(VpTree { features, nodes })
+    }
+}
+
+impl BuiltIndex for VpTree {
+    // [features, nodes, then (0, ids) for a leaf or (1, vp, inside, outside) for a split], and the split distances
+    #[cfg(feature = "msgpack")]
+    fn to_parts(&self) -> IndexParts {
+        let mut parts = IndexParts { ints: vec![self.features as u64, self.nodes.len() as u64], floats: vec![] };
+        for node in &self.nodes {
+            match node {
+                Node::Leaf(ids) => {
+                    parts.ints.push(0);
+                    parts.push_ids(ids);
+                },
+                Node::Split { vp, mu, inside, outside } => {
+                    parts.ints.extend([1, *vp as u64, *inside as u64, *outside as u64]);
+                    parts.floats.push(*mu);
+                },
+            }
+        }
+        parts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Backend, KnnClassifier, Metric};
+    use crate::testutil::Rng;
+
+    #[test]
+    fn test_vp_tree() {
+        let mut c = KnnClassifier::new(5);
+        let mut rng = Rng(99u64);
+        let mut rand = || rng.uniform();
+        for i in 0..1200 {
+            let p: Vec<f64> = (0..6).map(|f| if f < 2 { (rand() * 4.0).round() - 2.0 } else { rand() - 0.5 }).collect();
+            c.fit_one(&p, if i % 3 == 0 { "a" } else { "b" });
+        }
+        c.fit_one(&[0.; 6], "a");
+        let queries: Vec<Vec<f64>> = (0..30).map(|_| (0..6).map(|_| rand() * 4.0 - 2.0).collect()).collect();
+        for metric in [Metric::Cosine, Metric::Minkowski(0.5), Metric::Euclidean, Metric::Canberra] {
+            c.metric = metric;
+            c.backend = Backend::BruteForce;
+            let expected: Vec<_> = queries.iter().map(|q| c.nearest(q, 9)).collect();
+            c.backend = Backend::VpTree;
+            for (q, e) in queries.iter().zip(&expected) {
+                assert_eq!(c.nearest_indexed(q, 9).as_ref(), Some(e));
+            }
+        }
+        assert!(c.nearest_indexed(&[f64::NAN; 6], 3).is_none());
+    }
+}
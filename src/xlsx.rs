@@ -0,0 +1,126 @@
+//! Reader for spreadsheets (feature `xlsx`).
+//!
+//! Excel (`.xlsx`, `.xlsm`, `.xls`, `.xlsb`) and OpenDocument (`.ods`) files are read with calamine.
+//! Cells with numbers are used as is, text cells are parsed as numbers, and empty cells become `NaN`
+//! (missing values). A first row with text that is not a number in a feature column is a header and is skipped.
+
+use crate::{KnnClassifier, KnnError, KnnItem};
+use calamine::{open_workbook_auto, Data, Reader};
+use std::path::Path;
+
+impl KnnClassifier {
+    /// read the sheet `sheet` of a spreadsheet file, using the column `label_col` (0 = column A) as the label
+    ///
+    /// Empty rows are skipped. Returns the number of rows read.
+    pub fn from_xlsx(&mut self, path: impl AsRef<Path>, sheet: &str, label_col: usize) -> Result<usize, KnnError> {
+        let path = path.as_ref();
+        let mut workbook = open_workbook_auto(path).map_err(|e| KnnError::Io(format!("{}: {}", path.display(), e)))?;
+        let range = workbook.worksheet_range(sheet).map_err(|e| KnnError::Decode(format!("sheet {}: {}", sheet, e)))?;
+        // the range starts at the first used cell
+        let (first_row, first_col) = range.start().unwrap_or((0, 0));
+        let mut items = vec![];
+        for (i, row) in range.rows().enumerate() {
+            if row.iter().all(|cell| *cell == Data::Empty) { continue; }
+            let line = first_row as usize + i + 1;
+            match parse_row(row, first_col as usize, label_col) {
+                Ok(it) => items.push(it),
+                Err(_) if items.is_empty() && is_header(row, first_col as usize, label_col) => continue,
+                Err(msg) => return Err(KnnError::Parse { line, msg }),
+            }
+        }
+        let count = items.len();
+        for it in items {
+            self.push_item(it);
+        }
+        self.fit_statistics();
+        Ok(count)
+    }
+}
+
+// Function to read a row whose first cell is in column `first_col`
+fn parse_row(row: &[Data], first_col: usize, label_col: usize) -> Result<KnnItem, String> {
+    let mut it = KnnItem::new("", &[]);
+    let mut label = None;
+    for (j, cell) in row.iter().enumerate() {
+        if first_col + j == label_col {
+            label = Some(cell.to_string().trim().to_string());
+            continue;
+        }
+        let v = match cell {
+            Data::Empty => f64::NAN,
+            Data::Int(v) => *v as f64,
+            Data::Float(v) => *v,
+            Data::String(s) => s.trim().parse().map_err(|_| format!("invalid number: {}", s.trim()))?,
+            // Excel stores dates as days since 1900
+            Data::DateTime(d) => d.as_f64(),
+            _ => return Err(format!("invalid number: {}", cell)),
+        };
+        it.data.push(v);
+    }
+    it.label = label.filter(|l| !l.is_empty()).ok_or_else(|| "missing label".to_string())?;
+    Ok(it)
+}
+
+// true when a feature cell holds text that is not a number (a row of column names)
+fn is_header(row: &[Data], first_col: usize, label_col: usize) -> bool {
+    row.iter().enumerate().any(|(j, cell)| first_col + j != label_col && matches!(cell, Data::String(s) if s.trim().parse::<f64>().is_err()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    // Function to write a minimal .xlsx file with one sheet (inline strings, no shared string table)
+    fn write_xlsx(path: &Path, sheet: &str, rows: &[&[&str]]) {
+        let cell = |r: usize, c: usize, v: &str| {
+            let name = format!("{}{}", (b'A' + c as u8) as char, r + 1);
+            match v {
+                "" => String::new(),
+                v if v.parse::<f64>().is_ok() => format!(r#"<c r="{}"><v>{}</v></c>"#, name, v),
+                v => format!(r#"<c r="{}" t="inlineStr"><is><t>{}</t></is></c>"#, name, v),
+            }
+        };
+        let data: String = rows.iter().enumerate().map(|(r, row)| {
+            let cells: String = row.iter().enumerate().map(|(c, v)| cell(r, c, v)).collect();
+            format!(r#"<row r="{}">{}</row>"#, r + 1, cells)
+        }).collect();
+        let files = [
+            ("[Content_Types].xml", r#"<?xml version="1.0" encoding="UTF-8"?><Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#.to_string()),
+            ("_rels/.rels", r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#.to_string()),
+            ("xl/workbook.xml", format!(r#"<?xml version="1.0" encoding="UTF-8"?><workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="{}" sheetId="1" r:id="rId1"/></sheets></workbook>"#, sheet)),
+            ("xl/_rels/workbook.xml.rels", r#"<?xml version="1.0" encoding="UTF-8"?><Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#.to_string()),
+            ("xl/worksheets/sheet1.xml", format!(r#"<?xml version="1.0" encoding="UTF-8"?><worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>{}</sheetData></worksheet>"#, data)),
+        ];
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        for (name, content) in files {
+            zip.start_file(name, options).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+    }
+
+    #[test]
+    fn test_from_xlsx() {
+        let path = std::env::temp_dir().join(format!("knn_xlsx_{}.xlsx", std::process::id()));
+        write_xlsx(&path, "data", &[
+            &["height", "weight", "label"],
+            &["150", "80", "肥満"],
+            &["170", "60", "標準"],
+            &[],
+            &["160", "", "標準"],
+        ]);
+        let mut c = KnnClassifier::new(1);
+        assert_eq!(c.from_xlsx(&path, "data", 2).unwrap(), 3);
+        assert_eq!(c.items[0].data, [150., 80.]);
+        assert!(c.items[2].data[1].is_nan());
+        assert_eq!(c.predict_one(&[152., 78.]), "肥満");
+        // missing sheet, invalid number
+        assert!(matches!(c.from_xlsx(&path, "other", 2), Err(KnnError::Decode(_))));
+        write_xlsx(&path, "data", &[&["150", "80", "a"], &["170", "x", "b"]]);
+        assert!(matches!(KnnClassifier::new(1).from_xlsx(&path, "data", 2), Err(KnnError::Parse { line: 2, .. })));
+        std::fs::remove_file(&path).unwrap();
+        assert!(matches!(c.from_xlsx(&path, "data", 2), Err(KnnError::Io(_))));
+    }
+}